@@ -40,10 +40,11 @@ impl Container {
     pub fn read_ecus(&mut self, reader: &mut Raf) -> super::Result<()> {
         self.ecus.clear();
         let ecu_table_offset = self.cff_header.ecu_offset as usize + self.cff_header.base_addr;
+        let lang = self.ctf_header.get_languages(0)?;
         for i in 0..self.cff_header.ecu_count as usize {
             reader.seek(ecu_table_offset + (i*4));
             let offset_to_actual_ecu = reader.read_i32()? as usize;
-            self.ecus.push(ECU::new(reader, &self.ctf_header.get_languages(0), &self.cff_header,ecu_table_offset + offset_to_actual_ecu)?)
+            self.ecus.push(ECU::new(reader, &lang, &self.cff_header,ecu_table_offset + offset_to_actual_ecu)?)
         }
         Ok(())
     }
@@ -7,7 +7,7 @@ pub fn read_bitflag_string(bit_flag: &mut u32, reader: &mut Raf, base_addr: usiz
     if check_and_advance_bitflag(bit_flag) {
         let string_offset = reader.read_i32()? as usize;
         let reader_pos = reader.pos;
-        reader.seek(string_offset + base_addr);
+        reader.seek(string_offset.wrapping_add(base_addr)); // Bad offsets fail the read, not the seek
         let res = read_string(reader).unwrap_or("".into());
         reader.seek(reader_pos);
         Ok(res)
@@ -20,7 +20,7 @@ pub fn read_bitflag_dump(bit_flag: &mut u32, reader: &mut Raf, dump_size: usize,
     if check_and_advance_bitflag(bit_flag) {
         let dump_offset = reader.read_i32()? as usize;
         let reader_pos = reader.pos;
-        reader.seek(dump_offset + base_addr);
+        reader.seek(dump_offset.wrapping_add(base_addr));
         let res = reader.read_bytes(dump_size).unwrap_or([].into());
         reader.seek(reader_pos);
         Ok(res)
@@ -32,7 +32,7 @@ pub fn read_bitflag_dump(bit_flag: &mut u32, reader: &mut Raf, dump_size: usize,
 #[allow(dead_code)]
 pub fn read_bitflag_dump_as_string(bit_flag: &mut u32, reader: &mut Raf, dump_size: usize, base_addr: usize) -> super::Result<String> {
     let data = read_bitflag_dump(bit_flag, reader, dump_size, base_addr)?;
-    Ok(encoding_rs::ISO_8859_10.decode(&data).0.to_string())
+    Ok(decode_string(&data))
 }
 
 
@@ -48,7 +48,24 @@ fn check_and_advance_bitflag(bit_flag: &mut u32) -> bool {
 /// returning it as UTF-8 encoded
 fn read_string(reader: &mut Raf) -> super::Result<String> {
     reader.read_cstr_bytes().map_err(CaesarError::FileError)
-    .map(|b|encoding_rs::ISO_8859_10.decode(&b).0.to_string())
+    .map(|b| decode_string(&b))
+}
+
+/// Decodes a string from a CBF file, up to the first null.
+///
+/// Strings are Windows-1252 (Latin-1, plus a few symbols such as '€'), so German
+/// descriptions and units like 'µs' decode correctly. Control characters only
+/// appear in corrupt strings, and are replaced with U+FFFD so names stay printable
+pub fn decode_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    encoding_rs::WINDOWS_1252.decode_without_bom_handling(&bytes[..end]).0
+        .chars()
+        .map(|c| match c {
+            '\t' | '\n' | '\r' => c,
+            c if c.is_control() => char::REPLACEMENT_CHARACTER,
+            c => c
+        })
+        .collect()
 }
 
 
@@ -158,4 +175,37 @@ impl CaesarPrimitive for u8 {
     fn to_usize(&self) -> usize {
         *self as usize
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::raf::RafByteOrder;
+
+    #[test]
+    fn test_decode_string() {
+        // "Drehzahl Kühlerlüfter" (Radiator fan speed)
+        assert_eq!(decode_string(b"Drehzahl K\xFChlerl\xFCfter"), "Drehzahl Kühlerlüfter");
+        assert_eq!(decode_string(b"Zeit in \xB5s\0garbage"), "Zeit in µs");
+        assert_eq!(decode_string(b"\x80 \xB0C \x81"), "€ °C \u{FFFD}");
+        assert_eq!(decode_string(b"A\x07B"), "A\u{FFFD}B");
+        assert_eq!(decode_string(b""), "");
+    }
+
+    #[test]
+    fn test_read_bitflag_string() {
+        // String offset (4), then a string with no terminator before the end of the file
+        let mut reader = Raf::from_bytes(b"\x04\x00\x00\x00Gr\xF6\xDFe", RafByteOrder::LE);
+        let mut flag = 0b11;
+        assert_eq!(read_bitflag_string(&mut flag, &mut reader, 0).unwrap(), "");
+        assert_eq!(reader.pos, 4);
+
+        let mut reader = Raf::from_bytes(b"\x04\x00\x00\x00Gr\xF6\xDFe\0", RafByteOrder::LE);
+        assert_eq!(read_bitflag_string(&mut flag, &mut reader, 0).unwrap(), "Größe");
+
+        // Offset points outside the file
+        let mut reader = Raf::from_bytes(&[0xFF, 0xFF, 0xFF, 0xFF], RafByteOrder::LE);
+        let mut flag = 0b1;
+        assert_eq!(read_bitflag_string(&mut flag, &mut reader, 0x10).unwrap(), "");
+    }
+}
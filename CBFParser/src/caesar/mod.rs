@@ -23,6 +23,16 @@ impl From<std::io::Error> for CaesarError {
     }
 }
 
+impl std::fmt::Display for CaesarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaesarError::FileError(e) => write!(f, "Error reading CBF data: {:?}", e),
+            CaesarError::ProcessException(s) => write!(f, "{}", s),
+            CaesarError::IOError(e) => write!(f, "IO error: {}", e)
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, CaesarError>;
 
 
@@ -41,8 +41,10 @@ impl CTFHeader {
         Ok(res)
     }
 
-    pub fn get_languages(&self, idx: usize) -> CTFLanguage {
-        self.languages[idx].clone()
+    pub fn get_languages(&self, idx: usize) -> std::result::Result<CTFLanguage, CaesarError> {
+        self.languages.get(idx).cloned().ok_or_else(|| {
+            CaesarError::ProcessException(format!("CTF header has no language {} ({} languages)", idx, self.languages.len()))
+        })
     }
 }
 
@@ -83,8 +85,8 @@ impl CTFLanguage {
         for i in 0..self.string_count {
             reader.seek(table_offset + (i*4));
             let string_offset = reader.read_i32()? as usize;
-            reader.seek(table_offset + string_offset);
-            self.strings.push(reader.read_cstr_bytes().map(|b| encoding_rs::ISO_8859_10.decode(&b).0.to_string())?)
+            reader.seek(table_offset.wrapping_add(string_offset));
+            self.strings.push(reader.read_cstr_bytes().map(|b| creader::decode_string(&b))?)
         }
         Ok(())
     }
@@ -1,5 +1,5 @@
 use common::raf::Raf;
-use crate::{caesar::{CaesarError, PoolTuple, creader}, ctf::ctf_header::CTFLanguage, ecu::{ECU, com_param::ComParameter}};
+use crate::{caesar::{CaesarError, PoolTuple, creader}, ctf::ctf_header::CTFLanguage, ecu::{ECU, com_param::ComParameter, to_hex_string}};
 use super::preparation::Preparation;
 
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
@@ -213,6 +213,17 @@ impl Service {
         Ok(res)
     }
 
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "qualifier": self.qualifier,
+            "name": self.name,
+            "description": self.description,
+            "service_type": format!("{:?}", self.service_type),
+            "request": to_hex_string(&self.req_bytes),
+            "com_params": self.com_params.iter().map(|x| x.to_json()).collect::<Vec<_>>(),
+        })
+    }
+
     pub (crate) fn get_byte_count(&self) -> usize {
         self.request_bytes.count
     }
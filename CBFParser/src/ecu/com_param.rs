@@ -1,6 +1,6 @@
 use common::raf::Raf;
 use crate::caesar::{CaesarError, creader};
-use super::interface::ECUInterface;
+use super::{interface::ECUInterface, interface_subtype::ParamName, to_hex_string};
 
 
 #[derive(Debug, Clone, Default)]
@@ -52,4 +52,18 @@ impl ComParameter {
         println!("{:?}", res);
         Ok(res)
     }
+
+    /// Serializes the parameter's name and value. Names which are not a known [ParamName]
+    /// keep their raw name from the CBF
+    pub fn to_json(&self) -> serde_json::Value {
+        let name = match ParamName::from_string(&self.param_name) {
+            ParamName::CP_UNKNOWN => self.param_name.clone(),
+            p => p.to_string()
+        };
+        serde_json::json!({
+            "name": name,
+            "value": self.param_value,
+            "dump": to_hex_string(&self.dump),
+        })
+    }
 }
\ No newline at end of file
@@ -41,9 +41,19 @@ impl ECUInterface {
             reader.seek(com_param_file_offset + (i*4));
             let iface_string_ptr = reader.read_i32()? as usize + com_param_file_offset;
             reader.seek(iface_string_ptr);
-            let com_param = reader.read_cstr_bytes().map(|b| encoding_rs::ISO_8859_10.decode(&b).0.to_string())?;
+            let com_param = reader.read_cstr_bytes().map(|b| creader::decode_string(&b))?;
             res.com_params.push(com_param);
         }
         Ok(res)
     }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "qualifier": self.qualifier,
+            "name": self.name,
+            "description": self.desc,
+            "version": self.version_string,
+            "com_params": self.com_params,
+        })
+    }
 }
\ No newline at end of file
@@ -3,7 +3,7 @@ use crate::{caesar::{CaesarError, creader}, ctf::ctf_header::CTFLanguage};
 use super::com_param::ComParameter;
 
 #[allow(non_camel_case_types, dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ParamName {
     CP_BAUDRATE,
     CP_GLOBAL_REQUEST_CANIDENTIFIER,
@@ -44,6 +44,51 @@ impl Default for ParamName {
     }
 }
 
+impl std::fmt::Display for ParamName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl ParamName {
+    /// Resolves the name of a com parameter as it appears in the CBF.
+    /// Names which are not known return CP_UNKNOWN
+    pub fn from_string(name: &str) -> Self {
+        match name {
+            "CP_BAUDRATE" => Self::CP_BAUDRATE,
+            "CP_GLOBAL_REQUEST_CANIDENTIFIER" => Self::CP_GLOBAL_REQUEST_CANIDENTIFIER,
+            "CP_FUNCTIONAL_REQUEST_CANIDENTIFIER" => Self::CP_FUNCTIONAL_REQUEST_CANIDENTIFIER,
+            "CP_REQUEST_CANIDENTIFIER" => Self::CP_REQUEST_CANIDENTIFIER,
+            "CP_RESPONSE_CANIDENTIFIER" => Self::CP_RESPONSE_CANIDENTIFIER,
+            "CP_PARTNUMBERID" => Self::CP_PARTNUMBERID,
+            "CP_PARTBLOCK" => Self::CP_PARTBLOCK,
+            "CP_HWVERSIONID" => Self::CP_HWVERSIONID,
+            "CP_SWVERSIONID" => Self::CP_SWVERSIONID,
+            "CP_SWVERSIONBLOCK" => Self::CP_SWVERSIONBLOCK,
+            "CP_SUPPLIERID" => Self::CP_SUPPLIERID,
+            "CP_SWSUPPLIERBLOCK" => Self::CP_SWSUPPLIERBLOCK,
+            "CP_ADDRESSMODE" => Self::CP_ADDRESSMODE,
+            "CP_ADDRESSEXTENSION" => Self::CP_ADDRESSEXTENSION,
+            "CP_ROE_RESPONSE_CANIDENTIFIER" => Self::CP_ROE_RESPONSE_CANIDENTIFIER,
+            "CP_USE_TIMING_RECEIVED_FROM_ECU" => Self::CP_USE_TIMING_RECEIVED_FROM_ECU,
+            "CP_STMIN_SUG" => Self::CP_STMIN_SUG,
+            "CP_BLOCKSIZE_SUG" => Self::CP_BLOCKSIZE_SUG,
+            "CP_P2_TIMEOUT" => Self::CP_P2_TIMEOUT,
+            "CP_S3_TP_PHYS_TIMER" => Self::CP_S3_TP_PHYS_TIMER,
+            "CP_S3_TP_FUNC_TIMER" => Self::CP_S3_TP_FUNC_TIMER,
+            "CP_BR_SUG" => Self::CP_BR_SUG,
+            "CP_CAN_TRANSMIT" => Self::CP_CAN_TRANSMIT,
+            "CP_BS_MAX" => Self::CP_BS_MAX,
+            "CP_CS_MAX" => Self::CP_CS_MAX,
+            "CPI_ROUTINECOUNTER" => Self::CPI_ROUTINECOUNTER,
+            "CP_REQREPCOUNT" => Self::CP_REQREPCOUNT,
+            "CP_P2_EXT_TIMEOUT_7F_78" => Self::CP_P2_EXT_TIMEOUT_7F_78,
+            "CP_P2_EXT_TIMEOUT_7F_21" => Self::CP_P2_EXT_TIMEOUT_7F_21,
+            _ => Self::CP_UNKNOWN
+        }
+    }
+}
+
 
 #[derive(Debug, Clone, Default)]
 pub struct InterfaceSubType {
@@ -99,5 +144,81 @@ impl InterfaceSubType {
     pub fn get_cp_by_name(&self, name: &str) -> Option<u32> {
         self.comm_params.iter().find(|x| x.param_name == name).map(|x| x.param_value as u32)
     }
+
+    /// Returns the ISO-TP address extension byte (CP_ADDRESSEXTENSION), if CP_ADDRESSMODE
+    /// says the ECU uses extended or mixed addressing. A mode of 0 is normal addressing,
+    /// where the extension byte is not sent
+    pub fn get_isotp_addr_ext(&self) -> Option<u8> {
+        match self.get_cp_by_name("CP_ADDRESSMODE") {
+            Some(mode) if mode != 0 => self.get_cp_by_name("CP_ADDRESSEXTENSION").map(|x| x as u8),
+            _ => None
+        }
+    }
+
+    /// Returns every com parameter of the interface, with its name resolved and its value.
+    /// Parameters with a name that is not known are returned as CP_UNKNOWN, in the same
+    /// order as `comm_params`, so their raw name can still be looked up from there
+    pub fn all_com_params(&self) -> Vec<(ParamName, i32)> {
+        self.comm_params.iter().map(|x| (ParamName::from_string(&x.param_name), x.param_value)).collect()
+    }
+
+    /// Pretty prints every com parameter of the interface, one per line.
+    /// Unknown parameters are shown with their raw name from the CBF
+    pub fn format_com_params(&self) -> String {
+        let mut res = format!("Com parameters of {}:\n", self.qualifier);
+        for ((name, value), cp) in self.all_com_params().iter().zip(self.comm_params.iter()) {
+            let name = match name {
+                ParamName::CP_UNKNOWN => format!("{} (Unknown)", cp.param_name),
+                _ => name.to_string()
+            };
+            res.push_str(&format!("  {:<40} {:>10} (0x{:08X})\n", name, value, value));
+        }
+        res
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "qualifier": self.qualifier,
+            "name": self.name,
+            "description": self.description,
+            "com_params": self.comm_params.iter().map(|x| x.to_json()).collect::<Vec<_>>(),
+        })
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cp(name: &str, value: i32) -> ComParameter {
+        let mut cp = ComParameter::default();
+        cp.param_name = name.into();
+        cp.param_value = value;
+        cp
+    }
+
+    #[test]
+    fn test_isotp_addr_ext() {
+        let mut sub = InterfaceSubType::default();
+        sub.comm_params.push(cp("CP_ADDRESSEXTENSION", 0xF1));
+        assert_eq!(sub.get_isotp_addr_ext(), None);
+        sub.comm_params.push(cp("CP_ADDRESSMODE", 0));
+        assert_eq!(sub.get_isotp_addr_ext(), None);
+        sub.comm_params[1].param_value = 1;
+        assert_eq!(sub.get_isotp_addr_ext(), Some(0xF1));
+    }
+
+    #[test]
+    fn test_all_com_params() {
+        let sub = InterfaceSubType {
+            qualifier: "CAN_Sub".into(),
+            comm_params: vec![cp("CP_BAUDRATE", 500000), cp("CP_SOMETHING_NEW", 7)],
+            ..Default::default()
+        };
+        assert_eq!(sub.all_com_params(), vec![(ParamName::CP_BAUDRATE, 500000), (ParamName::CP_UNKNOWN, 7)]);
+        let txt = sub.format_com_params();
+        assert!(txt.contains("CP_BAUDRATE"));
+        assert!(txt.contains("CP_SOMETHING_NEW (Unknown)"));
+        assert!(txt.contains("0x0007A120"));
+    }
+}
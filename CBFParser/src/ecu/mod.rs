@@ -11,6 +11,12 @@ pub mod interface;
 pub mod interface_subtype;
 pub mod com_param;
 
+/// Formats a raw buffer as a hex string (Such as "0A1B2C") for JSON output, which is far
+/// easier to read than an array of integers
+pub (crate) fn to_hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
 
 #[derive(Debug, Clone, Copy, Default)]
 pub (crate) struct Block {
@@ -177,6 +183,21 @@ impl ECU {
         Ok(res)
     }
 
+    /// Serializes the parsed ECU, with its interfaces, com parameters, and the diagnostic
+    /// services of each variant. Strings are the ones resolved from the CTF language table
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "qualifier": self.qualifier,
+            "name": self.name,
+            "description": self.description,
+            "xml_version": self.xml_version,
+            "class_name": self.class_name,
+            "interfaces": self.interfaces.iter().map(|x| x.to_json()).collect::<Vec<_>>(),
+            "interface_sub_types": self.interface_sub_types.iter().map(|x| x.to_json()).collect::<Vec<_>>(),
+            "variants": self.variants.iter().map(|x| x.to_json()).collect::<Vec<_>>(),
+        })
+    }
+
     pub (crate) fn read_pool(reader: &mut Raf, pool: &Block) -> std::result::Result<Vec<u8>, CaesarError> {
         reader.seek(pool.block_offset);
         reader.read_bytes(pool.entry_count * pool.entry_size).map_err(CaesarError::FileError)
@@ -266,4 +287,48 @@ impl ECU {
         }
         Ok(res)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use self::{com_param::ComParameter, interface_subtype::InterfaceSubType};
+
+    #[test]
+    fn test_ecu_to_json() {
+        // No sample CBF is shipped with the repo, so build the parsed ECU by hand
+        let mut cp = ComParameter::default();
+        cp.param_name = "CP_REQUEST_CANIDENTIFIER".into();
+        cp.param_value = 0x7E0;
+        let mut sub = InterfaceSubType::default();
+        sub.qualifier = "CAN_Sub".into();
+        sub.comm_params.push(cp);
+
+        let mut service = Service::default();
+        service.qualifier = "DT_Read_VIN".into();
+        service.name = Some("Read VIN".into());
+        service.req_bytes = vec![0x22, 0xF1, 0x90];
+        let mut variant = ECUVariant::default();
+        variant.qualifier = "ENG_V1".into();
+        variant.services.push(service);
+
+        let ecu = ECU {
+            qualifier: "ENG".into(),
+            name: Some("Engine control unit".into()),
+            interface_sub_types: vec![sub],
+            variants: vec![variant],
+            ..Default::default()
+        };
+
+        let txt = serde_json::to_string_pretty(&ecu.to_json()).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&txt).unwrap();
+        assert_eq!(json["qualifier"], "ENG");
+        assert_eq!(json["name"], "Engine control unit");
+        let cp = &json["interface_sub_types"][0]["com_params"][0];
+        assert_eq!(cp["name"], "CP_REQUEST_CANIDENTIFIER");
+        assert_eq!(cp["value"], 0x7E0);
+        let service = &json["variants"][0]["services"][0];
+        assert_eq!(service["name"], "Read VIN");
+        assert_eq!(service["request"], "22F190");
+    }
+}
@@ -122,14 +122,13 @@ impl ECUVariant {
     }
 
     fn create_diag_services(&self, pool: Vec<i32>, parent_ecu: &ECU) -> std::result::Result<Vec<Service>, CaesarError> {
-        let mut res = vec![Service::default(); pool.len()];
-        parent_ecu.global_services.iter().for_each(|d| {
-            for (pos, idx) in pool.iter().enumerate() {
-                if d.pool_idx == *idx as usize {
-                    res[pos] = d.clone();
-                }
-            }
-        });
+        let res = resolve_diag_services(&pool, &parent_ecu.global_services);
+        if res.len() != self.diag_services.count {
+            println!(
+                "WARNING: Variant {} lists {} diag services, but only {} are in the ECU's service pool. Ignoring the rest",
+                self.qualifier, self.diag_services.count, res.len()
+            );
+        }
         Ok(res)
     }
 
@@ -241,4 +240,48 @@ impl ECUVariant {
         }
         Ok(())
     }
-}
\ No newline at end of file
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "qualifier": self.qualifier,
+            "name": self.name,
+            "description": self.description,
+            "services": self.services.iter().map(|x| x.to_json()).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Looks up each entry of a variant's diag service pool in the ECU's global service pool,
+/// keeping the variant's order. Entries which are not in the global pool are dropped, rather
+/// than being left as empty services
+fn resolve_diag_services(pool: &[i32], global_services: &[Service]) -> Vec<Service> {
+    pool.iter()
+        .filter_map(|idx| global_services.iter().find(|d| d.pool_idx as i64 == *idx as i64))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(pool_idx: usize) -> Service {
+        let mut s = Service::default();
+        s.qualifier = format!("DJ_{}", pool_idx);
+        s.pool_idx = pool_idx;
+        s
+    }
+
+    #[test]
+    fn test_resolve_diag_services() {
+        let global: Vec<Service> = (0..5).map(service).collect();
+        let res = resolve_diag_services(&[3, 0, 4], &global);
+        assert_eq!(res.len(), 3);
+        assert_eq!(res.iter().map(|s| s.qualifier.as_str()).collect::<Vec<_>>(), ["DJ_3", "DJ_0", "DJ_4"]);
+
+        // Offsets outside of the global pool are dropped
+        let res = resolve_diag_services(&[1, 7, -1], &global);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].qualifier, "DJ_1");
+    }
+}
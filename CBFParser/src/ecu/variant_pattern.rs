@@ -20,8 +20,11 @@ impl Default for ECUType {
 
 #[derive(Debug, Clone, Default)]
 pub struct VariantPattern {
-    unk_buffer_size: i32,
-    unk_buffer: Vec<u8>,
+    /// Length of [VariantPattern::ident_pattern]
+    ident_pattern_size: i32,
+    /// Bytes expected at the start of the identification data (After the
+    /// response SID and DID/local ID). Empty in most CBF files
+    pub ident_pattern: Vec<u8>,
 
     unk3: i32,
     unk4: i32,
@@ -49,12 +52,15 @@ pub struct VariantPattern {
     unk19: u32,
     unk20: u32,
 
-    unk21: String,
+    /// Qualifier of the identification service whose response is compared
+    pub ident_qualifier: String,
 
     unk22: i32,
     unk23: i32,
     uds_vendor_id: i32,
-    pattern_type: i32,
+    /// Comparison operator of the pattern. The values are not reversed yet,
+    /// [VariantPattern::matches] always compares for equality
+    pub pattern_type: i32,
 
     /// ECU Vendor ID (If using UDS)
     pub variant_id: ECUType,
@@ -68,11 +74,11 @@ impl VariantPattern {
 
         let mut bitflags = reader.read_u32()?;
 
-        let unk_buffer_size = creader::read_primitive(&mut bitflags, reader, 0i32)?;
+        let ident_pattern_size = creader::read_primitive(&mut bitflags, reader, 0i32)?;
         println!("Processing Variant Pattern - Base address: 0x{:08X}", base_addr);
         let mut res = VariantPattern {
-            unk_buffer_size,
-            unk_buffer: creader::read_bitflag_dump(&mut bitflags, reader, unk_buffer_size as usize, base_addr)?,
+            ident_pattern_size,
+            ident_pattern: creader::read_bitflag_dump(&mut bitflags, reader, ident_pattern_size as usize, base_addr)?,
 
             unk3: creader::read_primitive(&mut bitflags, reader, 0i32)?,
             unk4: creader::read_primitive(&mut bitflags, reader, 0i32)?,
@@ -93,7 +99,7 @@ impl VariantPattern {
             unk18: creader::read_primitive(&mut bitflags, reader, 0u8)? as u32,
             unk19: creader::read_primitive(&mut bitflags, reader, 0u8)? as u32,
             unk20: creader::read_primitive(&mut bitflags, reader, 0u8)? as u32,
-            unk21: creader::read_bitflag_string(&mut bitflags, reader, base_addr)?,
+            ident_qualifier: creader::read_bitflag_string(&mut bitflags, reader, base_addr)?,
             unk22: creader::read_primitive(&mut bitflags, reader, 0i32)?,
             unk23: creader::read_primitive(&mut bitflags, reader, 0i32)?,
             uds_vendor_id: creader::read_primitive(&mut bitflags, reader, 0i32)?,
@@ -112,4 +118,88 @@ impl VariantPattern {
             ECUType::UNK => 0
         }
     }
-}
\ No newline at end of file
+
+    /// Checks a positive identification response from the ECU against this pattern.
+    ///
+    /// * UDS - `ecu_response` is the response to ReadDataByIdentifier 0xF100
+    ///   (`62 F1 00 ..`). The 4 bytes after the DID are the variant ID.
+    /// * KWP2000 - `ecu_response` is the response to ReadECUIdentification 0x87
+    ///   (`5A 87 ..`). Bytes 4 and 5 are the diagnostic information (Variant ID).
+    ///
+    /// If [VariantPattern::ident_pattern] is not empty, the identification data
+    /// must also start with it.
+    pub fn matches(&self, ecu_response: &[u8]) -> bool {
+        let (data, id) = match self.variant_id {
+            ECUType::UDS => match ecu_response {
+                [0x62, 0xF1, 0x00, data @ ..] if data.len() >= 4 => {
+                    (data, u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as i32)
+                }
+                _ => return false,
+            },
+            ECUType::KWP => match ecu_response {
+                [0x5A, 0x87, data @ ..] if data.len() >= 4 => {
+                    (data, (data[2] as i32) << 8 | data[3] as i32)
+                }
+                _ => return false,
+            },
+            ECUType::UNK => return false,
+        };
+        id == self.get_vendor_id() && data.starts_with(&self.ident_pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(variant_id: ECUType, vendor_id: i32, ident_pattern: &[u8]) -> VariantPattern {
+        VariantPattern {
+            ident_pattern_size: ident_pattern.len() as i32,
+            ident_pattern: ident_pattern.to_vec(),
+            kwp_vendor_id: vendor_id,
+            uds_vendor_id: vendor_id,
+            variant_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_matches_uds() {
+        let p = pattern(ECUType::UDS, 0x0001_0203, &[]);
+        assert!(p.matches(&[0x62, 0xF1, 0x00, 0x00, 0x01, 0x02, 0x03]));
+        assert!(p.matches(&[0x62, 0xF1, 0x00, 0x00, 0x01, 0x02, 0x03, 0xFF]));
+        assert!(!p.matches(&[0x62, 0xF1, 0x00, 0x00, 0x01, 0x02, 0x04]));
+        // Wrong DID, wrong SID and a truncated ID
+        assert!(!p.matches(&[0x62, 0xF1, 0x01, 0x00, 0x01, 0x02, 0x03]));
+        assert!(!p.matches(&[0x5A, 0xF1, 0x00, 0x00, 0x01, 0x02, 0x03]));
+        assert!(!p.matches(&[0x62, 0xF1, 0x00, 0x00, 0x01, 0x02]));
+    }
+
+    #[test]
+    fn test_matches_kwp() {
+        let p = pattern(ECUType::KWP, 0x1234, &[]);
+        assert!(p.matches(&[0x5A, 0x87, 0xAA, 0xBB, 0x12, 0x34]));
+        assert!(!p.matches(&[0x5A, 0x87, 0x12, 0x34, 0xAA, 0xBB]));
+        assert!(!p.matches(&[0x5A, 0x86, 0xAA, 0xBB, 0x12, 0x34]));
+        assert!(!p.matches(&[0x5A, 0x87, 0xAA, 0xBB, 0x12]));
+    }
+
+    #[test]
+    fn test_matches_unknown_type() {
+        let p = pattern(ECUType::UNK, 0, &[]);
+        assert!(!p.matches(&[0x62, 0xF1, 0x00, 0x00, 0x00, 0x00, 0x00]));
+        assert!(!p.matches(&[0x5A, 0x87, 0x00, 0x00, 0x00, 0x00]));
+    }
+
+    #[test]
+    fn test_matches_ident_pattern() {
+        let p = pattern(ECUType::KWP, 0x1234, &[0xAA, 0xBB]);
+        assert!(p.matches(&[0x5A, 0x87, 0xAA, 0xBB, 0x12, 0x34]));
+        assert!(!p.matches(&[0x5A, 0x87, 0xAA, 0xBC, 0x12, 0x34]));
+
+        let p = pattern(ECUType::UDS, 0x0001_0203, &[0x00, 0x01, 0x02, 0x03, 0x04]);
+        assert!(p.matches(&[0x62, 0xF1, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04]));
+        // The ID matches, but the identification data is shorter than the pattern
+        assert!(!p.matches(&[0x62, 0xF1, 0x00, 0x00, 0x01, 0x02, 0x03]));
+    }
+}
@@ -4,12 +4,46 @@ pub mod caesar;
 pub mod ctf;
 pub mod ecu;
 pub mod diag;
+pub mod odx;
 
 pub fn read_cbf_complete(src: &mut File) -> caesar::Result<caesar::container::Container> {
-    let mut buffer = vec![0; src.metadata().unwrap().len() as usize];
+    let mut buffer = vec![0; src.metadata()?.len() as usize];
     src.read_exact(&mut buffer)?;
-    let mut br = common::raf::Raf::from_bytes(&buffer, common::raf::RafByteOrder::LE);
+    read_cbf_bytes(&buffer)
+}
+
+/// Same as [read_cbf_complete], for a CBF file which is already in memory.
+/// Truncated or malformed data returns an error
+pub fn read_cbf_bytes(data: &[u8]) -> caesar::Result<caesar::container::Container> {
+    let mut br = common::raf::Raf::from_bytes(data, common::raf::RafByteOrder::LE);
     let (mut container, raf) = caesar::container::Container::new(&mut br)?;
     container.read_ecus(raf)?;
     Ok(container)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_malformed_cbf() {
+        assert!(read_cbf_bytes(&[]).is_err());
+        // Valid stub header, then nothing
+        let mut data = vec![0u8; ctf::STUB_HEADER_SIZE];
+        data[..28].copy_from_slice(b"CBF-TRANSLATOR-VERSION:04.00");
+        data[0x401] = 3;
+        assert!(read_cbf_bytes(&data).is_err());
+        // Pseudo random garbage after the header
+        let mut x: u32 = 0x1234_5678;
+        for len in [4, 64, 1024, 16384] {
+            let mut d = data.clone();
+            d.extend((0..len).map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            }));
+            assert!(read_cbf_bytes(&d).is_err(), "{} bytes", len);
+        }
+    }
+}
@@ -20,6 +20,8 @@ fn help(err: String) -> ! {
     println!("Error: {}", err);
     println!("Usage:");
     println!("cbf_parser <INPUT.CBF>");
+    println!("cbf_parser <INPUT.CBF> -odx");
+    println!("cbf_parser <INPUT.CBF> -json");
     println!("cbf_parser <INPUT.CBF> -dump_strings <STRINGS.csv>");
     println!("cbf_parser <INPUT.CBF> -load_strings <STRINGS.csv>");
     std::process::exit(1);
@@ -30,18 +32,24 @@ fn main() {
 
     if args.len() == 4 {
         match args[2].as_str() {
-            "-dump_strings" => read_file(&args[1], Some(args[3].clone()), true),
-            "-load_strings" => read_file(&args[1], Some(args[3].clone()), false),
+            "-dump_strings" => read_file(&args[1], Some(args[3].clone()), true, false, false),
+            "-load_strings" => read_file(&args[1], Some(args[3].clone()), false, false, false),
             _ => help("String operation is not valid: {}".into())
         }
+    } else if args.len() == 3 {
+        match args[2].as_str() {
+            "-odx" => read_file(&args[1], None, false, true, false),
+            "-json" => read_file(&args[1], None, false, false, true),
+            _ => help(format!("Unknown option: {}", args[2]))
+        }
     } else if args.len() == 2 {
-        read_file(&args[1], None, false)
+        read_file(&args[1], None, false, false, false)
     } else {
         help(format!("Invalid number of args: {}", args.len() - 1))
     }
 }
 
-fn read_file(path: &String, str_path: Option<String>, is_dump: bool) {
+fn read_file(path: &String, str_path: Option<String>, is_dump: bool, export_odx: bool, export_json: bool) {
     if path.ends_with(".cff") {
         eprintln!("Cannot be used with CFF. Only CBF!");
         return;
@@ -65,7 +73,8 @@ fn read_file(path: &String, str_path: Option<String>, is_dump: bool) {
                 }
             }
             match container.read_ecus(reader) {
-                Ok(_) => decode_ecu(&container.ecus[0]),
+                Ok(_) if export_json => dump_ecu_json(&container.ecus[0]),
+                Ok(_) => decode_ecu(&container.ecus[0], export_odx),
                 Err(e) => {
                     eprintln!("Error decoding ECUS! {:?}", e)
                 }
@@ -77,7 +86,15 @@ fn read_file(path: &String, str_path: Option<String>, is_dump: bool) {
     }
 }
 
-fn decode_ecu(e: &ECU) {
+/// Writes the parsed CBF data as is, rather than converting it to an OVD ECU
+fn dump_ecu_json(e: &ECU) {
+    let path = format!("{}.cbf.json", e.qualifier);
+    let mut f = File::create(&path).expect("Cannot open output file");
+    f.write_all(serde_json::to_string_pretty(&e.to_json()).unwrap().as_bytes()).expect("Error writing output");
+    println!("Parsed CBF data written to {}", path)
+}
+
+fn decode_ecu(e: &ECU, export_odx: bool) {
     println!("Converting ECU {}", e.qualifier);
 
     let mut ecu = OvdECU {
@@ -89,6 +106,7 @@ fn decode_ecu(e: &ECU) {
 
     let mut connections = Vec::new();
     for x in e.interface_sub_types.iter() {
+        print!("{}", x.format_com_params());
         let connection = if x.qualifier.contains("CAN") { // Its CAN (ISOTP)
             Connection {
                 baud: x.get_cp_by_name("CP_BAUDRATE").unwrap_or_default(),
@@ -98,9 +116,10 @@ fn decode_ecu(e: &ECU) {
                 connection_type: common::schema::ConType::ISOTP {
                     blocksize: 8, // Some reason MB always uses 8
                     st_min: x.get_cp_by_name("CP_STMIN_SUG").unwrap_or(20), // Seems default for MB
-                    ext_isotp_addr: false, // MB never use extended ISO-TP addresing
+                    ext_isotp_addr: x.get_isotp_addr_ext().is_some(),
                     ext_can_addr: x.get_cp_by_name("CP_REQUEST_CANIDENTIFIER").unwrap_or_default() > 0x7FF
-                        || x.get_cp_by_name("CP_RESPONSE_CANIDENTIFIER").unwrap_or_default() > 0x7FF
+                        || x.get_cp_by_name("CP_RESPONSE_CANIDENTIFIER").unwrap_or_default() > 0x7FF,
+                    isotp_addr_ext: x.get_isotp_addr_ext(),
                 },
                 server_type: if x.qualifier.contains("UDS") { // Interface type is in qualifier name for ISO-TP
                     common::schema::ServerType::UDS
@@ -319,6 +338,12 @@ fn decode_ecu(e: &ECU) {
     //    f.write_all(serde_json::to_string_pretty(&new_ecu).unwrap().as_bytes()).expect("Error writing output");
     //}
 
+    if export_odx {
+        println!("Writing ODX");
+        let mut f = File::create(format!("{}.odx", ecu.name)).expect("Cannot open ODX output file");
+        f.write_all(cbf_parser::odx::to_odx(&ecu).as_bytes()).expect("Error writing ODX output");
+    }
+
     println!("ECU decoding complete. Output file is {}.json. Have a nice day!", ecu.name)
 }
 
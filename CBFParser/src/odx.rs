@@ -0,0 +1,529 @@
+//! Exports a converted ECU as a simplified ODX (ASAM MCD-2D) XML document, so data
+//! from CBF files can be brought into tools which only understand ODX.
+//!
+//! Only a subset of ODX 2.2 is written. Each ECU variant becomes an `ECU-VARIANT`
+//! diag layer containing:
+//!
+//! * `DIAG-DATA-DICTIONARY-SPEC`
+//!   * `DTC-DOPS` - A single DTC-DOP listing every DTC of the variant. The DTC name is
+//!     the `DISPLAY-TROUBLE-CODE`, and the summary and description form the `TEXT`.
+//!     Environment data is not exported.
+//!   * `DATA-OBJECT-PROPS` - One DOP per service parameter, holding its presentation
+//!     (See below) and unit.
+//! * `DIAG-COMMS` - One `DIAG-SERVICE` per service. `SEMANTIC` is `DATA` for downloads,
+//!   `FUNCTION` for functions, `ADJUSTMENT` for adjustments and `ACTUATION` for actuations.
+//! * `REQUESTS` - The request payload is split into a `SID-RQ` CODED-CONST param
+//!   (The first byte) and an `ID` CODED-CONST param (The remaining bytes, as a byte field),
+//!   followed by a VALUE param for each input parameter.
+//! * `POS-RESPONSES` - A `SID-PR` CODED-CONST param (Request SID + 0x40), followed by
+//!   a VALUE param for each output parameter.
+//!
+//! Presentations map onto `COMPU-METHOD` categories as follows:
+//!
+//! |OVD data format|COMPU-METHOD|
+//! |:--|:--|
+//! |Identical, String, HexDump, Binary|IDENTICAL|
+//! |Linear|LINEAR (`offset + multiplier * coded`)|
+//! |Table, Bool|TEXTTABLE|
+//! |ScaleLinear, RatFunc, ScaleRatFunc, TableInterpretation|SCALE-LINEAR, RAT-FUNC, SCALE-RAT-FUNC, TAB-INTP (Category only)|
+//! |CompuCode|COMPUCODE (Category only)|
+//!
+//! `SHORT-NAME`s are sanitized to letters, digits and underscores as ODX requires. The
+//! original name is kept in `LONG-NAME`.
+
+use common::schema::{
+    diag::{
+        dtc::ECUDTC,
+        service::{ParamByteOrder, Parameter, Service},
+        DataFormat, StringEncoding,
+    },
+    variant::ECUVariantDefinition,
+    OvdECU,
+};
+
+const ODX_MODEL_VERSION: &str = "2.2.0";
+
+/// Escapes text for use in XML content or attribute values
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Converts a name into a valid ODX SHORT-NAME / ID
+fn short_name(s: &str) -> String {
+    let mut res: String = s
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !res.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        res.insert(0, '_');
+    }
+    res
+}
+
+/// Minimal indenting XML writer
+struct XmlWriter {
+    out: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    fn new() -> Self {
+        Self {
+            out: String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"),
+            depth: 0,
+        }
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str("  ");
+        }
+    }
+
+    fn write_attrs(&mut self, attrs: &[(&str, &str)]) {
+        for (k, v) in attrs {
+            self.out.push_str(&format!(" {}=\"{}\"", k, escape_xml(v)));
+        }
+    }
+
+    fn open(&mut self, tag: &str, attrs: &[(&str, &str)]) {
+        self.indent();
+        self.out.push('<');
+        self.out.push_str(tag);
+        self.write_attrs(attrs);
+        self.out.push_str(">\n");
+        self.depth += 1;
+    }
+
+    fn close(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.indent();
+        self.out.push_str(&format!("</{}>\n", tag));
+    }
+
+    /// Writes an element with only text content
+    fn leaf(&mut self, tag: &str, attrs: &[(&str, &str)], text: &str) {
+        self.indent();
+        self.out.push('<');
+        self.out.push_str(tag);
+        self.write_attrs(attrs);
+        self.out
+            .push_str(&format!(">{}</{}>\n", escape_xml(text), tag));
+    }
+
+    /// Writes an element with no content
+    fn empty(&mut self, tag: &str, attrs: &[(&str, &str)]) {
+        self.indent();
+        self.out.push('<');
+        self.out.push_str(tag);
+        self.write_attrs(attrs);
+        self.out.push_str("/>\n");
+    }
+
+    /// Writes the SHORT-NAME and (If not empty) LONG-NAME of an element
+    fn names(&mut self, name: &str, long_name: &str) {
+        self.leaf("SHORT-NAME", &[], &short_name(name));
+        if !long_name.is_empty() {
+            self.leaf("LONG-NAME", &[], long_name);
+        }
+    }
+}
+
+/// A service of a variant, with the ODX semantic of the list it came from
+struct OdxService<'a> {
+    service: &'a Service,
+    semantic: &'static str,
+    id: String,
+}
+
+/// Base data type, physical data type and COMPU-METHOD category of a parameter
+fn param_types(fmt: &DataFormat) -> (&'static str, &'static str, &'static str) {
+    match fmt {
+        DataFormat::String(StringEncoding::ASCII) => {
+            ("A_ASCIISTRING", "A_UNICODE2STRING", "IDENTICAL")
+        }
+        DataFormat::String(StringEncoding::Utf8) => {
+            ("A_UTF8STRING", "A_UNICODE2STRING", "IDENTICAL")
+        }
+        DataFormat::String(StringEncoding::Utf16) => {
+            ("A_UNICODE2STRING", "A_UNICODE2STRING", "IDENTICAL")
+        }
+        DataFormat::HexDump | DataFormat::Binary => ("A_BYTEFIELD", "A_BYTEFIELD", "IDENTICAL"),
+        DataFormat::Identical => ("A_UINT32", "A_UINT32", "IDENTICAL"),
+        DataFormat::Linear { .. } => ("A_UINT32", "A_FLOAT64", "LINEAR"),
+        DataFormat::Table(_) | DataFormat::Bool { .. } => {
+            ("A_UINT32", "A_UNICODE2STRING", "TEXTTABLE")
+        }
+        DataFormat::ScaleLinear => ("A_UINT32", "A_FLOAT64", "SCALE-LINEAR"),
+        DataFormat::RatFunc => ("A_UINT32", "A_FLOAT64", "RAT-FUNC"),
+        DataFormat::ScaleRatFunc => ("A_UINT32", "A_FLOAT64", "SCALE-RAT-FUNC"),
+        DataFormat::TableInterpretation => ("A_UINT32", "A_FLOAT64", "TAB-INTP"),
+        DataFormat::CompuCode(_) => ("A_UINT32", "A_FLOAT64", "COMPUCODE"),
+    }
+}
+
+fn write_text_scale(w: &mut XmlWriter, lower: f32, upper: f32, text: &str) {
+    w.open("COMPU-SCALE", &[]);
+    w.leaf("LOWER-LIMIT", &[], &lower.to_string());
+    w.leaf("UPPER-LIMIT", &[], &upper.to_string());
+    w.open("COMPU-CONST", &[]);
+    w.leaf("VT", &[], text);
+    w.close("COMPU-CONST");
+    w.close("COMPU-SCALE");
+}
+
+fn write_compu_method(w: &mut XmlWriter, fmt: &DataFormat, category: &str) {
+    w.open("COMPU-METHOD", &[]);
+    w.leaf("CATEGORY", &[], category);
+    match fmt {
+        DataFormat::Linear { multiplier, offset } => {
+            w.open("COMPU-INTERNAL-TO-PHYS", &[]);
+            w.open("COMPU-SCALES", &[]);
+            w.open("COMPU-SCALE", &[]);
+            w.open("COMPU-RATIONAL-COEFFS", &[]);
+            w.open("COMPU-NUMERATOR", &[]);
+            w.leaf("V", &[], &offset.to_string());
+            w.leaf("V", &[], &multiplier.to_string());
+            w.close("COMPU-NUMERATOR");
+            w.open("COMPU-DENOMINATOR", &[]);
+            w.leaf("V", &[], "1");
+            w.close("COMPU-DENOMINATOR");
+            w.close("COMPU-RATIONAL-COEFFS");
+            w.close("COMPU-SCALE");
+            w.close("COMPU-SCALES");
+            w.close("COMPU-INTERNAL-TO-PHYS");
+        }
+        DataFormat::Table(entries) => {
+            w.open("COMPU-INTERNAL-TO-PHYS", &[]);
+            w.open("COMPU-SCALES", &[]);
+            for e in entries {
+                write_text_scale(w, e.start, e.end, &e.name);
+            }
+            w.close("COMPU-SCALES");
+            w.close("COMPU-INTERNAL-TO-PHYS");
+        }
+        DataFormat::Bool { pos_name, neg_name } => {
+            w.open("COMPU-INTERNAL-TO-PHYS", &[]);
+            w.open("COMPU-SCALES", &[]);
+            write_text_scale(w, 0.0, 0.0, neg_name.as_deref().unwrap_or("False"));
+            write_text_scale(w, 1.0, 1.0, pos_name.as_deref().unwrap_or("True"));
+            w.close("COMPU-SCALES");
+            w.close("COMPU-INTERNAL-TO-PHYS");
+        }
+        _ => {}
+    }
+    w.close("COMPU-METHOD");
+}
+
+fn write_dop(w: &mut XmlWriter, id: &str, p: &Parameter) {
+    let (base_type, phys_type, category) = param_types(&p.data_format);
+    w.open("DATA-OBJECT-PROP", &[("ID", id)]);
+    w.names(id, &p.name);
+    write_compu_method(w, &p.data_format, category);
+    let byte_order = match p.byte_order {
+        ParamByteOrder::BigEndian => "true",
+        ParamByteOrder::LittleEndian => "false",
+    };
+    w.open(
+        "DIAG-CODED-TYPE",
+        &[
+            ("BASE-DATA-TYPE", base_type),
+            ("IS-HIGHLOW-BYTE-ORDER", byte_order),
+            ("xsi:type", "STANDARD-LENGTH-TYPE"),
+        ],
+    );
+    w.leaf("BIT-LENGTH", &[], &p.length_bits.to_string());
+    w.close("DIAG-CODED-TYPE");
+    w.empty("PHYSICAL-TYPE", &[("BASE-DATA-TYPE", phys_type)]);
+    if !p.unit.is_empty() {
+        w.leaf("UNIT-TEXT", &[], &p.unit);
+    }
+    w.close("DATA-OBJECT-PROP");
+}
+
+fn write_dtc_dop(w: &mut XmlWriter, variant_id: &str, dtcs: &[ECUDTC]) {
+    let dop_id = format!("{}.DTC_DOP", variant_id);
+    w.open("DTC-DOPS", &[]);
+    w.open("DTC-DOP", &[("ID", &dop_id)]);
+    w.names("DTCs", "");
+    w.open("DTCS", &[]);
+    for (idx, dtc) in dtcs.iter().enumerate() {
+        w.open("DTC", &[("ID", &format!("{}.DTC_{}", variant_id, idx))]);
+        w.names(&dtc.error_name, "");
+        w.leaf("DISPLAY-TROUBLE-CODE", &[], &dtc.error_name);
+        let text = match (dtc.summary.is_empty(), dtc.description.is_empty()) {
+            (false, false) => format!("{} - {}", dtc.summary, dtc.description),
+            (true, _) => dtc.description.clone(),
+            (false, true) => dtc.summary.clone(),
+        };
+        w.leaf("TEXT", &[], &text);
+        w.close("DTC");
+    }
+    w.close("DTCS");
+    w.close("DTC-DOP");
+    w.close("DTC-DOPS");
+}
+
+/// Writes a CODED-CONST param
+fn write_coded_const(
+    w: &mut XmlWriter,
+    name: &str,
+    byte_pos: usize,
+    base_type: &str,
+    bits: usize,
+    value: &str,
+) {
+    w.open("PARAM", &[("SEMANTIC", name), ("xsi:type", "CODED-CONST")]);
+    w.names(name, "");
+    w.leaf("BYTE-POSITION", &[], &byte_pos.to_string());
+    w.leaf("CODED-VALUE", &[], value);
+    w.open(
+        "DIAG-CODED-TYPE",
+        &[
+            ("BASE-DATA-TYPE", base_type),
+            ("xsi:type", "STANDARD-LENGTH-TYPE"),
+        ],
+    );
+    w.leaf("BIT-LENGTH", &[], &bits.to_string());
+    w.close("DIAG-CODED-TYPE");
+    w.close("PARAM");
+}
+
+/// Writes a VALUE param for each parameter, referencing the DOPs `dop_prefix`.0, .1...
+fn write_value_params(w: &mut XmlWriter, dop_prefix: &str, params: &[Parameter]) {
+    for (idx, p) in params.iter().enumerate() {
+        w.open("PARAM", &[("SEMANTIC", "DATA"), ("xsi:type", "VALUE")]);
+        w.names(&p.name, &p.name);
+        w.leaf("BYTE-POSITION", &[], &(p.start_bit / 8).to_string());
+        if p.start_bit % 8 != 0 {
+            w.leaf("BIT-POSITION", &[], &(p.start_bit % 8).to_string());
+        }
+        w.empty("DOP-REF", &[("ID-REF", &format!("{}_{}", dop_prefix, idx))]);
+        w.close("PARAM");
+    }
+}
+
+fn write_variant(w: &mut XmlWriter, ecu: &OvdECU, v: &ECUVariantDefinition) {
+    let variant_id = short_name(&format!("{}_{}", ecu.name, v.name));
+    let mut services: Vec<OdxService> = Vec::new();
+    for (list, semantic) in [
+        (&v.downloads, "DATA"),
+        (&v.functions, "FUNCTION"),
+        (&v.adjustments, "ADJUSTMENT"),
+        (&v.actuations, "ACTUATION"),
+    ] {
+        for s in list {
+            services.push(OdxService {
+                service: s,
+                semantic,
+                id: format!("{}.{}", variant_id, services.len()),
+            })
+        }
+    }
+
+    w.open("ECU-VARIANT", &[("ID", &variant_id)]);
+    w.names(&v.name, &v.description);
+
+    w.open("DIAG-DATA-DICTIONARY-SPEC", &[]);
+    write_dtc_dop(w, &variant_id, &v.errors);
+    w.open("DATA-OBJECT-PROPS", &[]);
+    for s in &services {
+        for (idx, p) in s.service.input_params.iter().enumerate() {
+            write_dop(w, &format!("{}.DOP_IN_{}", s.id, idx), p);
+        }
+        for (idx, p) in s.service.output_params.iter().enumerate() {
+            write_dop(w, &format!("{}.DOP_OUT_{}", s.id, idx), p);
+        }
+    }
+    w.close("DATA-OBJECT-PROPS");
+    w.close("DIAG-DATA-DICTIONARY-SPEC");
+
+    w.open("DIAG-COMMS", &[]);
+    for s in &services {
+        w.open(
+            "DIAG-SERVICE",
+            &[("ID", &format!("{}.DS", s.id)), ("SEMANTIC", s.semantic)],
+        );
+        w.names(&s.service.name, &s.service.description);
+        w.empty("REQUEST-REF", &[("ID-REF", &format!("{}.RQ", s.id))]);
+        w.open("POS-RESPONSE-REFS", &[]);
+        w.empty("POS-RESPONSE-REF", &[("ID-REF", &format!("{}.PR", s.id))]);
+        w.close("POS-RESPONSE-REFS");
+        w.close("DIAG-SERVICE");
+    }
+    w.close("DIAG-COMMS");
+
+    w.open("REQUESTS", &[]);
+    for s in &services {
+        let payload = &s.service.payload;
+        w.open("REQUEST", &[("ID", &format!("{}.RQ", s.id))]);
+        w.names(&format!("RQ_{}", s.service.name), "");
+        w.open("PARAMS", &[]);
+        if let Some(sid) = payload.first() {
+            write_coded_const(w, "SID-RQ", 0, "A_UINT32", 8, &sid.to_string());
+        }
+        if payload.len() > 1 {
+            let id: String = payload[1..].iter().map(|b| format!("{:02X}", b)).collect();
+            write_coded_const(w, "ID", 1, "A_BYTEFIELD", (payload.len() - 1) * 8, &id);
+        }
+        write_value_params(w, &format!("{}.DOP_IN", s.id), &s.service.input_params);
+        w.close("PARAMS");
+        w.close("REQUEST");
+    }
+    w.close("REQUESTS");
+
+    w.open("POS-RESPONSES", &[]);
+    for s in &services {
+        w.open("POS-RESPONSE", &[("ID", &format!("{}.PR", s.id))]);
+        w.names(&format!("PR_{}", s.service.name), "");
+        w.open("PARAMS", &[]);
+        if let Some(sid) = s.service.payload.first() {
+            let sid_pr = sid.wrapping_add(0x40);
+            write_coded_const(w, "SID-PR", 0, "A_UINT32", 8, &sid_pr.to_string());
+        }
+        write_value_params(w, &format!("{}.DOP_OUT", s.id), &s.service.output_params);
+        w.close("PARAMS");
+        w.close("POS-RESPONSE");
+    }
+    w.close("POS-RESPONSES");
+
+    w.close("ECU-VARIANT");
+}
+
+/// Converts an ECU into a simplified ODX document. See the module documentation
+/// for what is exported
+pub fn to_odx(ecu: &OvdECU) -> String {
+    let mut w = XmlWriter::new();
+    w.out.push_str(
+        "<!-- Simplified ODX export of a Daimler CBF file, generated by cbf_parser -->\n",
+    );
+    w.open(
+        "ODX",
+        &[
+            ("MODEL-VERSION", ODX_MODEL_VERSION),
+            ("xmlns:xsi", "http://www.w3.org/2001/XMLSchema-instance"),
+        ],
+    );
+    w.open("DIAG-LAYER-CONTAINER", &[("ID", &short_name(&ecu.name))]);
+    w.names(&ecu.name, &ecu.description);
+    w.open("ECU-VARIANTS", &[]);
+    for v in &ecu.variants {
+        write_variant(&mut w, ecu, v);
+    }
+    w.close("ECU-VARIANTS");
+    w.close("DIAG-LAYER-CONTAINER");
+    w.close("ODX");
+    w.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::schema::diag::TableData;
+
+    fn param(name: &str, start_bit: usize, data_format: DataFormat) -> Parameter {
+        Parameter {
+            name: name.into(),
+            unit: "°C".into(),
+            start_bit,
+            length_bits: 8,
+            byte_order: ParamByteOrder::BigEndian,
+            data_format,
+            valid_bounds: None,
+        }
+    }
+
+    fn ecu() -> OvdECU {
+        OvdECU {
+            name: "CRD3".into(),
+            description: "Engine <CDI>".into(),
+            connections: Vec::new(),
+            variants: vec![ECUVariantDefinition {
+                name: "CRD3_0123".into(),
+                description: "".into(),
+                patterns: Vec::new(),
+                errors: vec![ECUDTC {
+                    error_name: "P2001".into(),
+                    summary: "Intake".into(),
+                    description: "Flap stuck".into(),
+                    envs: Vec::new(),
+                }],
+                adjustments: Vec::new(),
+                actuations: Vec::new(),
+                functions: Vec::new(),
+                downloads: vec![Service {
+                    name: "DT_21_05".into(),
+                    description: "Coolant temp".into(),
+                    payload: vec![0x21, 0x05],
+                    input_params: Vec::new(),
+                    output_params: vec![
+                        param(
+                            "Coolant temp",
+                            16,
+                            DataFormat::Linear {
+                                multiplier: 0.5,
+                                offset: -40.0,
+                            },
+                        ),
+                        param(
+                            "Fan",
+                            25,
+                            DataFormat::Table(vec![TableData {
+                                name: "Off".into(),
+                                start: 0.0,
+                                end: 0.0,
+                            }]),
+                        ),
+                    ],
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_short_name() {
+        assert_eq!(short_name("DT_21_05"), "DT_21_05");
+        assert_eq!(short_name("Coolant temp (°C)"), "Coolant_temp___C_");
+        assert_eq!(short_name("7G-Tronic"), "_7G_Tronic");
+    }
+
+    #[test]
+    fn test_to_odx() {
+        let odx = to_odx(&ecu());
+        assert!(odx.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(odx.contains("<LONG-NAME>Engine &lt;CDI&gt;</LONG-NAME>"));
+        assert!(odx.contains("<DISPLAY-TROUBLE-CODE>P2001</DISPLAY-TROUBLE-CODE>"));
+        assert!(odx.contains("<TEXT>Intake - Flap stuck</TEXT>"));
+        assert!(odx.contains("<DIAG-SERVICE ID=\"CRD3_CRD3_0123.0.DS\" SEMANTIC=\"DATA\">"));
+        assert!(odx.contains("<CODED-VALUE>33</CODED-VALUE>")); // SID-RQ
+        assert!(odx.contains("<CODED-VALUE>05</CODED-VALUE>")); // ID
+        assert!(odx.contains("<CODED-VALUE>97</CODED-VALUE>")); // SID-PR
+        assert!(odx.contains("<V>-40</V>\n"));
+        assert!(odx.contains("<V>0.5</V>\n"));
+        assert!(odx.contains("<VT>Off</VT>"));
+        assert!(odx.contains("<UNIT-TEXT>°C</UNIT-TEXT>"));
+        assert!(odx.contains("<BIT-POSITION>1</BIT-POSITION>"));
+        assert!(odx.contains("<DOP-REF ID-REF=\"CRD3_CRD3_0123.0.DOP_OUT_1\"/>"));
+        assert!(odx.contains("<DATA-OBJECT-PROP ID=\"CRD3_CRD3_0123.0.DOP_OUT_1\">"));
+        // Every element which is opened is closed
+        for tag in [
+            "ODX",
+            "ECU-VARIANT",
+            "PARAMS",
+            "DATA-OBJECT-PROP",
+            "COMPU-SCALE",
+        ] {
+            assert_eq!(
+                odx.matches(&format!("<{}>", tag)).count()
+                    + odx.matches(&format!("<{} ", tag)).count(),
+                odx.matches(&format!("</{}>", tag)).count(),
+                "{}",
+                tag
+            );
+        }
+    }
+}
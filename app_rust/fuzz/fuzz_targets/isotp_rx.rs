@@ -0,0 +1,73 @@
+//! Feeds random sequences of CAN Frames into the ISO-TP receivers, checking they never
+//! panic, and never return a payload larger than ISO-TP allows.
+//!
+//! Input format (See corpus/isotp_rx for examples):
+//! * 1 byte - Block size advertised by the receiver
+//! * Then for each frame:
+//!   * 1 byte - Time since the previous frame, in 10ms units
+//!   * The frame, as read by [CanFrame]'s Arbitrary impl (ID, DLC, data)
+#![no_main]
+
+use std::time::Duration;
+
+use arbitrary::{Arbitrary, Unstructured};
+use libfuzzer_sys::fuzz_target;
+
+// The app has no library crate, so its source files are included directly
+#[allow(dead_code)]
+#[path = "../../src/commapi"]
+mod commapi {
+    pub mod comm_api;
+    pub mod iso_tp;
+}
+
+use commapi::comm_api::CanFrame;
+use commapi::iso_tp::{
+    parse_flow_control, IsoTpConfig, IsoTpMultiReceiver, IsoTpReceiver, RxEvent, TxDlcMode,
+    DEFAULT_MAX_RX_BUFFERS, MAX_PAYLOAD_SIZE,
+};
+
+fn check_event(res: Result<RxEvent, commapi::iso_tp::IsoTpError>) {
+    if let Ok(RxEvent::Complete(payload)) = res {
+        assert!(!payload.is_empty() && payload.len() <= MAX_PAYLOAD_SIZE);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let block_size = match u.bytes(1) {
+        Ok(b) => b[0],
+        Err(_) => return,
+    };
+    let cfg = IsoTpConfig {
+        send_id: 0x7E0,
+        recv_id: 0x7E8,
+        block_size,
+        st_min: 0,
+        tx_dlc: TxDlcMode::Always8,
+        addr_ext: None,
+        rx_tolerance: None,
+        max_rx_buffers: DEFAULT_MAX_RX_BUFFERS,
+        can_fd: false,
+    };
+    let mut single = IsoTpReceiver::new(cfg);
+    let mut multi = IsoTpMultiReceiver::new(cfg);
+    let mut now = Duration::from_millis(0);
+    while !u.is_empty() {
+        let gap = match u.bytes(1) {
+            Ok(b) => b[0],
+            Err(_) => return,
+        };
+        let frame = match CanFrame::arbitrary(&mut u) {
+            Ok(f) => f,
+            Err(_) => return,
+        };
+        now += Duration::from_millis(gap as u64 * 10);
+
+        let _ = single.check_timeout(now);
+        check_event(single.on_frame_at(&frame, now));
+        let _ = multi.check_timeouts(now);
+        check_event(multi.on_frame_at(&frame, now));
+        let _ = parse_flow_control(&frame);
+    }
+});
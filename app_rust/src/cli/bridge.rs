@@ -0,0 +1,153 @@
+//! BRIDGE mode - Forwards every CAN Frame between two devices, optionally rewriting CAN IDs
+//!
+//! `--mode BRIDGE --api slcan --device /dev/ttyUSB0 --api-b socketcan --device-b can0
+//! [--baud 500000] [--ext] [--duration 10] [--remap 0x7E0:0x6F1] [--remap-ba 0x6F9:0x7E8]`
+//!
+//! `--adapter` and `--adapter-b` can be used instead of the `--api` and `--device` pairs.
+//!
+//! `--remap` rewrites IDs of frames going from device A to device B, and `--remap-ba`
+//! those going from B to A. Each takes a comma separated list of `from:to` pairs.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::commapi::comm_api::{CanFrame, ComServer, FilterType};
+
+use super::{parse_u32, CliArgs, CliResult};
+
+/// Table of CAN IDs to rewrite when bridging frames in one direction
+#[derive(Debug, Clone, Default)]
+pub struct IdRemap(HashMap<u32, u32>);
+
+impl IdRemap {
+    /// Parses a list of remaps such as `0x7E0:0x6F1,0x7E8:0x6F9`
+    pub fn parse(s: &str) -> CliResult<Self> {
+        s.split(',')
+            .filter(|p| !p.trim().is_empty())
+            .map(|pair| {
+                let (from, to) = pair
+                    .split_once(':')
+                    .ok_or_else(|| format!("Invalid remap '{}', expected from:to", pair))?;
+                match (parse_u32(from.trim()), parse_u32(to.trim())) {
+                    (Some(from), Some(to)) => Ok((from, to)),
+                    _ => Err(format!("Invalid CAN ID in remap '{}'", pair)),
+                }
+            })
+            .collect::<CliResult<HashMap<u32, u32>>>()
+            .map(Self)
+    }
+
+    /// Returns the frame with its ID rewritten, or None if its ID is not remapped
+    pub fn apply(&self, frame: &CanFrame) -> Option<CanFrame> {
+        self.0
+            .get(&frame.id)
+            .map(|id| CanFrame::new(*id, frame.get_data()))
+    }
+}
+
+/// Forwards frames read from `from` to `to`. Returns the number of frames forwarded
+fn forward(
+    from: &mut Box<dyn ComServer>,
+    to: &mut Box<dyn ComServer>,
+    remap: &IdRemap,
+    direction: &str,
+) -> CliResult<usize> {
+    let frames: Vec<CanFrame> = from
+        .read_can_packets(0, 100)
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(|f| match remap.apply(f) {
+            Some(r) => {
+                log::debug!("{} remapped 0x{:04X} -> 0x{:04X}", direction, f.id, r.id);
+                r
+            }
+            None => *f,
+        })
+        .collect();
+    if !frames.is_empty() {
+        to.send_can_packets(&frames, 0).map_err(|e| e.to_string())?;
+    }
+    Ok(frames.len())
+}
+
+fn open_bus(server: &mut Box<dyn ComServer>, baud: u32, ext: bool) -> CliResult<()> {
+    server
+        .open_can_interface(baud, ext)
+        .map_err(|e| e.to_string())?;
+    server
+        .add_can_filter(FilterType::Pass { id: 0, mask: 0 })
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub fn run(args: &CliArgs) -> CliResult<()> {
+    let baud = args.get_baud()?;
+    let duration = args.get_u32_or("duration", 0)?;
+    let ext = args.get_flag("ext");
+    let remap_ab = IdRemap::parse(args.get_str("remap").unwrap_or_default())?;
+    let remap_ba = IdRemap::parse(args.get_str("remap-ba").unwrap_or_default())?;
+
+    let mut a = super::open_device(args)?;
+    let mut b = super::open_device_with(args, "adapter-b", "api-b", "device-b")?;
+    open_bus(&mut a, baud, ext)?;
+    open_bus(&mut b, baud, ext)?;
+    println!(
+        "Bridging {} <-> {} at {} bps ({} remapped IDs)",
+        a.get_api(),
+        b.get_api(),
+        baud,
+        remap_ab.0.len() + remap_ba.0.len()
+    );
+
+    let start = Instant::now();
+    let (mut count_ab, mut count_ba) = (0, 0);
+    let mut res = Ok(());
+    while duration == 0 || start.elapsed() < Duration::from_secs(duration as u64) {
+        let step = forward(&mut a, &mut b, &remap_ab, "A->B")
+            .and_then(|ab| forward(&mut b, &mut a, &remap_ba, "B->A").map(|ba| (ab, ba)));
+        match step {
+            Ok((ab, ba)) => {
+                count_ab += ab;
+                count_ba += ba;
+                if ab == 0 && ba == 0 {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+            Err(e) => {
+                res = Err(e);
+                break;
+            }
+        }
+    }
+    println!(
+        "Forwarded {} frames A->B, {} frames B->A",
+        count_ab, count_ba
+    );
+
+    for mut s in [a, b] {
+        let _ = s.close_can_interface();
+        let _ = s.close_device();
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id_remap() {
+        let remap = IdRemap::parse("0x7E0:0x6F1, 0x7E8:0x6F9").unwrap();
+        let frame = CanFrame::new(0x7E0, &[0x02, 0x10, 0x03]);
+        let mapped = remap.apply(&frame).unwrap();
+        assert_eq!(mapped.id, 0x6F1);
+        assert_eq!(mapped.get_data(), frame.get_data());
+        assert!(remap.apply(&CanFrame::new(0x123, &[0x00])).is_none());
+
+        assert!(IdRemap::parse("").unwrap().0.is_empty());
+        assert!(IdRemap::parse("0x7E0").is_err());
+        assert!(IdRemap::parse("0x7E0:foo").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! CBFINFO mode - Prints how to connect to each ECU interface of a CBF file
+//!
+//! `--mode CBFINFO --file ECU.cbf [--all] [--scheme <SCHEME> --ecu <N>]`
+//!
+//! For each interface of each ECU in the file, this shows the CAN IDs, baud rate and
+//! timing parameters which can then be used with `--send-id`, `--recv-id`, `--baud`
+//! and `--stmin` in the other modes. `--all` also lists every other com parameter.
+//!
+//! With `--scheme`, the IDs the addressing scheme gives (See [super::get_diag_ids])
+//! are checked against the IDs of each interface.
+
+use std::fs::File;
+
+use cbf_parser::ecu::interface_subtype::InterfaceSubType;
+
+use crate::commapi::protocols::{addressing::AddressingScheme, DiagCfg};
+
+use super::{CliArgs, CliResult};
+
+/// Com parameters shown in the timing section, with the unit they are in
+const TIMING_PARAMS: &[(&str, &str)] = &[
+    ("CP_P2_TIMEOUT", "ms"),
+    ("CP_P2_EXT_TIMEOUT_7F_78", "ms"),
+    ("CP_P2_EXT_TIMEOUT_7F_21", "ms"),
+    ("CP_S3_TP_PHYS_TIMER", "ms"),
+    ("CP_S3_TP_FUNC_TIMER", "ms"),
+    ("CP_STMIN_SUG", "ms"),
+    ("CP_BLOCKSIZE_SUG", "frames"),
+];
+
+fn format_id(id: Option<u32>) -> String {
+    match id {
+        Some(id) => format!("0x{:03X}", id),
+        None => "-".into(),
+    }
+}
+
+/// Returns the `--scheme` and `--ecu` arguments which give the same IDs as the interface
+fn format_scheme(sub: &InterfaceSubType) -> String {
+    let ids = sub
+        .get_cp_by_name("CP_REQUEST_CANIDENTIFIER")
+        .zip(sub.get_cp_by_name("CP_RESPONSE_CANIDENTIFIER"));
+    match ids.and_then(|(send, recv)| AddressingScheme::identify(send, recv)) {
+        Some((AddressingScheme::StandardObd, ecu)) => format!("--scheme obd --ecu {}", ecu),
+        Some((_, ecu)) => format!("--scheme obd29 --ecu 0x{:02X}", ecu),
+        None => "None".into(),
+    }
+}
+
+/// Returns the connection table of a single ECU interface
+pub fn format_interface(sub: &InterfaceSubType, all: bool) -> String {
+    let mut res = format!("Interface {}\n", sub.qualifier);
+    let rows = [
+        (
+            "Request ID (--send-id)",
+            format_id(sub.get_cp_by_name("CP_REQUEST_CANIDENTIFIER")),
+        ),
+        (
+            "Response ID (--recv-id)",
+            format_id(sub.get_cp_by_name("CP_RESPONSE_CANIDENTIFIER")),
+        ),
+        ("Addressing scheme", format_scheme(sub)),
+        (
+            "Global request ID",
+            format_id(sub.get_cp_by_name("CP_GLOBAL_REQUEST_CANIDENTIFIER")),
+        ),
+        (
+            "Functional request ID",
+            format_id(sub.get_cp_by_name("CP_FUNCTIONAL_REQUEST_CANIDENTIFIER")),
+        ),
+        (
+            "Baud rate (--baud)",
+            sub.get_cp_by_name("CP_BAUDRATE")
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "-".into()),
+        ),
+        (
+            "ISO-TP address extension",
+            sub.get_isotp_addr_ext()
+                .map(|x| format!("0x{:02X}", x))
+                .unwrap_or_else(|| "None".into()),
+        ),
+    ];
+    for (name, value) in rows.iter() {
+        res.push_str(&format!("  {:<28} {}\n", name, value));
+    }
+    for (name, unit) in TIMING_PARAMS {
+        if let Some(v) = sub.get_cp_by_name(name) {
+            res.push_str(&format!("  {:<28} {} {}\n", name, v, unit));
+        }
+    }
+    if all {
+        res.push_str(&sub.format_com_params());
+    }
+    res
+}
+
+pub fn run(args: &CliArgs) -> CliResult<()> {
+    let path = args
+        .get_str("file")
+        .ok_or("Missing required argument --file")?;
+    let all = args.get_flag("all");
+    let expected = match args.get_str("scheme") {
+        Some(_) => {
+            let (send_id, recv_id, _) = super::get_diag_ids(args)?;
+            Some(DiagCfg {
+                send_id,
+                recv_id,
+                global_id: None,
+                recv_id_mask: None,
+            })
+        }
+        None => None,
+    };
+    let container = File::open(path)
+        .map_err(|e| format!("Cannot open {}: {}", path, e))
+        .and_then(|mut f| {
+            cbf_parser::read_cbf_complete(&mut f)
+                .map_err(|e| format!("Cannot parse {}: {}", path, e))
+        })?;
+    for ecu in &container.ecus {
+        println!(
+            "ECU {} ({})",
+            ecu.qualifier,
+            ecu.name.as_deref().unwrap_or("No name")
+        );
+        if ecu.interface_sub_types.is_empty() {
+            println!("  No interfaces");
+        }
+        for sub in &ecu.interface_sub_types {
+            print!("{}", format_interface(sub, all));
+            if let Some(cfg) = &expected {
+                let mismatches = AddressingScheme::check_ids(
+                    cfg,
+                    sub.get_cp_by_name("CP_REQUEST_CANIDENTIFIER"),
+                    sub.get_cp_by_name("CP_RESPONSE_CANIDENTIFIER"),
+                );
+                for m in mismatches {
+                    println!("  WARNING: {}", m);
+                }
+            }
+        }
+        println!();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cbf_parser::ecu::com_param::ComParameter;
+
+    fn cp(name: &str, value: i32) -> ComParameter {
+        let mut cp = ComParameter::default();
+        cp.param_name = name.into();
+        cp.param_value = value;
+        cp
+    }
+
+    #[test]
+    fn test_format_interface() {
+        let mut sub = InterfaceSubType::default();
+        sub.qualifier = "CAN_ISO_TP".into();
+        sub.comm_params = vec![
+            cp("CP_REQUEST_CANIDENTIFIER", 0x7E0),
+            cp("CP_RESPONSE_CANIDENTIFIER", 0x7E8),
+            cp("CP_BAUDRATE", 500_000),
+            cp("CP_STMIN_SUG", 10),
+        ];
+        let txt = format_interface(&sub, false);
+        assert!(txt.contains("Request ID (--send-id)       0x7E0"));
+        assert!(txt.contains("Response ID (--recv-id)      0x7E8"));
+        assert!(txt.contains("Global request ID            -"));
+        assert!(txt.contains("Addressing scheme            --scheme obd --ecu 0"));
+        assert!(txt.contains("500000"));
+        assert!(txt.contains("CP_STMIN_SUG                 10 ms"));
+        assert!(!txt.contains("CP_P2_TIMEOUT"));
+    }
+}
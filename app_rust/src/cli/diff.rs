@@ -0,0 +1,205 @@
+//! DIFF mode - Compares two recorded traces, and reports which payload bytes changed per CAN ID
+//!
+//! `--mode DIFF --a before.log --b after.log`
+//!
+//! Bytes which change constantly in both traces (Such as rolling counters, checksums
+//! or timestamps) are ignored, unless the values seen in each trace are completely different.
+//! See [trace_file](super::trace_file) for the supported trace formats.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{
+    trace_file::{self, TraceRecord},
+    CliArgs, CliResult,
+};
+
+/// Every value seen for each byte position of a CAN ID
+#[derive(Debug, Clone, Default)]
+struct IdSummary {
+    frames: u64,
+    dlcs: BTreeSet<usize>,
+    bytes: Vec<BTreeSet<u8>>,
+}
+
+impl IdSummary {
+    fn add(&mut self, data: &[u8]) {
+        self.frames += 1;
+        self.dlcs.insert(data.len());
+        if self.bytes.len() < data.len() {
+            self.bytes.resize(data.len(), BTreeSet::new());
+        }
+        for (set, b) in self.bytes.iter_mut().zip(data) {
+            set.insert(*b);
+        }
+    }
+
+    fn byte(&self, pos: usize) -> BTreeSet<u8> {
+        self.bytes.get(pos).cloned().unwrap_or_default()
+    }
+}
+
+fn summarise(records: &[TraceRecord]) -> BTreeMap<u32, IdSummary> {
+    let mut res: BTreeMap<u32, IdSummary> = BTreeMap::new();
+    for r in records {
+        res.entry(r.frame.id).or_default().add(r.frame.get_data());
+    }
+    res
+}
+
+/// Values seen at a byte position in each trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteChange {
+    pub pos: usize,
+    pub a: BTreeSet<u8>,
+    pub b: BTreeSet<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdDiff {
+    /// ID only seen in trace A, with the number of frames
+    OnlyInA(u64),
+    /// ID only seen in trace B, with the number of frames
+    OnlyInB(u64),
+    Changed {
+        /// DLCs seen in each trace, if they differ
+        dlc: Option<(BTreeSet<usize>, BTreeSet<usize>)>,
+        bytes: Vec<ByteChange>,
+    },
+}
+
+/// Returns true if the byte changed between the traces. A byte which is
+/// volatile in both traces is only a change if no value is common to both
+fn is_changed(a: &BTreeSet<u8>, b: &BTreeSet<u8>) -> bool {
+    if a.len() > 1 && b.len() > 1 {
+        a.is_disjoint(b)
+    } else {
+        a != b
+    }
+}
+
+/// Compares two traces. Only IDs which differ are returned
+pub fn diff_traces(a: &[TraceRecord], b: &[TraceRecord]) -> BTreeMap<u32, IdDiff> {
+    let a = summarise(a);
+    let b = summarise(b);
+    let ids: BTreeSet<u32> = a.keys().chain(b.keys()).copied().collect();
+    ids.into_iter()
+        .filter_map(|id| {
+            let diff = match (a.get(&id), b.get(&id)) {
+                (Some(a), None) => IdDiff::OnlyInA(a.frames),
+                (None, Some(b)) => IdDiff::OnlyInB(b.frames),
+                (Some(a), Some(b)) => {
+                    let bytes: Vec<ByteChange> = (0..a.bytes.len().max(b.bytes.len()))
+                        .map(|pos| ByteChange {
+                            pos,
+                            a: a.byte(pos),
+                            b: b.byte(pos),
+                        })
+                        .filter(|c| is_changed(&c.a, &c.b))
+                        .collect();
+                    let dlc = Some((a.dlcs.clone(), b.dlcs.clone())).filter(|(a, b)| a != b);
+                    if bytes.is_empty() && dlc.is_none() {
+                        return None;
+                    }
+                    IdDiff::Changed { dlc, bytes }
+                }
+                (None, None) => return None,
+            };
+            Some((id, diff))
+        })
+        .collect()
+}
+
+fn format_values(values: &BTreeSet<u8>) -> String {
+    match values.len() {
+        0 => "--".into(),
+        1..=4 => values
+            .iter()
+            .map(|v| format!("{:02X}", v))
+            .collect::<Vec<_>>()
+            .join("|"),
+        n => format!(
+            "{} values ({:02X}-{:02X})",
+            n,
+            values.iter().next().unwrap(),
+            values.iter().next_back().unwrap()
+        ),
+    }
+}
+
+pub fn run(args: &CliArgs) -> CliResult<()> {
+    let path_a = args.get_str("a").ok_or("Missing required argument --a")?;
+    let path_b = args.get_str("b").ok_or("Missing required argument --b")?;
+    let a = trace_file::load(path_a)?;
+    let b = trace_file::load(path_b)?;
+    println!("A: {} ({} frames)", path_a, a.len());
+    println!("B: {} ({} frames)", path_b, b.len());
+
+    let diffs = diff_traces(&a, &b);
+    for (id, diff) in &diffs {
+        match diff {
+            IdDiff::OnlyInA(n) => println!("ID 0x{:04X}: only in A ({} frames)", id, n),
+            IdDiff::OnlyInB(n) => println!("ID 0x{:04X}: only in B ({} frames)", id, n),
+            IdDiff::Changed { dlc, bytes } => {
+                let positions: Vec<String> = bytes.iter().map(|c| c.pos.to_string()).collect();
+                println!("ID 0x{:04X}: bytes changed [{}]", id, positions.join(", "));
+                if let Some((a, b)) = dlc {
+                    println!("  DLC {:?} -> {:?}", a, b);
+                }
+                for c in bytes {
+                    println!(
+                        "  byte {}: {} -> {}",
+                        c.pos,
+                        format_values(&c.a),
+                        format_values(&c.b)
+                    );
+                }
+            }
+        }
+    }
+    println!("{} IDs differ", diffs.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commapi::comm_api::CanFrame;
+
+    fn rec(id: u32, data: &[u8]) -> TraceRecord {
+        TraceRecord {
+            time: 0.0,
+            frame: CanFrame::new(id, data),
+        }
+    }
+
+    #[test]
+    fn test_diff_traces() {
+        // Byte 0 is a rolling counter, byte 1 is the door lock state
+        let a: Vec<TraceRecord> = (0..4u8)
+            .map(|c| rec(0x100, &[c, 0x00, 0xAA]))
+            .chain(std::iter::once(rec(0x200, &[0x01])))
+            .collect();
+        let b: Vec<TraceRecord> = (2..6u8)
+            .map(|c| rec(0x100, &[c, 0x01, 0xAA]))
+            .chain(std::iter::once(rec(0x300, &[0x01])))
+            .collect();
+
+        let diffs = diff_traces(&a, &b);
+        assert_eq!(diffs.len(), 3);
+        assert_eq!(
+            diffs[&0x100],
+            IdDiff::Changed {
+                dlc: None,
+                bytes: vec![ByteChange {
+                    pos: 1,
+                    a: std::iter::once(0x00).collect(),
+                    b: std::iter::once(0x01).collect(),
+                }],
+            }
+        );
+        assert_eq!(diffs[&0x200], IdDiff::OnlyInA(1));
+        assert_eq!(diffs[&0x300], IdDiff::OnlyInB(1));
+
+        assert!(diff_traces(&a, &a).is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+//! GREP mode - Searches a recorded trace for frames whose payload contains a byte pattern
+//!
+//! `--mode GREP --input trace.log --pattern "22 F1 ?? 00" [--id 0x7E0]`
+//!
+//! `??` in the pattern matches any byte. See [trace_file](super::trace_file)
+//! for the supported trace formats.
+
+use std::collections::BTreeMap;
+
+use super::{trace_file, CliArgs, CliResult};
+
+/// Hex byte pattern, where `None` matches any byte
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BytePattern(Vec<Option<u8>>);
+
+impl BytePattern {
+    /// Parses a pattern such as `22F1??00` or `22 F1 ?? 00`
+    pub fn parse(s: &str) -> CliResult<Self> {
+        let chars: Vec<char> = s.chars().filter(|c| !c.is_whitespace()).collect();
+        if chars.is_empty() {
+            return Err(format!("Invalid pattern '{}'", s));
+        }
+        chars
+            .chunks(2)
+            .map(|c| match c {
+                ['?', '?'] => Ok(None),
+                [hi, lo] => match (hi.to_digit(16), lo.to_digit(16)) {
+                    (Some(hi), Some(lo)) => Ok(Some((hi << 4 | lo) as u8)),
+                    _ => Err(format!("Invalid byte '{}{}' in pattern", hi, lo)),
+                },
+                _ => Err(format!("Invalid pattern '{}'", s)),
+            })
+            .collect::<CliResult<Vec<_>>>()
+            .map(Self)
+    }
+
+    /// Returns the offset of the first match of the pattern within `data`
+    pub fn find(&self, data: &[u8]) -> Option<usize> {
+        if self.0.len() > data.len() {
+            return None;
+        }
+        data.windows(self.0.len()).position(|w| {
+            w.iter()
+                .zip(self.0.iter())
+                .all(|(b, p)| p.is_none() || *p == Some(*b))
+        })
+    }
+}
+
+pub fn run(args: &CliArgs) -> CliResult<()> {
+    let input = args
+        .get_str("input")
+        .ok_or("Missing required argument --input")?;
+    let pattern = BytePattern::parse(
+        args.get_str("pattern")
+            .ok_or("Missing required argument --pattern")?,
+    )?;
+    let id = args.get_u32("id")?;
+
+    let records = trace_file::load(input)?;
+    let mut counts: BTreeMap<u32, u64> = BTreeMap::new();
+    let mut first_last: Option<(f64, f64)> = None;
+    for r in records
+        .iter()
+        .filter(|r| id.is_none() || id == Some(r.frame.id))
+    {
+        if let Some(offset) = pattern.find(r.frame.get_data()) {
+            println!("{:>17.6} {} @ byte {}", r.time, r.frame, offset);
+            *counts.entry(r.frame.id).or_default() += 1;
+            first_last = Some(first_last.map_or((r.time, r.time), |(f, _)| (f, r.time)));
+        }
+    }
+
+    let total: u64 = counts.values().sum();
+    println!("{} matches in {} frames", total, records.len());
+    if let Some((first, last)) = first_last {
+        println!("First match at {:.6}, last at {:.6}", first, last);
+    }
+    for (id, count) in counts {
+        println!("  ID 0x{:04X}: {}", id, count);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_pattern() {
+        let p = BytePattern::parse("22 F1 ?? 00").unwrap();
+        assert_eq!(
+            p,
+            BytePattern(vec![Some(0x22), Some(0xF1), None, Some(0x00)])
+        );
+        assert_eq!(p.find(&[0x04, 0x22, 0xF1, 0x90, 0x00]), Some(1));
+        assert_eq!(p.find(&[0x04, 0x22, 0xF1, 0x90, 0x01]), None);
+        assert_eq!(p.find(&[0x22, 0xF1]), None);
+        assert_eq!(BytePattern::parse("??").unwrap().find(&[0xAA]), Some(0));
+
+        assert!(BytePattern::parse("22F").is_err());
+        assert!(BytePattern::parse("2G").is_err());
+        assert!(BytePattern::parse("").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! INJECT mode - Places the frames of a recorded trace on the bus with their original timing
+//!
+//! `--mode INJECT --input trace.log [--id 0x7E0] [--baud 500000] [--ext]`
+//!
+//! Each frame is sent at its offset from the first frame of the trace, see
+//! [send_timed]. With `--id`, only frames with that ID are sent. See
+//! [trace_file](super::trace_file) for the supported trace formats.
+//!
+//! The timing error of each frame is printed once the sequence is sent.
+
+use std::time::Duration;
+
+use crate::commapi::{
+    comm_api::CanFrame,
+    latency::LatencyHistogram,
+    tx_scheduler::{send_timed, TimedSendReport},
+};
+
+use super::{
+    trace_file::{self, TraceRecord},
+    CliArgs, CliResult,
+};
+
+/// Converts trace records to frames at offsets from the first record, rounded to the
+/// microsecond resolution of the trace. Records timestamped before the first one are
+/// sent straight away
+pub fn to_sequence(records: &[TraceRecord], id: Option<u32>) -> Vec<(Duration, CanFrame)> {
+    let records: Vec<&TraceRecord> = records
+        .iter()
+        .filter(|r| id.is_none() || id == Some(r.frame.id))
+        .collect();
+    let start = match records.first() {
+        Some(r) => r.time,
+        None => return Vec::new(),
+    };
+    records
+        .iter()
+        .map(|r| {
+            let offset_us = ((r.time - start) * 1_000_000.0).round().max(0.0);
+            (Duration::from_micros(offset_us as u64), r.frame)
+        })
+        .collect()
+}
+
+fn print_report(report: &TimedSendReport) {
+    let mut histogram = LatencyHistogram::default();
+    report.errors().for_each(|e| histogram.add(e));
+    println!("---- INJECT RESULTS ----");
+    println!("Frames sent:     {}", report.actual.len());
+    println!("Max error:       {:?}", report.max_error());
+    println!("Mean error:      {:?}", report.mean_error());
+    println!("Timing error:");
+    histogram.print();
+}
+
+pub fn run(args: &CliArgs) -> CliResult<()> {
+    let input = args
+        .get_str("input")
+        .ok_or("Missing required argument --input")?;
+    let id = args.get_u32("id")?;
+    let baud = args.get_baud()?;
+    let frames = to_sequence(&trace_file::load(input)?, id);
+    if frames.is_empty() {
+        return Err(format!("No frames to send in {}", input));
+    }
+    let ext = args.get_flag("ext") || frames.iter().any(|(_, f)| f.id > 0x7FF);
+
+    let mut server = super::open_device(args)?;
+    server
+        .open_can_interface(baud, ext)
+        .map_err(|e| e.to_string())?;
+    println!(
+        "Sending {} frames over {:.3} s using {}",
+        frames.len(),
+        frames
+            .iter()
+            .map(|(d, _)| *d)
+            .max()
+            .unwrap_or_default()
+            .as_secs_f64(),
+        server.get_api()
+    );
+    let res = send_timed(server.as_mut(), &frames).map_err(|e| e.to_string());
+
+    let _ = server.close_can_interface();
+    let _ = server.close_device();
+    print_report(&res?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_sequence() {
+        let records: Vec<TraceRecord> = [
+            "(100.500000) can0 7E0#0322F190",
+            "(100.502500) can0 7E8#101462F190574444",
+            "(100.502000) can0 7E0#3000000000000000",
+            "(100.510000) can0 7E8#2130333034363200",
+        ]
+        .iter()
+        .filter_map(|l| trace_file::parse_line(l))
+        .collect();
+        let offsets = |seq: Vec<(Duration, CanFrame)>| {
+            seq.iter()
+                .map(|(d, f)| (d.as_micros(), f.id))
+                .collect::<Vec<_>>()
+        };
+
+        // Sent in offset order by send_timed, so the trace order does not matter
+        assert_eq!(
+            offsets(to_sequence(&records, None)),
+            vec![(0, 0x7E0), (2500, 0x7E8), (2000, 0x7E0), (10000, 0x7E8)]
+        );
+        // Offsets are from the first frame with the ID
+        assert_eq!(
+            offsets(to_sequence(&records, Some(0x7E8))),
+            vec![(0, 0x7E8), (7500, 0x7E8)]
+        );
+        assert!(to_sequence(&records, Some(0x123)).is_empty());
+    }
+}
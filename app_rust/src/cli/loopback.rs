@@ -0,0 +1,64 @@
+//! LOOPBACK mode - Checks the wiring and adapter on a bench before diagnosing an ECU
+//!
+//! `--mode LOOPBACK --tx-id 0x123 --rx-id 0x124 [--baud 500000] [--count 10] [--timeout 1000]`
+//!
+//! A test frame is sent on `--tx-id`, and a second node (Or a BRIDGE to a second adapter)
+//! has to send it back on `--rx-id`. See
+//! [ComServer::loopback_test](crate::commapi::comm_api::ComServer::loopback_test).
+//! The mode fails unless every test passes.
+
+use crate::commapi::{
+    comm_api::{FilterType, LoopbackResult},
+    latency::LatencyHistogram,
+};
+
+use super::{CliArgs, CliResult};
+
+pub fn run(args: &CliArgs) -> CliResult<()> {
+    let tx_id = args.get_u32_required("tx-id")?;
+    let rx_id = args.get_u32_required("rx-id")?;
+    let count = args.get_u32_or("count", 1)?;
+    let timeout = args.get_u32_or("timeout", 1000)?;
+    let baud = args.get_baud()?;
+    if tx_id == rx_id {
+        println!("Warning: --tx-id is the same as --rx-id, the test passes on any adapter which");
+        println!("reports its own frames, even with nothing connected");
+    }
+
+    let mut server = super::open_device(args)?;
+    server
+        .open_can_interface(baud, tx_id > 0x7FF || rx_id > 0x7FF)
+        .map_err(|e| e.to_string())?;
+    let res = server
+        .add_can_filter(FilterType::Pass {
+            id: rx_id,
+            mask: 0x1FFFFFFF,
+        })
+        .map_err(|e| e.to_string())
+        .and_then(|_| {
+            let mut histogram = LatencyHistogram::default();
+            let mut failed = 0;
+            for i in 0..count {
+                let res = server
+                    .loopback_test(tx_id, rx_id, timeout)
+                    .map_err(|e| e.to_string())?;
+                println!("Test {}: {}", i + 1, res);
+                match res {
+                    LoopbackResult::Pass { rtt } => histogram.add(rtt),
+                    _ => failed += 1,
+                }
+            }
+            if histogram.samples() > 0 {
+                println!("Round trip time:");
+                histogram.print();
+            }
+            match failed {
+                0 => Ok(()),
+                n => Err(format!("{} of {} loopback tests failed", n, count)),
+            }
+        });
+
+    let _ = server.close_can_interface();
+    let _ = server.close_device();
+    res
+}
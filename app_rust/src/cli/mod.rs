@@ -0,0 +1,454 @@
+//! Headless command line modes of OVD, selected with `--mode <MODE>`.
+//!
+//! Modes which talk to a vehicle open a device given by `--api` and `--device`, for example:
+//! `openvehiclediag --mode STRESS --api passthru --device "Macchina A0" --id 0x123`
+//!
+//! Alternatively, `--adapter` gives the device as a single spec, such as
+//! `--adapter slcan:/dev/ttyUSB0@500k`. See [crate::commapi::adapter_spec]
+//!
+//! `--api replay --device <FILE>` plays back a session recorded with SCRIPT mode's `--record`
+//! instead of using a real device. See [crate::commapi::replay]
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::commapi::{
+    adapter_spec::AdapterSpec,
+    comm_api::ComServer,
+    iface::{InterfaceConfig, InterfaceType, PayloadFlag, IFACE_CFG},
+    open_by_spec,
+    protocols::{addressing::AddressingScheme, DiagCfg, DiagProtocol, DiagServer},
+};
+
+pub mod bridge;
+pub mod cbf_info;
+pub mod diff;
+pub mod grep;
+pub mod inject;
+pub mod loopback;
+pub mod report;
+pub mod script;
+pub mod stress;
+pub mod trace;
+pub mod trace_export;
+pub mod trace_file;
+
+pub type CliResult<T> = std::result::Result<T, String>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CliMode {
+    Stress,
+    Trace,
+    Script,
+    Grep,
+    Diff,
+    Bridge,
+    Report,
+    CbfInfo,
+    Loopback,
+    Inject,
+}
+
+impl CliMode {
+    fn from_str(s: &str) -> CliResult<Self> {
+        match s.to_uppercase().as_str() {
+            "STRESS" => Ok(Self::Stress),
+            "TRACE" => Ok(Self::Trace),
+            "SCRIPT" => Ok(Self::Script),
+            "GREP" => Ok(Self::Grep),
+            "DIFF" => Ok(Self::Diff),
+            "BRIDGE" => Ok(Self::Bridge),
+            "REPORT" => Ok(Self::Report),
+            "CBFINFO" => Ok(Self::CbfInfo),
+            "LOOPBACK" => Ok(Self::Loopback),
+            "INJECT" => Ok(Self::Inject),
+            _ => Err(format!("Unknown mode '{}'", s)),
+        }
+    }
+}
+
+/// Parsed `--key value` arguments for the CLI modes
+#[derive(Debug, Clone)]
+pub struct CliArgs {
+    pub mode: CliMode,
+    params: HashMap<String, String>,
+}
+
+impl CliArgs {
+    /// Parses the programs arguments. Returns None if `--mode` was not specified,
+    /// in which case the GUI should be started.
+    ///
+    /// An argument not followed by a value (Such as `--ext`) is a flag, and is set to `true`
+    pub fn parse(args: &[String]) -> Option<CliResult<Self>> {
+        let mut params = HashMap::new();
+        let mut iter = args.iter().skip(1).peekable();
+        while let Some(a) = iter.next() {
+            if let Some(key) = a.strip_prefix("--") {
+                let value = match iter.peek() {
+                    Some(v) if !v.starts_with("--") => iter.next().unwrap().clone(),
+                    _ => "true".to_string(),
+                };
+                params.insert(key.to_lowercase(), value);
+            }
+        }
+        let mode = params.remove("mode")?;
+        Some(CliMode::from_str(&mode).map(|mode| Self { mode, params }))
+    }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.params.get(key).map(|s| s.as_str())
+    }
+
+    /// Returns true if a flag was given, unless it was explicitly set to `false` or `0`
+    pub fn get_flag(&self, key: &str) -> bool {
+        !matches!(self.get_str(key), None | Some("false") | Some("0"))
+    }
+
+    /// Returns a numeric argument. Accepts decimal or hex (0x prefixed) values
+    pub fn get_u32(&self, key: &str) -> CliResult<Option<u32>> {
+        match self.params.get(key) {
+            None => Ok(None),
+            Some(v) => parse_u32(v)
+                .map(Some)
+                .ok_or_else(|| format!("Invalid number '{}' for --{}", v, key)),
+        }
+    }
+
+    pub fn get_u32_or(&self, key: &str, default: u32) -> CliResult<u32> {
+        self.get_u32(key).map(|x| x.unwrap_or(default))
+    }
+
+    /// Returns the CAN bitrate from `--baud`, or from the `--adapter` spec, defaulting
+    /// to 500kbps
+    pub fn get_baud(&self) -> CliResult<u32> {
+        if let Some(baud) = self.get_u32("baud")? {
+            return Ok(baud);
+        }
+        let spec_baud = self
+            .get_str("adapter")
+            .and_then(|s| AdapterSpec::parse(s).ok())
+            .and_then(|s| s.bitrate);
+        Ok(spec_baud.unwrap_or(500_000))
+    }
+
+    pub fn get_u32_required(&self, key: &str) -> CliResult<u32> {
+        self.get_u32(key)?
+            .ok_or_else(|| format!("Missing required argument --{}", key))
+    }
+}
+
+/// Parses a decimal or hex (0x prefixed) number
+pub fn parse_u32(s: &str) -> Option<u32> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse::<u32>().ok()
+    }
+}
+
+/// Set by the Ctrl-C handler installed with [catch_ctrl_c]
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_ctrl_c(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes Ctrl-C set a flag rather than killing the process, so a mode which runs until it is
+/// stopped can check [interrupted] and shut down cleanly (Closing the adapter and finishing
+/// any files it is writing)
+pub fn catch_ctrl_c() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    // Only an atomic store is done in the handler, which is async signal safe
+    unsafe {
+        let handler: extern "C" fn(libc::c_int) = on_ctrl_c;
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+/// Returns true once Ctrl-C has been pressed, see [catch_ctrl_c]
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Opens the device requested with `--adapter`, or `--api` and `--device`
+pub fn open_device(args: &CliArgs) -> CliResult<Box<dyn ComServer>> {
+    open_device_with(args, "adapter", "api", "device")
+}
+
+/// Opens a device given by the arguments `adapter_key`, or `api_key` and `device_key`, for
+/// modes which use more than one device. See [adapter_spec](crate::commapi::adapter_spec)
+/// for the format of `adapter_key`
+pub fn open_device_with(
+    args: &CliArgs,
+    adapter_key: &str,
+    api_key: &str,
+    device_key: &str,
+) -> CliResult<Box<dyn ComServer>> {
+    let spec = match args.get_str(adapter_key) {
+        Some(s) => s.to_string(),
+        None => {
+            let api = args.get_str(api_key).unwrap_or("passthru");
+            let name = args.get_str(device_key);
+            if name.is_none() && !api.eq_ignore_ascii_case("passthru") {
+                return Err(format!("--{} is required for {}", device_key, api));
+            }
+            format!("{}:{}", api, name.unwrap_or(""))
+        }
+    };
+    // The spec's bitrate is picked up by CliArgs::get_baud when the CAN interface is opened
+    open_by_spec(&spec).map_err(|e| e.err_desc)
+}
+
+/// Returns the diagnostic protocol selected with `--protocol uds|kwp` (Default UDS)
+pub fn get_protocol(args: &CliArgs) -> CliResult<DiagProtocol> {
+    match args
+        .get_str("protocol")
+        .map(|p| p.to_lowercase())
+        .as_deref()
+    {
+        None | Some("uds") => Ok(DiagProtocol::UDS),
+        Some("kwp") | Some("kwp2000") => Ok(DiagProtocol::KWP2000),
+        Some(p) => Err(format!("Unknown protocol '{}', expected uds or kwp", p)),
+    }
+}
+
+/// Parses an addressing scheme given with `--scheme`. One of `obd` (11 bit OBD-II),
+/// `obd29` (29 bit OBD-II) or `custom:<request base>,<response base>[,<functional ID>]`
+pub fn parse_scheme(s: &str) -> CliResult<AddressingScheme> {
+    let lower = s.to_lowercase();
+    match lower.as_str() {
+        "obd" | "obd11" => return Ok(AddressingScheme::StandardObd),
+        "obd29" => return Ok(AddressingScheme::ExtendedObd),
+        _ => {}
+    }
+    let ids = lower
+        .strip_prefix("custom:")
+        .map(|ids| ids.split(',').map(parse_u32).collect::<Option<Vec<u32>>>());
+    match ids {
+        Some(Some(ids)) if ids.len() == 2 || ids.len() == 3 => Ok(AddressingScheme::Custom {
+            request_base: ids[0],
+            response_base: ids[1],
+            global_id: ids.get(2).copied(),
+        }),
+        _ => Err(format!(
+            "Invalid addressing scheme '{}', expected obd, obd29 or custom:<request>,<response>[,<functional>]",
+            s
+        )),
+    }
+}
+
+/// Returns the IDs of the ECU to talk to. These are `--send-id` and `--recv-id`, or
+/// are derived from `--scheme` (See [parse_scheme]) and `--ecu` (Default 0). Explicit
+/// IDs take priority over the scheme
+pub fn get_diag_ids(args: &CliArgs) -> CliResult<(u32, u32, Option<AddressingScheme>)> {
+    let scheme = args.get_str("scheme").map(parse_scheme).transpose()?;
+    let derived = match scheme {
+        Some(scheme) => {
+            let ecu = args.get_u32_or("ecu", 0)?;
+            let ecu = u8::try_from(ecu).map_err(|_| format!("Invalid ECU index {}", ecu))?;
+            Some(scheme.diag_cfg(ecu).map_err(|e| e.get_text())?)
+        }
+        None => None,
+    };
+    let id = |key: &str, derived: Option<u32>| -> CliResult<u32> {
+        args.get_u32(key)?
+            .or(derived)
+            .ok_or_else(|| format!("Missing required argument --{} (Or --scheme)", key))
+    };
+    Ok((
+        id("send-id", derived.map(|c| c.send_id))?,
+        id("recv-id", derived.map(|c| c.recv_id))?,
+        scheme,
+    ))
+}
+
+/// Key algorithm given with `--seckey`, for unlocking an ECU with SecurityAccess
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecKey {
+    /// The key is the seed XORed with these bytes, repeated over the length of the seed
+    Xor(Vec<u8>),
+    /// The key is always these bytes, whatever the seed
+    Const(Vec<u8>),
+}
+
+impl SecKey {
+    /// Parses `xor:<hex bytes>` or `const:<hex bytes>`, such as `xor:0x5A5A5A5A`
+    pub fn parse(s: &str) -> CliResult<Self> {
+        let (algo, bytes) = s.split_once(':').unwrap_or((s, ""));
+        let bytes = bytes.trim_start_matches("0x").trim_start_matches("0X");
+        let bytes = match hex::decode(bytes) {
+            Ok(b) if !b.is_empty() => b,
+            _ => return Err(format!("Invalid key bytes in --seckey '{}'", s)),
+        };
+        match algo.to_lowercase().as_str() {
+            "xor" => Ok(Self::Xor(bytes)),
+            "const" => Ok(Self::Const(bytes)),
+            _ => Err(format!(
+                "Unknown key algorithm '{}', expected xor:<bytes> or const:<bytes>",
+                algo
+            )),
+        }
+    }
+
+    /// Computes the key for a seed
+    pub fn key(&self, seed: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Xor(mask) => seed
+                .iter()
+                .zip(mask.iter().cycle())
+                .map(|(s, m)| s ^ m)
+                .collect(),
+            Self::Const(key) => key.clone(),
+        }
+    }
+}
+
+/// Unlocks the ECU with SecurityAccess if `--seckey` (See [SecKey::parse]) was given,
+/// at the level given by `--seclevel` (The odd requestSeed sub function, default 1)
+pub fn unlock_ecu(args: &CliArgs, ecu: &DiagServer) -> CliResult<()> {
+    let key = match args.get_str("seckey") {
+        Some(k) => SecKey::parse(k)?,
+        None => return Ok(()),
+    };
+    let level = args.get_u32_or("seclevel", 1)?;
+    let level = u8::try_from(level).map_err(|_| format!("Invalid security level {}", level))?;
+    match ecu {
+        DiagServer::UDS(uds) => uds
+            .security_access(level, |seed| key.key(seed))
+            .map_err(|e| format!("Cannot unlock security level {}: {}", level, e.get_text())),
+        _ => Err("--seckey is only supported with --protocol uds".into()),
+    }
+}
+
+/// Starts a diagnostic session with the ECU given by [get_diag_ids], using
+/// the protocol from [get_protocol].
+///
+/// Optional arguments are `--baud` (See [CliArgs::get_baud]), `--ext`, `--bs` (Default 8),
+/// `--stmin` (Default 20), `--recv-mask` (UDS only), to accept responses from any ID
+/// matching `--recv-id` under the mask, and `--global-id`, the functional request ID
+/// (Default that of `--scheme`, if any)
+#[allow(clippy::borrowed_box)]
+pub fn open_diag_server(args: &CliArgs, server: &Box<dyn ComServer>) -> CliResult<DiagServer> {
+    let protocol = get_protocol(args)?;
+    let recv_id_mask = args.get_u32("recv-mask")?;
+    if recv_id_mask.is_some() && protocol != DiagProtocol::UDS {
+        return Err("--recv-mask is only supported with --protocol uds".into());
+    }
+    let (send_id, recv_id, scheme) = get_diag_ids(args)?;
+    let ext_can = args.get_flag("ext") || matches!(scheme, Some(s) if s.is_extended());
+    let mut cfg = InterfaceConfig::new();
+    cfg.add_param(IFACE_CFG::BAUDRATE, args.get_baud()?);
+    cfg.add_param(IFACE_CFG::EXT_CAN_ADDR, ext_can as u32);
+    cfg.add_param(IFACE_CFG::EXT_ISOTP_ADDR, 0);
+    cfg.add_param(IFACE_CFG::ISOTP_BS, args.get_u32_or("bs", 8)?);
+    cfg.add_param(IFACE_CFG::ISOTP_ST_MIN, args.get_u32_or("stmin", 20)?);
+
+    let diag_cfg = DiagCfg {
+        send_id,
+        recv_id,
+        global_id: args
+            .get_u32("global-id")?
+            .or(scheme.and_then(|s| s.global_id())),
+        recv_id_mask,
+    };
+    DiagServer::new(
+        protocol,
+        server,
+        InterfaceType::IsoTp,
+        cfg,
+        Some(vec![PayloadFlag::ISOTP_PAD_FRAME]),
+        diag_cfg,
+    )
+    .map_err(|e| format!("Cannot start diagnostic session: {}", e.get_text()))
+}
+
+/// Runs the CLI mode, returning the process exit code
+pub fn run(args: CliArgs) -> i32 {
+    let res = match args.mode {
+        CliMode::Stress => stress::run(&args),
+        CliMode::Trace => trace::run(&args),
+        CliMode::Script => script::run(&args),
+        CliMode::Grep => grep::run(&args),
+        CliMode::Diff => diff::run(&args),
+        CliMode::Bridge => bridge::run(&args),
+        CliMode::Report => report::run(&args),
+        CliMode::CbfInfo => cbf_info::run(&args),
+        CliMode::Loopback => loopback::run(&args),
+        CliMode::Inject => inject::run(&args),
+    };
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> CliArgs {
+        let list: Vec<String> = ["ovd", "--mode", "SCRIPT"]
+            .iter()
+            .chain(list)
+            .map(|s| s.to_string())
+            .collect();
+        CliArgs::parse(&list).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_diag_ids() {
+        assert_eq!(
+            get_diag_ids(&args(&["--send-id", "0x5B4", "--recv-id", "0x4F4"])).unwrap(),
+            (0x5B4, 0x4F4, None)
+        );
+        assert_eq!(
+            get_diag_ids(&args(&["--scheme", "obd", "--ecu", "1"])).unwrap(),
+            (0x7E1, 0x7E9, Some(AddressingScheme::StandardObd))
+        );
+        // Explicit IDs take priority
+        let (send, recv, _) = get_diag_ids(&args(&[
+            "--scheme",
+            "OBD29",
+            "--ecu",
+            "0x10",
+            "--recv-id",
+            "0x123",
+        ]))
+        .unwrap();
+        assert_eq!((send, recv), (0x18DA10F1, 0x123));
+        assert_eq!(
+            parse_scheme("custom:0x600,0x680").unwrap(),
+            AddressingScheme::Custom {
+                request_base: 0x600,
+                response_base: 0x680,
+                global_id: None
+            }
+        );
+
+        assert!(get_diag_ids(&args(&["--send-id", "0x7E0"])).is_err());
+        assert!(get_diag_ids(&args(&["--scheme", "obd", "--ecu", "8"])).is_err());
+        assert!(get_diag_ids(&args(&["--scheme", "obd", "--ecu", "300"])).is_err());
+        for bad in ["vw", "custom:0x600", "custom:0x600,0x680,1,2", "custom:a,b"] {
+            assert!(parse_scheme(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_seckey() {
+        let xor = SecKey::parse("xor:0x5AA5").unwrap();
+        assert_eq!(xor, SecKey::Xor(vec![0x5A, 0xA5]));
+        assert_eq!(xor.key(&[0x00, 0xFF, 0x12]), vec![0x5A, 0x5A, 0x48]);
+        let fixed = SecKey::parse("CONST:11223344").unwrap();
+        assert_eq!(fixed.key(&[0xAB]), vec![0x11, 0x22, 0x33, 0x44]);
+
+        for bad in ["xor", "xor:", "xor:0xABC", "add:1234"] {
+            assert!(SecKey::parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
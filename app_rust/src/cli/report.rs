@@ -0,0 +1,54 @@
+//! REPORT mode - Prints a vehicle health report read over OBD-II
+//!
+//! `--mode REPORT [--baud 500000] [--send-id 0x7DF] [--recv-id 0x7E8] [--format text|json|html]`
+//!
+//! See [generate_report] for what the report contains.
+
+use crate::commapi::{
+    iface::{InterfaceConfig, InterfaceType, PayloadFlag, IFACE_CFG},
+    protocols::{
+        obd2::{report::generate_report, ObdServer},
+        DiagCfg, ProtocolServer,
+    },
+};
+
+use super::{CliArgs, CliResult};
+
+pub fn run(args: &CliArgs) -> CliResult<()> {
+    let format = args.get_str("format").unwrap_or("text");
+    if !matches!(format, "text" | "json" | "html") {
+        return Err(format!(
+            "Unknown report format '{}', expected text, json or html",
+            format
+        ));
+    }
+    let server = super::open_device(args)?;
+
+    let mut cfg = InterfaceConfig::new();
+    cfg.add_param(IFACE_CFG::BAUDRATE, args.get_baud()?);
+    cfg.add_param(IFACE_CFG::EXT_CAN_ADDR, 0);
+    cfg.add_param(IFACE_CFG::EXT_ISOTP_ADDR, 0);
+    let diag_cfg = DiagCfg {
+        send_id: args.get_u32_or("send-id", 0x7DF)?,
+        recv_id: args.get_u32_or("recv-id", 0x7E8)?,
+        global_id: None,
+        recv_id_mask: None,
+    };
+    let mut obd = ObdServer::start_diag_session(
+        &server,
+        InterfaceType::IsoTp,
+        cfg,
+        Some(vec![PayloadFlag::ISOTP_PAD_FRAME]),
+        diag_cfg,
+    )
+    .map_err(|e| format!("Cannot start OBD-II session: {}", e.get_text()))?;
+
+    let report = generate_report(&obd);
+    match format {
+        "json" => println!("{}", report.to_json()),
+        "html" => print!("{}", report.to_html()),
+        _ => print!("{}", report.to_text()),
+    }
+    obd.exit_diag_session();
+    Ok(())
+}
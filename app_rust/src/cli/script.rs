@@ -0,0 +1,80 @@
+//! SCRIPT mode - Runs a diagnostic script against a UDS or KWP2000 ECU
+//!
+//! `--mode SCRIPT --file proc.yaml --send-id 0x7E0 --recv-id 0x7E8 [--protocol uds|kwp]
+//! [--baud 500000] [--ext] [--record session.json] [--seckey xor:0x5A5A5A5A [--seclevel 1]]`
+//!
+//! See [crate::commapi::protocols::uds::script] for the script format. Services named
+//! in the script must belong to the protocol given by `--protocol` (Default UDS).
+//!
+//! Steps with `addressing: functional` are sent to `--global-id`, or the functional ID of
+//! `--scheme`.
+//!
+//! `--seckey` unlocks the ECU with SecurityAccess before the script runs, see
+//! [SecKey](super::SecKey) for the key algorithms. UDS only.
+//!
+//! `--record` saves every request and response of the session, which can be replayed
+//! later without the vehicle with `--api replay --device session.json`
+
+use crate::commapi::{
+    protocols::uds::script::{Script, ScriptRunner, StepOutcome},
+    replay::SessionRecorder,
+};
+
+use super::{CliArgs, CliResult};
+
+pub fn run(args: &CliArgs) -> CliResult<()> {
+    let file = args
+        .get_str("file")
+        .ok_or("Missing required argument --file")?;
+    let script = Script::load(file).map_err(|e| e.get_text())?;
+    let protocol = super::get_protocol(args)?;
+    let runner = ScriptRunner::for_protocol(&script, protocol).map_err(|e| e.get_text())?;
+
+    let record = args.get_str("record");
+    let mut server = super::open_device(args)?;
+    let recorder = record.map(|_| SessionRecorder::new(server.clone_box()));
+    if let Some(r) = &recorder {
+        server = Box::new(r.clone());
+    }
+    let mut ecu = super::open_diag_server(args, &server)?;
+    if let Err(e) = super::unlock_ecu(args, &ecu) {
+        ecu.kill_diag_server();
+        let _ = server.close_device();
+        return Err(e);
+    }
+
+    println!("Running script '{}' ({})", script.name, protocol.get_name());
+    let report =
+        runner.run_with(|sid, data, addressing| ecu.run_cmd_addressed(sid, data, addressing));
+    for step in &report.steps {
+        match &step.outcome {
+            StepOutcome::Passed => match &step.response {
+                Some(r) => println!("[PASS] {} - {:02X?}", step.name, r),
+                None => println!("[PASS] {}", step.name),
+            },
+            StepOutcome::Branched(nrc) => println!(
+                "[NRC ] {} - ECU responded with 0x{:02X} ({})",
+                step.name,
+                nrc,
+                protocol.nrc_desc(*nrc)
+            ),
+            StepOutcome::Failed(e) => println!("[FAIL] {} - {}", step.name, e),
+        }
+    }
+
+    ecu.kill_diag_server();
+    let _ = server.close_device();
+    if let (Some(path), Some(r)) = (record, &recorder) {
+        r.get_session().save(path).map_err(|e| e.to_string())?;
+        println!("Session recorded to {}", path);
+    }
+
+    if report.passed() {
+        println!("Script passed");
+        Ok(())
+    } else if report.completed {
+        Err("Script completed with failed steps".into())
+    } else {
+        Err("Script aborted".into())
+    }
+}
@@ -0,0 +1,294 @@
+//! STRESS mode - Transmits frames at a target rate for validating adapter throughput
+//!
+//! `--mode STRESS --id 0x123 --rate 1000 --count 100000 [--baud 500000]`
+//!
+//! When the adapter's Tx queue is full, the frame is retried after a back-off which
+//! doubles on each retry, from [QUEUE_FULL_BACKOFF] up to [MAX_QUEUE_FULL_BACKOFF]. The
+//! frame is dropped after [MAX_QUEUE_FULL_RETRIES] retries.
+
+use std::{cmp::min, time::Duration};
+
+use crate::commapi::{
+    comm_api::{CanFrame, ComServerError},
+    iso_tp::{Clock, SystemClock},
+    latency::LatencyHistogram,
+};
+
+use super::{CliArgs, CliResult};
+
+/// First back-off after the adapter reports its Tx queue is full
+pub const QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(1);
+/// Longest back-off between retries of a frame
+pub const MAX_QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(50);
+/// Number of retries of a frame while the Tx queue is full, before it is dropped
+pub const MAX_QUEUE_FULL_RETRIES: u32 = 10;
+
+/// Returns true if the error indicates the Tx queue of the adapter named `api` is full
+fn is_tx_queue_full(api: &str, e: &ComServerError) -> bool {
+    match api {
+        "SAE J2534" => e.err_code == 0x10, // ERR_BUFFER_FULL
+        "Socket CAN" => e.err_code == 105, // ENOBUFS
+        _ => e.err_desc.to_lowercase().contains("full"),
+    }
+}
+
+/// Time since the start of the test that frame `i` is due at `rate` frames/sec. This is
+/// counted from the start rather than from the previous frame, so slow sends do not cause
+/// drift
+pub fn send_time(rate: u32, i: u64) -> Duration {
+    Duration::from_nanos((i as u128 * 1_000_000_000 / rate as u128) as u64)
+}
+
+/// Results of a stress test
+#[derive(Debug, Clone)]
+pub struct StressStats {
+    pub sent: u64,
+    pub send_errors: u64,
+    /// Number of sends rejected because the Tx queue was full, including retries
+    pub queue_full: u64,
+    /// Frames given up on after [MAX_QUEUE_FULL_RETRIES]
+    pub dropped: u64,
+    /// Time between successful sends
+    pub histogram: LatencyHistogram,
+    pub elapsed: Duration,
+}
+
+/// Sends `count` frames at `rate` frames/sec, timed by `clock`. `send` is given the index of
+/// the frame to send, and `is_queue_full` tells if a send failed because the adapter's Tx
+/// queue is full, in which case the frame is retried after a back-off
+pub fn stress<S, Q>(
+    clock: &dyn Clock,
+    rate: u32,
+    count: u64,
+    mut send: S,
+    is_queue_full: Q,
+) -> StressStats
+where
+    S: FnMut(u64) -> Result<(), ComServerError>,
+    Q: Fn(&ComServerError) -> bool,
+{
+    let mut stats = StressStats {
+        sent: 0,
+        send_errors: 0,
+        queue_full: 0,
+        dropped: 0,
+        histogram: LatencyHistogram::default(),
+        elapsed: Duration::ZERO,
+    };
+    let mut last_send: Option<Duration> = None;
+    let start = clock.now();
+    for i in 0..count {
+        let target = start + send_time(rate, i);
+        let now = clock.now();
+        if target > now {
+            clock.sleep(target - now);
+        }
+        let mut backoff = QUEUE_FULL_BACKOFF;
+        let mut retries = 0;
+        loop {
+            match send(i) {
+                Ok(()) => {
+                    stats.sent += 1;
+                    let now = clock.now();
+                    if let Some(last) = last_send {
+                        stats.histogram.add(now - last);
+                    }
+                    last_send = Some(now);
+                }
+                Err(e) if is_queue_full(&e) => {
+                    stats.queue_full += 1;
+                    if retries < MAX_QUEUE_FULL_RETRIES {
+                        retries += 1;
+                        clock.sleep(backoff);
+                        backoff = min(backoff * 2, MAX_QUEUE_FULL_BACKOFF);
+                        continue;
+                    }
+                    stats.dropped += 1;
+                }
+                Err(e) => {
+                    stats.send_errors += 1;
+                    if stats.send_errors == 1 {
+                        eprintln!("First send error: {}", e);
+                    }
+                }
+            }
+            break;
+        }
+    }
+    stats.elapsed = clock.now() - start;
+    stats
+}
+
+pub fn run(args: &CliArgs) -> CliResult<()> {
+    let id = args.get_u32_required("id")?;
+    let rate = args.get_u32_or("rate", 1000)?;
+    let count = args.get_u32_or("count", 10000)?;
+    let baud = args.get_baud()?;
+    if rate == 0 {
+        return Err("--rate must be greater than 0".into());
+    }
+
+    let mut server = super::open_device(args)?;
+    server
+        .open_can_interface(baud, id > 0x7FF)
+        .map_err(|e| e.to_string())?;
+
+    println!(
+        "Sending {} frames on ID 0x{:04X} at {} frames/sec using {}",
+        count,
+        id,
+        rate,
+        server.get_api()
+    );
+
+    let api = server.get_api().to_string();
+    let stats = stress(
+        &SystemClock::default(),
+        rate,
+        count as u64,
+        |i| {
+            let frame = CanFrame::new(id, &i.to_be_bytes());
+            server.send_can_packets(&[frame], 0).map(|_| ())
+        },
+        |e| is_tx_queue_full(&api, e),
+    );
+
+    let _ = server.close_can_interface();
+    let _ = server.close_device();
+
+    println!("---- STRESS RESULTS ----");
+    println!("Duration:        {:.3} s", stats.elapsed.as_secs_f64());
+    println!("Target rate:     {} frames/sec", rate);
+    println!(
+        "Achieved rate:   {:.1} frames/sec",
+        stats.sent as f64 / stats.elapsed.as_secs_f64()
+    );
+    println!("Frames sent:     {}/{}", stats.sent, count);
+    println!("Send errors:     {}", stats.send_errors);
+    println!("Tx queue full:   {}", stats.queue_full);
+    println!("Frames dropped:  {}", stats.dropped);
+    println!("Inter-frame send latency:");
+    stats.histogram.print();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commapi::iso_tp::MockClock;
+
+    fn queue_full() -> ComServerError {
+        ComServerError {
+            err_code: 0x10,
+            err_desc: "Tx buffer full".into(),
+        }
+    }
+
+    #[test]
+    fn test_send_time() {
+        assert_eq!(send_time(1000, 0), Duration::ZERO);
+        assert_eq!(send_time(1000, 1), Duration::from_millis(1));
+        assert_eq!(send_time(3, 1), Duration::from_nanos(333_333_333));
+        // No rounding error builds up over the test
+        assert_eq!(send_time(3, 3_000_000), Duration::from_secs(1_000_000));
+        assert_eq!(
+            send_time(7, u32::MAX as u64 * 7),
+            Duration::from_secs(u32::MAX as u64)
+        );
+    }
+
+    #[test]
+    fn test_stress_pacing() {
+        let clock = MockClock::default();
+        let mut times = Vec::new();
+        let stats = stress(
+            &clock,
+            1000,
+            5,
+            |_| {
+                times.push(clock.now());
+                Ok(())
+            },
+            |_| false,
+        );
+        let ms = |v: &[u64]| {
+            v.iter()
+                .map(|&m| Duration::from_millis(m))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(times, ms(&[0, 1, 2, 3, 4]));
+        assert_eq!((stats.sent, stats.send_errors), (5, 0));
+        assert_eq!(stats.histogram.samples(), 4);
+        assert_eq!(stats.elapsed, Duration::from_millis(4));
+
+        // Sends slower than the rate are not delayed further
+        let mut times = Vec::new();
+        stress(
+            &clock,
+            1000,
+            3,
+            |_| {
+                times.push(clock.now());
+                clock.advance(Duration::from_micros(2500));
+                Ok(())
+            },
+            |_| false,
+        );
+        let start = times[0];
+        let offsets: Vec<u128> = times.iter().map(|t| (*t - start).as_micros()).collect();
+        assert_eq!(offsets, [0, 2500, 5000]);
+    }
+
+    #[test]
+    fn test_stress_queue_full_backoff() {
+        let clock = MockClock::default();
+        let mut attempts = Vec::new();
+        let stats = stress(
+            &clock,
+            1000,
+            2,
+            |i| {
+                attempts.push((i, clock.now()));
+                match (i, attempts.len()) {
+                    (0, n) if n <= 3 => Err(queue_full()),
+                    _ => Ok(()),
+                }
+            },
+            |e| is_tx_queue_full("SAE J2534", e),
+        );
+        let ms = |i, m| (i, Duration::from_millis(m));
+        // Retried after 1, 2 and 4ms. Frame 1 is late, so is sent straight away
+        assert_eq!(attempts, [ms(0, 0), ms(0, 1), ms(0, 3), ms(0, 7), ms(1, 7)]);
+        assert_eq!((stats.sent, stats.queue_full, stats.dropped), (2, 3, 0));
+    }
+
+    #[test]
+    fn test_stress_queue_full_drop() {
+        let clock = MockClock::default();
+        let stats = stress(
+            &clock,
+            1000,
+            2,
+            |_| Err(queue_full()),
+            |e| is_tx_queue_full("SAE J2534", e),
+        );
+        let retries = MAX_QUEUE_FULL_RETRIES as u64;
+        assert_eq!(stats.sent, 0);
+        assert_eq!(stats.dropped, 2);
+        assert_eq!(stats.queue_full, 2 * (retries + 1));
+        // 1 + 2 + 4 + 8 + 16 + 32ms, then 50ms for the rest of the retries of each frame
+        let per_frame = Duration::from_millis(63 + 50 * (retries - 6));
+        assert_eq!(stats.elapsed, per_frame * 2);
+
+        // Other errors are not retried
+        let stats = stress(
+            &clock,
+            1000,
+            2,
+            |_| Err(queue_full()),
+            |e| is_tx_queue_full("Socket CAN", e),
+        );
+        assert_eq!((stats.send_errors, stats.queue_full), (2, 0));
+        assert_eq!(stats.elapsed, Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,801 @@
+//! TRACE mode - Prints every CAN Frame on the bus
+//!
+//! `--mode TRACE [--baud 500000 | --btr 0x852B] [--ext] [--duration 10] [--annotate-uds] [--error-counters] [--batch-ms 20]
+//! [--output capture.csv [--format csv|asc|parquet]] [--quiet] [--display-rate 100]
+//! [--isotp 0x7E0:0x7E8] [--sync-epoch now|<unix ms>]`
+//!
+//! `--btr` opens the bus with raw SJA1000 bit timing registers rather than a baud rate,
+//! see [ComServer::open_can_interface_raw](crate::commapi::comm_api::ComServer::open_can_interface_raw).
+//!
+//! With `--annotate-uds`, ISO-TP frames are decoded and the UDS service
+//! of single and first frames is shown next to each frame.
+//!
+//! With `--isotp`, the ISO-TP messages sent between each pair of IDs (Such as a tester and
+//! an ECU) are reassembled, and each complete message is printed on one line after its last
+//! frame. See [IsoTpWatcher].
+//!
+//! With `--error-counters`, the CAN controller's TEC and REC are printed every
+//! second, if the adapter can report them.
+//!
+//! With `--batch-ms`, frames are read in batches of everything arriving within that many
+//! milliseconds, see [ComServer::read_can_packets_batched](crate::commapi::comm_api::ComServer::read_can_packets_batched).
+//! The number of reads is printed at the end, for comparing against unbatched reads.
+//!
+//! With `--output`, every frame is streamed to a file, see [trace_export](super::trace_export)
+//! for the formats. `--quiet` stops frames from also being printed.
+//!
+//! `--display-rate` limits how many frames are printed per second, so that a slow terminal
+//! cannot stall reading from the adapter on a busy bus. Frames over the limit are only
+//! counted, see [DisplayLimiter]. The export file always gets every frame. Printing is
+//! unlimited by default, or limited to [EXPORT_DISPLAY_RATE] with `--output`.
+//!
+//! TRACE runs until `--duration` is over, or until Ctrl-C is pressed. Either way the adapter
+//! is closed, the export file is finished, and a summary of the capture is printed (See
+//! [CaptureStats]).
+//!
+//! Frames are shown with their own timestamp where the adapter provides one. These are
+//! checked with a [TimestampMonitor], so a rollover of the adapter's counter does not make
+//! the trace go backwards.
+//!
+//! With `--sync-epoch`, the capture is anchored to wall-clock time so it can be lined up
+//! with GPS or video logs. Frame times then count from the anchor, and exports also get
+//! each frame's wall-clock time. See [TimeSync] for how precise this is.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::commapi::{
+    comm_api::{btr_bitrate, CanFrame, FilterType},
+    iso_tp::{
+        decode_st_min, IsoTpConfig, IsoTpError, IsoTpReceiver, RxEvent, TxDlcMode,
+        DEFAULT_MAX_RX_BUFFERS,
+    },
+    protocols::{
+        uds::{UDSCommand, UDSNegativeCode},
+        CommandError, Selectable,
+    },
+};
+
+use super::{
+    trace_export::{ExportFormat, TraceExport},
+    CliArgs, CliResult,
+};
+
+/// Range of adapter timestamps. J2534 timestamps are a 32 bit microsecond
+/// counter, which rolls over roughly every 71.6 minutes
+const HW_TIMESTAMP_WRAP_US: u64 = 1 << 32;
+
+/// Keeps frame timestamps monotonic.
+///
+/// A backwards jump of more than half the counter range is a rollover, and the
+/// range is added to every later timestamp. Any other backwards jump is logged,
+/// and later timestamps are shifted so the trace carries on from the last frame
+#[derive(Debug, Clone)]
+pub struct TimestampMonitor {
+    wrap_us: u64,
+    offset: u64,
+    last_raw: Option<u64>,
+    /// Number of counter rollovers corrected
+    pub rollovers: u32,
+    /// Number of other backwards jumps corrected
+    pub backward_jumps: u32,
+}
+
+impl TimestampMonitor {
+    pub fn new(wrap_us: u64) -> Self {
+        Self {
+            wrap_us,
+            offset: 0,
+            last_raw: None,
+            rollovers: 0,
+            backward_jumps: 0,
+        }
+    }
+
+    /// Returns the corrected timestamp of the next frame
+    pub fn correct(&mut self, raw_us: u64) -> u64 {
+        if let Some(last) = self.last_raw {
+            if raw_us < last {
+                let jump = last - raw_us;
+                if jump > self.wrap_us / 2 {
+                    self.rollovers += 1;
+                    self.offset += self.wrap_us;
+                } else {
+                    log::warn!("TRACE - Frame timestamp went backwards by {}us", jump);
+                    self.backward_jumps += 1;
+                    self.offset += jump;
+                }
+            }
+        }
+        self.last_raw = Some(raw_us);
+        raw_us + self.offset
+    }
+}
+
+/// Latest `--sync-epoch` accepted (Year 3000), so a value in microseconds or nanoseconds
+/// is caught rather than exported as a date far in the future
+const MAX_SYNC_EPOCH_MS: u64 = 32_503_680_000_000;
+
+/// Anchors a trace to wall-clock time, for `--sync-epoch`.
+///
+/// The anchor is taken when TRACE starts, and is either the host's clock (`now`), or a
+/// Unix time in milliseconds given by the caller (Such as `$(date +%s%3N)`, or a time
+/// read from a GPS receiver). Frame times count from the anchor.
+///
+/// The adapter's timestamps count from an arbitrary zero, which can be before the anchor
+/// or after it (Host clock timestamps start when the CAN interface is opened). The offset
+/// between them and the anchor is taken from the first timestamped frame, assuming it
+/// arrived when the read returning it completed. Later frames keep the adapter's timing
+/// between frames.
+///
+/// Precision limits:
+/// * Times between frames have the adapter's resolution (1us on J2534). Adapters without
+///   timestamps only give the time of each read, which can hold several frames
+/// * The wall-clock time of every frame is only as good as the anchor. The host's clock
+///   is typically within a few ms when synchronised with NTP
+/// * The adapter's offset is late by the USB and driver latency of the first read,
+///   typically 1-10ms, or up to `--batch-ms` when reads are batched
+/// * The adapter's clock drifts from wall-clock time (50ppm is about 0.2s per hour), which
+///   is not corrected, so long captures should be re-anchored
+#[derive(Debug, Clone)]
+pub struct TimeSync {
+    /// Wall-clock time of the anchor, in microseconds since the Unix epoch
+    pub epoch_us: u64,
+    /// Time since the anchor of the adapter's timestamp 0, once known. Negative if the
+    /// adapter's clock started before the anchor
+    hw_offset_us: Option<i64>,
+}
+
+impl TimeSync {
+    pub fn new(epoch_us: u64) -> Self {
+        Self {
+            epoch_us,
+            hw_offset_us: None,
+        }
+    }
+
+    /// Parses `--sync-epoch`, either `now` or a Unix time in milliseconds
+    pub fn parse(s: &str) -> CliResult<Self> {
+        if s.eq_ignore_ascii_case("now") {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| "System clock is set before 1970".to_string())?;
+            return Ok(Self::new(now.as_micros() as u64));
+        }
+        match s.parse::<u64>() {
+            Ok(ms) if ms <= MAX_SYNC_EPOCH_MS => Ok(Self::new(ms * 1000)),
+            _ => Err(format!(
+                "Invalid --sync-epoch '{}', expected now or a Unix time in milliseconds",
+                s
+            )),
+        }
+    }
+
+    /// Returns the time of a frame since the anchor. `hw_us` is the adapter's (Corrected)
+    /// timestamp of the frame if it has one, and `read_us` the time since the anchor that
+    /// the read returning the frame completed
+    pub fn relative_us(&mut self, hw_us: Option<u64>, read_us: u64) -> u64 {
+        match hw_us {
+            Some(t) => {
+                let offset = *self.hw_offset_us.get_or_insert(read_us as i64 - t as i64);
+                (t as i64 + offset).max(0) as u64
+            }
+            None => read_us,
+        }
+    }
+
+    /// Returns the wall-clock time of a frame `relative_us` after the anchor, in
+    /// microseconds since the Unix epoch
+    pub fn absolute_us(&self, relative_us: u64) -> u64 {
+        self.epoch_us + relative_us
+    }
+
+    /// Time since the anchor of the adapter's timestamp 0, once a timestamped frame has
+    /// been received
+    pub fn hw_offset_us(&self) -> Option<i64> {
+        self.hw_offset_us
+    }
+}
+
+/// Default for `--display-rate` when frames are exported to a file
+pub const EXPORT_DISPLAY_RATE: u32 = 100;
+
+const DISPLAY_WINDOW: Duration = Duration::from_secs(1);
+
+/// Limits how many frames are printed per second.
+///
+/// Frames over the limit are dropped from the display, and summarised with
+/// [DisplayLimiter::roll] once the second they arrived in is over
+#[derive(Debug, Clone)]
+pub struct DisplayLimiter {
+    /// Frames per second to print, 0 for no limit
+    max_per_sec: u32,
+    window_start: Duration,
+    shown: u32,
+    /// Frames dropped in the current window
+    window_dropped: u64,
+    /// Total number of frames dropped from the display
+    pub dropped: u64,
+}
+
+impl DisplayLimiter {
+    pub fn new(max_per_sec: u32) -> Self {
+        Self {
+            max_per_sec,
+            window_start: Duration::from_secs(0),
+            shown: 0,
+            window_dropped: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Starts a new window if the current one has ended at `now`, returning how
+    /// many frames were dropped in the window that ended
+    pub fn roll(&mut self, now: Duration) -> Option<u64> {
+        if now < self.window_start + DISPLAY_WINDOW {
+            return None;
+        }
+        self.window_start = now;
+        self.shown = 0;
+        match std::mem::take(&mut self.window_dropped) {
+            0 => None,
+            n => Some(n),
+        }
+    }
+
+    /// Returns true if the next frame should be printed
+    pub fn allow(&mut self) -> bool {
+        if self.max_per_sec == 0 || self.shown < self.max_per_sec {
+            self.shown += 1;
+            true
+        } else {
+            self.window_dropped += 1;
+            self.dropped += 1;
+            false
+        }
+    }
+}
+
+/// Formats an STmin byte as per ISO15765-2
+fn format_st_min(st_min: u8) -> String {
+    match st_min {
+        0x00..=0x7F => format!("{}ms", decode_st_min(st_min).as_millis()),
+        0xF1..=0xF9 => format!("{}us", decode_st_min(st_min).as_micros()),
+        _ => format!("Reserved(0x{:02X})", st_min),
+    }
+}
+
+/// Describes the UDS message at the start of an ISO-TP payload
+fn describe_uds(payload: &[u8]) -> String {
+    let sid = match payload.first() {
+        Some(s) => *s,
+        None => return "Empty".into(),
+    };
+    let name = |sid: u8| UDSCommand::from_sid(sid).map(|c| c.get_name());
+    if sid == 0x7F {
+        let svc = payload
+            .get(1)
+            .and_then(|s| name(*s))
+            .unwrap_or_else(|| "unknown service".into());
+        return match payload.get(2) {
+            Some(nrc) => format!(
+                "Negative response to {}: {}",
+                svc,
+                UDSNegativeCode::from_byte(*nrc).get_desc()
+            ),
+            None => format!("Negative response to {}", svc),
+        };
+    }
+    if let Some(n) = name(sid) {
+        format!("{} request", n)
+    } else if let Some(n) = name(sid.wrapping_sub(0x40)) {
+        format!("{} response", n)
+    } else {
+        format!("SID 0x{:02X}", sid)
+    }
+}
+
+/// Decodes the ISO-TP PCI of a frame, and the UDS service it carries.
+/// Returns None if the frame is not an ISO-TP frame
+pub fn annotate_uds(frame: &CanFrame) -> Option<String> {
+    let data = frame.get_data();
+    let pci = *data.first()?;
+    match pci & 0xF0 {
+        0x00 => {
+            let len = (pci & 0x0F) as usize;
+            let payload = data.get(1..(1 + len).min(data.len()))?;
+            Some(format!("SF len={} {}", len, describe_uds(payload)))
+        }
+        0x10 => {
+            let len = ((pci as usize & 0x0F) << 8) | *data.get(1)? as usize;
+            Some(format!("FF len={} {}", len, describe_uds(&data[2..])))
+        }
+        0x20 => Some(format!("CF SN={}", pci & 0x0F)),
+        0x30 => {
+            let fs = match pci & 0x0F {
+                0x00 => "Continue".to_string(),
+                0x01 => "Wait".to_string(),
+                0x02 => "Overflow".to_string(),
+                x => format!("Reserved({})", x),
+            };
+            match (data.get(1), data.get(2)) {
+                (Some(bs), Some(st_min)) => Some(format!(
+                    "FC: FS={} BS={} STmin={}",
+                    fs,
+                    bs,
+                    format_st_min(*st_min)
+                )),
+                _ => Some(format!("FC: FS={}", fs)),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Reassembles the ISO-TP messages exchanged between pairs of diagnostic IDs, such as
+/// a factory tester and an ECU, whilst only listening to the bus.
+///
+/// Each ID of a pair is reassembled separately with a passive receiver (See
+/// [IsoTpReceiver::new_passive]), with the other ID as its destination. The watcher never
+/// sends anything, the node receiving the message sends the flow control frames
+#[derive(Debug, Clone)]
+pub struct IsoTpWatcher {
+    /// Destination of messages from each watched ID
+    peers: HashMap<u32, u32>,
+    receivers: HashMap<u32, IsoTpReceiver>,
+}
+
+impl IsoTpWatcher {
+    /// Watches each (request ID, response ID) pair in both directions
+    pub fn new(pairs: &[(u32, u32)]) -> Self {
+        let mut peers = HashMap::new();
+        for (req, resp) in pairs {
+            peers.insert(*req, *resp);
+            peers.insert(*resp, *req);
+        }
+        let receivers = peers
+            .iter()
+            .map(|(id, peer)| {
+                let cfg = IsoTpConfig {
+                    send_id: *peer,
+                    recv_id: *id,
+                    block_size: 0,
+                    st_min: 0,
+                    tx_dlc: TxDlcMode::Always8,
+                    addr_ext: None,
+                    rx_tolerance: None,
+                    max_rx_buffers: DEFAULT_MAX_RX_BUFFERS,
+                    can_fd: false,
+                };
+                (*id, IsoTpReceiver::new_passive(cfg))
+            })
+            .collect();
+        Self { peers, receivers }
+    }
+
+    /// Parses `--isotp`, a comma separated list of `<request ID>:<response ID>` pairs
+    pub fn parse_pairs(s: &str) -> CliResult<Vec<(u32, u32)>> {
+        s.split(',')
+            .map(|pair| {
+                pair.split_once(':')
+                    .and_then(|(req, resp)| Some((super::parse_u32(req)?, super::parse_u32(resp)?)))
+                    .ok_or_else(|| {
+                        format!(
+                            "Invalid ID pair '{}' for --isotp, expected 0x7E0:0x7E8",
+                            pair
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// Feeds a frame from the bus. Returns a line describing the message if the frame
+    /// completed one, or describing the error if the frame broke one
+    pub fn on_frame(&mut self, frame: &CanFrame) -> Option<String> {
+        let rx = self.receivers.get_mut(&frame.id)?;
+        let dest = self.peers[&frame.id];
+        match rx.on_frame(frame) {
+            Ok(RxEvent::Complete(payload)) => Some(format!(
+                "0x{:03X} -> 0x{:03X}: {}",
+                frame.id,
+                dest,
+                payload
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            )),
+            Ok(_) => None,
+            // Part of a message which started before the trace did
+            Err(IsoTpError::UnexpectedConsecutiveFrame) => None,
+            Err(e) => Some(format!("0x{:03X} -> 0x{:03X}: {}", frame.id, dest, e)),
+        }
+    }
+}
+
+/// Counts the frames received from each CAN ID during a trace
+#[derive(Debug, Clone, Default)]
+pub struct CaptureStats {
+    counts: HashMap<u32, u64>,
+    total: u64,
+}
+
+impl CaptureStats {
+    pub fn add(&mut self, frame: &CanFrame) {
+        *self.counts.entry(frame.id).or_insert(0) += 1;
+        self.total += 1;
+    }
+
+    /// Returns each CAN ID with its frame count, most frequent first. IDs with the same
+    /// count are sorted by ID
+    pub fn by_frequency(&self) -> Vec<(u32, u64)> {
+        let mut res: Vec<(u32, u64)> = self.counts.iter().map(|(id, n)| (*id, *n)).collect();
+        res.sort_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(&y.0)));
+        res
+    }
+
+    /// Summarises a capture which lasted `elapsed`
+    pub fn summary(&self, elapsed: Duration) -> String {
+        let secs = elapsed.as_secs_f64();
+        let fps = match secs > 0.0 {
+            true => self.total as f64 / secs,
+            false => 0.0,
+        };
+        let mut res = format!(
+            "Captured {} frames from {} unique IDs in {:.1}s ({:.1} frames/sec)",
+            self.total,
+            self.counts.len(),
+            secs,
+            fps
+        );
+        for (id, n) in self.by_frequency() {
+            res.push_str(&format!("\n  0x{:03X}: {}", id, n));
+        }
+        res
+    }
+}
+
+/// Describes the error state of a CAN controller from its error counters
+fn error_state(tec: u8, rec: u8) -> &'static str {
+    if tec == 255 {
+        "Bus off"
+    } else if tec >= 128 || rec >= 128 {
+        "Error passive"
+    } else if tec >= 96 || rec >= 96 {
+        "Error warning"
+    } else {
+        "Error active"
+    }
+}
+
+pub fn run(args: &CliArgs) -> CliResult<()> {
+    let mut sync = args
+        .get_str("sync-epoch")
+        .map(TimeSync::parse)
+        .transpose()?;
+    let anchor = Instant::now();
+    let baud = args.get_baud()?;
+    let duration = args.get_u32_or("duration", 0)?;
+    let ext = args.get_flag("ext");
+    let annotate = args.get_flag("annotate-uds");
+    let quiet = args.get_flag("quiet");
+    let mut watcher = args
+        .get_str("isotp")
+        .map(IsoTpWatcher::parse_pairs)
+        .transpose()?
+        .map(|pairs| IsoTpWatcher::new(&pairs));
+    let show_counters = args.get_flag("error-counters");
+    let batch_ms = args.get_u32_or("batch-ms", 0)?;
+
+    let btr = args.get_u32("btr")?;
+    let mut export = match args.get_str("output") {
+        Some(path) => {
+            let format = ExportFormat::parse(args.get_str("format").unwrap_or("csv"))?;
+            let epoch_us = sync.as_ref().map(|s| s.epoch_us);
+            Some((path, TraceExport::create(path, format, epoch_us)?))
+        }
+        None => None,
+    };
+    let default_rate = match export {
+        Some(_) => EXPORT_DISPLAY_RATE,
+        None => 0,
+    };
+    let mut display = DisplayLimiter::new(args.get_u32_or("display-rate", default_rate)?);
+
+    let mut server = super::open_device(args)?;
+    match btr {
+        Some(b) if b > 0xFFFF => return Err(format!("Invalid bit timing 0x{:X} for --btr", b)),
+        Some(b) => server.open_can_interface_raw(b as u16, ext),
+        None => server.open_can_interface(baud, ext),
+    }
+    .map_err(|e| e.to_string())?;
+    server
+        .add_can_filter(FilterType::Pass { id: 0, mask: 0 })
+        .map_err(|e| e.to_string())?;
+
+    let baud = match btr {
+        Some(b) => btr_bitrate(b as u16),
+        None => baud,
+    };
+    println!("Tracing CAN at {} bps using {}", baud, server.get_api());
+    if show_counters && server.get_error_counters().is_none() {
+        println!("{} cannot report CAN error counters", server.get_api());
+    }
+    if let Some(s) = &sync {
+        println!(
+            "Trace anchored to Unix time {}.{:03}s, frame times count from the anchor",
+            s.epoch_us / 1_000_000,
+            s.epoch_us / 1000 % 1000
+        );
+    }
+    super::catch_ctrl_c();
+    // Frame times count from the wall-clock anchor when there is one
+    let start = match sync {
+        Some(_) => anchor,
+        None => Instant::now(),
+    };
+    let mut last_counters = start;
+    let mut stats = CaptureStats::default();
+    let mut res = Ok(());
+    let (mut reads, mut total) = (0u64, 0u64);
+    let mut timestamps = TimestampMonitor::new(HW_TIMESTAMP_WRAP_US);
+    // Reads time out after 100ms, so Ctrl-C is noticed promptly on a quiet bus
+    while duration == 0 || start.elapsed() < Duration::from_secs(duration as u64) {
+        if super::interrupted() {
+            println!("Stopping trace");
+            break;
+        }
+        let read = match batch_ms {
+            0 => server.read_can_packets(10, 100),
+            ms => server.read_can_packets_batched(ms, 1000),
+        };
+        reads += 1;
+        let frames = match read {
+            Ok(f) => f,
+            Err(e) => {
+                res = Err(e.to_string());
+                break;
+            }
+        };
+        let elapsed = start.elapsed();
+        let time_us = elapsed.as_micros() as u64;
+        let time = time_us as f64 / 1_000_000.0;
+        total += frames.len() as u64;
+        if let Some(n) = display.roll(elapsed) {
+            println!("{:>12.6} ... {} frames not shown", time, n);
+        }
+        for f in frames {
+            stats.add(&f);
+            let hw_us = f.timestamp_us.map(|t| timestamps.correct(t));
+            let frame_us = match sync.as_mut() {
+                Some(s) => s.relative_us(hw_us, time_us),
+                None => hw_us.unwrap_or(time_us),
+            };
+            if let Some((path, file)) = export.as_mut() {
+                if let Err(e) = file.write_frame(frame_us, &f) {
+                    res = Err(format!("Cannot write to {}: {}", path, e));
+                    break;
+                }
+            }
+            let time = frame_us as f64 / 1_000_000.0;
+            if !quiet && display.allow() {
+                match annotate_uds(&f).filter(|_| annotate) {
+                    Some(a) => println!("{:>12.6} {} - {}", time, f, a),
+                    None => println!("{:>12.6} {}", time, f),
+                }
+            }
+            if let Some(msg) = watcher.as_mut().and_then(|w| w.on_frame(&f)) {
+                println!("{:>12.6} ISO-TP {}", time, msg);
+            }
+        }
+        if let Some((path, file)) = export.as_mut() {
+            if let Err(e) = file.flush() {
+                res = Err(format!("Cannot write to {}: {}", path, e));
+            }
+        }
+        if res.is_err() {
+            break;
+        }
+        if show_counters && last_counters.elapsed() >= Duration::from_secs(1) {
+            last_counters = Instant::now();
+            if let Some((tec, rec)) = server.get_error_counters() {
+                println!(
+                    "{:>12.6} Error counters: TEC={} REC={} ({})",
+                    time,
+                    tec,
+                    rec,
+                    error_state(tec, rec)
+                );
+            }
+        }
+    }
+
+    let elapsed = start.elapsed();
+    let _ = server.close_can_interface();
+    println!("{}", stats.summary(elapsed));
+    println!(
+        "Read {} frames in {} reads ({:.1} frames per read)",
+        total,
+        reads,
+        total as f64 / reads.max(1) as f64
+    );
+    if display.dropped > 0 {
+        println!(
+            "{} frames were not shown due to --display-rate",
+            display.dropped
+        );
+    }
+    if timestamps.rollovers > 0 || timestamps.backward_jumps > 0 {
+        println!(
+            "Corrected {} timestamp rollovers and {} backwards jumps",
+            timestamps.rollovers, timestamps.backward_jumps
+        );
+    }
+    if let Some(offset) = sync.as_ref().and_then(|s| s.hw_offset_us()) {
+        println!(
+            "Adapter timestamps were offset by {}us to the anchor",
+            offset
+        );
+    }
+    if let Some((path, file)) = export {
+        let frames = file.frames();
+        match file.finish() {
+            Ok(_) => println!("Exported {} frames to {}", frames, path),
+            Err(e) => res = Err(format!("Cannot write to {}: {}", path, e)),
+        }
+    }
+    let _ = server.close_device();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotate_flow_control() {
+        let fc = |d: &[u8]| annotate_uds(&CanFrame::new(0x7E0, d)).unwrap();
+        assert_eq!(fc(&[0x30, 0x08, 0x14]), "FC: FS=Continue BS=8 STmin=20ms");
+        assert_eq!(fc(&[0x31, 0x00, 0xF3]), "FC: FS=Wait BS=0 STmin=300us");
+        assert_eq!(
+            fc(&[0x32, 0x00, 0x80]),
+            "FC: FS=Overflow BS=0 STmin=Reserved(0x80)"
+        );
+        assert_eq!(fc(&[0x30]), "FC: FS=Continue");
+    }
+
+    #[test]
+    fn test_capture_stats() {
+        let mut stats = CaptureStats::default();
+        for id in [0x7E8, 0x100, 0x7E8, 0x200, 0x100, 0x7E8] {
+            stats.add(&CanFrame::new(id, &[0x00]));
+        }
+        assert_eq!(
+            stats.by_frequency(),
+            vec![(0x7E8, 3), (0x100, 2), (0x200, 1)]
+        );
+        let summary = stats.summary(Duration::from_secs(2));
+        assert!(summary.starts_with("Captured 6 frames from 3 unique IDs in 2.0s"));
+        assert!(summary.contains("(3.0 frames/sec)"));
+        assert!(summary.ends_with("0x7E8: 3\n  0x100: 2\n  0x200: 1"));
+        assert!(CaptureStats::default()
+            .summary(Duration::from_secs(0))
+            .contains("0.0 frames/sec"));
+    }
+
+    #[test]
+    fn test_error_state() {
+        assert_eq!(error_state(0, 0), "Error active");
+        assert_eq!(error_state(100, 0), "Error warning");
+        assert_eq!(error_state(0, 128), "Error passive");
+        assert_eq!(error_state(255, 0), "Bus off");
+    }
+
+    #[test]
+    fn test_timestamp_rollover() {
+        let mut m = TimestampMonitor::new(HW_TIMESTAMP_WRAP_US);
+        let raw = [0xFFFF_FF00, 0xFFFF_FFF0, 0x10, 0x100];
+        let res: Vec<u64> = raw.iter().map(|t| m.correct(*t)).collect();
+        assert_eq!(
+            res,
+            vec![0xFFFF_FF00, 0xFFFF_FFF0, 0x1_0000_0010, 0x1_0000_0100]
+        );
+        assert_eq!((m.rollovers, m.backward_jumps), (1, 0));
+
+        // A small jump backwards is not a rollover
+        let mut m = TimestampMonitor::new(HW_TIMESTAMP_WRAP_US);
+        let res: Vec<u64> = [1000, 900, 950].iter().map(|t| m.correct(*t)).collect();
+        assert_eq!(res, vec![1000, 1000, 1050]);
+        assert_eq!((m.rollovers, m.backward_jumps), (0, 1));
+    }
+
+    #[test]
+    fn test_time_sync() {
+        let mut sync = TimeSync::parse("1760000000123").unwrap();
+        assert_eq!(sync.epoch_us, 1_760_000_000_123_000);
+        // First frame read 250ms after the anchor sets the adapter's offset
+        assert_eq!(sync.relative_us(Some(9_000_000), 250_000), 250_000);
+        assert_eq!(sync.hw_offset_us(), Some(-8_750_000));
+        // Later frames keep the adapter's timing, not the time they were read
+        assert_eq!(sync.relative_us(Some(9_000_400), 300_000), 250_400);
+        assert_eq!(sync.absolute_us(250_400), 1_760_000_000_373_400);
+        // Frames without a timestamp use the time they were read
+        assert_eq!(TimeSync::new(0).relative_us(None, 1234), 1234);
+
+        // Adapter clock started 400ms after the anchor, once the device was opened
+        let mut sync = TimeSync::new(0);
+        assert_eq!(sync.relative_us(Some(50_000), 450_000), 450_000);
+        assert_eq!(sync.hw_offset_us(), Some(400_000));
+        assert_eq!(sync.relative_us(Some(60_000), 500_000), 460_000);
+
+        assert!(TimeSync::parse("now").unwrap().epoch_us > 0);
+        assert!(TimeSync::parse("-1").is_err());
+        assert!(TimeSync::parse("1760000000123000").is_err());
+    }
+
+    #[test]
+    fn test_display_limiter() {
+        let ms = Duration::from_millis;
+        let mut d = DisplayLimiter::new(2);
+        assert_eq!(d.roll(ms(0)), None);
+        assert!(d.allow());
+        assert!(d.allow());
+        assert!(!d.allow());
+        assert!(!d.allow());
+        assert_eq!(d.roll(ms(999)), None);
+        assert_eq!(d.roll(ms(1000)), Some(2));
+        assert!(d.allow());
+        assert_eq!(d.roll(ms(2000)), None);
+        assert_eq!(d.dropped, 2);
+
+        let mut d = DisplayLimiter::new(0);
+        assert!((0..10_000).all(|_| d.allow()));
+        assert_eq!(d.dropped, 0);
+    }
+
+    #[test]
+    fn test_annotate_frames() {
+        let a = |d: &[u8]| annotate_uds(&CanFrame::new(0x7E0, d)).unwrap();
+        assert_eq!(
+            a(&[0x03, 0x22, 0xF1, 0x90]),
+            "SF len=3 ReadDataByID request"
+        );
+        assert_eq!(
+            a(&[0x10, 0x14, 0x62, 0xF1, 0x90, 0x57, 0x44, 0x42]),
+            "FF len=20 ReadDataByID response"
+        );
+        assert_eq!(a(&[0x21, 0x00]), "CF SN=1");
+        assert!(
+            a(&[0x03, 0x7F, 0x22, 0x31]).starts_with("SF len=3 Negative response to ReadDataByID")
+        );
+    }
+
+    #[test]
+    fn test_isotp_watcher() {
+        let pairs = IsoTpWatcher::parse_pairs("0x7E0:0x7E8").unwrap();
+        let mut watcher = IsoTpWatcher::new(&pairs);
+        let mut feed = |id: u32, data: &[u8]| watcher.on_frame(&CanFrame::new(id, data));
+        assert_eq!(
+            feed(0x7E0, &[0x03, 0x22, 0xF1, 0x90]),
+            Some("0x7E0 -> 0x7E8: 22 F1 90".to_string())
+        );
+        // The tester's flow control is only seen, the watcher does not ask for one
+        assert_eq!(feed(0x7E8, &[0x10, 0x09, 0x62, 0xF1, 0x90, 1, 2, 3]), None);
+        assert_eq!(feed(0x7E0, &[0x30, 0x00, 0x00]), None);
+        assert_eq!(feed(0x123, &[0x02, 0x01, 0x02]), None);
+        assert_eq!(
+            feed(0x7E8, &[0x21, 4, 5, 6, 0xAA, 0xAA, 0xAA, 0xAA]),
+            Some("0x7E8 -> 0x7E0: 62 F1 90 01 02 03 04 05 06".to_string())
+        );
+        // A consecutive frame from a message which started before the trace
+        assert_eq!(feed(0x7E8, &[0x22, 1, 2, 3, 4, 5, 6, 7]), None);
+
+        assert_eq!(
+            IsoTpWatcher::parse_pairs("0x7E0:0x7E8,0x7E1:0x7E9").unwrap(),
+            vec![(0x7E0, 0x7E8), (0x7E1, 0x7E9)]
+        );
+        assert!(IsoTpWatcher::parse_pairs("0x7E0").is_err());
+    }
+}
@@ -0,0 +1,546 @@
+//! Export of TRACE captures, as CSV or Parquet for loading long captures into pandas or
+//! Polars, or as a Vector ASCII (ASC) trace for opening in CANalyzer or CANoe.
+//!
+//! Frames are streamed to the file as they are read, so the capture is never held in
+//! memory, and the file is flushed after every read from the adapter. If the trace is
+//! interrupted, at most the frames from the last read are lost.
+//!
+//! The CSV columns are `timestamp_us,id,dlc,b0,...,b7`. Bytes beyond the frame's DLC are
+//! left empty. CAN FD frames only have their first 8 bytes exported. When the capture is
+//! anchored to wall-clock time (`--sync-epoch`), a `unix_us` column with each frame's
+//! wall-clock time follows `timestamp_us`.
+//!
+//! ASC files have their `Begin Triggerblock` header written when the export is created,
+//! and `End TriggerBlock` written when it is finished. CAN FD frames are exported as
+//! classic frames with their first 8 bytes. ASC timestamps are relative to the header's
+//! date when the capture is anchored to wall-clock time. Otherwise the header has the time
+//! the export was created, and timestamps count from the first frame.
+//!
+//! Parquet export needs the `parquet-export` feature. The columns are `timestamp_us`,
+//! `unix_us` (Only when anchored), `id`, `dlc` and `data`, which has all of the frame's
+//! data, including CAN FD frames. Frames are buffered into row groups of
+//! [PARQUET_ROW_GROUP_FRAMES], and the file can only be read once the export is finished,
+//! as Parquet's metadata is written at the end.
+
+use std::{
+    cmp::min,
+    fs::File,
+    io::{BufWriter, Write},
+    time::{Duration, UNIX_EPOCH},
+};
+
+#[cfg(feature = "parquet-export")]
+use parquet::{
+    data_type::{ByteArray, ByteArrayType, DataType, Int32Type, Int64Type},
+    errors::ParquetError,
+    file::{
+        properties::WriterProperties,
+        writer::{SerializedFileWriter, SerializedRowGroupWriter},
+    },
+    schema::parser::parse_message_type,
+};
+#[cfg(feature = "parquet-export")]
+use std::sync::Arc;
+
+use crate::commapi::comm_api::CanFrame;
+
+use super::CliResult;
+
+const CSV_HEADER: &str = "timestamp_us,id,dlc,b0,b1,b2,b3,b4,b5,b6,b7";
+
+/// Number of data byte columns
+const DATA_COLUMNS: usize = 8;
+
+/// Channel number frames are logged on in ASC files
+const ASC_CHANNEL: u8 = 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Asc,
+    #[cfg(feature = "parquet-export")]
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn parse(s: &str) -> CliResult<Self> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "asc" => Ok(Self::Asc),
+            #[cfg(feature = "parquet-export")]
+            "parquet" => Ok(Self::Parquet),
+            #[cfg(not(feature = "parquet-export"))]
+            "parquet" => Err(
+                "This build does not include Parquet export, build with --features parquet-export"
+                    .into(),
+            ),
+            _ => Err(format!(
+                "Unknown export format '{}', expected csv, asc or parquet",
+                s
+            )),
+        }
+    }
+}
+
+/// Export file being written by TRACE, in either format
+#[derive(Debug)]
+pub enum TraceExport {
+    Csv(CsvExport<BufWriter<File>>),
+    Asc(AscExport<BufWriter<File>>),
+    #[cfg(feature = "parquet-export")]
+    Parquet(Box<ParquetExport<BufWriter<File>>>),
+}
+
+impl TraceExport {
+    /// Creates (Or truncates) the file at `path`. `epoch_us` is the wall-clock time of the
+    /// start of the capture in microseconds since the Unix epoch, if it is anchored to one
+    pub fn create(path: &str, format: ExportFormat, epoch_us: Option<u64>) -> CliResult<Self> {
+        let f = File::create(path).map_err(|e| format!("Cannot create {}: {}", path, e))?;
+        let out = BufWriter::new(f);
+        match format {
+            ExportFormat::Csv => CsvExport::with_epoch(out, epoch_us).map(Self::Csv),
+            ExportFormat::Asc => {
+                let start = match epoch_us {
+                    Some(us) => chrono::DateTime::from(UNIX_EPOCH + Duration::from_micros(us)),
+                    None => chrono::Local::now(),
+                };
+                let date = start.format(ASC_DATE_FORMAT).to_string();
+                match epoch_us {
+                    Some(_) => AscExport::anchored(out, &date),
+                    None => AscExport::new(out, &date),
+                }
+                .map(Self::Asc)
+            }
+            #[cfg(feature = "parquet-export")]
+            ExportFormat::Parquet => {
+                ParquetExport::new(out, epoch_us).map(|e| Self::Parquet(Box::new(e)))
+            }
+        }
+        .map_err(|e| format!("Cannot write to {}: {}", path, e))
+    }
+
+    pub fn write_frame(&mut self, timestamp_us: u64, frame: &CanFrame) -> std::io::Result<()> {
+        match self {
+            Self::Csv(e) => e.write_frame(timestamp_us, frame),
+            Self::Asc(e) => e.write_frame(timestamp_us, frame),
+            #[cfg(feature = "parquet-export")]
+            Self::Parquet(e) => e.write_frame(timestamp_us, frame),
+        }
+    }
+
+    /// Writes everything buffered so far to the file
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Csv(e) => e.flush(),
+            Self::Asc(e) => e.flush(),
+            #[cfg(feature = "parquet-export")]
+            Self::Parquet(e) => e.flush(),
+        }
+    }
+
+    /// Number of frames written
+    pub fn frames(&self) -> u64 {
+        match self {
+            Self::Csv(e) => e.frames,
+            Self::Asc(e) => e.frames,
+            #[cfg(feature = "parquet-export")]
+            Self::Parquet(e) => e.frames,
+        }
+    }
+
+    /// Finishes the file, writing its footer if the format has one
+    pub fn finish(self) -> std::io::Result<()> {
+        match self {
+            Self::Csv(e) => e.finish().map(|_| ()),
+            Self::Asc(e) => e.finish().map(|_| ()),
+            #[cfg(feature = "parquet-export")]
+            Self::Parquet(e) => e.finish().map(|_| ()),
+        }
+    }
+}
+
+/// Writes frames as CSV rows
+#[derive(Debug)]
+pub struct CsvExport<W: Write> {
+    out: W,
+    epoch_us: Option<u64>,
+    /// Number of frames written
+    pub frames: u64,
+}
+
+impl<W: Write> CsvExport<W> {
+    /// Starts the export, writing the header row
+    pub fn new(out: W) -> std::io::Result<Self> {
+        Self::with_epoch(out, None)
+    }
+
+    /// Starts the export of a capture which started at `epoch_us` (Microseconds since the
+    /// Unix epoch), if given, adding the `unix_us` column
+    pub fn with_epoch(mut out: W, epoch_us: Option<u64>) -> std::io::Result<Self> {
+        match epoch_us {
+            Some(_) => writeln!(out, "{}", CSV_HEADER.replacen(',', ",unix_us,", 1))?,
+            None => writeln!(out, "{}", CSV_HEADER)?,
+        }
+        Ok(Self {
+            out,
+            epoch_us,
+            frames: 0,
+        })
+    }
+
+    pub fn write_frame(&mut self, timestamp_us: u64, frame: &CanFrame) -> std::io::Result<()> {
+        let data = frame.get_data();
+        write!(self.out, "{}", timestamp_us)?;
+        if let Some(epoch) = self.epoch_us {
+            write!(self.out, ",{}", epoch + timestamp_us)?;
+        }
+        write!(self.out, ",0x{:X},{}", frame.id, frame.dlc)?;
+        for i in 0..DATA_COLUMNS {
+            match data.get(i) {
+                Some(b) => write!(self.out, ",{:02X}", b)?,
+                None => write!(self.out, ",")?,
+            }
+        }
+        writeln!(self.out)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes everything buffered so far to the file
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Flushes the export and returns the writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Format of the date in the ASC header, such as `Thu Oct 16 09:41:07.123 am 2026`
+const ASC_DATE_FORMAT: &str = "%a %b %d %I:%M:%S%.3f %P %Y";
+
+/// Writes frames as a Vector ASCII trace
+#[derive(Debug)]
+pub struct AscExport<W: Write> {
+    out: W,
+    /// Timestamp which is written as 0. None until the first frame if the export counts
+    /// from the first frame
+    start_us: Option<u64>,
+    /// Number of frames written
+    pub frames: u64,
+}
+
+impl<W: Write> AscExport<W> {
+    /// Starts the export, writing the header with the measurement's start `date`.
+    /// Timestamps are written relative to the first frame's
+    pub fn new(out: W, date: &str) -> std::io::Result<Self> {
+        Self::with_start(out, date, None)
+    }
+
+    /// Starts the export of a capture whose timestamps are already relative to the
+    /// header's `date`
+    pub fn anchored(out: W, date: &str) -> std::io::Result<Self> {
+        Self::with_start(out, date, Some(0))
+    }
+
+    fn with_start(mut out: W, date: &str, start_us: Option<u64>) -> std::io::Result<Self> {
+        writeln!(out, "date {}", date)?;
+        writeln!(out, "base hex  timestamps absolute")?;
+        writeln!(out, "no internal events logged")?;
+        writeln!(out, "Begin Triggerblock {}", date)?;
+        writeln!(out, "{:>11.6} Start of measurement", 0.0)?;
+        Ok(Self {
+            out,
+            start_us,
+            frames: 0,
+        })
+    }
+
+    pub fn write_frame(&mut self, timestamp_us: u64, frame: &CanFrame) -> std::io::Result<()> {
+        let data = &frame.get_data()[..min(frame.dlc as usize, 8)];
+        // Extended IDs are marked with a trailing 'x'
+        let id = match frame.id {
+            id if id > 0x7FF => format!("{:X}x", id),
+            id => format!("{:X}", id),
+        };
+        // Frames with an earlier timestamp than the first are written at 0
+        let start = *self.start_us.get_or_insert(timestamp_us);
+        write!(
+            self.out,
+            "{:>11.6} {}  {:<15} Rx   d {}",
+            timestamp_us.saturating_sub(start) as f64 / 1_000_000.0,
+            ASC_CHANNEL,
+            id,
+            data.len()
+        )?;
+        for b in data {
+            write!(self.out, " {:02X}", b)?;
+        }
+        writeln!(self.out)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes everything buffered so far to the file
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Writes the end of the trace, flushes the export and returns the writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        writeln!(self.out, "End TriggerBlock")?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Number of frames in each row group of a Parquet export
+#[cfg(feature = "parquet-export")]
+pub const PARQUET_ROW_GROUP_FRAMES: usize = 65_536;
+
+/// Parquet schema of an export, without and with the `unix_us` column
+#[cfg(feature = "parquet-export")]
+fn parquet_schema(anchored: bool) -> String {
+    format!(
+        "message can_frame {{
+            REQUIRED INT64 timestamp_us;
+            {}
+            REQUIRED INT32 id (INTEGER(32, false));
+            REQUIRED INT32 dlc (INTEGER(8, false));
+            REQUIRED BYTE_ARRAY data;
+        }}",
+        if anchored {
+            "REQUIRED INT64 unix_us;"
+        } else {
+            ""
+        }
+    )
+}
+
+/// Writes the next column of a row group
+#[cfg(feature = "parquet-export")]
+fn write_column<T: DataType, W: Write + Send>(
+    rg: &mut SerializedRowGroupWriter<'_, W>,
+    values: &[T::T],
+) -> parquet::errors::Result<()> {
+    let mut col = rg
+        .next_column()?
+        .ok_or_else(|| ParquetError::General("Export has fewer columns than its schema".into()))?;
+    col.typed::<T>().write_batch(values, None, None)?;
+    col.close()
+}
+
+/// Writes frames to a Parquet file, a row group at a time
+#[cfg(feature = "parquet-export")]
+pub struct ParquetExport<W: Write + Send> {
+    writer: SerializedFileWriter<W>,
+    epoch_us: Option<u64>,
+    /// Columns of the frames not written yet
+    timestamps: Vec<i64>,
+    ids: Vec<i32>,
+    dlcs: Vec<i32>,
+    data: Vec<ByteArray>,
+    /// Number of frames written
+    pub frames: u64,
+}
+
+#[cfg(feature = "parquet-export")]
+impl<W: Write + Send> std::fmt::Debug for ParquetExport<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParquetExport")
+            .field("epoch_us", &self.epoch_us)
+            .field("frames", &self.frames)
+            .finish()
+    }
+}
+
+#[cfg(feature = "parquet-export")]
+impl<W: Write + Send> ParquetExport<W> {
+    /// Starts the export of a capture which started at `epoch_us` (Microseconds since the
+    /// Unix epoch), if given, adding the `unix_us` column
+    pub fn new(out: W, epoch_us: Option<u64>) -> std::io::Result<Self> {
+        let schema = parse_message_type(&parquet_schema(epoch_us.is_some()))?;
+        let props = WriterProperties::builder()
+            .set_created_by(format!("OpenVehicleDiag {}", env!("CARGO_PKG_VERSION")))
+            .build();
+        Ok(Self {
+            writer: SerializedFileWriter::new(out, Arc::new(schema), Arc::new(props))?,
+            epoch_us,
+            timestamps: Vec::new(),
+            ids: Vec::new(),
+            dlcs: Vec::new(),
+            data: Vec::new(),
+            frames: 0,
+        })
+    }
+
+    pub fn write_frame(&mut self, timestamp_us: u64, frame: &CanFrame) -> std::io::Result<()> {
+        self.timestamps.push(timestamp_us as i64);
+        self.ids.push(frame.id as i32);
+        self.dlcs.push(frame.dlc as i32);
+        self.data.push(ByteArray::from(frame.get_data().to_vec()));
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes the buffered frames as a row group once there are enough of them
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if self.timestamps.len() >= PARQUET_ROW_GROUP_FRAMES {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn write_row_group(&mut self) -> parquet::errors::Result<()> {
+        if self.timestamps.is_empty() {
+            return Ok(());
+        }
+        let mut rg = self.writer.next_row_group()?;
+        write_column::<Int64Type, W>(&mut rg, &self.timestamps)?;
+        if let Some(epoch) = self.epoch_us {
+            let unix_us: Vec<i64> = self.timestamps.iter().map(|t| epoch as i64 + t).collect();
+            write_column::<Int64Type, W>(&mut rg, &unix_us)?;
+        }
+        write_column::<Int32Type, W>(&mut rg, &self.ids)?;
+        write_column::<Int32Type, W>(&mut rg, &self.dlcs)?;
+        write_column::<ByteArrayType, W>(&mut rg, &self.data)?;
+        rg.close()?;
+        self.timestamps.clear();
+        self.ids.clear();
+        self.dlcs.clear();
+        self.data.clear();
+        Ok(())
+    }
+
+    /// Writes the remaining frames and the file's metadata, flushes the export and returns
+    /// the writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.write_row_group()?;
+        let mut out = self.writer.into_inner()?;
+        out.flush()?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_export() {
+        let mut csv = CsvExport::new(Vec::new()).unwrap();
+        csv.write_frame(1000, &CanFrame::new(0x7E8, &[0x03, 0x62, 0xF1]))
+            .unwrap();
+        csv.write_frame(
+            2500,
+            &CanFrame::new(0x18DAF110, &[0, 1, 2, 3, 4, 5, 6, 0xFF]),
+        )
+        .unwrap();
+        assert_eq!(csv.frames, 2);
+        let text = String::from_utf8(csv.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines[1], "1000,0x7E8,3,03,62,F1,,,,,");
+        assert_eq!(lines[2], "2500,0x18DAF110,8,00,01,02,03,04,05,06,FF");
+        // Every row has the same number of columns as the header
+        assert!(lines.iter().all(|l| l.split(',').count() == 11));
+
+        // Capture anchored to wall-clock time
+        let mut csv = CsvExport::with_epoch(Vec::new(), Some(1_760_000_000_000_000)).unwrap();
+        csv.write_frame(1500, &CanFrame::new(0x7E8, &[0x03]))
+            .unwrap();
+        let text = String::from_utf8(csv.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            "timestamp_us,unix_us,id,dlc,b0,b1,b2,b3,b4,b5,b6,b7"
+        );
+        assert_eq!(lines[1], "1500,1760000000001500,0x7E8,1,03,,,,,,,");
+
+        #[cfg(not(feature = "parquet-export"))]
+        assert!(ExportFormat::parse("parquet").is_err());
+        assert_eq!(ExportFormat::parse("CSV"), Ok(ExportFormat::Csv));
+    }
+
+    #[test]
+    fn test_asc_export() {
+        let mut asc = AscExport::anchored(Vec::new(), "Thu Oct 16 09:41:07.123 am 2026").unwrap();
+        asc.write_frame(1000, &CanFrame::new(0x7E8, &[0x03, 0x62, 0xF1]))
+            .unwrap();
+        asc.write_frame(2_500_000, &CanFrame::new(0x18DAF110, &[]))
+            .unwrap();
+        assert_eq!(asc.frames, 2);
+        let text = String::from_utf8(asc.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "date Thu Oct 16 09:41:07.123 am 2026");
+        assert_eq!(
+            lines[3],
+            "Begin Triggerblock Thu Oct 16 09:41:07.123 am 2026"
+        );
+        assert_eq!(lines[4], "   0.000000 Start of measurement");
+        assert_eq!(lines[5], "   0.001000 1  7E8             Rx   d 3 03 62 F1");
+        assert_eq!(lines[6], "   2.500000 1  18DAF110x       Rx   d 0");
+        assert_eq!(lines[7], "End TriggerBlock");
+
+        assert_eq!(ExportFormat::parse("asc"), Ok(ExportFormat::Asc));
+
+        // Without an anchor, times count from the first frame's
+        let mut asc = AscExport::new(Vec::new(), "Thu Oct 16 09:41:07.123 am 2026").unwrap();
+        for t in [86_400_001_000, 86_400_003_500, 86_400_000_000] {
+            asc.write_frame(t, &CanFrame::new(0x7E8, &[0x01])).unwrap();
+        }
+        let text = String::from_utf8(asc.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[5], "   0.000000 1  7E8             Rx   d 1 01");
+        assert_eq!(lines[6], "   0.002500 1  7E8             Rx   d 1 01");
+        assert_eq!(lines[7], "   0.000000 1  7E8             Rx   d 1 01");
+    }
+
+    #[cfg(feature = "parquet-export")]
+    #[test]
+    fn test_parquet_export() {
+        use parquet::{
+            file::reader::{FileReader, SerializedFileReader},
+            record::RowAccessor,
+        };
+
+        let path = std::env::temp_dir().join(format!(
+            "ovd_test_parquet_export_{}.parquet",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let fd_data: Vec<u8> = (0..64).collect();
+        let mut export =
+            TraceExport::create(path, ExportFormat::Parquet, Some(1_760_000_000_000_000)).unwrap();
+        export
+            .write_frame(1000, &CanFrame::new(0x7E8, &[0x03, 0x62, 0xF1]))
+            .unwrap();
+        export.flush().unwrap();
+        export
+            .write_frame(2500, &CanFrame::new_fd(0x18DAF110, &fd_data))
+            .unwrap();
+        assert_eq!(export.frames(), 2);
+        export.finish().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        let _ = std::fs::remove_file(path);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get_long(0).unwrap(), 1000);
+        assert_eq!(rows[0].get_long(1).unwrap(), 1_760_000_000_001_000);
+        assert_eq!(rows[0].get_uint(2).unwrap(), 0x7E8);
+        assert_eq!(rows[0].get_ubyte(3).unwrap(), 3);
+        assert_eq!(rows[0].get_bytes(4).unwrap().data(), &[0x03, 0x62, 0xF1]);
+        assert_eq!(rows[1].get_long(1).unwrap(), 1_760_000_000_002_500);
+        assert_eq!(rows[1].get_uint(2).unwrap(), 0x18DAF110);
+        assert_eq!(rows[1].get_bytes(4).unwrap().data(), &fd_data[..]);
+
+        assert_eq!(ExportFormat::parse("Parquet"), Ok(ExportFormat::Parquet));
+    }
+}
@@ -0,0 +1,119 @@
+//! Parsing of recorded CAN traces for the offline analysis modes.
+//!
+//! Two formats are understood, and can be mixed in one file:
+//! * candump logs - `(1612345678.123456) can0 7E0#0322F190`, or `(1612345678.123456) can0 7E0 [4] 03 22 F1 90`
+//! * TRACE mode output - `    1.234567 ID: 0x07E0 Data: [03, 22, F1, 90]`
+//!
+//! Lines which cannot be parsed (Such as comments or headers) are skipped.
+
+use crate::commapi::comm_api::CanFrame;
+
+use super::CliResult;
+
+/// A single frame in a recorded trace
+#[derive(Debug, Clone)]
+pub struct TraceRecord {
+    /// Timestamp of the frame in seconds, as recorded in the trace
+    pub time: f64,
+    pub frame: CanFrame,
+}
+
+fn parse_hex_bytes<'a>(iter: impl Iterator<Item = &'a str>) -> Option<Vec<u8>> {
+    iter.map(|b| match b.len() {
+        2 => u8::from_str_radix(b, 16).ok(),
+        _ => None,
+    })
+    .collect::<Option<Vec<u8>>>()
+    .filter(|d| d.len() <= 8)
+}
+
+fn parse_candump(line: &str) -> Option<TraceRecord> {
+    let (time, rest) = line.strip_prefix('(')?.split_once(')')?;
+    let time = time.parse::<f64>().ok()?;
+    // Skip the interface name
+    let mut parts = rest.split_whitespace().skip(1);
+    let frame = parts.next()?;
+    let (id, data) = match frame.split_once('#') {
+        Some((id, data)) => {
+            let bytes = data
+                .as_bytes()
+                .chunks(2)
+                .map(|c| std::str::from_utf8(c).unwrap_or(""));
+            (id, parse_hex_bytes(bytes)?)
+        }
+        None => {
+            let dlc = parts
+                .next()?
+                .strip_prefix('[')?
+                .strip_suffix(']')?
+                .parse::<usize>()
+                .ok()?;
+            let bytes = parse_hex_bytes(parts.take(dlc))?;
+            if bytes.len() != dlc {
+                return None;
+            }
+            (frame, bytes)
+        }
+    };
+    let id = u32::from_str_radix(id, 16).ok()?;
+    Some(TraceRecord {
+        time,
+        frame: CanFrame::new(id, &data),
+    })
+}
+
+fn parse_ovd_trace(line: &str) -> Option<TraceRecord> {
+    let mut parts = line.split_whitespace();
+    let time = parts.next()?.parse::<f64>().ok()?;
+    let id = line.split_once("ID: 0x")?.1.split_whitespace().next()?;
+    let id = u32::from_str_radix(id, 16).ok()?;
+    let data = line.split_once("Data: [")?.1.split_once(']')?.0;
+    let data = parse_hex_bytes(data.split(',').map(|b| b.trim()).filter(|b| !b.is_empty()))?;
+    Some(TraceRecord {
+        time,
+        frame: CanFrame::new(id, &data),
+    })
+}
+
+/// Parses a single line of a trace. Returns None if the line holds no frame
+pub fn parse_line(line: &str) -> Option<TraceRecord> {
+    let line = line.trim();
+    if line.starts_with('(') {
+        parse_candump(line)
+    } else {
+        parse_ovd_trace(line)
+    }
+}
+
+/// Loads every frame from a trace file
+pub fn load(path: &str) -> CliResult<Vec<TraceRecord>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    Ok(text.lines().filter_map(parse_line).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let r = parse_line("(1612345678.500000) can0 7E0#0322F190").unwrap();
+        assert_eq!(r.frame.id, 0x7E0);
+        assert_eq!(r.frame.get_data(), &[0x03, 0x22, 0xF1, 0x90]);
+        assert!((r.time - 1612345678.5).abs() < 1e-6);
+
+        let r = parse_line("(0.250000)  can1  18DAF110   [3]  02 10 03").unwrap();
+        assert_eq!(r.frame.id, 0x18DAF110);
+        assert_eq!(r.frame.get_data(), &[0x02, 0x10, 0x03]);
+
+        let r = parse_line("    1.234567 ID: 0x07E8 Data: [02, 50, 03] - SF len=2").unwrap();
+        assert_eq!(r.frame.id, 0x7E8);
+        assert_eq!(r.frame.get_data(), &[0x02, 0x50, 0x03]);
+
+        let r = parse_line("    2.000000 ID: 0x0100 Data: []").unwrap();
+        assert!(r.frame.get_data().is_empty());
+
+        assert!(parse_line("Tracing CAN at 500000 bps using SLCAN").is_none());
+        assert!(parse_line("(0.1) can0 7E0#03F").is_none());
+    }
+}
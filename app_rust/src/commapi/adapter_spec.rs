@@ -0,0 +1,247 @@
+//! Opening an adapter from a single spec string, such as `slcan:/dev/ttyUSB0@500k`.
+//!
+//! The format is `<backend>[:<channel>][@<bitrate>]`, where backend is one of:
+//! * `passthru` - Channel is the device name, or `auto` (Or nothing) for the first device found
+//! * `socketcan` - Channel is the network interface, such as `can0` (Linux only)
+//! * `slcan` - Channel is the serial port
+//! * `replay` - Channel is a session file recorded by SCRIPT mode's `--record`
+//!
+//! Bitrate is optional, and can be written in bps (`500000`), kbps (`500k`) or Mbps (`1M`).
+//! Opening the adapter does not open a CAN channel, so it is up to the caller to use it
+
+#[cfg(feature = "passthru")]
+use crate::{
+    commapi::passthru_api::PassthruApi,
+    passthru::{PassthruDevice, PassthruDrv},
+};
+
+#[cfg(all(target_os = "linux", feature = "socket-can"))]
+use crate::commapi::socket_can_api::SocketCanAPI;
+
+use super::{
+    can_transport::TransportServer,
+    comm_api::{ComServer, ComServerError},
+    replay::{RecordedSession, ReplayServer},
+    slcan_api::SlcanApi,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Backend {
+    Passthru,
+    SocketCan,
+    Slcan,
+    Replay,
+}
+
+impl Backend {
+    fn from_str(s: &str) -> Result<Self, ComServerError> {
+        match s.to_lowercase().as_str() {
+            "passthru" | "j2534" => Ok(Self::Passthru),
+            "socketcan" => Ok(Self::SocketCan),
+            "slcan" => Ok(Self::Slcan),
+            "replay" => Ok(Self::Replay),
+            "peak" | "pcan" => Err(spec_error(
+                "PCAN adapters are not supported, use passthru with the PCAN J2534 driver instead"
+                    .into(),
+            )),
+            _ => Err(spec_error(format!(
+                "Unknown backend '{}', expected passthru, socketcan, slcan or replay",
+                s
+            ))),
+        }
+    }
+}
+
+/// Adapter parsed from a spec string. See the [module docs](self) for the format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdapterSpec {
+    pub backend: Backend,
+    /// Device name, interface or file. None to use the first device found
+    pub channel: Option<String>,
+    /// Bitrate of the CAN bus in bps, if given
+    pub bitrate: Option<u32>,
+}
+
+fn spec_error(desc: String) -> ComServerError {
+    ComServerError {
+        err_code: 97,
+        err_desc: desc,
+    }
+}
+
+/// Parses a bitrate in bps, with an optional `k` (kbps) or `M` (Mbps) suffix
+fn parse_bitrate(s: &str) -> Option<u32> {
+    let (num, mult) = match s.chars().last()? {
+        'k' | 'K' => (&s[..s.len() - 1], 1_000),
+        'm' | 'M' => (&s[..s.len() - 1], 1_000_000),
+        _ => (s, 1),
+    };
+    let res = if num.contains('.') {
+        (num.parse::<f32>().ok()? * mult as f32).round() as u32
+    } else {
+        num.parse::<u32>().ok()?.checked_mul(mult)?
+    };
+    if res == 0 {
+        None
+    } else {
+        Some(res)
+    }
+}
+
+impl AdapterSpec {
+    pub fn parse(spec: &str) -> Result<Self, ComServerError> {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            return Err(spec_error("Adapter spec is empty".into()));
+        }
+        let (backend, rest) = match spec.split_once(':') {
+            Some((b, r)) => (b, r),
+            None => spec.split_at(spec.find('@').unwrap_or(spec.len())),
+        };
+        let backend = Backend::from_str(backend)?;
+        let (channel, bitrate) = match rest.rsplit_once('@') {
+            Some((c, b)) => {
+                let bitrate = parse_bitrate(b).ok_or_else(|| {
+                    spec_error(format!(
+                        "Invalid bitrate '{}', expected a value like 500000, 500k or 1M",
+                        b
+                    ))
+                })?;
+                (c, Some(bitrate))
+            }
+            None => (rest, None),
+        };
+        let channel = match channel {
+            "" | "auto" => None,
+            c => Some(c.to_string()),
+        };
+        if channel.is_none() && backend != Backend::Passthru {
+            return Err(spec_error(format!(
+                "A channel is required for {:?}, for example {}",
+                backend,
+                match backend {
+                    Backend::SocketCan => "socketcan:can0",
+                    Backend::Slcan => "slcan:/dev/ttyUSB0@500k",
+                    _ => "replay:session.json",
+                }
+            )));
+        }
+        Ok(Self {
+            backend,
+            channel,
+            bitrate,
+        })
+    }
+
+    /// Creates the server for the adapter, without opening it
+    pub fn create(&self) -> Result<Box<dyn ComServer>, ComServerError> {
+        // parse() ensures every backend except passthru has a channel
+        let channel = self.channel.clone().unwrap_or_default();
+        Ok(match self.backend {
+            #[cfg(feature = "passthru")]
+            Backend::Passthru => {
+                let devices = PassthruDevice::find_all()
+                    .map_err(|_| spec_error("Could not find any Passthru devices".into()))?;
+                let dev = match &self.channel {
+                    Some(n) => devices.into_iter().find(|d| &d.name == n),
+                    None => devices.into_iter().next(),
+                }
+                .ok_or_else(|| spec_error("Passthru device not found".into()))?;
+                let drv = PassthruDrv::load_lib(dev.drv_path.clone())
+                    .map_err(|_| spec_error(format!("Cannot locate driver at {}", dev.drv_path)))?;
+                Box::new(PassthruApi::new(dev, drv))
+            }
+            #[cfg(not(feature = "passthru"))]
+            Backend::Passthru => {
+                return Err(spec_error(
+                    "This build does not include Passthru support".into(),
+                ))
+            }
+            #[cfg(all(target_os = "linux", feature = "socket-can"))]
+            Backend::SocketCan => Box::new(SocketCanAPI::new(channel)),
+            #[cfg(not(all(target_os = "linux", feature = "socket-can")))]
+            Backend::SocketCan => {
+                return Err(spec_error(
+                    "This build does not include SocketCAN support".into(),
+                ))
+            }
+            Backend::Slcan => Box::new(TransportServer::new(Box::new(SlcanApi::new(channel)))),
+            Backend::Replay => Box::new(ReplayServer::new(RecordedSession::load(&channel)?)),
+        })
+    }
+
+    /// Creates and opens the server for the adapter
+    pub fn open(&self) -> Result<Box<dyn ComServer>, ComServerError> {
+        let mut server = self.create()?;
+        server.open_device()?;
+        Ok(server)
+    }
+}
+
+/// Parses an adapter spec (Such as `socketcan:can0` or `slcan:/dev/ttyUSB0@500k`), and
+/// returns the opened adapter. The CAN interface is not opened, so callers which need the
+/// spec's bitrate should parse it with [AdapterSpec::parse].
+/// See the [module docs](self) for the format
+pub fn open_by_spec(spec: &str) -> Result<Box<dyn ComServer>, ComServerError> {
+    AdapterSpec::parse(spec)?.open()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        let spec = AdapterSpec::parse("slcan:/dev/ttyUSB0@500k").unwrap();
+        assert_eq!(spec.backend, Backend::Slcan);
+        assert_eq!(spec.channel.as_deref(), Some("/dev/ttyUSB0"));
+        assert_eq!(spec.bitrate, Some(500_000));
+
+        let spec = AdapterSpec::parse("passthru:auto").unwrap();
+        assert_eq!((spec.backend, spec.channel), (Backend::Passthru, None));
+        assert_eq!(
+            AdapterSpec::parse("passthru@1M").unwrap().bitrate,
+            Some(1_000_000)
+        );
+        assert_eq!(
+            AdapterSpec::parse("PASSTHRU:Macchina A0@83.3k").unwrap(),
+            AdapterSpec {
+                backend: Backend::Passthru,
+                channel: Some("Macchina A0".into()),
+                bitrate: Some(83_300),
+            }
+        );
+        // Only the first ':' separates the backend
+        assert_eq!(
+            AdapterSpec::parse("replay:C:\\logs\\a.json")
+                .unwrap()
+                .channel
+                .as_deref(),
+            Some("C:\\logs\\a.json")
+        );
+
+        for bad in [
+            "",
+            "foo:bar",
+            "peak:PCAN_USBBUS1@500k",
+            "socketcan",
+            "slcan:COM3@fast",
+            "slcan:COM3@0",
+        ] {
+            let err = AdapterSpec::parse(bad).unwrap_err();
+            assert_eq!(err.err_code, 97, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_open_by_spec() {
+        let path =
+            std::env::temp_dir().join(format!("ovd_test_open_by_spec_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        RecordedSession::default().save(path).unwrap();
+        let server = open_by_spec(&format!("replay:{}@250k", path)).unwrap();
+        assert_eq!(server.get_api(), "Replay");
+        assert!(open_by_spec(&format!("replay:{}.missing", path)).is_err());
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,1016 @@
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use super::{
+    comm_api::{
+        CanFrame, Capability, ComServer, ComServerError, DeviceCapabilities, FilterType,
+        FrameCounter, FrameCounters, FrameStamper, ISO15765Data, TimestampSource,
+    },
+    iso_tp::{
+        Clock, FlowStatus, IsoTpConfig, IsoTpMultiReceiver, IsoTpTransmitter, RxEvent, RxTolerance,
+        SystemClock, TxDlcMode, TxPoll, DEFAULT_MAX_RX_BUFFERS,
+    },
+    tx_scheduler::TxScheduler,
+};
+
+/// Maximum number of flow control wait frames to accept before giving up
+const MAX_FC_WAIT_FRAMES: u32 = 10;
+
+/// Minimal interface an adapter must implement in order to be used by OVD.
+///
+/// An adapter only has to be able to send and receive raw CAN Frames. ISO-TP
+/// and everything above it is handled by [TransportServer], which turns any
+/// `CanTransport` into a full [ComServer].
+pub trait CanTransport: Send + Sync + std::fmt::Debug {
+    /// Attempts to open and connect to the device
+    fn open(&mut self) -> Result<(), ComServerError>;
+
+    /// Closes the connection to the device
+    fn close(&mut self) -> Result<(), ComServerError>;
+
+    /// Attempts to open the CAN Channel on the device
+    ///
+    /// ## Params
+    /// * `bus_speed` - Speed of the vehicle Canbus in bps
+    /// * `is_ext_can` - Use extended CAN Addressing (29bit CAN ID)
+    fn open_can(&mut self, bus_speed: u32, is_ext_can: bool) -> Result<(), ComServerError>;
+
+    /// Attempts to open the CAN Channel on the device with exact bit timing registers.
+    /// See [ComServer::open_can_interface_raw] for the format
+    fn open_can_raw(&mut self, _btr0btr1: u16, _is_ext_can: bool) -> Result<(), ComServerError> {
+        Err(ComServerError::not_supported("Raw CAN bit timing"))
+    }
+
+    /// Closes the CAN Channel on the device
+    fn close_can(&mut self) -> Result<(), ComServerError>;
+
+    /// Sends a list of CAN Frames.
+    ///
+    /// ## Returns
+    /// The number of CAN Frames successfully written to the bus
+    fn send_frames(&mut self, data: &[CanFrame], timeout_ms: u32) -> Result<usize, ComServerError>;
+
+    /// Reads up to `max_msgs` CAN Frames from the device. A timeout of 0 returns
+    /// whatever is currently in the devices Rx queue.
+    fn read_frames(
+        &mut self,
+        timeout_ms: u32,
+        max_msgs: usize,
+    ) -> Result<Vec<CanFrame>, ComServerError>;
+
+    /// Adds a pass or block filter to the CAN Channel, returning the filter ID
+    fn add_filter(&mut self, f: FilterType) -> Result<u32, ComServerError>;
+
+    /// Removes a filter that was created with [add_filter](fn@add_filter)
+    fn rem_filter(&mut self, filter_idx: u32) -> Result<(), ComServerError>;
+
+    /// Clears the devices Rx queue
+    fn clear_rx_buffer(&mut self) -> Result<(), ComServerError>;
+
+    /// Clears the devices Tx queue
+    fn clear_tx_buffer(&mut self) -> Result<(), ComServerError>;
+
+    /// Returns the battery voltage in Volts, or -1.0 if the device cannot measure it
+    fn read_battery_voltage(&mut self) -> Result<f32, ComServerError> {
+        Ok(-1.0)
+    }
+
+    /// Returns true if the CAN Channel is currently open
+    fn is_connected(&self) -> bool;
+
+    /// Retrieves the device's capabilities. ISO15765 support is provided
+    /// by [TransportServer], so it does not need to be reported here
+    fn get_capabilities(&self) -> DeviceCapabilities;
+
+    /// Returns a 1 word string indicating which hardware API the device uses
+    fn get_api(&self) -> &'static str;
+
+    /// Returns the bitrate of the open CAN Channel, if known
+    fn get_bitrate(&self) -> Option<u32> {
+        None
+    }
+
+    /// Returns the filters currently applied to the CAN Channel
+    fn get_active_filters(&self) -> Vec<FilterType> {
+        Vec::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IsoTpFilter {
+    id: u32,
+    mask: u32,
+    fc: u32,
+}
+
+#[derive(Debug, Default)]
+struct IsoTpChannel {
+    open: bool,
+    block_size: u8,
+    st_min: u8,
+    /// Block size and STmin set by [TransportServer::set_rx_flow_control]. Takes
+    /// priority over the ISO15765 params for our flow control frames
+    rx_flow_control: Option<(u8, u8)>,
+    /// DLC mode forced by [TransportServer::set_tx_dlc_mode]
+    tx_dlc: Option<TxDlcMode>,
+    /// Extended addressing was requested when the channel was opened. Like J2534's
+    /// ISO15765_ADDR_TYPE, the first byte of each payload is then the address extension
+    ext_addressing: bool,
+    /// Address extension of the last payload sent, which is also used in our flow
+    /// control frames. Until a payload has been sent, frames are received with normal addressing
+    addr_ext: Option<u8>,
+    /// Receive tolerance set by [TransportServer::set_rx_tolerance]
+    rx_tolerance: Option<RxTolerance>,
+    /// Reassembly buffer limit set by [TransportServer::set_max_rx_buffers]
+    max_rx_buffers: Option<usize>,
+    /// CAN FD single frames enabled by [TransportServer::set_can_fd]
+    can_fd: bool,
+    /// Filter (Response ID, mask, flow control ID), and its CAN Filter ID on the transport
+    filter: Option<(IsoTpFilter, u32)>,
+    /// Reassembly buffers, keyed by the ECU's response ID
+    receiver: Option<IsoTpMultiReceiver>,
+    rx_queue: Vec<ISO15765Data>,
+}
+
+/// Software ISO-TP stack which runs on top of any [CanTransport]
+#[derive(Debug, Clone)]
+pub struct TransportServer {
+    transport: Arc<Mutex<Box<dyn CanTransport>>>,
+    isotp: Arc<Mutex<IsoTpChannel>>,
+    clock: Arc<dyn Clock>,
+    stamper: Arc<RwLock<FrameStamper>>,
+    counter: Arc<FrameCounter>,
+    /// Sends the periodic messages, while any are running. All other frames are
+    /// then sent through it too, so they are interleaved by CAN ID
+    scheduler: Arc<Mutex<Option<TxScheduler>>>,
+}
+
+impl TransportServer {
+    pub fn new(transport: Box<dyn CanTransport>) -> Self {
+        Self::with_clock(transport, Arc::new(SystemClock::default()))
+    }
+
+    /// Creates a server which uses `clock` for ISO-TP transmit timing (STmin and
+    /// flow control timeout). Used with a [MockClock](super::iso_tp::MockClock) in tests
+    pub fn with_clock(transport: Box<dyn CanTransport>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            transport: Arc::new(Mutex::new(transport)),
+            isotp: Arc::new(Mutex::new(IsoTpChannel::default())),
+            clock,
+            stamper: Arc::new(RwLock::new(FrameStamper::default())),
+            counter: Arc::new(FrameCounter::default()),
+            scheduler: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Sends frames on the transport, through the [TxScheduler] if periodic messages
+    /// are running. Frames sent by the scheduler do not wait for `timeout_ms`
+    fn transmit(&self, data: &[CanFrame], timeout_ms: u32) -> Result<usize, ComServerError> {
+        if let Some(scheduler) = self.scheduler.lock().unwrap().as_ref() {
+            // Counted by the scheduler's send function
+            return data.iter().try_fold(0, |n, f| Ok(n + scheduler.send(*f)?));
+        }
+        let res = self.transport.lock().unwrap().send_frames(data, timeout_ms);
+        self.counter.add_sent(data.len(), &res);
+        res
+    }
+
+    /// Stops the periodic messages
+    fn stop_scheduler(&self) {
+        // Dropping the scheduler stops its thread
+        self.scheduler.lock().unwrap().take();
+    }
+
+    fn channel_not_open() -> ComServerError {
+        ComServerError {
+            err_code: 0x41,
+            err_desc: "ISO15765 channel is not open".into(),
+        }
+    }
+
+    /// Forces the DLC of every transmitted ISO-TP frame, including flow control.
+    ///
+    /// With `None` (The default), the `pad_frame` flag of each payload decides the
+    /// DLC of its frames, and flow control frames are always sent with DLC 8.
+    /// See [TxDlcMode] for which to use
+    pub fn set_tx_dlc_mode(&self, mode: Option<TxDlcMode>) {
+        let mut channel = self.isotp.lock().unwrap();
+        channel.tx_dlc = mode;
+        channel.receiver =
+            Self::build_config(&channel, TxDlcMode::Always8).map(IsoTpMultiReceiver::new);
+    }
+
+    /// Makes the ISO-TP receiver tolerate late, reordered and repeated consecutive frames
+    /// on a noisy bus. This is best-effort only, see [RxTolerance]. None (The default)
+    /// restores strict ISO15765-2 behaviour
+    pub fn set_rx_tolerance(&self, tolerance: Option<RxTolerance>) {
+        let mut channel = self.isotp.lock().unwrap();
+        channel.rx_tolerance = tolerance;
+        channel.receiver =
+            Self::build_config(&channel, TxDlcMode::Always8).map(IsoTpMultiReceiver::new);
+    }
+
+    /// Limits how many payloads (One per source ID) are reassembled at once. Once the
+    /// limit is reached, the oldest incomplete payload is dropped. Defaults to
+    /// [DEFAULT_MAX_RX_BUFFERS]
+    pub fn set_max_rx_buffers(&self, max: usize) {
+        let mut channel = self.isotp.lock().unwrap();
+        channel.max_rx_buffers = Some(max);
+        channel.receiver =
+            Self::build_config(&channel, TxDlcMode::Always8).map(IsoTpMultiReceiver::new);
+    }
+
+    /// Sends payloads of up to [FD_SF_MAX_LEN](super::iso_tp::FD_SF_MAX_LEN) bytes as a
+    /// single CAN FD frame, rather than as multiple classic frames. Fails if the adapter
+    /// does not support CAN FD
+    pub fn set_can_fd(&self, enabled: bool) -> Result<(), ComServerError> {
+        if enabled
+            && self
+                .transport
+                .lock()
+                .unwrap()
+                .get_capabilities()
+                .support_can_fd()
+                != Capability::Yes
+        {
+            return Err(ComServerError::not_supported("CAN FD"));
+        }
+        let mut channel = self.isotp.lock().unwrap();
+        channel.can_fd = enabled;
+        channel.receiver =
+            Self::build_config(&channel, TxDlcMode::Always8).map(IsoTpMultiReceiver::new);
+        Ok(())
+    }
+
+    /// Builds the channel config. `tx_dlc` is used unless the channel has its DLC mode forced
+    fn build_config(channel: &IsoTpChannel, tx_dlc: TxDlcMode) -> Option<IsoTpConfig> {
+        let (block_size, st_min) = channel
+            .rx_flow_control
+            .unwrap_or((channel.block_size, channel.st_min));
+        channel.filter.map(|(f, _)| IsoTpConfig {
+            send_id: f.fc,
+            recv_id: f.id,
+            block_size,
+            st_min,
+            tx_dlc: channel.tx_dlc.unwrap_or(tx_dlc),
+            addr_ext: channel.addr_ext.filter(|_| channel.ext_addressing),
+            rx_tolerance: channel.rx_tolerance,
+            max_rx_buffers: channel.max_rx_buffers.unwrap_or(DEFAULT_MAX_RX_BUFFERS),
+            can_fd: channel.can_fd,
+        })
+    }
+
+    /// Sends a frame of the ISO-TP channel, counting it in the [FrameCounters]
+    fn send_isotp_frame(&self, frame: CanFrame) -> Result<usize, ComServerError> {
+        self.transmit(&[frame], 0)
+    }
+
+    /// Reads frames for the ISO-TP channel, counting them in the [FrameCounters]
+    fn read_isotp_frames(
+        &self,
+        timeout_ms: u32,
+        max_msgs: usize,
+    ) -> Result<Vec<CanFrame>, ComServerError> {
+        let frames = self
+            .transport
+            .lock()
+            .unwrap()
+            .read_frames(timeout_ms, max_msgs)?;
+        self.counter.add_rx(frames.len());
+        Ok(frames)
+    }
+
+    /// Feeds frames read from the transport into the ISO-TP receiver, sending
+    /// flow control frames where required
+    fn process_rx_frames(
+        &self,
+        channel: &mut IsoTpChannel,
+        frames: &[CanFrame],
+    ) -> Result<(), ComServerError> {
+        let filter = match channel.filter {
+            Some((f, _)) => f,
+            None => return Ok(()),
+        };
+        for frame in frames {
+            if frame.id & filter.mask != filter.id & filter.mask {
+                continue;
+            }
+            let receiver = match channel.receiver.as_mut() {
+                Some(r) => r,
+                None => return Ok(()),
+            };
+            match receiver.on_frame_at(frame, self.clock.now()) {
+                Ok(RxEvent::None) => {}
+                Ok(RxEvent::FlowControl(fc)) => {
+                    self.send_isotp_frame(fc)?;
+                }
+                Ok(RxEvent::Complete(mut data)) => {
+                    let ext_addressing = channel.ext_addressing && channel.addr_ext.is_some();
+                    if ext_addressing {
+                        // Hand the ECU's address byte back in front of the payload
+                        data.insert(0, frame.get_data()[0]);
+                    }
+                    channel.rx_queue.push(ISO15765Data {
+                        id: frame.id,
+                        data,
+                        pad_frame: false,
+                        ext_addressing,
+                    })
+                }
+                Err(e) => log::warn!("ISO-TP - Receive error from 0x{:04X}: {}", frame.id, e),
+            }
+        }
+        Ok(())
+    }
+
+    fn send_isotp_payload(
+        &self,
+        channel: &mut IsoTpChannel,
+        payload: &ISO15765Data,
+    ) -> Result<(), ComServerError> {
+        let mut data = payload.data.as_slice();
+        // A payload can opt out of the channel's extended addressing, so only the
+        // payload decides whether it starts with an address byte
+        if payload.ext_addressing {
+            let (addr, rest) = data.split_first().ok_or_else(|| ComServerError {
+                err_code: 99,
+                err_desc: "Extended addressing payload has no address byte".into(),
+            })?;
+            if channel.addr_ext != Some(*addr) {
+                channel.addr_ext = Some(*addr);
+                channel.receiver =
+                    Self::build_config(channel, TxDlcMode::Always8).map(IsoTpMultiReceiver::new);
+            }
+            data = rest;
+        }
+        let mut cfg = Self::build_config(channel, TxDlcMode::from_pad_frame(payload.pad_frame))
+            .ok_or_else(Self::channel_not_open)?;
+        cfg.addr_ext = channel.addr_ext.filter(|_| payload.ext_addressing);
+        let mut tx = IsoTpTransmitter::new(cfg, data)?;
+        self.send_isotp_frame(tx.first_frame())?;
+
+        let mut wait_count = 0;
+        loop {
+            let now = self.clock.now();
+            match tx.poll(now) {
+                TxPoll::Complete => return Ok(()),
+                TxPoll::Frame(cf) => {
+                    self.send_isotp_frame(cf)?;
+                }
+                TxPoll::WaitUntil(t) => self.clock.sleep(t - now),
+                TxPoll::AwaitFlowControl => {
+                    if tx.flow_control_timed_out(now) {
+                        return Err(ComServerError {
+                            err_code: 0x42,
+                            err_desc: "Timeout waiting for ISO-TP flow control".into(),
+                        });
+                    }
+                    let frames = self.read_isotp_frames(10, 1)?;
+                    // With extended addressing, the PCI follows the address byte
+                    let fc = frames.iter().find(|f| {
+                        f.id == cfg.recv_id
+                            && f.get_data().get(cfg.addr_len()).map(|b| b & 0xF0) == Some(0x30)
+                    });
+                    match fc {
+                        Some(fc) => match tx.on_flow_control(fc)? {
+                            FlowStatus::Wait => {
+                                wait_count += 1;
+                                if wait_count > MAX_FC_WAIT_FRAMES {
+                                    return Err(ComServerError {
+                                        err_code: 0x43,
+                                        err_desc: "ECU sent too many flow control wait frames"
+                                            .into(),
+                                    });
+                                }
+                            }
+                            FlowStatus::ContinueToSend => wait_count = 0,
+                            FlowStatus::Overflow => unreachable!(),
+                        },
+                        // Not flow control, let the receiver have it
+                        None => self.process_rx_frames(channel, &frames)?,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ComServer for TransportServer {
+    fn open_device(&mut self) -> Result<(), ComServerError> {
+        self.transport.lock().unwrap().open()
+    }
+
+    fn close_device(&mut self) -> Result<(), ComServerError> {
+        self.stop_scheduler();
+        self.transport.lock().unwrap().close()
+    }
+
+    fn send_can_packets(
+        &mut self,
+        data: &[CanFrame],
+        timeout_ms: u32,
+    ) -> Result<usize, ComServerError> {
+        self.transmit(data, timeout_ms)
+    }
+
+    fn send_can_packets_detailed(
+        &mut self,
+        data: &[CanFrame],
+        timeout_ms: u32,
+    ) -> Vec<Result<(), ComServerError>> {
+        let not_sent = |f: &CanFrame, res: Result<usize, ComServerError>| match res? {
+            0 => Err(ComServerError::frame_not_sent(f)),
+            _ => Ok(()),
+        };
+        if let Some(scheduler) = self.scheduler.lock().unwrap().as_ref() {
+            // Periodic messages which are due can win arbitration between the frames
+            return data
+                .iter()
+                .map(|f| not_sent(f, scheduler.send(*f)))
+                .collect();
+        }
+        // Hold the transport for the whole batch, so no other frames get sent in between
+        let mut transport = self.transport.lock().unwrap();
+        data.iter()
+            .map(|f| {
+                let res = transport.send_frames(std::slice::from_ref(f), timeout_ms);
+                self.counter.add_sent(1, &res);
+                not_sent(f, res)
+            })
+            .collect()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.transport.lock().unwrap().is_connected()
+    }
+
+    fn read_can_packets(
+        &self,
+        timeout_ms: u32,
+        max_msgs: usize,
+    ) -> Result<Vec<CanFrame>, ComServerError> {
+        let frames = self
+            .transport
+            .lock()
+            .unwrap()
+            .read_frames(timeout_ms, max_msgs)?;
+        self.counter.add_rx(frames.len());
+        // No transport timestamps frames, so these are always from the host clock
+        let stamper = self.stamper.read().unwrap();
+        Ok(frames.into_iter().map(|f| stamper.stamp(f, None)).collect())
+    }
+
+    fn send_iso15765_data(
+        &self,
+        data: &[ISO15765Data],
+        _timeout_ms: u32,
+    ) -> Result<usize, ComServerError> {
+        let mut channel = self.isotp.lock().unwrap();
+        if !channel.open {
+            return Err(Self::channel_not_open());
+        }
+        for (idx, payload) in data.iter().enumerate() {
+            if let Err(e) = self.send_isotp_payload(&mut channel, payload) {
+                if idx == 0 {
+                    return Err(e);
+                }
+                return Ok(idx);
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn read_iso15765_packets(
+        &self,
+        timeout_ms: u32,
+        max_msgs: usize,
+    ) -> Result<Vec<ISO15765Data>, ComServerError> {
+        let mut channel = self.isotp.lock().unwrap();
+        if !channel.open {
+            return Err(Self::channel_not_open());
+        }
+        let start = Instant::now();
+        loop {
+            if channel.rx_queue.len() >= max_msgs {
+                break;
+            }
+            let frames =
+                self.read_isotp_frames(std::cmp::min(timeout_ms, 10), max_msgs.max(1) * 16)?;
+            self.process_rx_frames(&mut channel, &frames)?;
+            let now = self.clock.now();
+            if let Some((_, e)) = channel
+                .receiver
+                .as_mut()
+                .and_then(|r| r.check_timeouts(now).into_iter().next())
+            {
+                return Err(e.into());
+            }
+            // Keep reading whilst a multi-frame message is still arriving
+            let in_progress = channel
+                .receiver
+                .as_ref()
+                .map(|r| r.in_progress())
+                .unwrap_or(false);
+            if start.elapsed().as_millis() >= timeout_ms as u128
+                && (!in_progress || frames.is_empty())
+            {
+                break;
+            }
+        }
+        let count = std::cmp::min(max_msgs, channel.rx_queue.len());
+        Ok(channel.rx_queue.drain(0..count).collect())
+    }
+
+    fn open_can_interface(
+        &mut self,
+        bus_speed: u32,
+        is_ext_can: bool,
+    ) -> Result<(), ComServerError> {
+        self.transport
+            .lock()
+            .unwrap()
+            .open_can(bus_speed, is_ext_can)?;
+        self.stamper.write().unwrap().restart();
+        self.counter.reset();
+        Ok(())
+    }
+
+    fn open_can_interface_raw(
+        &mut self,
+        btr0btr1: u16,
+        is_ext_can: bool,
+    ) -> Result<(), ComServerError> {
+        self.transport
+            .lock()
+            .unwrap()
+            .open_can_raw(btr0btr1, is_ext_can)?;
+        self.stamper.write().unwrap().restart();
+        self.counter.reset();
+        Ok(())
+    }
+
+    fn set_timestamp_source(&mut self, source: TimestampSource) -> Result<(), ComServerError> {
+        self.stamper.write().unwrap().source = source;
+        Ok(())
+    }
+
+    fn close_can_interface(&mut self) -> Result<(), ComServerError> {
+        self.stop_scheduler();
+        self.transport.lock().unwrap().close_can()
+    }
+
+    fn open_iso15765_interface(
+        &mut self,
+        bus_speed: u32,
+        is_ext_can: bool,
+        ext_addressing: bool,
+    ) -> Result<(), ComServerError> {
+        self.transport
+            .lock()
+            .unwrap()
+            .open_can(bus_speed, is_ext_can)?;
+        let mut channel = self.isotp.lock().unwrap();
+        *channel = IsoTpChannel::default();
+        channel.open = true;
+        channel.ext_addressing = ext_addressing;
+        Ok(())
+    }
+
+    fn close_iso15765_interface(&mut self) -> Result<(), ComServerError> {
+        let mut channel = self.isotp.lock().unwrap();
+        if !channel.open {
+            return Ok(());
+        }
+        *channel = IsoTpChannel::default();
+        self.transport.lock().unwrap().close_can()
+    }
+
+    fn add_can_filter(&mut self, f: FilterType) -> Result<u32, ComServerError> {
+        if let FilterType::IsoTP { .. } = f {
+            return Err(ComServerError {
+                err_code: 99,
+                err_desc: "Cannot apply a FlowControl filter to CAN".into(),
+            });
+        }
+        self.transport.lock().unwrap().add_filter(f)
+    }
+
+    fn rem_can_filter(&mut self, filter_idx: u32) -> Result<(), ComServerError> {
+        self.transport.lock().unwrap().rem_filter(filter_idx)
+    }
+
+    fn add_iso15765_filter(&mut self, f: FilterType) -> Result<u32, ComServerError> {
+        let mut channel = self.isotp.lock().unwrap();
+        if !channel.open {
+            return Err(Self::channel_not_open());
+        }
+        if channel.filter.is_some() {
+            return Err(ComServerError {
+                err_code: 1,
+                err_desc: "Only 1 ISO-TP filter is supported".into(),
+            });
+        }
+        if let FilterType::IsoTP { id, mask, fc } = f {
+            let can_filter = self
+                .transport
+                .lock()
+                .unwrap()
+                .add_filter(FilterType::Pass { id, mask })?;
+            channel.filter = Some((IsoTpFilter { id, mask, fc }, can_filter));
+            channel.receiver =
+                Self::build_config(&channel, TxDlcMode::Always8).map(IsoTpMultiReceiver::new);
+            Ok(1)
+        } else {
+            Err(ComServerError {
+                err_code: 99,
+                err_desc: "Cannot apply a pass/block filter to ISOTP".into(),
+            })
+        }
+    }
+
+    fn rem_iso15765_filter(&mut self, _filter_idx: u32) -> Result<(), ComServerError> {
+        let mut channel = self.isotp.lock().unwrap();
+        if let Some((_, can_filter)) = channel.filter.take() {
+            channel.receiver = None;
+            self.transport.lock().unwrap().rem_filter(can_filter)?;
+        }
+        Ok(())
+    }
+
+    fn set_iso15765_params(
+        &mut self,
+        separation_time_min: u32,
+        block_size: u32,
+    ) -> Result<(), ComServerError> {
+        let mut channel = self.isotp.lock().unwrap();
+        if !channel.open {
+            return Err(Self::channel_not_open());
+        }
+        channel.st_min = separation_time_min as u8;
+        channel.block_size = block_size as u8;
+        channel.receiver =
+            Self::build_config(&channel, TxDlcMode::Always8).map(IsoTpMultiReceiver::new);
+        Ok(())
+    }
+
+    fn set_rx_flow_control(&mut self, block_size: u8, st_min: u8) -> Result<(), ComServerError> {
+        let mut channel = self.isotp.lock().unwrap();
+        if !channel.open {
+            return Err(Self::channel_not_open());
+        }
+        channel.rx_flow_control = Some((block_size, st_min));
+        channel.receiver =
+            Self::build_config(&channel, TxDlcMode::Always8).map(IsoTpMultiReceiver::new);
+        Ok(())
+    }
+
+    fn clear_can_rx_buffer(&self) -> Result<(), ComServerError> {
+        self.transport.lock().unwrap().clear_rx_buffer()
+    }
+
+    fn clear_can_tx_buffer(&self) -> Result<(), ComServerError> {
+        self.transport.lock().unwrap().clear_tx_buffer()
+    }
+
+    fn clear_iso15765_rx_buffer(&self) -> Result<(), ComServerError> {
+        let mut channel = self.isotp.lock().unwrap();
+        channel.rx_queue.clear();
+        if let Some(r) = channel.receiver.as_mut() {
+            r.reset()
+        }
+        self.transport.lock().unwrap().clear_rx_buffer()
+    }
+
+    fn clear_iso15765_tx_buffer(&self) -> Result<(), ComServerError> {
+        self.transport.lock().unwrap().clear_tx_buffer()
+    }
+
+    fn read_battery_voltage(&self) -> Result<f32, ComServerError> {
+        self.transport.lock().unwrap().read_battery_voltage()
+    }
+
+    fn clone_box(&self) -> Box<dyn ComServer> {
+        Box::new(self.clone())
+    }
+
+    fn get_capabilities(&self) -> DeviceCapabilities {
+        let mut caps = self.transport.lock().unwrap().get_capabilities();
+        // ISO-TP is done in software, so its available if CAN is
+        caps.iso15765 = if caps.can == Capability::Yes {
+            Capability::Yes
+        } else {
+            Capability::No
+        };
+        caps
+    }
+
+    fn get_api(&self) -> &str {
+        self.transport.lock().unwrap().get_api()
+    }
+
+    fn get_bitrate(&self) -> Option<u32> {
+        self.transport.lock().unwrap().get_bitrate()
+    }
+
+    /// The transport cannot schedule frames itself, so periodic messages are sent
+    /// by a [TxScheduler], which is started with the first message
+    fn add_periodic_message(
+        &mut self,
+        frame: &CanFrame,
+        interval: Duration,
+    ) -> Result<u32, ComServerError> {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        if scheduler.is_none() {
+            let transport = self.transport.clone();
+            let counter = self.counter.clone();
+            *scheduler = Some(TxScheduler::start(self.clock.clone(), move |f| {
+                let res = transport
+                    .lock()
+                    .unwrap()
+                    .send_frames(std::slice::from_ref(f), 0);
+                counter.add_sent(1, &res);
+                res
+            }));
+        }
+        let res = scheduler.as_ref().unwrap().add_periodic(*frame, interval);
+        if res.is_err() && !scheduler.as_ref().unwrap().has_periodic() {
+            scheduler.take();
+        }
+        res
+    }
+
+    fn remove_periodic_message(&mut self, msg_id: u32) -> Result<(), ComServerError> {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        match scheduler.as_ref() {
+            Some(s) if s.remove_periodic(msg_id) => {
+                if !s.has_periodic() {
+                    // Other frames no longer need to go through the scheduler
+                    scheduler.take();
+                }
+                Ok(())
+            }
+            _ => Err(ComServerError {
+                err_code: 99,
+                err_desc: format!("No periodic message with ID {}", msg_id),
+            }),
+        }
+    }
+
+    fn frame_counters(&self) -> FrameCounters {
+        self.counter.get()
+    }
+
+    fn get_active_filters(&self) -> Vec<FilterType> {
+        let isotp_filter = self.isotp.lock().unwrap().filter.map(|(f, _)| f);
+        let mut filters = self.transport.lock().unwrap().get_active_filters();
+        if let Some(f) = isotp_filter {
+            // The ISO-TP filter is applied to the transport as a pass filter, show it as ISO-TP
+            filters.retain(|x| {
+                *x != FilterType::Pass {
+                    id: f.id,
+                    mask: f.mask,
+                }
+            });
+            filters.push(FilterType::IsoTP {
+                id: f.id,
+                mask: f.mask,
+                fc: f.fc,
+            });
+        }
+        filters
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::commapi::iso_tp::MockClock;
+
+    /// Transport with an ECU which answers every ISO-TP first frame with flow control
+    #[derive(Debug, Default)]
+    pub(crate) struct MockEcu {
+        pub(crate) addr_ext: Option<u8>,
+        pub(crate) sent: Arc<Mutex<Vec<CanFrame>>>,
+        pub(crate) rx: Vec<CanFrame>,
+    }
+
+    impl CanTransport for MockEcu {
+        fn open(&mut self) -> Result<(), ComServerError> {
+            Ok(())
+        }
+        fn close(&mut self) -> Result<(), ComServerError> {
+            Ok(())
+        }
+        fn open_can(&mut self, _bus_speed: u32, _is_ext_can: bool) -> Result<(), ComServerError> {
+            Ok(())
+        }
+        fn close_can(&mut self) -> Result<(), ComServerError> {
+            Ok(())
+        }
+        fn send_frames(
+            &mut self,
+            data: &[CanFrame],
+            _timeout_ms: u32,
+        ) -> Result<usize, ComServerError> {
+            for f in data {
+                let pci_idx = self.addr_ext.map_or(0, |_| 1);
+                if f.get_data().get(pci_idx).map(|b| b & 0xF0) == Some(0x10) {
+                    let mut fc = self.addr_ext.map(|a| vec![a]).unwrap_or_default();
+                    fc.extend_from_slice(&[0x30, 0x00, 0x00]);
+                    self.rx.push(CanFrame::new(0x7E8, &fc));
+                }
+                self.sent.lock().unwrap().push(*f);
+            }
+            Ok(data.len())
+        }
+        fn read_frames(
+            &mut self,
+            _timeout_ms: u32,
+            max_msgs: usize,
+        ) -> Result<Vec<CanFrame>, ComServerError> {
+            let n = max_msgs.min(self.rx.len());
+            Ok(self.rx.drain(..n).collect())
+        }
+        fn add_filter(&mut self, _f: FilterType) -> Result<u32, ComServerError> {
+            Ok(0)
+        }
+        fn rem_filter(&mut self, _filter_idx: u32) -> Result<(), ComServerError> {
+            Ok(())
+        }
+        fn clear_rx_buffer(&mut self) -> Result<(), ComServerError> {
+            Ok(())
+        }
+        fn clear_tx_buffer(&mut self) -> Result<(), ComServerError> {
+            Ok(())
+        }
+        fn is_connected(&self) -> bool {
+            true
+        }
+        fn get_capabilities(&self) -> DeviceCapabilities {
+            DeviceCapabilities {
+                name: "Mock".into(),
+                vendor: "N/A".into(),
+                library_path: "N/A".into(),
+                device_fw_version: "N/A".into(),
+                library_version: "N/A".into(),
+                j1850vpw: Capability::NA,
+                j1850pwm: Capability::NA,
+                can: Capability::Yes,
+                iso15765: Capability::NA,
+                iso9141: Capability::NA,
+                iso14230: Capability::NA,
+                ip: Capability::NA,
+                battery_voltage: Capability::NA,
+            }
+        }
+        fn get_api(&self) -> &'static str {
+            "Mock"
+        }
+    }
+
+    #[test]
+    fn test_ext_addressing_multi_frame_send() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut server = TransportServer::new(Box::new(MockEcu {
+            addr_ext: Some(0xF1),
+            sent: sent.clone(),
+            rx: Vec::new(),
+        }));
+        server
+            .open_iso15765_interface(500_000, false, true)
+            .unwrap();
+        server
+            .add_iso15765_filter(FilterType::IsoTP {
+                id: 0x7E8,
+                mask: 0xFFFF,
+                fc: 0x7E0,
+            })
+            .unwrap();
+        // Address byte, then a 12 byte payload which needs a first frame and 2 CFs
+        let mut data = vec![0x10];
+        data.extend(1..=12u8);
+        let payload = ISO15765Data {
+            id: 0x7E0,
+            data,
+            pad_frame: false,
+            ext_addressing: true,
+        };
+        assert_eq!(server.send_iso15765_data(&[payload], 0).unwrap(), 1);
+
+        // ISO-TP frames are counted like raw CAN frames
+        let counters = server.frame_counters();
+        assert_eq!((counters.tx, counters.rx), (3, 1));
+
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0].get_data(), [0x10, 0x10, 0x0C, 1, 2, 3, 4, 5]);
+        assert_eq!(sent[1].get_data()[..2], [0x10, 0x21]);
+        assert_eq!(sent[2].get_data()[..2], [0x10, 0x22]);
+    }
+
+    #[test]
+    fn test_ext_addressing_opt_out() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut server = TransportServer::new(Box::new(MockEcu {
+            addr_ext: None,
+            sent: sent.clone(),
+            rx: Vec::new(),
+        }));
+        server
+            .open_iso15765_interface(500_000, false, true)
+            .unwrap();
+        server
+            .add_iso15765_filter(FilterType::IsoTP {
+                id: 0x7E8,
+                mask: 0xFFFF,
+                fc: 0x7E0,
+            })
+            .unwrap();
+        // No address byte, the SID is the first byte of the payload
+        let mut data = vec![0x2E];
+        data.extend(1..=11u8);
+        let payload = ISO15765Data {
+            id: 0x7E0,
+            data,
+            pad_frame: false,
+            ext_addressing: false,
+        };
+        assert_eq!(server.send_iso15765_data(&[payload], 0).unwrap(), 1);
+
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].get_data(), [0x10, 0x0C, 0x2E, 1, 2, 3, 4, 5]);
+        assert_eq!(sent[1].get_data(), [0x21, 6, 7, 8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn test_software_periodic_messages() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let clock = Arc::new(MockClock::default());
+        let mut server = TransportServer::with_clock(
+            Box::new(MockEcu {
+                addr_ext: None,
+                sent: sent.clone(),
+                rx: Vec::new(),
+            }),
+            clock,
+        );
+        server.open_can_interface(500_000, false).unwrap();
+        let keep_alive = CanFrame::new(0x100, &[0x01]);
+        assert!(server
+            .add_periodic_message(&keep_alive, Duration::from_millis(0))
+            .is_err());
+        assert!(server.scheduler.lock().unwrap().is_none());
+
+        let id = server
+            .add_periodic_message(&keep_alive, Duration::from_millis(100))
+            .unwrap();
+        // Requests go through the scheduler, after the periodic message which is due now
+        assert_eq!(
+            server
+                .send_can_packets(&[CanFrame::new(0x7E0, &[0x3E, 0x00])], 0)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            sent.lock()
+                .unwrap()
+                .iter()
+                .map(|f| f.id)
+                .collect::<Vec<_>>(),
+            vec![0x100, 0x7E0]
+        );
+        assert_eq!(server.frame_counters().tx, 2);
+
+        server.remove_periodic_message(id).unwrap();
+        assert!(server.remove_periodic_message(id).is_err());
+        assert!(server.scheduler.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rx_flow_control_config() {
+        let mut channel = IsoTpChannel {
+            open: true,
+            block_size: 8,
+            st_min: 20,
+            filter: Some((
+                IsoTpFilter {
+                    id: 0x7E8,
+                    mask: 0xFFFF,
+                    fc: 0x7E0,
+                },
+                0,
+            )),
+            ..Default::default()
+        };
+        let cfg = TransportServer::build_config(&channel, TxDlcMode::Always8).unwrap();
+        assert_eq!((cfg.block_size, cfg.st_min), (8, 20));
+
+        // Rx flow control overrides the ISO15765 params, even if they are set after it
+        channel.rx_flow_control = Some((2, 5));
+        let cfg = TransportServer::build_config(&channel, TxDlcMode::Always8).unwrap();
+        assert_eq!((cfg.block_size, cfg.st_min), (2, 5));
+    }
+}
@@ -1,14 +1,44 @@
 use std::cmp::min;
 use std::fmt;
 use std::fmt::Debug;
-use std::time::Instant;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use std::{fmt::Formatter, result::Result};
 
-#[derive(Debug, Copy, Clone, Default)]
+/// Largest data length of a CAN FD frame
+pub const CAN_FD_MAX_LEN: usize = 64;
+
+/// Returns the smallest CAN FD data length which can hold `len` bytes. Above 8 bytes,
+/// CAN FD frames can only be 12, 16, 20, 24, 32, 48 or 64 bytes long
+pub fn can_fd_frame_len(len: usize) -> usize {
+    match len {
+        0..=8 => len,
+        9..=12 => 12,
+        13..=16 => 16,
+        17..=20 => 20,
+        21..=24 => 24,
+        25..=32 => 32,
+        33..=48 => 48,
+        _ => CAN_FD_MAX_LEN,
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct CanFrame {
     pub id: u32,
+    /// Data length in bytes. Above 8, this is a CAN FD frame
     pub dlc: u8,
-    data: [u8; 8],
+    data: [u8; CAN_FD_MAX_LEN],
+    /// When the frame was received, in microseconds. None for frames which were not
+    /// read from an adapter, or if the adapter does not timestamp frames.
+    /// See [TimestampSource] for which clock this is from
+    pub timestamp_us: Option<u64>,
+}
+
+impl Default for CanFrame {
+    fn default() -> Self {
+        Self::new(0, &[])
+    }
 }
 
 impl CanFrame {
@@ -16,37 +46,184 @@ impl CanFrame {
         &self.data[0..self.dlc as usize]
     }
     pub fn new(id: u32, data: &[u8]) -> Self {
-        let dlc = min(data.len(), 8) as usize;
-        let mut can_data: [u8; 8] = [0; 8];
+        Self::with_max_len(id, data, 8)
+    }
+
+    /// Creates a CAN FD frame, which can hold up to [CAN_FD_MAX_LEN] bytes. If the
+    /// data is not a valid CAN FD length, it is padded with 0x00 up to the next one.
+    /// See [can_fd_frame_len]
+    pub fn new_fd(id: u32, data: &[u8]) -> Self {
+        let mut res = Self::with_max_len(id, data, CAN_FD_MAX_LEN);
+        res.dlc = can_fd_frame_len(res.dlc as usize) as u8;
+        res
+    }
+
+    fn with_max_len(id: u32, data: &[u8], max_len: usize) -> Self {
+        let dlc = min(data.len(), max_len);
+        let mut can_data = [0; CAN_FD_MAX_LEN];
         can_data[0..dlc].copy_from_slice(&data[0..dlc]);
         Self {
             id,
             dlc: dlc as u8,
             data: can_data,
+            timestamp_us: None,
         }
     }
+
+    /// Returns true if this is a CAN FD frame (More than 8 bytes of data)
+    pub fn is_fd(&self) -> bool {
+        self.dlc > 8
+    }
 }
 
-#[cfg(target_os = "linux")]
-impl From<CanFrame> for socketcan::CANFrame {
-    fn from(s: CanFrame) -> Self {
-        Self::new(s.id, s.get_data(), false, false).unwrap()
+#[cfg(feature = "fuzz")]
+impl<'a> arbitrary::Arbitrary<'a> for CanFrame {
+    /// Reads the ID (4 bytes, big endian), the DLC (1 byte, modulo 16), then the data,
+    /// so that fuzz corpora can be written by hand from recorded traces.
+    /// DLCs 9-15 use the CAN FD lengths (12, 16, 20, 24, 32, 48 and 64 bytes)
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let id = u.bytes(4)?;
+        let id = u32::from_be_bytes([id[0], id[1], id[2], id[3]]);
+        let len = match u.bytes(1)?[0] % 16 {
+            dlc @ 0..=8 => dlc as usize,
+            dlc @ 9..=12 => 8 + (dlc as usize - 8) * 4,
+            13 => 32,
+            14 => 48,
+            _ => CAN_FD_MAX_LEN,
+        };
+        Ok(if len > 8 {
+            CanFrame::new_fd(id, u.bytes(len)?)
+        } else {
+            CanFrame::new(id, u.bytes(len)?)
+        })
     }
 }
 
-#[cfg(target_os = "linux")]
-impl From<socketcan::CANFrame> for CanFrame {
-    fn from(s: socketcan::CANFrame) -> Self {
-        let data = s.data();
-        let mut res = Self {
-            id: s.id(),
-            dlc: data.len() as u8,
-            data: [0, 0, 0, 0, 0, 0, 0, 0],
+/// Clock used for the timestamps of received frames.
+///
+/// The two clocks are separate domains, and timestamps from one cannot be compared with
+/// timestamps from the other:
+/// * Hardware timestamps come from the adapter's own clock. They are the most accurate
+///   time the frame was on the bus, but start from an arbitrary point and drift slowly
+///   against the host clock. J2534 adapters use a 32 bit microsecond counter, which wraps
+///   around roughly every 71 minutes.
+/// * Host timestamps are taken when the frame is read from the adapter, relative to
+///   when the CAN interface was opened. They share the host's clock with other logs,
+///   but include USB and driver latency, so frames read in one batch share a timestamp.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TimestampSource {
+    /// Use the adapter's timestamp, or the host clock if the adapter does not have one
+    #[default]
+    Hardware,
+    /// Always use the host clock
+    Host,
+}
+
+/// Timestamps received frames using the selected [TimestampSource]
+#[derive(Debug, Copy, Clone)]
+pub struct FrameStamper {
+    pub source: TimestampSource,
+    epoch: Instant,
+}
+
+impl Default for FrameStamper {
+    fn default() -> Self {
+        Self {
+            source: TimestampSource::default(),
+            epoch: Instant::now(),
+        }
+    }
+}
+
+impl FrameStamper {
+    /// Restarts host timestamps from 0. Called when the CAN interface is opened
+    pub fn restart(&mut self) {
+        self.epoch = Instant::now()
+    }
+
+    /// Timestamps a frame which has just been read. `hardware_us` is the adapter's
+    /// timestamp for the frame, if it has one
+    pub fn stamp(&self, mut frame: CanFrame, hardware_us: Option<u64>) -> CanFrame {
+        frame.timestamp_us = match (self.source, hardware_us) {
+            (TimestampSource::Hardware, Some(t)) => Some(t),
+            _ => Some(self.epoch.elapsed().as_micros() as u64),
         };
-        for x in 0..data.len() {
-            res.data[x] = data[x];
+        frame
+    }
+}
+
+/// Number of CAN frames an adapter has handled since its CAN interface was opened.
+/// See [ComServer::frame_counters]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct FrameCounters {
+    /// Frames received from the bus
+    pub rx: u64,
+    /// Frames sent to the bus
+    pub tx: u64,
+    /// Frames which the adapter failed to send, or received but could not be read
+    pub dropped: u64,
+}
+
+/// Thread-safe [FrameCounters], which adapters update as frames are sent and received.
+/// Reading the counters never waits for the adapter, so they can be polled whilst
+/// another thread is blocked reading from it
+#[derive(Debug, Default)]
+pub struct FrameCounter {
+    rx: AtomicU64,
+    tx: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl FrameCounter {
+    pub fn add_rx(&self, frames: usize) {
+        self.rx.fetch_add(frames as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_tx(&self, frames: usize) {
+        self.tx.fetch_add(frames as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_dropped(&self, frames: usize) {
+        self.dropped.fetch_add(frames as u64, Ordering::Relaxed);
+    }
+
+    /// Adds the result of sending `requested` frames
+    pub fn add_sent(&self, requested: usize, res: &Result<usize, ComServerError>) {
+        let sent = *res.as_ref().unwrap_or(&0);
+        self.add_tx(sent);
+        self.add_dropped(requested.saturating_sub(sent));
+    }
+
+    /// Sets every counter back to 0. Called when the CAN interface is opened
+    pub fn reset(&self) {
+        self.rx.store(0, Ordering::Relaxed);
+        self.tx.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> FrameCounters {
+        FrameCounters {
+            rx: self.rx.load(Ordering::Relaxed),
+            tx: self.tx.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
         }
-        res
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "socket-can"))]
+impl std::convert::TryFrom<CanFrame> for socketcan::CANFrame {
+    type Error = socketcan::ConstructionError;
+
+    /// Fails for CAN FD frames, which do not fit a classic SocketCAN frame
+    fn try_from(s: CanFrame) -> Result<Self, Self::Error> {
+        Self::new(s.id, s.get_data(), false, false)
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "socket-can"))]
+impl From<socketcan::CANFrame> for CanFrame {
+    fn from(s: socketcan::CANFrame) -> Self {
+        Self::new(s.id(), s.data())
     }
 }
 
@@ -65,6 +242,7 @@ impl std::fmt::Display for CanFrame {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
 pub struct ISO15765Data {
     pub(crate) id: u32,
     pub(crate) data: Vec<u8>,
@@ -111,12 +289,72 @@ pub struct ComServerError {
     pub err_desc: String,
 }
 
+impl ComServerError {
+    /// Error for a feature the adapter or its API does not support
+    pub(crate) fn not_supported(feature: &str) -> Self {
+        Self {
+            err_code: 98,
+            err_desc: format!("{} is not supported by this adapter", feature),
+        }
+    }
+
+    /// Error for a CAN Frame the adapter did not write to the bus
+    pub(crate) fn frame_not_sent(f: &CanFrame) -> Self {
+        Self {
+            err_code: 99,
+            err_desc: format!("CAN Frame 0x{:04X} was not written to the bus", f.id),
+        }
+    }
+}
+
 impl std::fmt::Display for ComServerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Error code {} ({})", self.err_code, self.err_desc)
     }
 }
 
+/// Returns the nominal bitrate (bps) of SJA1000 style bit timing registers, assuming
+/// the usual 16MHz oscillator. See [ComServer::open_can_interface_raw] for the format
+pub fn btr_bitrate(btr0btr1: u16) -> u32 {
+    let [btr0, btr1] = btr0btr1.to_be_bytes();
+    let brp = (btr0 & 0x3F) as u32 + 1;
+    let tseg1 = (btr1 & 0x0F) as u32 + 1;
+    let tseg2 = ((btr1 >> 4) & 0x07) as u32 + 1;
+    // CAN clock is half the oscillator frequency
+    8_000_000 / (brp * (1 + tseg1 + tseg2))
+}
+
+/// Standard bitrates and their bit timing registers (16MHz oscillator), as used by
+/// PCAN-Basic's `PCAN_BAUD_*` constants
+const STANDARD_BTRS: &[(u32, u16)] = &[
+    (1_000_000, 0x0014),
+    (800_000, 0x0016),
+    (500_000, 0x001C),
+    (250_000, 0x011C),
+    (125_000, 0x031C),
+    (100_000, 0x432F),
+    (50_000, 0x472F),
+    (20_000, 0x532F),
+    (10_000, 0x672F),
+    (5_000, 0x7F7F),
+];
+
+/// Returns the bit timing registers for a standard bitrate (bps), for use with
+/// [ComServer::open_can_interface_raw]. The reverse of [btr_bitrate]
+pub fn btr_for_bitrate(bitrate: u32) -> Result<u16, ComServerError> {
+    STANDARD_BTRS
+        .iter()
+        .find(|(b, _)| *b == bitrate)
+        .map(|(_, btr)| *btr)
+        .ok_or_else(|| ComServerError {
+            err_code: 98,
+            err_desc: format!(
+                "No standard bit timing for {} bps, use exact BTR0/BTR1 values instead",
+                bitrate
+            ),
+        })
+}
+
 #[derive(Debug, Copy, Clone, Eq, Ord, PartialOrd, PartialEq)]
 pub enum Capability {
     // The device supports the capability
@@ -231,6 +469,27 @@ pub trait ComServer: Send + Sync + Debug {
         timeout_ms: u32,
     ) -> Result<usize, ComServerError>;
 
+    /// Sends a list of CAN Frames, returning the result of each frame rather
+    /// than a single count, so it is visible which frames failed to send.
+    ///
+    /// Frames are sent in order, and a failed frame does not stop the remaining
+    /// frames from being sent. See [send_can_packets](fn@ComServer::send_can_packets)
+    /// for the meaning of `timeout_ms`
+    fn send_can_packets_detailed(
+        &mut self,
+        data: &[CanFrame],
+        timeout_ms: u32,
+    ) -> Vec<Result<(), ComServerError>> {
+        data.iter()
+            .map(|f| {
+                if self.send_can_packets(std::slice::from_ref(f), timeout_ms)? == 0 {
+                    return Err(ComServerError::frame_not_sent(f));
+                }
+                Ok(())
+            })
+            .collect()
+    }
+
     /// Returns a boolean indicating if there is at least 1 channel communicating with the car
     fn is_connected(&self) -> bool;
 
@@ -249,6 +508,50 @@ pub trait ComServer: Send + Sync + Debug {
         max_msgs: usize,
     ) -> Result<Vec<CanFrame>, ComServerError>;
 
+    /// Reads every [can frame](CanFrame) arriving within `window_ms` in one batch, returning
+    /// early once `max_msgs` frames have been read.
+    ///
+    /// On a busy bus this replaces many short [read_can_packets](fn@read_can_packets)
+    /// calls (Each one an FFI call and lock acquisition on most adapters) with as few
+    /// as one. The adapter is only asked again if it returns before the window is over.
+    fn read_can_packets_batched(
+        &self,
+        window_ms: u32,
+        max_msgs: usize,
+    ) -> Result<Vec<CanFrame>, ComServerError> {
+        read_batched(
+            |timeout, max| self.read_can_packets(timeout, max),
+            window_ms,
+            max_msgs,
+        )
+    }
+
+    /// Checks the wiring and adapter on a bench, by sending a test frame on `tx_id` and
+    /// waiting up to `timeout_ms` for it to come back on `rx_id`, from a second node (Or a
+    /// bridge) which resends every frame it receives. The CAN interface must be open with a
+    /// filter which passes `rx_id`.
+    ///
+    /// Frames on other IDs are ignored. `rx_id` should not be the same as `tx_id`, as
+    /// adapters which report their own transmitted frames would then pass without anything
+    /// being connected
+    fn loopback_test(
+        &mut self,
+        tx_id: u32,
+        rx_id: u32,
+        timeout_ms: u32,
+    ) -> Result<LoopbackResult, ComServerError> {
+        let frame = CanFrame::new(tx_id, &LOOPBACK_PATTERN);
+        self.clear_can_rx_buffer()?;
+        let sent_at = Instant::now();
+        self.send_can_packets(&[frame], timeout_ms)?;
+        wait_for_echo(
+            |timeout, max| self.read_can_packets(timeout, max),
+            rx_id,
+            sent_at,
+            Duration::from_millis(timeout_ms as u64),
+        )
+    }
+
     /// Sends a list of ISO-TP (ISO15765) payloads to a vehicles Canbus network
     ///
     /// NOTE: You must set the flow control filter (Response ID) and configure the block size
@@ -297,6 +600,30 @@ pub trait ComServer: Send + Sync + Debug {
         is_ext_can: bool,
     ) -> Result<(), ComServerError>;
 
+    /// Same as [open_can_interface](fn@ComServer::open_can_interface), but with exact bit
+    /// timing rather than a bus speed, for buses running at speeds the adapter does not have
+    /// a preset for (Such as 83.333 kbps). Only use this if you know the bus timing.
+    ///
+    /// ## BTR format
+    /// `btr0btr1` holds the SJA1000 bus timing registers, BTR0 in the high byte and BTR1 in
+    /// the low byte. This is the same format used by PCAN-Basic and the SLCAN `sxxyy` command.
+    /// * BTR0 bits 7-6 - Synchronization jump width - 1
+    /// * BTR0 bits 5-0 - Baud rate prescaler (BRP) - 1
+    /// * BTR1 bit 7 - Sampling (0 = sample once, 1 = sample 3 times)
+    /// * BTR1 bits 6-4 - Time segment 2 (TSEG2) - 1
+    /// * BTR1 bits 3-0 - Time segment 1 (TSEG1) - 1
+    ///
+    /// With a 16MHz oscillator, the bitrate is `8MHz / (BRP * (1 + TSEG1 + TSEG2))`.
+    /// For example `0x001C` is 500 kbps, `0x031C` is 125 kbps and `0x852B` is 83.333 kbps.
+    /// See [btr_bitrate]
+    fn open_can_interface_raw(
+        &mut self,
+        _btr0btr1: u16,
+        _is_ext_can: bool,
+    ) -> Result<(), ComServerError> {
+        Err(ComServerError::not_supported("Raw CAN bit timing"))
+    }
+
     /// Attempts to destroy the CAN Interface on the adapter
     fn close_can_interface(&mut self) -> Result<(), ComServerError>;
 
@@ -360,6 +687,22 @@ pub trait ComServer: Send + Sync + Debug {
         block_size: u32,
     ) -> Result<(), ComServerError>;
 
+    /// Sets the block size and STmin the adapter advertises in its flow control frames
+    /// whilst receiving multi-frame payloads from the ECU. These only apply to receiving.
+    /// When sending, the adapter always follows the ECU's flow control frames.
+    ///
+    /// A smaller block size makes the ECU wait for a flow control frame more often,
+    /// which is slower, but gives slow adapters a chance to empty their Rx buffers
+    /// so frames are not dropped. A block size of 0 (No more flow control frames) and
+    /// an STmin of 0 is the fastest, but needs an adapter which can keep up with
+    /// the ECU sending back to back frames.
+    ///
+    /// The default implementation sets both with [`set_iso15765_params`](fn@set_iso15765_params),
+    /// which on J2534 adapters are already the receive side flow control parameters
+    fn set_rx_flow_control(&mut self, block_size: u8, st_min: u8) -> Result<(), ComServerError> {
+        self.set_iso15765_params(st_min as u32, block_size as u32)
+    }
+
     /// Tells the adapter to clear any data in its Rx buffer
     /// that is from CAN protocol
     fn clear_can_rx_buffer(&self) -> Result<(), ComServerError>;
@@ -391,6 +734,215 @@ pub trait ComServer: Send + Sync + Debug {
 
     /// Returns a 1 word string indicating which hardware API the device uses
     fn get_api(&self) -> &str;
+
+    /// Selects which clock the timestamps of received CAN frames come from.
+    /// Defaults to [TimestampSource::Hardware]
+    fn set_timestamp_source(&mut self, _source: TimestampSource) -> Result<(), ComServerError> {
+        Err(ComServerError::not_supported("Frame timestamps"))
+    }
+
+    /// Asks the adapter to send a CAN Frame every `interval` on the open CAN channel.
+    /// Unlike a software timer, the adapter schedules the frame itself, so it keeps
+    /// its cadence however busy the host is.
+    ///
+    /// Adapters can only schedule a few messages at once (J2534 only guarantees 10),
+    /// and all are stopped when the CAN channel is closed. Adapters which cannot schedule
+    /// frames send them from a [TxScheduler](super::tx_scheduler::TxScheduler) instead.
+    ///
+    /// # Returns
+    /// * The ID of the message, for [ComServer::remove_periodic_message]
+    fn add_periodic_message(
+        &mut self,
+        _frame: &CanFrame,
+        _interval: Duration,
+    ) -> Result<u32, ComServerError> {
+        Err(ComServerError::not_supported("Hardware periodic messages"))
+    }
+
+    /// Stops sending a message started with [ComServer::add_periodic_message]
+    fn remove_periodic_message(&mut self, _msg_id: u32) -> Result<(), ComServerError> {
+        Err(ComServerError::not_supported("Hardware periodic messages"))
+    }
+
+    /// Returns the bitrate of the open CAN channel, if the adapter keeps track of it
+    fn get_bitrate(&self) -> Option<u32> {
+        None
+    }
+
+    /// Returns the transmit and receive error counters (TEC, REC) of the adapter's
+    /// CAN controller, or None if the adapter cannot report them.
+    ///
+    /// A counter reaching 128 puts the controller in the error passive state,
+    /// and a TEC above 255 takes it bus off. Counters above 255 are reported as 255
+    fn get_error_counters(&self) -> Option<(u8, u8)> {
+        None
+    }
+
+    /// Resets the CAN controller's error counters back to 0
+    fn reset_error_counters(&mut self) -> Result<(), ComServerError> {
+        Err(ComServerError::not_supported(
+            "Resetting CAN error counters",
+        ))
+    }
+
+    /// Returns how many CAN frames have been sent, received and dropped since the CAN
+    /// interface was opened. Unlike the adapter's other state, this is cheap enough
+    /// to poll (Such as once a second from the UI) whilst a trace is running.
+    /// Adapters which do not count frames always return 0s
+    fn frame_counters(&self) -> FrameCounters {
+        FrameCounters::default()
+    }
+
+    /// Returns the filters currently applied to the adapter, if it keeps track of them
+    fn get_active_filters(&self) -> Vec<FilterType> {
+        Vec::new()
+    }
+
+    /// Returns a printable, multi-line summary of the adapter and its current state,
+    /// for bug reports and the adapter info views. This only reads existing state,
+    /// nothing is sent to the adapter
+    fn description(&self) -> String {
+        let caps = self.get_capabilities();
+        let mut res = String::new();
+        res.push_str(&format!(
+            "Adapter:          {} ({})\n",
+            caps.name, caps.vendor
+        ));
+        res.push_str(&format!("API:              {}\n", self.get_api()));
+        res.push_str(&format!("Library:          {}\n", caps.library_path));
+        res.push_str(&format!("Library version:  {}\n", caps.library_version));
+        res.push_str(&format!("Firmware version: {}\n", caps.device_fw_version));
+        res.push_str(&format!(
+            "Connected:        {}\n",
+            if self.is_connected() { "Yes" } else { "No" }
+        ));
+        match self.get_bitrate() {
+            Some(b) => res.push_str(&format!("Bitrate:          {} bps\n", b)),
+            None => res.push_str("Bitrate:          Unknown\n"),
+        }
+        if let Some((tec, rec)) = self.get_error_counters() {
+            res.push_str(&format!("Error counters:   TEC {} REC {}\n", tec, rec));
+        }
+        let filters = self.get_active_filters();
+        if filters.is_empty() {
+            res.push_str("Filters:          None\n");
+        } else {
+            res.push_str("Filters:\n");
+            for f in filters {
+                let line = match f {
+                    FilterType::Pass { id, mask } => {
+                        format!("Pass   ID 0x{:04X} Mask 0x{:04X}", id, mask)
+                    }
+                    FilterType::Block { id, mask } => {
+                        format!("Block  ID 0x{:04X} Mask 0x{:04X}", id, mask)
+                    }
+                    FilterType::IsoTP { id, mask, fc } => {
+                        format!("ISO-TP ID 0x{:04X} Mask 0x{:04X} FC 0x{:04X}", id, mask, fc)
+                    }
+                };
+                res.push_str(&format!("  {}\n", line));
+            }
+        }
+        res.push_str("Capabilities:\n");
+        let cap_list = [
+            ("CAN", caps.can),
+            ("ISO15765", caps.iso15765),
+            ("ISO9141", caps.iso9141),
+            ("ISO14230", caps.iso14230),
+            ("J1850PWM", caps.j1850pwm),
+            ("J1850VPW", caps.j1850vpw),
+            ("DoIP", caps.ip),
+            ("Battery voltage", caps.battery_voltage),
+        ];
+        for (name, cap) in cap_list.iter() {
+            res.push_str(&format!("  {:<16}{:?}\n", name, cap));
+        }
+        res
+    }
+}
+
+/// Calls `read(timeout_ms, max_msgs)` until `window_ms` has passed or `max_msgs` frames
+/// have been read. See [ComServer::read_can_packets_batched]
+fn read_batched<F>(
+    mut read: F,
+    window_ms: u32,
+    max_msgs: usize,
+) -> Result<Vec<CanFrame>, ComServerError>
+where
+    F: FnMut(u32, usize) -> Result<Vec<CanFrame>, ComServerError>,
+{
+    let start = Instant::now();
+    let window = Duration::from_millis(window_ms as u64);
+    let mut res = Vec::new();
+    loop {
+        let remaining = window.checked_sub(start.elapsed()).unwrap_or_default();
+        res.extend(read(remaining.as_millis() as u32, max_msgs - res.len())?);
+        if res.len() >= max_msgs || remaining == Duration::from_millis(0) {
+            return Ok(res);
+        }
+    }
+}
+
+/// Data of the frame sent by [ComServer::loopback_test]. Alternating bits and all 0s / 1s
+/// catch bit timing problems as well as missing wiring
+pub const LOOPBACK_PATTERN: [u8; 8] = [0x55, 0xAA, 0x00, 0xFF, 0x4F, 0x56, 0x44, 0x01];
+
+/// Outcome of a [ComServer::loopback_test]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoopbackResult {
+    /// The test frame came back unchanged after `rtt`
+    Pass { rtt: Duration },
+    /// Nothing was received on the echo ID. The bus is not connected, or the other node
+    /// is not running
+    NoEcho,
+    /// A frame was received on the echo ID, but with different data. This points at bit
+    /// errors on the bus (Such as a baud rate mismatch) or the wrong node answering
+    WrongData { actual: Vec<u8> },
+}
+
+impl std::fmt::Display for LoopbackResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pass { rtt } => write!(f, "PASS - Round trip time {}us", rtt.as_micros()),
+            Self::NoEcho => write!(f, "FAIL - No echo received"),
+            Self::WrongData { actual } => write!(
+                f,
+                "FAIL - Wrong data echoed. Sent {:02X?}, received {:02X?}",
+                LOOPBACK_PATTERN, actual
+            ),
+        }
+    }
+}
+
+/// Reads frames with `read` until one arrives on `rx_id`, or `timeout` after `sent_at`
+fn wait_for_echo<F>(
+    mut read: F,
+    rx_id: u32,
+    sent_at: Instant,
+    timeout: Duration,
+) -> Result<LoopbackResult, ComServerError>
+where
+    F: FnMut(u32, usize) -> Result<Vec<CanFrame>, ComServerError>,
+{
+    loop {
+        let remaining = match timeout.checked_sub(sent_at.elapsed()) {
+            Some(r) => r,
+            None => return Ok(LoopbackResult::NoEcho),
+        };
+        if let Some(f) = read(remaining.as_millis() as u32, 1)?
+            .iter()
+            .find(|f| f.id == rx_id)
+        {
+            return Ok(match f.get_data() == LOOPBACK_PATTERN {
+                true => LoopbackResult::Pass {
+                    rtt: sent_at.elapsed(),
+                },
+                false => LoopbackResult::WrongData {
+                    actual: f.get_data().to_vec(),
+                },
+            });
+        }
+    }
 }
 
 impl Clone for Box<dyn ComServer> {
@@ -398,3 +950,147 @@ impl Clone for Box<dyn ComServer> {
         self.clone_box()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_btr_bitrate() {
+        assert_eq!(btr_bitrate(0x001C), 500_000);
+        assert_eq!(btr_bitrate(0x031C), 125_000);
+        assert_eq!(btr_bitrate(0x852B), 83_333);
+    }
+
+    #[test]
+    fn test_btr_for_bitrate() {
+        for (bitrate, btr) in [
+            (1_000_000, 0x0014),
+            (800_000, 0x0016),
+            (500_000, 0x001C),
+            (250_000, 0x011C),
+            (125_000, 0x031C),
+            (100_000, 0x432F),
+            (50_000, 0x472F),
+            (20_000, 0x532F),
+            (10_000, 0x672F),
+            (5_000, 0x7F7F),
+        ] {
+            assert_eq!(btr_for_bitrate(bitrate).unwrap(), btr, "{}", bitrate);
+            assert_eq!(btr_bitrate(btr), bitrate);
+        }
+        assert!(btr_for_bitrate(83_333).is_err());
+        // Not a BTR value passed as a bitrate by mistake
+        assert!(btr_for_bitrate(0x001C).is_err());
+    }
+
+    #[test]
+    fn test_frame_stamper() {
+        let frame = CanFrame::new(0x7E8, &[0x01]);
+        let mut stamper = FrameStamper::default();
+        assert_eq!(stamper.stamp(frame, Some(123)).timestamp_us, Some(123));
+        // No hardware timestamp, fall back to host
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(stamper.stamp(frame, None).timestamp_us.unwrap() >= 2000);
+
+        stamper.source = TimestampSource::Host;
+        stamper.restart();
+        let host_us = stamper
+            .stamp(frame, Some(123_456_789))
+            .timestamp_us
+            .unwrap();
+        assert!(host_us < 1_000_000);
+    }
+
+    #[test]
+    fn test_frame_counter() {
+        let counter = FrameCounter::default();
+        counter.add_rx(5);
+        counter.add_sent(3, &Ok(2));
+        counter.add_sent(4, &Err(ComServerError::not_supported("Sending")));
+        assert_eq!(
+            counter.get(),
+            FrameCounters {
+                rx: 5,
+                tx: 2,
+                dropped: 5
+            }
+        );
+        counter.reset();
+        assert_eq!(counter.get(), FrameCounters::default());
+    }
+
+    #[test]
+    fn test_read_batched() {
+        // Adapter which returns at most 4 frames per call, regardless of the timeout
+        let frames: Vec<CanFrame> = (0..20).map(|i| CanFrame::new(i, &[i as u8])).collect();
+        let mut queue = frames.clone();
+        let mut calls = 0;
+        let res = read_batched(
+            |_, max| {
+                calls += 1;
+                let count = min(max, min(4, queue.len()));
+                Ok(queue.drain(0..count).collect())
+            },
+            1000,
+            10,
+        )
+        .unwrap();
+        assert_eq!(res, frames[0..10]);
+        assert_eq!(calls, 3);
+
+        // An idle bus is read once the window is over, with a 0 timeout to drain the queue
+        let mut timeouts = Vec::new();
+        let res = read_batched(
+            |timeout, _| {
+                timeouts.push(timeout);
+                std::thread::sleep(Duration::from_millis(timeout as u64));
+                Ok(Vec::new())
+            },
+            20,
+            10,
+        )
+        .unwrap();
+        assert!(res.is_empty());
+        assert!(timeouts[0] > 0 && timeouts[0] <= 20);
+        assert_eq!(timeouts.last(), Some(&0));
+    }
+
+    #[test]
+    fn test_wait_for_echo() {
+        let timeout = Duration::from_millis(50);
+        let echo = |id: u32, data: &'static [u8]| {
+            let mut frames = vec![
+                vec![],
+                vec![CanFrame::new(0x100, &[0x01])],
+                vec![CanFrame::new(id, data)],
+            ]
+            .into_iter();
+            move |_, _| Ok(frames.next().unwrap_or_default())
+        };
+        let res = wait_for_echo(
+            echo(0x201, &LOOPBACK_PATTERN),
+            0x201,
+            Instant::now(),
+            timeout,
+        );
+        assert!(matches!(res, Ok(LoopbackResult::Pass { .. })));
+        assert_eq!(
+            wait_for_echo(echo(0x201, &[0x55, 0xAA]), 0x201, Instant::now(), timeout).unwrap(),
+            LoopbackResult::WrongData {
+                actual: vec![0x55, 0xAA]
+            }
+        );
+        // Only the frame on another ID, until the timeout
+        assert_eq!(
+            wait_for_echo(
+                echo(0x300, &LOOPBACK_PATTERN),
+                0x201,
+                Instant::now(),
+                timeout
+            )
+            .unwrap(),
+            LoopbackResult::NoEcho
+        );
+    }
+}
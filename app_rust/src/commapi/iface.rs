@@ -20,6 +20,12 @@ pub enum IFACE_CFG {
     PAD_FLOW_CONTROL,
     ISOTP_BS,
     ISOTP_ST_MIN,
+    /// Address extension byte for ISO-TP extended / mixed addressing. When set,
+    /// it is added in front of every payload sent, and removed from every payload received
+    ISOTP_ADDR_EXT,
+    /// Set to 1 to pad the last CAN frame of every ISO-TP message to 8 bytes. See
+    /// [IsoTpFrameOptions]
+    ISOTP_PAD_FRAME,
 }
 
 impl ToString for IFACE_CFG {
@@ -32,7 +38,12 @@ impl ToString for IFACE_CFG {
 #[allow(non_camel_case_types)]
 pub enum PayloadFlag {
     ISOTP_PAD_FRAME,
+    /// Do not pad the message, even if the interface is configured to
+    ISOTP_NO_PAD_FRAME,
     ISOTP_EXT_ADDR,
+    /// Send the message with normal addressing, even if the interface is configured
+    /// for extended addressing
+    ISOTP_NO_EXT_ADDR,
 }
 
 #[derive(Debug, Clone)]
@@ -90,6 +101,52 @@ impl InterfaceConfig {
     }
 }
 
+/// How ISO-TP messages are framed for a whole session, so padding and addressing
+/// only have to be configured once rather than on every message.
+///
+/// Flags on a message take priority over the session's options:
+/// * [PayloadFlag::ISOTP_PAD_FRAME] / [PayloadFlag::ISOTP_NO_PAD_FRAME] pad or don't pad
+///   that message.
+/// * [PayloadFlag::ISOTP_EXT_ADDR] marks a message which already starts with its
+///   address byte, on an interface which is not configured for extended addressing
+/// * [PayloadFlag::ISOTP_NO_EXT_ADDR] sends that message with normal addressing
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct IsoTpFrameOptions {
+    pub pad_frame: bool,
+    pub ext_addressing: bool,
+}
+
+impl IsoTpFrameOptions {
+    /// Reads [IFACE_CFG::ISOTP_PAD_FRAME], and [IFACE_CFG::EXT_ISOTP_ADDR] or
+    /// [IFACE_CFG::ISOTP_ADDR_EXT] for extended addressing. Missing parameters are off
+    pub fn from_config(cfg: &InterfaceConfig) -> Self {
+        Self {
+            pad_frame: cfg.get_param_or_default(IFACE_CFG::ISOTP_PAD_FRAME, 0) > 0,
+            ext_addressing: cfg.get_param_or_default(IFACE_CFG::EXT_ISOTP_ADDR, 0) > 0
+                || cfg.get_param(IFACE_CFG::ISOTP_ADDR_EXT).is_ok(),
+        }
+    }
+
+    /// Returns the `pad_frame` and `ext_addressing` fields to send a payload with
+    pub fn for_payload(&self, p: &InterfacePayload) -> (bool, bool) {
+        let pad_frame = if p.is_flag_set(PayloadFlag::ISOTP_PAD_FRAME) {
+            true
+        } else if p.is_flag_set(PayloadFlag::ISOTP_NO_PAD_FRAME) {
+            false
+        } else {
+            self.pad_frame
+        };
+        let ext_addressing = if p.is_flag_set(PayloadFlag::ISOTP_EXT_ADDR) {
+            true
+        } else if p.is_flag_set(PayloadFlag::ISOTP_NO_EXT_ADDR) {
+            false
+        } else {
+            self.ext_addressing
+        };
+        (pad_frame, ext_addressing)
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 #[allow(dead_code)]
 pub enum BufferType {
@@ -217,6 +274,8 @@ impl Interface for CanbusInterface {
 #[derive(Debug, Clone)]
 pub struct IsoTPInterface {
     dev: Box<dyn ComServer>,
+    addr_ext: Option<u8>,
+    opts: IsoTpFrameOptions,
 }
 
 impl IsoTPInterface {
@@ -229,6 +288,8 @@ impl IsoTPInterface {
         } else {
             Ok(Box::new(IsoTPInterface {
                 dev: dev.clone_box(),
+                addr_ext: None,
+                opts: IsoTpFrameOptions::default(),
             }))
         }
     }
@@ -247,10 +308,15 @@ impl Interface for IsoTPInterface {
     }
 
     fn setup(&mut self, cfg: &InterfaceConfig) -> InterfaceResult<()> {
+        self.addr_ext = cfg
+            .get_param(IFACE_CFG::ISOTP_ADDR_EXT)
+            .ok()
+            .map(|x| x as u8);
+        self.opts = IsoTpFrameOptions::from_config(cfg);
         self.dev.open_iso15765_interface(
             cfg.get_param(IFACE_CFG::BAUDRATE)?,
             cfg.get_param_or_default(IFACE_CFG::EXT_CAN_ADDR, 0) > 0,
-            cfg.get_param_or_default(IFACE_CFG::EXT_ISOTP_ADDR, 0) > 0,
+            self.opts.ext_addressing,
         )?;
         // Use default if not specified
         self.dev.set_iso15765_params(
@@ -262,22 +328,38 @@ impl Interface for IsoTPInterface {
     fn send_data(&mut self, data: &[InterfacePayload], timeout: u32) -> InterfaceResult<usize> {
         let isotp_data: Vec<ISO15765Data> = data
             .iter()
-            .map(|t| ISO15765Data {
-                id: t.id,
-                data: t.data.clone(),
-                pad_frame: t.is_flag_set(PayloadFlag::ISOTP_PAD_FRAME),
-                ext_addressing: t.is_flag_set(PayloadFlag::ISOTP_EXT_ADDR),
+            .map(|t| {
+                let (pad_frame, ext_addressing) = self.opts.for_payload(t);
+                let data = match self.addr_ext {
+                    // Payloads flagged as extended already start with their address byte
+                    Some(ae) if ext_addressing && !t.is_flag_set(PayloadFlag::ISOTP_EXT_ADDR) => {
+                        let mut data = vec![ae];
+                        data.extend_from_slice(&t.data);
+                        data
+                    }
+                    _ => t.data.clone(),
+                };
+                ISO15765Data {
+                    id: t.id,
+                    data,
+                    pad_frame,
+                    ext_addressing,
+                }
             })
             .collect();
         self.dev.send_iso15765_data(&isotp_data, timeout)
     }
 
     fn recv_data(&mut self, max: usize, timeout: u32) -> InterfaceResult<Vec<InterfacePayload>> {
+        let strip_addr = self.addr_ext.is_some();
         self.dev.read_iso15765_packets(timeout, max).map(|v| {
             v.iter()
                 .map(|f| InterfacePayload {
                     id: f.id,
-                    data: f.data.clone(),
+                    data: match f.data.split_first() {
+                        Some((_, rest)) if strip_addr && f.ext_addressing => rest.to_vec(),
+                        _ => f.data.clone(),
+                    },
                     flags: vec![],
                 })
                 .collect()
@@ -303,6 +385,8 @@ impl Interface for IsoTPInterface {
     fn clone_box(&self) -> Box<dyn Interface> {
         Box::new(Self {
             dev: self.dev.clone(),
+            addr_ext: self.addr_ext,
+            opts: self.opts,
         })
     }
 }
@@ -547,3 +631,47 @@ impl Interface for DynamicInterface {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(flags: &[PayloadFlag]) -> InterfacePayload {
+        let mut p = InterfacePayload::new(0x7E0, &[0x3E, 0x00]);
+        p.flags = flags.to_vec();
+        p
+    }
+
+    #[test]
+    fn test_isotp_frame_options() {
+        let mut cfg = InterfaceConfig::new();
+        assert_eq!(
+            IsoTpFrameOptions::from_config(&cfg),
+            IsoTpFrameOptions::default()
+        );
+        cfg.add_param(IFACE_CFG::ISOTP_PAD_FRAME, 1);
+        cfg.add_param(IFACE_CFG::ISOTP_ADDR_EXT, 0xF1);
+        let opts = IsoTpFrameOptions::from_config(&cfg);
+        assert!(opts.pad_frame && opts.ext_addressing);
+
+        // Messages without flags use the config
+        assert_eq!(opts.for_payload(&payload(&[])), (true, true));
+        // Flags on a message take priority
+        assert_eq!(
+            opts.for_payload(&payload(&[PayloadFlag::ISOTP_NO_PAD_FRAME])),
+            (false, true)
+        );
+        assert_eq!(
+            opts.for_payload(&payload(&[PayloadFlag::ISOTP_NO_EXT_ADDR])),
+            (true, false)
+        );
+        let opts = IsoTpFrameOptions::default();
+        assert_eq!(
+            opts.for_payload(&payload(&[
+                PayloadFlag::ISOTP_PAD_FRAME,
+                PayloadFlag::ISOTP_EXT_ADDR
+            ])),
+            (true, true)
+        );
+    }
+}
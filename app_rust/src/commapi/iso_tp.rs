@@ -0,0 +1,1341 @@
+//! Software implementation of ISO15765-2 (ISO-TP).
+//!
+//! This is used for adapters which can only send and receive raw CAN Frames,
+//! allowing the diagnostic protocols to run on top of them. The state machines here
+//! never touch the adapter themselves - they only consume and produce [CanFrame]s.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::comm_api::{can_fd_frame_len, CanFrame, ComServerError};
+
+/// Maximum payload size of a classic ISO-TP message
+pub const MAX_PAYLOAD_SIZE: usize = 4095;
+/// Byte used to pad frames when padding is requested
+pub const PAD_BYTE: u8 = 0x00;
+
+/// Time to wait for a flow control frame from the ECU (N_Bs)
+pub const N_BS_TIMEOUT: Duration = Duration::from_millis(1000);
+/// Time to wait for the next consecutive frame from the ECU (N_Cr)
+pub const N_CR_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Default for [IsoTpConfig::max_rx_buffers]
+pub const DEFAULT_MAX_RX_BUFFERS: usize = 16;
+
+/// Largest payload that fits in a classic single frame. Anything longer must be
+/// sent as a first frame, so a first frame declaring this length or less is malformed
+pub const SF_MAX_LEN: usize = 7;
+
+/// Largest payload that fits in a CAN FD single frame, which has the PCI byte followed
+/// by the length (SF_DL) in a second byte
+pub const FD_SF_MAX_LEN: usize = 62;
+
+const PCI_SINGLE_FRAME: u8 = 0x00;
+const PCI_FIRST_FRAME: u8 = 0x10;
+const PCI_CONSECUTIVE_FRAME: u8 = 0x20;
+const PCI_FLOW_CONTROL: u8 = 0x30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IsoTpError {
+    /// Payload is too large to be sent via ISO-TP
+    PayloadTooLarge(usize),
+    /// Consecutive frame arrived with the wrong sequence number
+    WrongSequence { expected: u8, actual: u8 },
+    /// Consecutive frame arrived without a first frame
+    UnexpectedConsecutiveFrame,
+    /// The ECU reported an overflow in its flow control frame
+    Overflow,
+    /// Frame PCI could not be decoded
+    InvalidFrame,
+    /// First frame declared a length that should have been sent as a single frame
+    InvalidFirstFrameLength(usize),
+    /// The ECU stopped sending consecutive frames part way through a payload.
+    /// `partial` holds the data received before the timeout
+    Timeout {
+        expected_len: usize,
+        partial: Vec<u8>,
+    },
+}
+
+impl std::fmt::Display for IsoTpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IsoTpError::PayloadTooLarge(s) => {
+                write!(f, "Payload too large for ISO-TP ({} bytes)", s)
+            }
+            IsoTpError::WrongSequence { expected, actual } => write!(
+                f,
+                "Consecutive frame out of sequence. Expected {:01X}, got {:01X}",
+                expected, actual
+            ),
+            IsoTpError::UnexpectedConsecutiveFrame => {
+                write!(f, "Consecutive frame received without a first frame")
+            }
+            IsoTpError::Overflow => write!(f, "ECU reported a flow control overflow"),
+            IsoTpError::InvalidFrame => write!(f, "Invalid ISO-TP frame"),
+            IsoTpError::InvalidFirstFrameLength(len) => write!(
+                f,
+                "First frame declared {} bytes, which must be sent as a single frame",
+                len
+            ),
+            IsoTpError::Timeout {
+                expected_len,
+                partial,
+            } => write!(
+                f,
+                "Timeout waiting for consecutive frame after {}/{} bytes. Received {:02X?}",
+                partial.len(),
+                expected_len,
+                partial
+            ),
+        }
+    }
+}
+
+impl From<IsoTpError> for ComServerError {
+    fn from(e: IsoTpError) -> Self {
+        ComServerError {
+            err_code: 0x40,
+            err_desc: e.to_string(),
+        }
+    }
+}
+
+/// Source of time for the ISO-TP timing logic (STmin, timeouts).
+///
+/// [SystemClock] is used normally. [MockClock] lets tests advance time
+/// deterministically rather than sleeping
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    /// Monotonic time since an arbitrary fixed point
+    fn now(&self) -> Duration;
+    /// Waits for the duration to pass
+    fn sleep(&self, d: Duration);
+    /// Busy-waits until [Clock::now] reaches `t`, for waits too short to trust an OS sleep
+    fn spin_until(&self, t: Duration) {
+        while self.now() < t {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// Real time clock
+#[derive(Debug, Copy, Clone)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, d: Duration) {
+        std::thread::sleep(d)
+    }
+}
+
+/// Virtual clock which only moves when told to. Sleeping advances it instantly
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: Mutex<Duration>,
+}
+
+impl MockClock {
+    pub fn advance(&self, d: Duration) {
+        *self.now.lock().unwrap() += d
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, d: Duration) {
+        self.advance(d)
+    }
+
+    fn spin_until(&self, t: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now = (*now).max(t);
+    }
+}
+
+/// Flow status sent by the receiver in a flow control frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FlowStatus {
+    ContinueToSend,
+    Wait,
+    Overflow,
+}
+
+/// DLC of transmitted ISO-TP frames.
+///
+/// ISO15765-2 requires classic CAN frames to have a DLC of 8, with unused bytes padded,
+/// and ISO15765-4 (OBD-II over CAN) mandates it. Many CAN gateways silently drop
+/// ISO-TP frames shorter than 8 bytes, so [TxDlcMode::Always8] is the safe choice.
+/// [TxDlcMode::Minimal] (CAN frame data optimisation) should only be used with ECUs
+/// which are known to accept short frames, or which reject padded ones
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TxDlcMode {
+    /// Every frame is sent with DLC 8, padded with [PAD_BYTE]
+    Always8,
+    /// Frames are sent with the smallest DLC that fits their data
+    Minimal,
+}
+
+impl TxDlcMode {
+    /// Converts the `pad_frame` flag of an ISO-TP payload to a DLC mode
+    pub fn from_pad_frame(pad_frame: bool) -> Self {
+        if pad_frame {
+            TxDlcMode::Always8
+        } else {
+            TxDlcMode::Minimal
+        }
+    }
+}
+
+/// Configuration of one ISO-TP channel
+#[derive(Debug, Copy, Clone)]
+pub struct IsoTpConfig {
+    /// CAN ID we transmit on (Requests and our flow control frames)
+    pub send_id: u32,
+    /// CAN ID the ECU responds on
+    pub recv_id: u32,
+    /// Block size to advertise in our flow control frames
+    pub block_size: u8,
+    /// Separation time to advertise in our flow control frames
+    pub st_min: u8,
+    /// DLC of every transmitted frame
+    pub tx_dlc: TxDlcMode,
+    /// Address extension byte for extended or mixed addressing (N_TA / N_AE).
+    /// When set, it is sent before the PCI of every transmitted frame, and the first
+    /// byte of every received frame is treated as an address rather than the PCI
+    pub addr_ext: Option<u8>,
+    /// Makes the receiver tolerate a flaky bus. None for strict ISO15765-2 behaviour
+    pub rx_tolerance: Option<RxTolerance>,
+    /// Maximum number of payloads an [IsoTpMultiReceiver] reassembles at once (One per
+    /// source ID). Stops a flood of first frames from spoofed IDs exhausting memory
+    pub max_rx_buffers: usize,
+    /// Payloads which are too long for a classic single frame, but fit in a CAN FD
+    /// single frame, are sent as one CAN FD frame instead of a first frame and consecutive
+    /// frames. Longer payloads still use classic frames. CAN FD single frames are always
+    /// accepted by the receiver
+    pub can_fd: bool,
+}
+
+/// Best-effort robustness for receiving on noisy buses.
+///
+/// ISO-TP has no way of asking the ECU to retransmit a frame, so a consecutive frame which
+/// is truly lost still aborts the payload. This only helps when frames arrive late, out of
+/// order (Some adapters reorder frames between their receive buffers) or more than once.
+/// Each anomaly is logged. Off by default, as it is not standard behaviour
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RxTolerance {
+    /// Time to wait for the next consecutive frame, replacing [N_CR_TIMEOUT]
+    pub cf_timeout: Duration,
+    /// How far (In sequence numbers) a consecutive frame may be ahead of or behind the
+    /// expected one. Frames ahead are held until the missing frames arrive, frames behind
+    /// are treated as duplicates and dropped. Values above 7 are treated as 7, since
+    /// sequence numbers wrap at 16
+    pub seq_window: u8,
+}
+
+impl IsoTpConfig {
+    /// Number of bytes at the start of each frame taken by the address extension
+    pub(crate) fn addr_len(&self) -> usize {
+        self.addr_ext.map_or(0, |_| 1)
+    }
+
+    /// Largest payload which fits in a single frame with this addressing
+    pub fn sf_max_len(&self) -> usize {
+        SF_MAX_LEN - self.addr_len()
+    }
+
+    /// Largest payload which is sent as a single frame, including CAN FD single frames
+    fn tx_sf_max_len(&self) -> usize {
+        if self.can_fd {
+            FD_SF_MAX_LEN - self.addr_len()
+        } else {
+            self.sf_max_len()
+        }
+    }
+}
+
+/// Converts an STmin byte into a duration, as per ISO15765-2.
+/// Reserved values are treated as the maximum of 127ms
+pub fn decode_st_min(st_min: u8) -> Duration {
+    match st_min {
+        0x00..=0x7F => Duration::from_millis(st_min as u64),
+        0xF1..=0xF9 => Duration::from_micros((st_min - 0xF0) as u64 * 100),
+        _ => Duration::from_millis(0x7F),
+    }
+}
+
+fn make_frame(cfg: &IsoTpConfig, data: &[u8]) -> CanFrame {
+    let mut buf = Vec::with_capacity(8);
+    if let Some(ae) = cfg.addr_ext {
+        buf.push(ae);
+    }
+    buf.extend_from_slice(data);
+    if buf.len() > 8 {
+        // CAN FD frames can only have certain lengths, so these are always padded
+        buf.resize(can_fd_frame_len(buf.len()), PAD_BYTE);
+        return CanFrame::new_fd(cfg.send_id, &buf);
+    }
+    if cfg.tx_dlc == TxDlcMode::Always8 {
+        buf.resize(std::cmp::max(buf.len(), 8), PAD_BYTE);
+    }
+    CanFrame::new(cfg.send_id, &buf)
+}
+
+/// Builds a flow control frame for a channel
+pub fn flow_control_frame(cfg: &IsoTpConfig, status: FlowStatus) -> CanFrame {
+    let fs = match status {
+        FlowStatus::ContinueToSend => 0x00,
+        FlowStatus::Wait => 0x01,
+        FlowStatus::Overflow => 0x02,
+    };
+    make_frame(cfg, &[PCI_FLOW_CONTROL | fs, cfg.block_size, cfg.st_min])
+}
+
+/// Parses a flow control frame, returning the flow status, block size and STmin
+pub fn parse_flow_control(frame: &CanFrame) -> Option<(FlowStatus, u8, u8)> {
+    parse_flow_control_data(frame.get_data())
+}
+
+/// Parses the data of a flow control frame, starting at its PCI
+fn parse_flow_control_data(data: &[u8]) -> Option<(FlowStatus, u8, u8)> {
+    if data.len() < 3 || data[0] & 0xF0 != PCI_FLOW_CONTROL {
+        return None;
+    }
+    let status = match data[0] & 0x0F {
+        0x00 => FlowStatus::ContinueToSend,
+        0x01 => FlowStatus::Wait,
+        0x02 => FlowStatus::Overflow,
+        _ => return None,
+    };
+    Some((status, data[1], data[2]))
+}
+
+/// Result of feeding a frame into an [IsoTpReceiver]
+#[derive(Debug, Clone, PartialEq)]
+pub enum RxEvent {
+    /// Frame consumed, nothing to do
+    None,
+    /// A flow control frame must be sent to the ECU
+    FlowControl(CanFrame),
+    /// A complete payload was received
+    Complete(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+struct RxState {
+    expected_len: usize,
+    data: Vec<u8>,
+    next_seq: u8,
+    block_count: u8,
+    /// When the last frame of this payload arrived, if fed via [IsoTpReceiver::on_frame_at]
+    last_rx: Option<Duration>,
+    /// Consecutive frames (Sequence number, data) which arrived ahead of the expected one
+    early: Vec<(u8, Vec<u8>)>,
+}
+
+impl RxState {
+    fn push_cf(&mut self, data: &[u8]) {
+        let take = std::cmp::min(
+            self.expected_len.saturating_sub(self.data.len()),
+            data.len(),
+        );
+        self.data.extend_from_slice(&data[..take]);
+        self.next_seq = (self.next_seq + 1) & 0x0F;
+    }
+}
+
+/// Reassembles incoming CAN Frames into ISO-TP payloads
+#[derive(Debug, Clone)]
+pub struct IsoTpReceiver {
+    cfg: IsoTpConfig,
+    state: Option<RxState>,
+    /// Never asks for flow control frames, see [IsoTpReceiver::new_passive]
+    passive: bool,
+}
+
+impl IsoTpReceiver {
+    pub fn new(cfg: IsoTpConfig) -> Self {
+        Self {
+            cfg,
+            state: None,
+            passive: false,
+        }
+    }
+
+    /// Creates a receiver for sniffing a session between two other nodes, which
+    /// reassembles payloads without ever returning [RxEvent::FlowControl]. The real
+    /// receiver of the payload sends the flow control frames, so the block size and
+    /// STmin of `cfg` are ignored, and consecutive frames are simply followed in the
+    /// order they are seen.
+    ///
+    /// Nothing can be asked to be sent again, so a frame which is missed (Such as
+    /// one dropped by the adapter on a busy bus) aborts the payload with
+    /// [IsoTpError::WrongSequence]. Only [RxTolerance] can help with frames which
+    /// arrive late or out of order
+    pub fn new_passive(cfg: IsoTpConfig) -> Self {
+        Self {
+            passive: true,
+            ..Self::new(cfg)
+        }
+    }
+
+    /// Returns true if a multi-frame payload is currently being received
+    pub fn in_progress(&self) -> bool {
+        self.state.is_some()
+    }
+
+    /// Aborts any payload currently being received
+    pub fn reset(&mut self) {
+        self.state = None
+    }
+
+    /// Processes an incoming frame from the ECU
+    pub fn on_frame(&mut self, frame: &CanFrame) -> Result<RxEvent, IsoTpError> {
+        let data = frame.get_data();
+        if data.len() <= self.cfg.addr_len() {
+            return Err(IsoTpError::InvalidFrame);
+        }
+        let data = &data[self.cfg.addr_len()..];
+        match data[0] & 0xF0 {
+            PCI_SINGLE_FRAME => {
+                let (len, start) = match data[0] & 0x0F {
+                    // CAN FD single frame, with the length in the next byte. Payloads
+                    // which fit a classic single frame must not use the escape
+                    0 if frame.is_fd() => match data.get(1) {
+                        Some(&len) if len as usize > self.cfg.sf_max_len() => (len as usize, 2),
+                        _ => return Err(IsoTpError::InvalidFrame),
+                    },
+                    len => (len as usize, 1),
+                };
+                if len == 0 || len > data.len() - start {
+                    return Err(IsoTpError::InvalidFrame);
+                }
+                // A new single frame aborts any reception in progress
+                self.state = None;
+                Ok(RxEvent::Complete(Vec::from(&data[start..start + len])))
+            }
+            PCI_FIRST_FRAME => {
+                if data.len() < 2 {
+                    return Err(IsoTpError::InvalidFrame);
+                }
+                let len = (((data[0] & 0x0F) as usize) << 8) | data[1] as usize;
+                // A CAN FD first frame carries more data than a classic single frame, so
+                // also check the length against what this first frame holds
+                if len <= self.cfg.sf_max_len() || len <= data.len() - 2 {
+                    // Reassembling this would complete on the first frame with
+                    // garbage from the padding, so drop it instead
+                    log::warn!(
+                        "ISO-TP - Ignoring malformed first frame from 0x{:04X} with length {}",
+                        frame.id,
+                        len
+                    );
+                    self.state = None;
+                    return Err(IsoTpError::InvalidFirstFrameLength(len));
+                }
+                let mut buf = Vec::with_capacity(len);
+                buf.extend_from_slice(&data[2..]);
+                self.state = Some(RxState {
+                    expected_len: len,
+                    data: buf,
+                    next_seq: 1,
+                    block_count: 0,
+                    last_rx: None,
+                    early: Vec::new(),
+                });
+                if self.passive {
+                    return Ok(RxEvent::None);
+                }
+                Ok(RxEvent::FlowControl(flow_control_frame(
+                    &self.cfg,
+                    FlowStatus::ContinueToSend,
+                )))
+            }
+            PCI_CONSECUTIVE_FRAME => {
+                let state = match self.state.as_mut() {
+                    Some(s) => s,
+                    None => return Err(IsoTpError::UnexpectedConsecutiveFrame),
+                };
+                let seq = data[0] & 0x0F;
+                if seq != state.next_seq {
+                    let expected = state.next_seq;
+                    let window = self.cfg.rx_tolerance.map_or(0, |t| t.seq_window.min(7));
+                    if seq.wrapping_sub(expected) & 0x0F <= window {
+                        log::warn!(
+                            "ISO-TP - Consecutive frame {:01X} from 0x{:04X} arrived early, expected {:01X}",
+                            seq,
+                            frame.id,
+                            expected
+                        );
+                        state.early.push((seq, Vec::from(&data[1..])));
+                        return Ok(self.end_of_frame());
+                    }
+                    if expected.wrapping_sub(seq) & 0x0F <= window {
+                        log::warn!(
+                            "ISO-TP - Dropping repeated consecutive frame {:01X} from 0x{:04X}",
+                            seq,
+                            frame.id
+                        );
+                        return Ok(RxEvent::None);
+                    }
+                    self.state = None;
+                    return Err(IsoTpError::WrongSequence {
+                        expected,
+                        actual: seq,
+                    });
+                }
+                state.push_cf(&data[1..]);
+                // Frames which arrived early may now be next in line
+                while let Some(pos) = state.early.iter().position(|(s, _)| *s == state.next_seq) {
+                    let (_, early) = state.early.remove(pos);
+                    state.push_cf(&early);
+                }
+                if state.data.len() >= state.expected_len {
+                    let res = self.state.take().unwrap().data;
+                    return Ok(RxEvent::Complete(res));
+                }
+                Ok(self.end_of_frame())
+            }
+            // Flow control frames are for the transmitter, not us
+            PCI_FLOW_CONTROL => Ok(RxEvent::None),
+            _ => Err(IsoTpError::InvalidFrame),
+        }
+    }
+
+    /// Counts a consecutive frame towards the current block, returning the flow control
+    /// frame to send if the block is complete
+    fn end_of_frame(&mut self) -> RxEvent {
+        let state = match self.state.as_mut() {
+            Some(s) if !self.passive => s,
+            _ => return RxEvent::None,
+        };
+        state.block_count = state.block_count.wrapping_add(1);
+        if self.cfg.block_size != 0 && state.block_count == self.cfg.block_size {
+            state.block_count = 0;
+            return RxEvent::FlowControl(flow_control_frame(&self.cfg, FlowStatus::ContinueToSend));
+        }
+        RxEvent::None
+    }
+
+    /// Processes an incoming frame which arrived at `now`. Unlike [IsoTpReceiver::on_frame],
+    /// payloads received this way time out in [IsoTpReceiver::check_timeout]
+    pub fn on_frame_at(&mut self, frame: &CanFrame, now: Duration) -> Result<RxEvent, IsoTpError> {
+        let res = self.on_frame(frame);
+        if let Some(state) = self.state.as_mut() {
+            state.last_rx = Some(now);
+        }
+        res
+    }
+
+    /// Aborts the payload being received if the ECU has not sent a consecutive frame
+    /// within [N_CR_TIMEOUT] (Or the timeout of [RxTolerance]). The error holds whatever
+    /// data was received before the timeout
+    pub fn check_timeout(&mut self, now: Duration) -> Result<(), IsoTpError> {
+        let cf_timeout = self.cfg.rx_tolerance.map_or(N_CR_TIMEOUT, |t| t.cf_timeout);
+        let timed_out = match &self.state {
+            Some(RxState {
+                last_rx: Some(t), ..
+            }) => now.saturating_sub(*t) > cf_timeout,
+            _ => false,
+        };
+        if !timed_out {
+            return Ok(());
+        }
+        let state = self.state.take().unwrap();
+        Err(IsoTpError::Timeout {
+            expected_len: state.expected_len,
+            partial: state.data,
+        })
+    }
+}
+
+/// Reassembles ISO-TP payloads from several ECUs at once.
+///
+/// With functional addressing, multiple ECUs respond to the same request and their
+/// consecutive frames interleave on the bus. Each source CAN ID therefore gets its own
+/// [IsoTpReceiver]. Flow control is sent to each ECU at the same offset from its
+/// response ID as the configured `send_id` is from `recv_id`
+/// (For example 0x7E8 -> 0x7E0, 0x7E9 -> 0x7E1)
+///
+/// Only payloads which are part way through being received hold a receiver. Once there
+/// are more than [IsoTpConfig::max_rx_buffers] of them, the oldest is dropped
+#[derive(Debug, Clone)]
+pub struct IsoTpMultiReceiver {
+    cfg: IsoTpConfig,
+    receivers: HashMap<u32, IsoTpReceiver>,
+    /// Source IDs in `receivers`, oldest first
+    order: VecDeque<u32>,
+}
+
+impl IsoTpMultiReceiver {
+    pub fn new(cfg: IsoTpConfig) -> Self {
+        Self {
+            cfg,
+            receivers: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Returns true if a multi-frame payload is being received from any ECU
+    pub fn in_progress(&self) -> bool {
+        self.receivers.values().any(|r| r.in_progress())
+    }
+
+    /// Aborts all payloads currently being received
+    pub fn reset(&mut self) {
+        self.receivers.clear();
+        self.order.clear();
+    }
+
+    /// Returns the receiver for a source ID, creating it if this is the first frame from it
+    fn receiver_for(&mut self, id: u32) -> &mut IsoTpReceiver {
+        let cfg = self.cfg;
+        let order = &mut self.order;
+        self.receivers.entry(id).or_insert_with(|| {
+            order.push_back(id);
+            IsoTpReceiver::new(IsoTpConfig {
+                send_id: cfg.send_id.wrapping_add(id).wrapping_sub(cfg.recv_id),
+                recv_id: id,
+                ..cfg
+            })
+        })
+    }
+
+    fn remove(&mut self, id: u32) {
+        self.receivers.remove(&id);
+        self.order.retain(|x| *x != id);
+    }
+
+    /// Drops the receiver of a source ID if it has nothing in progress, then
+    /// evicts the oldest payloads until there are no more than `max_rx_buffers`
+    fn tidy(&mut self, id: u32) {
+        if !matches!(self.receivers.get(&id), Some(r) if r.in_progress()) {
+            self.remove(id);
+        }
+        let max = std::cmp::max(self.cfg.max_rx_buffers, 1);
+        while self.receivers.len() > max {
+            let oldest = match self.order.pop_front() {
+                Some(id) => id,
+                None => break,
+            };
+            log::warn!(
+                "ISO-TP - Too many payloads being received at once, dropping the one from 0x{:04X}",
+                oldest
+            );
+            self.receivers.remove(&oldest);
+        }
+    }
+
+    /// Processes an incoming frame, using the reassembly buffer of the frame's source ID
+    pub fn on_frame(&mut self, frame: &CanFrame) -> Result<RxEvent, IsoTpError> {
+        let res = self.receiver_for(frame.id).on_frame(frame);
+        self.tidy(frame.id);
+        res
+    }
+
+    /// Processes an incoming frame which arrived at `now`, see [IsoTpReceiver::on_frame_at]
+    pub fn on_frame_at(&mut self, frame: &CanFrame, now: Duration) -> Result<RxEvent, IsoTpError> {
+        let res = self.receiver_for(frame.id).on_frame_at(frame, now);
+        self.tidy(frame.id);
+        res
+    }
+
+    /// Checks every ECU's payload for a timeout, see [IsoTpReceiver::check_timeout].
+    /// Returns the source ID and error of each payload that timed out
+    pub fn check_timeouts(&mut self, now: Duration) -> Vec<(u32, IsoTpError)> {
+        let res: Vec<(u32, IsoTpError)> = self
+            .receivers
+            .iter_mut()
+            .filter_map(|(id, r)| r.check_timeout(now).err().map(|e| (*id, e)))
+            .collect();
+        for (id, _) in &res {
+            self.remove(*id);
+        }
+        res
+    }
+}
+
+/// What the transmitter needs to do next, see [IsoTpTransmitter::poll]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TxPoll {
+    /// Send this frame now
+    Frame(CanFrame),
+    /// STmin has not passed yet, nothing can be sent until this time
+    WaitUntil(Duration),
+    /// A flow control frame from the ECU is required
+    AwaitFlowControl,
+    /// The whole payload has been sent
+    Complete,
+}
+
+/// Segments an ISO-TP payload into CAN Frames
+#[derive(Debug, Clone)]
+pub struct IsoTpTransmitter {
+    cfg: IsoTpConfig,
+    data: Vec<u8>,
+    offset: usize,
+    seq: u8,
+    block_size: u8,
+    sent_in_block: u8,
+    st_min: Duration,
+    awaiting_fc: bool,
+    fc_wait_start: Option<Duration>,
+    last_cf_time: Option<Duration>,
+}
+
+impl IsoTpTransmitter {
+    pub fn new(cfg: IsoTpConfig, data: &[u8]) -> Result<Self, IsoTpError> {
+        if data.len() > MAX_PAYLOAD_SIZE {
+            return Err(IsoTpError::PayloadTooLarge(data.len()));
+        }
+        Ok(Self {
+            cfg,
+            data: Vec::from(data),
+            offset: 0,
+            seq: 1,
+            block_size: 0,
+            sent_in_block: 0,
+            st_min: Duration::from_millis(0),
+            awaiting_fc: false,
+            fc_wait_start: None,
+            last_cf_time: None,
+        })
+    }
+
+    /// Returns the first frame to send. This is either a single frame (In which case
+    /// the transmission is already complete), or a first frame, after which the ECU's
+    /// flow control frame must be passed to [on_flow_control](fn@on_flow_control)
+    pub fn first_frame(&mut self) -> CanFrame {
+        if self.data.len() <= self.cfg.sf_max_len() {
+            let mut buf = vec![PCI_SINGLE_FRAME | self.data.len() as u8];
+            buf.extend_from_slice(&self.data);
+            self.offset = self.data.len();
+            make_frame(&self.cfg, &buf)
+        } else if self.data.len() <= self.cfg.tx_sf_max_len() {
+            let mut buf = vec![PCI_SINGLE_FRAME, self.data.len() as u8];
+            buf.extend_from_slice(&self.data);
+            self.offset = self.data.len();
+            make_frame(&self.cfg, &buf)
+        } else {
+            let len = self.data.len();
+            let mut buf = vec![
+                PCI_FIRST_FRAME | ((len >> 8) & 0x0F) as u8,
+                (len & 0xFF) as u8,
+            ];
+            let ff_len = 6 - self.cfg.addr_len();
+            buf.extend_from_slice(&self.data[0..ff_len]);
+            self.offset = ff_len;
+            self.awaiting_fc = true;
+            make_frame(&self.cfg, &buf)
+        }
+    }
+
+    /// Returns true once every byte of the payload has been placed into a frame
+    pub fn is_complete(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    /// Processes a flow control frame from the ECU
+    pub fn on_flow_control(&mut self, frame: &CanFrame) -> Result<FlowStatus, IsoTpError> {
+        let (status, bs, st_min) = frame
+            .get_data()
+            .get(self.cfg.addr_len()..)
+            .and_then(parse_flow_control_data)
+            .ok_or(IsoTpError::InvalidFrame)?;
+        // Any flow control frame (Including wait) restarts the N_Bs timer
+        self.fc_wait_start = None;
+        match status {
+            FlowStatus::ContinueToSend => {
+                self.block_size = bs;
+                self.sent_in_block = 0;
+                self.st_min = decode_st_min(st_min);
+                self.awaiting_fc = false;
+                self.last_cf_time = None;
+            }
+            FlowStatus::Overflow => return Err(IsoTpError::Overflow),
+            FlowStatus::Wait => {}
+        }
+        Ok(status)
+    }
+
+    /// Minimum time to wait between consecutive frames, as requested by the ECU
+    pub fn separation_time(&self) -> Duration {
+        self.st_min
+    }
+
+    /// Returns the next consecutive frame to send, or None if either the payload
+    /// is complete, or the current block is exhausted and another flow control frame
+    /// is required
+    pub fn next_consecutive_frame(&mut self) -> Option<CanFrame> {
+        if self.is_complete() {
+            return None;
+        }
+        if self.awaiting_fc {
+            return None;
+        }
+        let end = std::cmp::min(self.offset + 7 - self.cfg.addr_len(), self.data.len());
+        let mut buf = vec![PCI_CONSECUTIVE_FRAME | self.seq];
+        buf.extend_from_slice(&self.data[self.offset..end]);
+        self.offset = end;
+        self.seq = (self.seq + 1) & 0x0F;
+        // Only compared with the block size, so it may wrap when there is no block limit
+        self.sent_in_block = self.sent_in_block.wrapping_add(1);
+        if self.block_size != 0 && self.sent_in_block == self.block_size && !self.is_complete() {
+            self.awaiting_fc = true;
+        }
+        Some(make_frame(&self.cfg, &buf))
+    }
+
+    /// Returns what should be done next at time `now` (From a [Clock]), respecting
+    /// the ECU's STmin between consecutive frames
+    pub fn poll(&mut self, now: Duration) -> TxPoll {
+        if self.is_complete() {
+            return TxPoll::Complete;
+        }
+        if self.awaiting_fc {
+            self.fc_wait_start.get_or_insert(now);
+            return TxPoll::AwaitFlowControl;
+        }
+        if let Some(last) = self.last_cf_time {
+            if now < last + self.st_min {
+                return TxPoll::WaitUntil(last + self.st_min);
+            }
+        }
+        match self.next_consecutive_frame() {
+            Some(f) => {
+                self.last_cf_time = Some(now);
+                TxPoll::Frame(f)
+            }
+            None => TxPoll::Complete,
+        }
+    }
+
+    /// Returns true if the ECU has not sent a flow control frame within [N_BS_TIMEOUT]
+    /// of [IsoTpTransmitter::poll] first returning [TxPoll::AwaitFlowControl]
+    pub fn flow_control_timed_out(&self, now: Duration) -> bool {
+        matches!(self.fc_wait_start, Some(start) if now.saturating_sub(start) > N_BS_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> IsoTpConfig {
+        IsoTpConfig {
+            send_id: 0x7E0,
+            recv_id: 0x7E8,
+            block_size: 0,
+            st_min: 0,
+            tx_dlc: TxDlcMode::Minimal,
+            addr_ext: None,
+            rx_tolerance: None,
+            max_rx_buffers: DEFAULT_MAX_RX_BUFFERS,
+            can_fd: false,
+        }
+    }
+
+    #[test]
+    fn test_single_frame() {
+        let mut tx = IsoTpTransmitter::new(cfg(), &[0x22, 0xF1, 0x90]).unwrap();
+        let f = tx.first_frame();
+        assert_eq!(f.get_data(), &[0x03, 0x22, 0xF1, 0x90]);
+        assert!(tx.is_complete());
+
+        let mut rx = IsoTpReceiver::new(cfg());
+        assert_eq!(
+            rx.on_frame(&f),
+            Ok(RxEvent::Complete(vec![0x22, 0xF1, 0x90]))
+        );
+    }
+
+    #[test]
+    fn test_multi_frame_round_trip() {
+        // The largest payload is more than 255 consecutive frames
+        for len in [20, MAX_PAYLOAD_SIZE] {
+            let payload: Vec<u8> = (0..len).map(|x| x as u8).collect();
+            let mut tx = IsoTpTransmitter::new(cfg(), &payload).unwrap();
+            let mut rx = IsoTpReceiver::new(cfg());
+
+            let fc = match rx.on_frame(&tx.first_frame()).unwrap() {
+                RxEvent::FlowControl(fc) => fc,
+                x => panic!("Expected flow control, got {:?}", x),
+            };
+            assert_eq!(tx.on_flow_control(&fc), Ok(FlowStatus::ContinueToSend));
+
+            let mut res = None;
+            while let Some(cf) = tx.next_consecutive_frame() {
+                if let RxEvent::Complete(d) = rx.on_frame(&cf).unwrap() {
+                    res = Some(d)
+                }
+            }
+            assert_eq!(res, Some(payload));
+        }
+    }
+
+    #[test]
+    fn test_flow_control_round_trip() {
+        // The ECU asks for blocks of 4 frames, 10ms apart
+        let mut ecu_cfg = cfg();
+        ecu_cfg.block_size = 4;
+        ecu_cfg.st_min = 10;
+        let payload: Vec<u8> = (0..64).collect();
+        let mut tx = IsoTpTransmitter::new(cfg(), &payload).unwrap();
+        let mut rx = IsoTpReceiver::new(ecu_cfg);
+
+        let mut event = rx.on_frame(&tx.first_frame()).unwrap();
+        let (mut flow_controls, mut frames) = (0, 0);
+        let res = loop {
+            match event {
+                RxEvent::FlowControl(fc) => {
+                    flow_controls += 1;
+                    tx.on_flow_control(&fc).unwrap();
+                    assert_eq!(tx.separation_time(), Duration::from_millis(10));
+                }
+                RxEvent::Complete(d) => break d,
+                RxEvent::None => {}
+            }
+            let cf = tx.next_consecutive_frame().expect("Transmitter stalled");
+            frames += 1;
+            event = rx.on_frame(&cf).unwrap();
+        };
+        assert_eq!(res, payload);
+        // 6 bytes in the first frame, then 9 consecutive frames in blocks of 4
+        assert_eq!(frames, 9);
+        assert_eq!(flow_controls, 3);
+    }
+
+    #[test]
+    fn test_passive_receiver() {
+        // Sniffing a transfer where the ECU asks for blocks of 4 frames. The passive
+        // receiver asks for nothing, it only sees the ECU's flow control frames
+        let mut ecu_cfg = cfg();
+        ecu_cfg.block_size = 4;
+        let payload: Vec<u8> = (0..64).collect();
+        let mut tx = IsoTpTransmitter::new(cfg(), &payload).unwrap();
+        let mut ecu = IsoTpReceiver::new(ecu_cfg);
+        let mut sniffer = IsoTpReceiver::new_passive(ecu_cfg);
+
+        let ff = tx.first_frame();
+        let mut event = ecu.on_frame(&ff).unwrap();
+        assert_eq!(sniffer.on_frame(&ff), Ok(RxEvent::None));
+        let res = loop {
+            if let RxEvent::FlowControl(fc) = event {
+                tx.on_flow_control(&fc).unwrap();
+            }
+            let cf = tx.next_consecutive_frame().expect("Transmitter stalled");
+            event = ecu.on_frame(&cf).unwrap();
+            match sniffer.on_frame(&cf).unwrap() {
+                RxEvent::Complete(d) => break d,
+                RxEvent::FlowControl(_) => panic!("Passive receiver asked for flow control"),
+                RxEvent::None => {}
+            }
+        };
+        assert_eq!(res, payload);
+
+        // A missed consecutive frame cannot be recovered
+        sniffer
+            .on_frame(&CanFrame::new(0x7E8, &[0x10, 0x14, 1, 2, 3, 4, 5, 6]))
+            .unwrap();
+        assert!(sniffer
+            .on_frame(&CanFrame::new(0x7E8, &[0x22, 1, 2, 3, 4, 5, 6, 7]))
+            .is_err());
+        assert!(!sniffer.in_progress());
+    }
+
+    #[test]
+    fn test_block_size() {
+        let mut c = cfg();
+        c.block_size = 1;
+        let payload: Vec<u8> = (0..20).collect();
+        let mut tx = IsoTpTransmitter::new(cfg(), &payload).unwrap();
+        tx.first_frame();
+        tx.on_flow_control(&flow_control_frame(&c, FlowStatus::ContinueToSend))
+            .unwrap();
+        assert!(tx.next_consecutive_frame().is_some());
+        assert!(tx.next_consecutive_frame().is_none()); // Need another FC
+        tx.on_flow_control(&flow_control_frame(&c, FlowStatus::ContinueToSend))
+            .unwrap();
+        assert!(tx.next_consecutive_frame().is_some());
+        assert!(tx.is_complete());
+    }
+
+    #[test]
+    fn test_first_frame_min_length() {
+        let mut rx = IsoTpReceiver::new(cfg());
+        assert_eq!(
+            rx.on_frame(&CanFrame::new(0x7E8, &[0x10, 0x07, 1, 2, 3, 4, 5, 6])),
+            Err(IsoTpError::InvalidFirstFrameLength(7))
+        );
+        assert!(!rx.in_progress());
+        assert!(matches!(
+            rx.on_frame(&CanFrame::new(0x7E8, &[0x10, 0x08, 1, 2, 3, 4, 5, 6])),
+            Ok(RxEvent::FlowControl(_))
+        ));
+
+        // 7 bytes is the longest payload sent as a single frame, 8 the shortest first frame
+        let pci = |len: usize| {
+            let mut tx = IsoTpTransmitter::new(cfg(), &vec![0xAA; len]).unwrap();
+            tx.first_frame().get_data()[0] & 0xF0
+        };
+        assert_eq!(pci(SF_MAX_LEN), PCI_SINGLE_FRAME);
+        assert_eq!(pci(SF_MAX_LEN + 1), PCI_FIRST_FRAME);
+    }
+
+    #[test]
+    fn test_fd_first_frame_length() {
+        // 62 bytes of data in the first frame, but only 10 declared
+        let mut ff = vec![0x10, 0x0A];
+        ff.extend_from_slice(&[0xAA; 62]);
+        let mut rx = IsoTpReceiver::new(cfg());
+        assert_eq!(
+            rx.on_frame(&CanFrame::new_fd(0x7E8, &ff)),
+            Err(IsoTpError::InvalidFirstFrameLength(10))
+        );
+        assert!(!rx.in_progress());
+        assert_eq!(
+            rx.on_frame(&CanFrame::new_fd(0x7E8, &[0x21; 64])),
+            Err(IsoTpError::UnexpectedConsecutiveFrame)
+        );
+
+        // One byte more than the first frame holds needs a consecutive frame
+        ff[1] = 63;
+        assert!(matches!(
+            rx.on_frame(&CanFrame::new_fd(0x7E8, &ff)),
+            Ok(RxEvent::FlowControl(_))
+        ));
+        assert_eq!(
+            rx.on_frame(&CanFrame::new_fd(0x7E8, &[0x21, 0xBB, 0xCC])),
+            Ok(RxEvent::Complete([vec![0xAA; 62], vec![0xBB]].concat()))
+        );
+    }
+
+    #[test]
+    fn test_rx_timeout_partial() {
+        let ms = Duration::from_millis;
+        let mut rx = IsoTpReceiver::new(cfg());
+        rx.on_frame_at(
+            &CanFrame::new(0x7E8, &[0x10, 0x14, 1, 2, 3, 4, 5, 6]),
+            ms(0),
+        )
+        .unwrap();
+        rx.on_frame_at(
+            &CanFrame::new(0x7E8, &[0x21, 7, 8, 9, 10, 11, 12, 13]),
+            ms(5),
+        )
+        .unwrap();
+        assert_eq!(rx.check_timeout(ms(5) + N_CR_TIMEOUT), Ok(()));
+        assert_eq!(
+            rx.check_timeout(ms(6) + N_CR_TIMEOUT),
+            Err(IsoTpError::Timeout {
+                expected_len: 20,
+                partial: (1..=13).collect(),
+            })
+        );
+        assert!(!rx.in_progress());
+
+        // Payloads from each ECU time out separately
+        let mut multi = IsoTpMultiReceiver::new(cfg());
+        multi
+            .on_frame_at(
+                &CanFrame::new(0x7E8, &[0x10, 0x14, 1, 2, 3, 4, 5, 6]),
+                ms(0),
+            )
+            .unwrap();
+        multi
+            .on_frame_at(
+                &CanFrame::new(0x7E9, &[0x10, 0x14, 1, 2, 3, 4, 5, 6]),
+                ms(500),
+            )
+            .unwrap();
+        let timeouts = multi.check_timeouts(ms(1200));
+        assert_eq!(timeouts.len(), 1);
+        assert_eq!(timeouts[0].0, 0x7E8);
+        assert!(multi.in_progress());
+    }
+
+    #[test]
+    fn test_wrong_sequence() {
+        let mut rx = IsoTpReceiver::new(cfg());
+        rx.on_frame(&CanFrame::new(0x7E8, &[0x10, 0x0A, 1, 2, 3, 4, 5, 6]))
+            .unwrap();
+        assert_eq!(
+            rx.on_frame(&CanFrame::new(0x7E8, &[0x22, 7, 8, 9, 10])),
+            Err(IsoTpError::WrongSequence {
+                expected: 1,
+                actual: 2
+            })
+        );
+        assert!(!rx.in_progress());
+    }
+
+    #[test]
+    fn test_rx_tolerance() {
+        let mut c = cfg();
+        c.rx_tolerance = Some(RxTolerance {
+            cf_timeout: Duration::from_secs(3),
+            seq_window: 2,
+        });
+        let ff = CanFrame::new(0x7E8, &[0x10, 0x1E, 1, 2, 3, 4, 5, 6]);
+        let cf = |seq: u8, start: u8| {
+            let mut data = vec![0x20 | seq];
+            data.extend(start..start + 7);
+            CanFrame::new(0x7E8, &data)
+        };
+
+        // Strict receivers abort on a reordered frame
+        let mut rx = IsoTpReceiver::new(cfg());
+        rx.on_frame(&ff).unwrap();
+        rx.on_frame(&cf(1, 7)).unwrap();
+        assert!(rx.on_frame(&cf(3, 21)).is_err());
+
+        let mut rx = IsoTpReceiver::new(c);
+        rx.on_frame(&ff).unwrap();
+        assert_eq!(rx.on_frame(&cf(1, 7)), Ok(RxEvent::None));
+        assert_eq!(rx.on_frame(&cf(3, 21)), Ok(RxEvent::None)); // Early
+        assert_eq!(rx.on_frame(&cf(2, 14)), Ok(RxEvent::None));
+        assert_eq!(rx.on_frame(&cf(3, 21)), Ok(RxEvent::None)); // Repeated
+        assert_eq!(
+            rx.on_frame(&cf(4, 28)),
+            Ok(RxEvent::Complete((1..=30).collect()))
+        );
+
+        // Too far out of order
+        rx.on_frame(&ff).unwrap();
+        assert!(rx.on_frame(&cf(4, 28)).is_err());
+
+        // Consecutive frame timeout is extended
+        let ms = Duration::from_millis;
+        rx.on_frame_at(&ff, ms(0)).unwrap();
+        assert_eq!(rx.check_timeout(ms(2000)), Ok(()));
+        assert!(rx.check_timeout(ms(3001)).is_err());
+    }
+
+    #[test]
+    fn test_interleaved_sources() {
+        let mut rx = IsoTpMultiReceiver::new(cfg());
+        let frames = [
+            CanFrame::new(0x7E8, &[0x10, 0x0A, 1, 2, 3, 4, 5, 6]),
+            CanFrame::new(0x7E9, &[0x10, 0x09, 11, 12, 13, 14, 15, 16]),
+            CanFrame::new(0x7E9, &[0x21, 17, 18, 19]),
+            CanFrame::new(0x7E8, &[0x21, 7, 8, 9, 10]),
+        ];
+        let mut events: Vec<RxEvent> = frames.iter().map(|f| rx.on_frame(f).unwrap()).collect();
+        assert_eq!(
+            events.drain(0..2).collect::<Vec<_>>(),
+            vec![
+                RxEvent::FlowControl(CanFrame::new(0x7E0, &[0x30, 0x00, 0x00])),
+                RxEvent::FlowControl(CanFrame::new(0x7E1, &[0x30, 0x00, 0x00])),
+            ]
+        );
+        assert_eq!(
+            events,
+            vec![
+                RxEvent::Complete((11..=19).collect()),
+                RxEvent::Complete((1..=10).collect()),
+            ]
+        );
+        assert!(!rx.in_progress());
+    }
+
+    #[test]
+    fn test_max_rx_buffers() {
+        let mut c = cfg();
+        c.max_rx_buffers = 2;
+        let mut rx = IsoTpMultiReceiver::new(c);
+        for id in 0x700..0x710 {
+            rx.on_frame(&CanFrame::new(id, &[0x10, 0x0A, 1, 2, 3, 4, 5, 6]))
+                .unwrap();
+        }
+        assert_eq!(rx.receivers.len(), 2);
+        // Only the newest payloads are still being received
+        assert_eq!(
+            rx.on_frame(&CanFrame::new(0x700, &[0x21, 7, 8, 9, 10])),
+            Err(IsoTpError::UnexpectedConsecutiveFrame)
+        );
+        assert_eq!(
+            rx.on_frame(&CanFrame::new(0x70F, &[0x21, 7, 8, 9, 10])),
+            Ok(RxEvent::Complete((1..=10).collect()))
+        );
+        // Receivers with nothing in progress are not kept
+        assert_eq!(rx.receivers.len(), 1);
+    }
+
+    #[test]
+    fn test_tx_dlc() {
+        let mut c = cfg();
+        let mut tx = IsoTpTransmitter::new(c, &[0x3E, 0x00]).unwrap();
+        assert_eq!(tx.first_frame().dlc, 3);
+
+        c.tx_dlc = TxDlcMode::Always8;
+        let mut tx = IsoTpTransmitter::new(c, &[0x3E, 0x00]).unwrap();
+        let f = tx.first_frame();
+        assert_eq!(
+            f.get_data(),
+            &[0x02, 0x3E, 0x00, PAD_BYTE, PAD_BYTE, PAD_BYTE, PAD_BYTE, PAD_BYTE]
+        );
+        assert_eq!(flow_control_frame(&c, FlowStatus::ContinueToSend).dlc, 8);
+    }
+
+    #[test]
+    fn test_extended_addressing() {
+        let mut c = cfg();
+        c.addr_ext = Some(0xF1);
+        // 7 bytes no longer fit in a single frame
+        let payload: Vec<u8> = (0..7).collect();
+        let mut tx = IsoTpTransmitter::new(c, &payload).unwrap();
+        let ff = tx.first_frame();
+        assert_eq!(
+            ff.get_data(),
+            &[0xF1, 0x10, 0x07, 0x00, 0x01, 0x02, 0x03, 0x04]
+        );
+
+        let mut rx = IsoTpReceiver::new(c);
+        let fc = match rx.on_frame(&ff).unwrap() {
+            RxEvent::FlowControl(fc) => fc,
+            x => panic!("Expected flow control, got {:?}", x),
+        };
+        assert_eq!(fc.get_data(), &[0xF1, 0x30, 0x00, 0x00]);
+        assert_eq!(tx.on_flow_control(&fc), Ok(FlowStatus::ContinueToSend));
+
+        let cf = tx.next_consecutive_frame().unwrap();
+        assert_eq!(cf.get_data(), &[0xF1, 0x21, 0x05, 0x06]);
+        assert_eq!(rx.on_frame(&cf), Ok(RxEvent::Complete(payload)));
+
+        let mut tx = IsoTpTransmitter::new(c, &[0x3E, 0x00]).unwrap();
+        let sf = tx.first_frame();
+        assert_eq!(sf.get_data(), &[0xF1, 0x02, 0x3E, 0x00]);
+        assert_eq!(rx.on_frame(&sf), Ok(RxEvent::Complete(vec![0x3E, 0x00])));
+    }
+
+    #[test]
+    fn test_fd_single_frame() {
+        let mut c = cfg();
+        c.can_fd = true;
+        for len in 8..=FD_SF_MAX_LEN {
+            let payload: Vec<u8> = (0..len).map(|x| x as u8).collect();
+            let mut tx = IsoTpTransmitter::new(c, &payload).unwrap();
+            let sf = tx.first_frame();
+            assert!(tx.is_complete());
+            assert!(sf.is_fd());
+            assert_eq!(sf.dlc as usize, can_fd_frame_len(len + 2));
+            assert_eq!(&sf.get_data()[0..2], &[0x00, len as u8]);
+            // Received by a classic receiver too
+            let mut rx = IsoTpReceiver::new(cfg());
+            assert_eq!(rx.on_frame(&sf), Ok(RxEvent::Complete(payload)));
+        }
+        // Short payloads still use the classic format
+        let mut tx = IsoTpTransmitter::new(c, &[0x3E, 0x00]).unwrap();
+        assert_eq!(tx.first_frame().get_data(), &[0x02, 0x3E, 0x00]);
+        // One byte too many for a single frame
+        let mut tx = IsoTpTransmitter::new(c, &[0xAA; FD_SF_MAX_LEN + 1]).unwrap();
+        let ff = tx.first_frame();
+        assert!(!ff.is_fd());
+        assert_eq!(ff.get_data()[0] & 0xF0, PCI_FIRST_FRAME);
+
+        // With an address extension, there is 1 byte less
+        c.addr_ext = Some(0xF1);
+        let mut tx = IsoTpTransmitter::new(c, &[0xAA; FD_SF_MAX_LEN]).unwrap();
+        assert_eq!(tx.first_frame().get_data()[1] & 0xF0, PCI_FIRST_FRAME);
+
+        // The escape is not valid in a classic frame
+        let mut rx = IsoTpReceiver::new(cfg());
+        assert_eq!(
+            rx.on_frame(&CanFrame::new(0x7E8, &[0x00, 0x03, 1, 2, 3])),
+            Err(IsoTpError::InvalidFrame)
+        );
+    }
+
+    #[test]
+    fn test_fd_single_frame_length_limits() {
+        let mut rx = IsoTpReceiver::new(cfg());
+        let sf = |len: u8| {
+            let mut data = vec![0x00, len];
+            data.extend_from_slice(&[0xAA; 62]);
+            CanFrame::new_fd(0x7E8, &data)
+        };
+        // Only the PCI byte
+        assert_eq!(
+            rx.on_frame(&CanFrame::new(0x7E8, &[0x00])),
+            Err(IsoTpError::InvalidFrame)
+        );
+        assert_eq!(rx.on_frame(&sf(0)), Err(IsoTpError::InvalidFrame));
+        // 7 bytes fit a classic single frame, so the escape is not allowed
+        assert_eq!(rx.on_frame(&sf(7)), Err(IsoTpError::InvalidFrame));
+        assert_eq!(rx.on_frame(&sf(8)), Ok(RxEvent::Complete(vec![0xAA; 8])));
+        assert_eq!(rx.on_frame(&sf(62)), Ok(RxEvent::Complete(vec![0xAA; 62])));
+        assert_eq!(rx.on_frame(&sf(63)), Err(IsoTpError::InvalidFrame));
+
+        // With an address extension, the limits are 1 byte lower
+        let mut c = cfg();
+        c.addr_ext = Some(0xF1);
+        let mut rx = IsoTpReceiver::new(c);
+        let sf = |len: u8| {
+            let mut data = vec![0xF1, 0x00, len];
+            data.extend_from_slice(&[0xAA; 61]);
+            CanFrame::new_fd(0x7E8, &data)
+        };
+        assert_eq!(rx.on_frame(&sf(6)), Err(IsoTpError::InvalidFrame));
+        assert_eq!(rx.on_frame(&sf(7)), Ok(RxEvent::Complete(vec![0xAA; 7])));
+        assert_eq!(rx.on_frame(&sf(61)), Ok(RxEvent::Complete(vec![0xAA; 61])));
+        assert_eq!(rx.on_frame(&sf(62)), Err(IsoTpError::InvalidFrame));
+    }
+
+    #[test]
+    fn test_poll_timing() {
+        let clock = MockClock::default();
+        let payload: Vec<u8> = (0..20).collect();
+        let mut tx = IsoTpTransmitter::new(cfg(), &payload).unwrap();
+        tx.first_frame();
+
+        // No flow control yet
+        assert_eq!(tx.poll(clock.now()), TxPoll::AwaitFlowControl);
+        clock.advance(N_BS_TIMEOUT);
+        assert!(!tx.flow_control_timed_out(clock.now()));
+        clock.advance(Duration::from_millis(1));
+        assert!(tx.flow_control_timed_out(clock.now()));
+
+        // Block size 0, STmin 10ms
+        let mut c = cfg();
+        c.st_min = 10;
+        tx.on_flow_control(&flow_control_frame(&c, FlowStatus::ContinueToSend))
+            .unwrap();
+        assert!(!tx.flow_control_timed_out(clock.now()));
+        let start = clock.now();
+        assert!(matches!(tx.poll(clock.now()), TxPoll::Frame(_)));
+        assert_eq!(
+            tx.poll(clock.now()),
+            TxPoll::WaitUntil(start + Duration::from_millis(10))
+        );
+        clock.advance(Duration::from_millis(9));
+        assert!(matches!(tx.poll(clock.now()), TxPoll::WaitUntil(_)));
+        clock.sleep(Duration::from_millis(1));
+        assert!(matches!(tx.poll(clock.now()), TxPoll::Frame(_)));
+        assert_eq!(tx.poll(clock.now()), TxPoll::Complete);
+    }
+
+    #[test]
+    fn test_st_min() {
+        assert_eq!(decode_st_min(0x14), Duration::from_millis(20));
+        assert_eq!(decode_st_min(0xF3), Duration::from_micros(300));
+        assert_eq!(decode_st_min(0x80), Duration::from_millis(127));
+    }
+}
@@ -0,0 +1,161 @@
+//! Histogram of durations, used for measuring adapter and ECU latency
+
+use std::time::Duration;
+
+/// Upper bounds (in microseconds) of each histogram bucket for adapter level timings.
+/// Anything slower goes into the final overflow bucket
+pub const ADAPTER_BUCKETS_US: &[u64] = &[100, 250, 500, 1_000, 2_000, 5_000, 10_000, 50_000];
+
+/// Upper bounds (in microseconds) of each histogram bucket for diagnostic
+/// request/response round trips, which can take seconds with ResponsePending
+pub const DIAG_BUCKETS_US: &[u64] = &[
+    1_000, 2_000, 5_000, 10_000, 20_000, 50_000, 100_000, 200_000, 500_000, 1_000_000, 2_000_000,
+    5_000_000,
+];
+
+/// Simple histogram of durations
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    buckets_us: &'static [u64],
+    counts: Vec<u64>,
+    min: Option<Duration>,
+    max: Option<Duration>,
+    total: Duration,
+    samples: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::with_buckets(ADAPTER_BUCKETS_US)
+    }
+}
+
+impl LatencyHistogram {
+    /// Creates a histogram with the given (Ascending) bucket upper bounds in microseconds
+    pub fn with_buckets(buckets_us: &'static [u64]) -> Self {
+        Self {
+            buckets_us,
+            counts: vec![0; buckets_us.len() + 1],
+            min: None,
+            max: None,
+            total: Duration::default(),
+            samples: 0,
+        }
+    }
+
+    pub fn add(&mut self, d: Duration) {
+        let us = d.as_micros() as u64;
+        let idx = self
+            .buckets_us
+            .iter()
+            .position(|b| us < *b)
+            .unwrap_or(self.buckets_us.len());
+        self.counts[idx] += 1;
+        self.min = Some(self.min.map_or(d, |m| m.min(d)));
+        self.max = Some(self.max.map_or(d, |m| m.max(d)));
+        self.total += d;
+        self.samples += 1;
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.samples {
+            0 => None,
+            n => Some(self.total / n as u32),
+        }
+    }
+
+    /// Estimates the `p`th percentile (0-100), by interpolating within the bucket
+    /// that holds it. Returns None if there are no samples
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let (min, max) = (self.min?, self.max?);
+        let rank = ((p.clamp(0.0, 100.0) / 100.0) * self.samples as f64)
+            .ceil()
+            .max(1.0) as u64;
+        let mut before = 0;
+        for (idx, count) in self.counts.iter().enumerate() {
+            if *count == 0 || before + count < rank {
+                before += count;
+                continue;
+            }
+            let lower = match idx {
+                0 => min,
+                _ => Duration::from_micros(self.buckets_us[idx - 1]),
+            };
+            let upper = self
+                .buckets_us
+                .get(idx)
+                .map(|b| Duration::from_micros(*b))
+                .unwrap_or(max);
+            let frac = (rank - before) as f64 / *count as f64;
+            let est = lower + (upper.saturating_sub(lower)).mul_f64(frac);
+            return Some(est.clamp(min, max));
+        }
+        Some(max)
+    }
+
+    pub fn print(&self) {
+        if self.samples == 0 {
+            println!("  No samples");
+            return;
+        }
+        let max_count = *self.counts.iter().max().unwrap_or(&1);
+        for (idx, count) in self.counts.iter().enumerate() {
+            let label = match self.buckets_us.get(idx) {
+                Some(b) => format!("< {:>6} us", b),
+                None => format!(">= {:>5} us", self.buckets_us[self.buckets_us.len() - 1]),
+            };
+            let bar = "#".repeat(((count * 50) / max_count) as usize);
+            println!("  {} | {:>8} {}", label, count, bar);
+        }
+        let us = |d: Option<Duration>| d.unwrap_or_default().as_micros();
+        println!(
+            "  min {} us, avg {} us, max {} us",
+            us(self.min),
+            us(self.mean()),
+            us(self.max)
+        );
+        println!(
+            "  p50 {} us, p95 {} us, p99 {} us",
+            us(self.percentile(50.0)),
+            us(self.percentile(95.0)),
+            us(self.percentile(99.0))
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let mut h = LatencyHistogram::with_buckets(DIAG_BUCKETS_US);
+        assert!(h.percentile(50.0).is_none());
+        // 90 fast responses (1.5ms), 10 slow ones (300ms)
+        for _ in 0..90 {
+            h.add(Duration::from_micros(1_500));
+        }
+        for _ in 0..10 {
+            h.add(Duration::from_millis(300));
+        }
+        assert_eq!(h.samples(), 100);
+        let p50 = h.percentile(50.0).unwrap();
+        assert!(p50 >= Duration::from_millis(1) && p50 <= Duration::from_millis(2));
+        let p95 = h.percentile(95.0).unwrap();
+        assert!(p95 >= Duration::from_millis(200) && p95 <= Duration::from_millis(300));
+        assert_eq!(h.percentile(100.0), Some(Duration::from_millis(300)));
+        assert_eq!(h.percentile(0.0), Some(Duration::from_micros(1_500)));
+    }
+}
@@ -1,9 +1,19 @@
+pub mod adapter_spec;
+pub mod can_transport;
 #[allow(dead_code)]
 pub mod comm_api;
 pub mod iface;
+pub mod iso_tp;
+pub mod latency;
+#[cfg(feature = "passthru")]
 pub mod passthru_api;
 pub mod pdu_api;
 pub mod protocols;
+pub mod replay;
+pub mod slcan_api;
+pub mod tx_scheduler;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "socket-can"))]
 pub mod socket_can_api;
+
+pub use adapter_spec::open_by_spec;
@@ -1,16 +1,25 @@
 use crate::commapi::comm_api::{
-    CanFrame, Capability, ComServer, ComServerError, DeviceCapabilities, FilterType, ISO15765Data,
+    CanFrame, Capability, ComServer, ComServerError, DeviceCapabilities, FilterType, FrameCounter,
+    FrameCounters, FrameStamper, ISO15765Data, TimestampSource,
 };
 use crate::passthru::{self, DrvVersion, PassthruDevice, PassthruDrv};
 use j2534_rust::FilterType::{BLOCK_FILTER, FLOW_CONTROL_FILTER, PASS_FILTER};
 use j2534_rust::IoctlID::READ_VBATT;
-use j2534_rust::PassthruError::{ERR_FAILED, ERR_INVALID_CHANNEL_ID};
+use j2534_rust::PassthruError::{ERR_EXCEEDED_LIMIT, ERR_FAILED, ERR_INVALID_CHANNEL_ID};
 use j2534_rust::{
     ConnectFlags, IoctlID, IoctlParam, Loggable, PassthruError, Protocol, SConfig, SConfigList,
     TxFlag, PASSTHRU_MSG,
 };
 use std::sync::{Arc, Mutex, RwLock};
-use std::{os::raw::c_void, time::Instant};
+use std::{
+    os::raw::c_void,
+    time::{Duration, Instant},
+};
+
+/// Shortest interval J2534 allows between periodic messages
+const PERIODIC_MIN_INTERVAL_MS: u128 = 5;
+/// Longest interval J2534 allows between periodic messages
+const PERIODIC_MAX_INTERVAL_MS: u128 = 65535;
 
 #[derive(Debug, Clone)]
 pub struct PassthruApi {
@@ -21,6 +30,10 @@ pub struct PassthruApi {
     can_channel_idx: Arc<RwLock<Option<u32>>>,
     iso15765_channel_idx: Arc<RwLock<Option<u32>>>,
     iso9141_channel_idx: Arc<RwLock<Option<u32>>>,
+    stamper: Arc<RwLock<FrameStamper>>,
+    /// IDs of the periodic messages running on the CAN channel
+    periodic_msgs: Arc<RwLock<Vec<u32>>>,
+    counter: Arc<FrameCounter>,
 }
 
 impl ComServer for PassthruApi {
@@ -56,11 +69,14 @@ impl ComServer for PassthruApi {
             .iter()
             .map(|cf| PassthruApi::can_frame_to_pt_msg(cf))
             .collect();
-        self.driver
+        let res = self
+            .driver
             .lock()
             .unwrap()
             .write_messages(channel_id, &mut msgs, timeout_ms)
-            .map_err(|e| self.convert_error(e))
+            .map_err(|e| self.convert_error(e));
+        self.counter.add_sent(data.len(), &res);
+        res
     }
 
     fn read_can_packets(
@@ -72,16 +88,22 @@ impl ComServer for PassthruApi {
             Some(id) => id,
             None => return Err(self.convert_error(ERR_INVALID_CHANNEL_ID)),
         };
+        let stamper = *self.stamper.read().unwrap();
         self.driver
             .lock()
             .unwrap()
             .read_messages(channel_id, max_msgs as u32, timeout_ms)
             .map(|read| {
-                read.iter()
-                    .map(|msg| PassthruApi::pt_msg_to_can_frame(msg))
-                    .filter_map(Option::Some)
-                    .map(|x| x.unwrap())
-                    .collect()
+                let frames: Vec<CanFrame> = read
+                    .iter()
+                    .filter_map(|msg| {
+                        PassthruApi::pt_msg_to_can_frame(msg)
+                            .map(|f| stamper.stamp(f, Some(msg.timestamp as u64)))
+                    })
+                    .collect();
+                self.counter.add_rx(frames.len());
+                self.counter.add_dropped(read.len() - frames.len());
+                frames
             })
             .map_err(|e| self.convert_error(e))
     }
@@ -174,6 +196,8 @@ impl ComServer for PassthruApi {
             )
             .map_err(|e| self.convert_error(e))?;
         *self.can_channel_idx.write().unwrap() = Some(channel_id);
+        self.stamper.write().unwrap().restart();
+        self.counter.reset();
         *self.iso15765_channel_idx.write().unwrap() = None; // Physically impossible to have both CAN and ISOTP enabled at the same time
         Ok(())
     }
@@ -189,6 +213,7 @@ impl ComServer for PassthruApi {
                 .disconnect(lock.unwrap())
                 .map_err(|e| self.convert_error(e))?;
             *lock = None;
+            self.periodic_msgs.write().unwrap().clear(); // Stopped by the adapter on disconnect
         }
         Ok(())
     }
@@ -462,6 +487,75 @@ impl ComServer for PassthruApi {
             .map_err(|e| self.convert_error(e))
     }
 
+    fn add_periodic_message(
+        &mut self,
+        frame: &CanFrame,
+        interval: Duration,
+    ) -> Result<u32, ComServerError> {
+        let channel_id = match *self.can_channel_idx.read().unwrap() {
+            Some(id) => id,
+            None => return Err(self.convert_error(ERR_INVALID_CHANNEL_ID)),
+        };
+        let interval_ms = interval.as_millis();
+        if !(PERIODIC_MIN_INTERVAL_MS..=PERIODIC_MAX_INTERVAL_MS).contains(&interval_ms) {
+            return Err(ComServerError {
+                err_code: 99,
+                err_desc: format!(
+                    "Periodic interval of {} ms is outside J2534's range of {}-{} ms",
+                    interval_ms, PERIODIC_MIN_INTERVAL_MS, PERIODIC_MAX_INTERVAL_MS
+                ),
+            });
+        }
+        let msg = PassthruApi::can_frame_to_pt_msg(frame);
+        let msg_id = self
+            .driver
+            .lock()
+            .unwrap()
+            .start_periodic_msg(channel_id, &msg, interval_ms as u32)
+            .map_err(|e| {
+                if e == ERR_EXCEEDED_LIMIT {
+                    ComServerError {
+                        err_code: e as u32,
+                        err_desc: format!(
+                            "Adapter cannot send any more periodic messages ({} running)",
+                            self.periodic_msgs.read().unwrap().len()
+                        ),
+                    }
+                } else {
+                    self.convert_error(e)
+                }
+            })?;
+        self.periodic_msgs.write().unwrap().push(msg_id);
+        Ok(msg_id)
+    }
+
+    fn remove_periodic_message(&mut self, msg_id: u32) -> Result<(), ComServerError> {
+        let mut msgs = self.periodic_msgs.write().unwrap();
+        let idx = match msgs.iter().position(|id| *id == msg_id) {
+            Some(idx) => idx,
+            None => {
+                return Err(ComServerError {
+                    err_code: 99,
+                    err_desc: format!("No periodic message with ID {}", msg_id),
+                })
+            }
+        };
+        if let Some(channel_id) = *self.can_channel_idx.read().unwrap() {
+            self.driver
+                .lock()
+                .unwrap()
+                .stop_periodic_msg(channel_id, msg_id)
+                .map_err(|e| self.convert_error(e))?;
+        }
+        msgs.remove(idx);
+        Ok(())
+    }
+
+    fn set_timestamp_source(&mut self, source: TimestampSource) -> Result<(), ComServerError> {
+        self.stamper.write().unwrap().source = source;
+        Ok(())
+    }
+
     fn clone_box(&self) -> Box<dyn ComServer> {
         Box::new(Self {
             device: self.device.clone(),
@@ -471,6 +565,9 @@ impl ComServer for PassthruApi {
             can_channel_idx: self.can_channel_idx.clone(),
             iso15765_channel_idx: self.iso15765_channel_idx.clone(),
             iso9141_channel_idx: self.iso9141_channel_idx.clone(),
+            stamper: self.stamper.clone(),
+            periodic_msgs: self.periodic_msgs.clone(),
+            counter: self.counter.clone(),
         })
     }
 
@@ -511,6 +608,10 @@ impl ComServer for PassthruApi {
         "SAE J2534"
     }
 
+    fn frame_counters(&self) -> FrameCounters {
+        self.counter.get()
+    }
+
     fn is_connected(&self) -> bool {
         return self.iso15765_channel_idx.read().unwrap().is_some()
             || self.can_channel_idx.read().unwrap().is_some()
@@ -528,6 +629,9 @@ impl PassthruApi {
             can_channel_idx: Arc::from(RwLock::new(None)),
             iso15765_channel_idx: Arc::from(RwLock::new(None)),
             iso9141_channel_idx: Arc::from(RwLock::new(None)),
+            stamper: Arc::new(RwLock::new(FrameStamper::default())),
+            periodic_msgs: Arc::new(RwLock::new(Vec::new())),
+            counter: Arc::new(FrameCounter::default()),
         }
     }
 
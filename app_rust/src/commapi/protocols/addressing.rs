@@ -0,0 +1,183 @@
+//! Named CAN ID conventions for diagnostic requests, so an ECU can be picked by its
+//! index in a scheme rather than entering its request and response IDs by hand.
+//!
+//! Manufacturers which do not follow a fixed pattern (Such as Mercedes, where each
+//! ECU's IDs are listed in its CBF file) can be described with [AddressingScheme::Custom],
+//! or found in a CBF file with [AddressingScheme::identify].
+
+use std::convert::TryFrom;
+
+use super::{DiagCfg, ProtocolError, ProtocolResult};
+
+/// Functional (Broadcast) request ID of 11 bit OBD-II
+const OBD_FUNCTIONAL_ID: u32 = 0x7DF;
+/// Functional (Broadcast) request ID of 29 bit OBD-II
+const OBD29_FUNCTIONAL_ID: u32 = 0x18DB33F1;
+/// Source address of an external test tool on 29 bit OBD-II
+const OBD29_TESTER_ADDR: u32 = 0xF1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AddressingScheme {
+    /// ISO 15765-4 11 bit IDs. ECU `n` (0-7) is requested on 0x7E0 + n and responds
+    /// on 0x7E8 + n. Functional requests are sent to 0x7DF
+    StandardObd,
+    /// ISO 15765-4 29 bit (Normal fixed) IDs. ECU `n` is the ECU's address, which is
+    /// requested on 0x18DAnnF1 and responds on 0x18DAF1nn. Functional requests are sent
+    /// to 0x18DB33F1
+    ExtendedObd,
+    /// ECU `n` is requested on `request_base` + n and responds on `response_base` + n
+    Custom {
+        request_base: u32,
+        response_base: u32,
+        global_id: Option<u32>,
+    },
+}
+
+impl AddressingScheme {
+    /// Returns true if the scheme uses 29 bit CAN IDs
+    pub fn is_extended(&self) -> bool {
+        match self {
+            Self::StandardObd => false,
+            Self::ExtendedObd => true,
+            Self::Custom {
+                request_base,
+                response_base,
+                ..
+            } => *request_base > 0x7FF || *response_base > 0x7FF,
+        }
+    }
+
+    /// Returns the functional (Broadcast) request ID of the scheme, if it has one
+    pub fn global_id(&self) -> Option<u32> {
+        match self {
+            Self::StandardObd => Some(OBD_FUNCTIONAL_ID),
+            Self::ExtendedObd => Some(OBD29_FUNCTIONAL_ID),
+            Self::Custom { global_id, .. } => *global_id,
+        }
+    }
+
+    /// Returns the IDs used to talk to ECU `ecu` under the scheme
+    pub fn diag_cfg(&self, ecu: u8) -> ProtocolResult<DiagCfg> {
+        let (send_id, recv_id) = match self {
+            Self::StandardObd if ecu > 7 => {
+                return Err(ProtocolError::CustomError(format!(
+                    "11 bit OBD-II only has ECUs 0-7, not {}",
+                    ecu
+                )))
+            }
+            Self::StandardObd => (0x7E0 + ecu as u32, 0x7E8 + ecu as u32),
+            Self::ExtendedObd => (
+                0x18DA0000 | (ecu as u32) << 8 | OBD29_TESTER_ADDR,
+                0x18DA0000 | OBD29_TESTER_ADDR << 8 | ecu as u32,
+            ),
+            Self::Custom {
+                request_base,
+                response_base,
+                ..
+            } => (request_base + ecu as u32, response_base + ecu as u32),
+        };
+        Ok(DiagCfg {
+            send_id,
+            recv_id,
+            global_id: self.global_id(),
+            recv_id_mask: None,
+        })
+    }
+
+    /// Finds the standard scheme and ECU index with the given request and response IDs,
+    /// such as the `CP_REQUEST_CANIDENTIFIER` and `CP_RESPONSE_CANIDENTIFIER` com
+    /// parameters of a CBF interface
+    pub fn identify(send_id: u32, recv_id: u32) -> Option<(Self, u8)> {
+        [Self::StandardObd, Self::ExtendedObd]
+            .iter()
+            .find_map(|scheme| {
+                let ecu = match scheme {
+                    Self::ExtendedObd => send_id >> 8 & 0xFF,
+                    _ => send_id.checked_sub(0x7E0)?,
+                };
+                let ecu = u8::try_from(ecu).ok()?;
+                let cfg = scheme.diag_cfg(ecu).ok()?;
+                if cfg.send_id == send_id && cfg.recv_id == recv_id {
+                    Some((*scheme, ecu))
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Checks IDs derived from the scheme against the IDs an ECU is known to use (Such as
+    /// from its CBF file). Returns a description of each ID which does not match
+    pub fn check_ids(
+        cfg: &DiagCfg,
+        request_id: Option<u32>,
+        response_id: Option<u32>,
+    ) -> Vec<String> {
+        let mut res = Vec::new();
+        for (name, derived, expected) in [
+            ("request", cfg.send_id, request_id),
+            ("response", cfg.recv_id, response_id),
+        ] {
+            match expected {
+                Some(id) if id != derived => res.push(format!(
+                    "ECU uses {} ID 0x{:X}, but the addressing scheme gives 0x{:X}",
+                    name, id, derived
+                )),
+                _ => {}
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diag_cfg() {
+        let cfg = AddressingScheme::StandardObd.diag_cfg(1).unwrap();
+        assert_eq!(
+            (cfg.send_id, cfg.recv_id, cfg.global_id),
+            (0x7E1, 0x7E9, Some(0x7DF))
+        );
+        assert!(AddressingScheme::StandardObd.diag_cfg(8).is_err());
+
+        let cfg = AddressingScheme::ExtendedObd.diag_cfg(0x10).unwrap();
+        assert_eq!((cfg.send_id, cfg.recv_id), (0x18DA10F1, 0x18DAF110));
+        assert!(AddressingScheme::ExtendedObd.is_extended());
+
+        let custom = AddressingScheme::Custom {
+            request_base: 0x600,
+            response_base: 0x680,
+            global_id: None,
+        };
+        let cfg = custom.diag_cfg(2).unwrap();
+        assert_eq!(
+            (cfg.send_id, cfg.recv_id, cfg.global_id),
+            (0x602, 0x682, None)
+        );
+        assert!(!custom.is_extended());
+    }
+
+    #[test]
+    fn test_identify() {
+        assert_eq!(
+            AddressingScheme::identify(0x7E0, 0x7E8),
+            Some((AddressingScheme::StandardObd, 0))
+        );
+        assert_eq!(
+            AddressingScheme::identify(0x18DA33F1, 0x18DAF133),
+            Some((AddressingScheme::ExtendedObd, 0x33))
+        );
+        // Mercedes IC, which has no fixed scheme
+        assert_eq!(AddressingScheme::identify(0x5B4, 0x4F4), None);
+        assert_eq!(AddressingScheme::identify(0x7E0, 0x7E9), None);
+
+        let cfg = AddressingScheme::StandardObd.diag_cfg(0).unwrap();
+        assert!(AddressingScheme::check_ids(&cfg, Some(0x7E0), None).is_empty());
+        assert_eq!(
+            AddressingScheme::check_ids(&cfg, Some(0x7E0), Some(0x7E9)).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::cmp::min;
+
+use image::{GenericImageView, Rgba};
+
+use crate::commapi::protocols::{ProtocolResult, ProtocolServer};
+
+use super::KWP2000ECU;
+
+/*
+Some instrument clusters (Such as the W203 IC) have a test routine which draws a
+line on the LCD, started with StartRoutineByLocalID ($31). An image can be drawn
+on the LCD by converting it into vertical line segments, one per run of dark
+pixels in each column, and drawing them one at a time.
+*/
+
+/// Local ID of the LCD test routine
+const LCD_TEST_ROUTINE: u8 = 0x03;
+/// Test routine option to draw a line
+const DRAW_LINE: u8 = 0x06;
+
+/// Line segment on the LCD, in pixels from the top left corner
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub start_x: u8,
+    pub start_y: u8,
+    pub end_x: u8,
+    pub end_y: u8,
+}
+
+/// Returns the luminance (0-255) of each LCD pixel, row by row, with the image
+/// scaled to the width of the LCD
+fn sample_luma<I: GenericImageView<Pixel = Rgba<u8>>>(
+    img: &I,
+    lcd_width: u32,
+    lcd_height: u32,
+) -> Vec<f32> {
+    // Scale factor for the image
+    let sf = img.width() as f32 / lcd_width as f32;
+    let mut res = Vec::with_capacity((lcd_width * lcd_height) as usize);
+    for y in 0..lcd_height {
+        for x in 0..lcd_width {
+            let x_coord = min((x as f32 * sf) as u32, img.width() - 1);
+            let y_coord = min((y as f32 * sf) as u32, img.height() - 1);
+            let rgb = img.get_pixel(x_coord, y_coord).0;
+            // ITU-R BT.601 luma
+            res.push(0.299 * rgb[0] as f32 + 0.587 * rgb[1] as f32 + 0.114 * rgb[2] as f32);
+        }
+    }
+    res
+}
+
+/// Converts luminance values to pixels which are on (Dark) or off. With `dither`,
+/// the error from each pixel is spread to its neighbours (Floyd-Steinberg), so
+/// shades of grey come out as a pattern of dots rather than solid black or white.
+/// `width` must not be 0
+fn to_monochrome(mut luma: Vec<f32>, width: usize, threshold: u8, dither: bool) -> Vec<bool> {
+    let threshold = threshold as f32;
+    let height = luma.len() / width;
+    let mut res = Vec::with_capacity(luma.len());
+    for y in 0..height {
+        for x in 0..width {
+            let old = luma[y * width + x];
+            let on = old < threshold;
+            res.push(on);
+            if !dither {
+                continue;
+            }
+            let err = old - if on { 0.0 } else { 255.0 };
+            let mut spread = |dx: isize, dy: usize, weight: f32| {
+                let nx = x as isize + dx;
+                if nx >= 0 && (nx as usize) < width && y + dy < height {
+                    luma[(y + dy) * width + nx as usize] += err * weight / 16.0;
+                }
+            };
+            spread(1, 0, 7.0);
+            spread(-1, 1, 3.0);
+            spread(0, 1, 5.0);
+            spread(1, 1, 1.0);
+        }
+    }
+    res
+}
+
+/// Converts an image to vertical line segments which draw it on a LCD that is
+/// `lcd_width` x `lcd_height` pixels (Up to 256 x 256).
+///
+/// The image is scaled to the width of the LCD, keeping its aspect ratio, and
+/// anything below the LCD is cut off. A pixel is drawn if its luminance (0-255)
+/// is below `threshold`. For photos and other greyscale images, `dither` gives
+/// far more legible results than a plain threshold
+pub fn image_to_lines<I: GenericImageView<Pixel = Rgba<u8>>>(
+    img: &I,
+    lcd_width: u32,
+    lcd_height: u32,
+    threshold: u8,
+    dither: bool,
+) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    if lcd_width == 0 || img.width() == 0 || img.height() == 0 {
+        return lines;
+    }
+    let width = lcd_width as usize;
+    let pixels = to_monochrome(
+        sample_luma(img, lcd_width, lcd_height),
+        width,
+        threshold,
+        dither,
+    );
+    for x in 0..lcd_width {
+        let mut new_line = true;
+        for y in 0..lcd_height {
+            if pixels[y as usize * width + x as usize] {
+                if new_line {
+                    lines.push(Line {
+                        start_x: x as u8,
+                        start_y: y as u8,
+                        end_x: x as u8,
+                        end_y: y as u8,
+                    });
+                    new_line = false;
+                } else if let Some(line) = lines.last_mut() {
+                    // Extend the line in this column
+                    line.end_y = y as u8;
+                }
+            } else {
+                new_line = true;
+            }
+        }
+    }
+    lines
+}
+
+/// Draws a single line on the LCD
+pub fn draw_line(ecu: &KWP2000ECU, line: &Line) -> ProtocolResult<()> {
+    ecu.run_command(
+        super::Service::StartRoutineByLocalID.into(),
+        &[
+            LCD_TEST_ROUTINE,
+            DRAW_LINE,
+            line.start_x,
+            line.start_y,
+            line.end_x,
+            line.end_y,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Clears the LCD, then draws `lines` (From [image_to_lines]) on it.
+///
+/// The LCD is cleared by drawing a vertical line the height of the LCD, which is
+/// how the W203 IC behaves in test mode. Note the IC clears the LCD again once the
+/// test routine stops, so it has to be kept active (By drawing an empty line)
+/// for the image to stay visible
+pub fn draw_lines(ecu: &KWP2000ECU, lcd_height: u32, lines: &[Line]) -> ProtocolResult<()> {
+    draw_line(
+        ecu,
+        &Line {
+            start_x: 0,
+            start_y: 0,
+            end_x: 0,
+            end_y: lcd_height as u8,
+        },
+    )?;
+    for l in lines {
+        draw_line(ecu, l)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    /// Image from rows of '#' (Black) and '.' (White), each pixel `scale` x `scale`
+    fn bitmap(rows: &[&str], scale: u32) -> RgbaImage {
+        RgbaImage::from_fn(
+            rows[0].len() as u32 * scale,
+            rows.len() as u32 * scale,
+            |x, y| match rows[(y / scale) as usize].as_bytes()[(x / scale) as usize] {
+                b'#' => BLACK,
+                _ => WHITE,
+            },
+        )
+    }
+
+    fn line(x: u8, start_y: u8, end_y: u8) -> Line {
+        Line {
+            start_x: x,
+            start_y,
+            end_x: x,
+            end_y,
+        }
+    }
+
+    #[test]
+    fn test_image_to_lines() {
+        let rows = ["#.##", "#..#", "..##"];
+        let expected = vec![line(0, 0, 1), line(2, 0, 0), line(2, 2, 2), line(3, 0, 2)];
+        assert_eq!(
+            image_to_lines(&bitmap(&rows, 1), 4, 3, 128, false),
+            expected
+        );
+        // Image twice the size of the LCD is scaled down
+        assert_eq!(
+            image_to_lines(&bitmap(&rows, 2), 4, 3, 128, false),
+            expected
+        );
+        // Rows below the LCD are cut off
+        assert_eq!(
+            image_to_lines(&bitmap(&rows, 1), 4, 2, 128, false),
+            vec![line(0, 0, 1), line(2, 0, 0), line(3, 0, 1)]
+        );
+        // Nothing is dark enough
+        assert!(image_to_lines(&bitmap(&rows, 1), 4, 3, 0, false).is_empty());
+        assert!(image_to_lines(&bitmap(&rows, 1), 0, 3, 128, false).is_empty());
+    }
+
+    #[test]
+    fn test_dither() {
+        let grey = RgbaImage::from_fn(8, 8, |_, _| Rgba([128, 128, 128, 255]));
+        // Without dithering, every pixel is just above the threshold
+        assert!(image_to_lines(&grey, 8, 8, 128, false).is_empty());
+        // With dithering, about half of the pixels are drawn
+        let drawn: u32 = image_to_lines(&grey, 8, 8, 128, true)
+            .iter()
+            .map(|l| (l.end_y - l.start_y) as u32 + 1)
+            .sum();
+        assert!((24..=40).contains(&drawn), "{} pixels drawn", drawn);
+
+        // Black and white images are not changed by dithering
+        let rows = ["#.##", "#..#", "..##"];
+        assert_eq!(
+            image_to_lines(&bitmap(&rows, 1), 4, 3, 128, true),
+            image_to_lines(&bitmap(&rows, 1), 4, 3, 128, false)
+        );
+    }
+}
@@ -22,11 +22,12 @@ use crate::commapi::{
 };
 
 use super::{
-    CautionLevel, CommandError, DiagCfg, ECUCommand, ProtocolError, ProtocolResult, ProtocolServer,
-    Selectable, DTC,
+    CautionLevel, CommandError, DiagCfg, ECUCommand, ExchangeRecord, ProtocolError, ProtocolResult,
+    ProtocolServer, Selectable, DTC,
 };
 
 pub mod clear_diag_information;
+pub mod draw_routine;
 pub mod ecu_reset;
 pub mod read_ecu_identification;
 pub mod read_status_dtc;
@@ -148,6 +149,16 @@ impl Into<u8> for Service {
     }
 }
 
+impl Service {
+    /// Converts a service name (Such as `ReadDataByLocalID`) to a KWP2000 service, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        let mut all = Self::get_cmd_list();
+        all.extend_from_slice(&[Self::StartDiagSession, Self::TesterPresent]);
+        all.into_iter()
+            .find(|s| s.get_name().eq_ignore_ascii_case(name))
+    }
+}
+
 impl ECUCommand for Service {
     fn get_caution_level(&self) -> CautionLevel {
         match &self {
@@ -345,6 +356,32 @@ impl CommandError for KwpNegativeCode {
             _ => Self::Unknown(b),
         }
     }
+
+    fn get_byte(&self) -> Option<u8> {
+        Some(match self {
+            Self::GeneralReject => 0x10,
+            Self::ServiceNotSupported => 0x11,
+            Self::SubFunctionNotSupported => 0x12,
+            Self::Busy => 0x21,
+            Self::RequestSequenceError => 0x22,
+            Self::RoutineNotComplete => 0x23,
+            Self::RequestOutOfRange => 0x31,
+            Self::SecurityAccessDenied => 0x33,
+            Self::InvalidKey => 0x35,
+            Self::ExceededAttempts => 0x36,
+            Self::TimeDelayNotExpired => 0x37,
+            Self::DownloadNotAccepted => 0x40,
+            Self::UploadNotAccepted => 0x50,
+            Self::TransferSuspended => 0x71,
+            Self::ResponsePending => 0x78,
+            Self::ServiceNotSupportedActiveSession => 0x80,
+            Self::DataDecompressionFailed => 0x9A,
+            Self::DataDecryptionFailed => 0x9B,
+            Self::ECUNotResponding => 0xA0,
+            Self::ECUAddressUnknown => 0xA1,
+            Self::CustomDaimler(b) | Self::Reserved(b) | Self::Unknown(b) => *b,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -356,6 +393,7 @@ pub struct KWP2000ECU {
     curr_session_type: Arc<RwLock<DiagSession>>,
     send_id: u32,
     cmd_mutex: Arc<Mutex<()>>,
+    last_exchange: ExchangeRecord,
 }
 
 #[derive(Debug, Clone)]
@@ -550,6 +588,7 @@ impl ProtocolServer for KWP2000ECU {
             send_id: diag_cfg.send_id,
             curr_session_type: session_type, // Assumed,
             cmd_mutex: Arc::new(Mutex::new(())),
+            last_exchange: ExchangeRecord::default(),
         };
 
         if let Err(e) = ecu.set_diag_session_mode(DiagSession::Extended) {
@@ -569,10 +608,19 @@ impl ProtocolServer for KWP2000ECU {
         if self.cmd_tx.send((cmd, Vec::from(args), true)).is_err() {
             return Err(ProtocolError::CustomError("Channel Tx failed".into()));
         }
-        let resp = self.cmd_rx.recv().unwrap()?;
-        if resp[0] == 0x7F {
-            let neg_code = KwpNegativeCode::from_byte(resp[2]);
-            Err(ProtocolError::ProtocolError(Box::new(neg_code)))
+        let resp = self.cmd_rx.recv().unwrap();
+        self.last_exchange.record(cmd, args, &resp);
+        let resp = resp?;
+        if resp.first() == Some(&0x7F) {
+            match resp.get(2) {
+                Some(nrc) => Err(ProtocolError::ProtocolError(Box::new(
+                    KwpNegativeCode::from_byte(*nrc),
+                ))),
+                None => Err(ProtocolError::InvalidResponseSize {
+                    expect: 3,
+                    actual: resp.len(),
+                }),
+            }
         } else {
             Ok(resp)
         }
@@ -625,4 +673,8 @@ impl ProtocolServer for KWP2000ECU {
             None => None,
         }
     }
+
+    fn last_exchange(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        self.last_exchange.get()
+    }
 }
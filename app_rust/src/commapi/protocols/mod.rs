@@ -1,4 +1,8 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use comm_api::ComServerError;
 use kwp2000::KWP2000ECU;
@@ -8,12 +12,14 @@ use self::{kwp2000::read_ecu_identification, uds::read_data};
 
 use super::{
     comm_api::{self, ComServer},
-    iface::{Interface, InterfaceConfig, InterfacePayload, InterfaceType, PayloadFlag},
+    iface::{BufferType, Interface, InterfaceConfig, InterfacePayload, InterfaceType, PayloadFlag},
 };
 
+pub mod addressing;
 pub mod kwp2000;
 pub mod obd2;
 pub mod uds;
+pub mod variant_check;
 pub mod vin;
 
 #[derive(Debug)]
@@ -35,6 +41,13 @@ impl ProtocolError {
             ProtocolError::Timeout => true,
         }
     }
+
+    /// Returns true if the error could be caused by a transient problem on the bus
+    /// (A timeout or an error from the adapter), so re-sending the request might succeed.
+    /// Negative responses from the ECU are never transient
+    pub fn is_transient(&self) -> bool {
+        matches!(self, ProtocolError::Timeout | ProtocolError::CommError(_))
+    }
 }
 
 impl From<ComServerError> for ProtocolError {
@@ -47,6 +60,15 @@ unsafe impl Send for ProtocolError {}
 unsafe impl Sync for ProtocolError {}
 
 impl ProtocolError {
+    /// Returns the negative response code the ECU responded with, if this
+    /// error is a negative response
+    pub fn get_nrc(&self) -> Option<u8> {
+        match self {
+            ProtocolError::ProtocolError(e) => e.get_byte(),
+            _ => None,
+        }
+    }
+
     pub fn get_text(&self) -> String {
         match self {
             ProtocolError::CommError(e) => e.to_string(),
@@ -62,6 +84,18 @@ impl ProtocolError {
 
 pub type ProtocolResult<T> = std::result::Result<T, ProtocolError>;
 
+/// Time to wait for an ECU to respond to a request, if the protocol
+/// does not specify otherwise
+pub const DEFAULT_RESPONSE_TIMEOUT_MS: u32 = 2000;
+
+/// Maximum total time to keep waiting whilst an ECU keeps responding with
+/// ResponsePending (0x78), if the protocol does not specify otherwise
+pub const DEFAULT_PENDING_BUDGET: Duration = Duration::from_secs(60);
+
+/// Time to wait for a negative response to a request which suppresses its positive
+/// response. This is the default P2server_max (50ms) plus a margin for the adapter
+pub const SUPPRESSED_RESPONSE_WINDOW_MS: u32 = 150;
+
 pub trait Selectable: Into<u8> {
     fn get_desc(&self) -> String;
     fn get_name(&self) -> String;
@@ -88,6 +122,10 @@ pub trait CommandError {
     fn from_byte(b: u8) -> Self
     where
         Self: Sized;
+    /// Returns the raw negative response code, if this error came from one
+    fn get_byte(&self) -> Option<u8> {
+        None
+    }
 }
 
 impl std::fmt::Debug for Box<dyn CommandError> {
@@ -112,6 +150,23 @@ pub struct DTC {
     pub(crate) id: u32,
 }
 
+impl DTC {
+    /// Converts a DTC reported as raw hex (2 bytes with KWP2000, 3 bytes with UDS) to its
+    /// SAE J2012 name. For example 0x8123 becomes B0123. The failure type byte of a 3 byte
+    /// DTC is kept, so 0x012345 becomes P012345. Returns None if the DTC is not raw hex
+    pub fn get_sae_name(&self) -> Option<String> {
+        let len = self.error.len();
+        if len != 4 && len != 6 {
+            return None;
+        }
+        let raw = u32::from_str_radix(&self.error, 16).ok()?;
+        let bits = len as u32 * 4;
+        let letter = ['P', 'C', 'B', 'U'][(raw >> (bits - 2)) as usize];
+        let code = raw & ((1 << (bits - 2)) - 1);
+        Some(format!("{}{:0width$X}", letter, code, width = len))
+    }
+}
+
 impl Display for DTC {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -127,14 +182,78 @@ pub struct DiagCfg {
     pub send_id: u32,
     pub recv_id: u32,
     pub global_id: Option<u32>,
+    /// Accept responses from any CAN ID which matches `recv_id` under this mask, for
+    /// ECUs which respond on an unexpected ID. None only accepts `recv_id` itself
+    pub recv_id_mask: Option<u32>,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DiagProtocol {
     KWP2000,
     UDS,
 }
 
+impl DiagProtocol {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::KWP2000 => "KWP2000",
+            Self::UDS => "UDS",
+        }
+    }
+
+    /// Returns the SID of a service of this protocol from its name, ignoring case.
+    /// The same operation can have a different name and SID in each protocol
+    /// (Such as UDS ReadDTCInformation, and KWP2000 ReadDTCByStatus)
+    pub fn service_by_name(&self, name: &str) -> Option<u8> {
+        match self {
+            Self::KWP2000 => kwp2000::Service::from_name(name).map(|s| s.into()),
+            Self::UDS => uds::UDSCommand::from_name(name).map(|c| c.into()),
+        }
+    }
+
+    /// Returns the description of a negative response code of this protocol
+    pub fn nrc_desc(&self, nrc: u8) -> String {
+        match self {
+            Self::KWP2000 => kwp2000::KwpNegativeCode::from_byte(nrc).get_desc(),
+            Self::UDS => uds::UDSNegativeCode::from_byte(nrc).get_desc(),
+        }
+    }
+}
+
+/// Raw bytes of a request (Starting with the SID), and the ECU's response to it
+type Exchange = (Vec<u8>, Vec<u8>);
+
+/// Raw bytes of the most recent request sent by a diagnostic server, and the ECU's response.
+/// Only the last exchange is kept, and its buffers are reused, so recording every request is cheap
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ExchangeRecord(Arc<Mutex<Option<Exchange>>>);
+
+impl ExchangeRecord {
+    /// Records a request and the result of sending it. Negative responses reach the
+    /// diagnostic server as errors, so they are recorded as `7F <SID> <NRC>`. The
+    /// response is empty if the ECU did not respond
+    pub(crate) fn record(&self, cmd: u8, args: &[u8], res: &ProtocolResult<Vec<u8>>) {
+        let mut last = self.0.lock().unwrap();
+        let (req_buf, resp_buf) = last.get_or_insert_with(Default::default);
+        req_buf.clear();
+        req_buf.push(cmd);
+        req_buf.extend_from_slice(args);
+        resp_buf.clear();
+        match res {
+            Ok(resp) => resp_buf.extend_from_slice(resp),
+            Err(e) => {
+                if let Some(nrc) = e.get_nrc() {
+                    resp_buf.extend_from_slice(&[0x7F, cmd, nrc]);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum DiagServer {
     KWP2000(KWP2000ECU),
@@ -189,6 +308,33 @@ impl DiagServer {
         }
     }
 
+    /// Same as [DiagServer::run_cmd], but the request can be sent functionally.
+    /// See [uds::RequestAddressing]. KWP2000 only supports physical requests
+    pub fn run_cmd_addressed(
+        &mut self,
+        cmd: u8,
+        args: &[u8],
+        addressing: uds::RequestAddressing,
+    ) -> ProtocolResult<Vec<u8>> {
+        match self {
+            Self::KWP2000(s) if addressing == uds::RequestAddressing::Physical => {
+                s.run_command(cmd, args)
+            }
+            Self::KWP2000(_) => Err(ProtocolError::CustomError(
+                "Functional requests are only supported with UDS".into(),
+            )),
+            Self::UDS(s) => s.run_command_addressed(cmd, args, addressing),
+        }
+    }
+
+    /// See [ProtocolServer::last_exchange]
+    pub fn last_exchange(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        match self {
+            Self::KWP2000(s) => s.last_exchange(),
+            Self::UDS(s) => s.last_exchange(),
+        }
+    }
+
     pub fn into_kwp(&mut self) -> Option<&mut KWP2000ECU> {
         match self {
             Self::KWP2000(s) => Some(s),
@@ -250,6 +396,21 @@ pub trait ProtocolServer: Sized {
     fn is_in_diag_session(&self) -> bool;
     fn get_last_error(&self) -> Option<String>;
 
+    /// Returns the raw bytes of the most recent request (Starting with the SID) and the
+    /// ECU's response to it, including negative responses, so a response which fails to
+    /// decode can be inspected without enabling logging. The response is empty if the
+    /// ECU did not respond. None if no request has been sent yet
+    fn last_exchange(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        None
+    }
+
+    /// Returns true if the request asks the ECU not to send a positive response
+    /// (suppressPosRspMsgIndicationBit). The ECU still sends a negative response if
+    /// the request fails, or ResponsePending if it needs more time
+    fn is_positive_response_suppressed(_cmd: u8, _args: &[u8]) -> bool {
+        false
+    }
+
     fn run_command_resp(
         interface: &mut Box<dyn Interface>,
         flags: &Option<Vec<PayloadFlag>>,
@@ -258,45 +419,130 @@ pub trait ProtocolServer: Sized {
         args: &[u8],
         receive_require: bool,
     ) -> std::result::Result<Vec<u8>, ProtocolError> {
+        Self::run_command_resp_timeout(
+            interface,
+            flags,
+            send_id,
+            cmd,
+            args,
+            receive_require,
+            DEFAULT_RESPONSE_TIMEOUT_MS,
+            DEFAULT_RESPONSE_TIMEOUT_MS,
+            DEFAULT_PENDING_BUDGET,
+        )
+    }
+
+    /// Same as [ProtocolServer::run_command_resp], but waits up to `timeout_ms`
+    /// for the ECU to respond, and up to `pending_timeout_ms` after each ResponsePending.
+    ///
+    /// If the ECU keeps responding with ResponsePending for longer than `pending_budget`,
+    /// the request fails rather than waiting forever on a stuck ECU
+    #[allow(clippy::too_many_arguments)]
+    fn run_command_resp_timeout(
+        interface: &mut Box<dyn Interface>,
+        flags: &Option<Vec<PayloadFlag>>,
+        send_id: u32,
+        cmd: u8,
+        args: &[u8],
+        receive_require: bool,
+        timeout_ms: u32,
+        pending_timeout_ms: u32,
+        pending_budget: Duration,
+    ) -> std::result::Result<Vec<u8>, ProtocolError> {
+        Self::run_command_resp_from(
+            interface,
+            flags,
+            send_id,
+            cmd,
+            args,
+            receive_require,
+            timeout_ms,
+            pending_timeout_ms,
+            pending_budget,
+        )
+        .map(|(_, data)| data)
+    }
+
+    /// Same as [ProtocolServer::run_command_resp_timeout], but also returns the CAN ID
+    /// the response came from, or None if there was no response.
+    /// See [DiagCfg::recv_id_mask]
+    #[allow(clippy::too_many_arguments)]
+    fn run_command_resp_from(
+        interface: &mut Box<dyn Interface>,
+        flags: &Option<Vec<PayloadFlag>>,
+        send_id: u32,
+        cmd: u8,
+        args: &[u8],
+        receive_require: bool,
+        timeout_ms: u32,
+        pending_timeout_ms: u32,
+        pending_budget: Duration,
+    ) -> std::result::Result<(Option<u32>, Vec<u8>), ProtocolError> {
         let mut tx_data = vec![cmd];
         tx_data.extend_from_slice(args);
         let mut tx = InterfacePayload::new(send_id, &tx_data);
         if let Some(f) = flags {
             tx.flags = f.clone();
         }
+        log::debug!("DIAG 0x{:04X} -> {:02X?}", send_id, tx_data);
         if !receive_require {
             interface
                 .send_data(&[tx], 0)
-                .map(|_| vec![])
+                .map(|_| (None, vec![]))
                 .map_err(ProtocolError::CommError)
         } else {
-            // Await max 1 second for response
-            let mut res = interface.send_recv_data(tx, 0, 2000)?;
-            if res.data[0] == 0x7F && res.data[2] == 0x78 {
+            let mut res = if Self::is_positive_response_suppressed(cmd, args) {
+                interface.clear_buffer(BufferType::RX)?;
+                interface.send_data(&[tx], 0)?;
+                // Only a negative response can arrive, and it must do so within P2
+                let window = timeout_ms.min(SUPPRESSED_RESPONSE_WINDOW_MS);
+                match interface.recv_data(1, window)?.into_iter().next() {
+                    Some(r) => r,
+                    None => {
+                        log::debug!("DIAG - No negative response to suppressed request");
+                        return Ok((None, vec![]));
+                    }
+                }
+            } else {
+                interface.send_recv_data(tx, 0, timeout_ms)?
+            };
+            let pending_start = Instant::now();
+            while res.data[0] == 0x7F && res.data[2] == 0x78 {
                 // ResponsePending
-                println!("DIAG - ECU is processing request - Waiting!");
-                match interface.recv_data(1, 2000) {
+                let elapsed = pending_start.elapsed();
+                if elapsed >= pending_budget {
+                    return Err(ProtocolError::CustomError(format!(
+                        "ECU exceeded responsePending budget of {} ms",
+                        pending_budget.as_millis()
+                    )));
+                }
+                log::debug!("DIAG - ECU is processing request - Waiting!");
+                let wait_ms =
+                    (pending_timeout_ms as u128).min((pending_budget - elapsed).as_millis());
+                match interface.recv_data(1, wait_ms as u32) {
                     Ok(data) => {
                         if let Some(d) = data.get(0) {
                             res = d.clone();
-                        } else {
+                        } else if pending_start.elapsed() < pending_budget {
                             return Err(ProtocolError::ProtocolError(Box::new(
                                 Self::Error::from_byte(res.data[2]),
                             )));
                         }
+                        // Otherwise the budget check above will fail the request
                     }
                     Err(e) => return Err(ProtocolError::CommError(e)),
                 }
             }
+            log::debug!("DIAG 0x{:04X} <- {:02X?}", res.id, res.data);
             if res.data[0] == 0x7F {
                 // Still error :(
                 Err(ProtocolError::ProtocolError(Box::new(
                     Self::Error::from_byte(res.data[2]),
                 )))
             } else if res.data[0] == (cmd + 0x40) {
-                Ok(res.data)
+                Ok((Some(res.id), res.data))
             } else {
-                eprintln!(
+                log::warn!(
                     "DIAG - Command response did not match request? Send: {:02X} - Recv: {:02X}",
                     cmd, res.data[0]
                 );
@@ -305,3 +551,59 @@ pub trait ProtocolServer: Sized {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dtc(error: &str) -> DTC {
+        DTC {
+            error: error.into(),
+            state: DTCState::Stored,
+            check_engine_on: false,
+            id: 0,
+        }
+    }
+
+    #[test]
+    fn test_nrc_desc() {
+        assert_eq!(DiagProtocol::UDS.nrc_desc(0x11), "Service is not supported");
+        assert_eq!(DiagProtocol::UDS.nrc_desc(0x31), "Requested data is out of range");
+        assert_eq!(DiagProtocol::UDS.nrc_desc(0x78), "ECU is responding. Wait");
+        assert_eq!(DiagProtocol::UDS.nrc_desc(0x50), "Reserved error 0x50");
+        // Every code has a description
+        for nrc in 0..=255 {
+            assert!(!DiagProtocol::UDS.nrc_desc(nrc).is_empty(), "0x{:02X}", nrc);
+        }
+    }
+
+    #[test]
+    fn test_exchange_record() {
+        let record = ExchangeRecord::default();
+        assert_eq!(record.get(), None);
+        record.record(0x22, &[0xF1, 0x90], &Ok(vec![0x62, 0xF1, 0x90, 0x57]));
+        let shared = record.clone();
+        assert_eq!(
+            shared.get(),
+            Some((vec![0x22, 0xF1, 0x90], vec![0x62, 0xF1, 0x90, 0x57]))
+        );
+        // Negative responses arrive as errors
+        let nrc = uds::UDSNegativeCode::from_byte(0x31);
+        record.record(0x22, &[0xF1, 0x90], &Err(ProtocolError::ProtocolError(Box::new(nrc))));
+        assert_eq!(shared.get(), Some((vec![0x22, 0xF1, 0x90], vec![0x7F, 0x22, 0x31])));
+        // Only the last exchange is kept
+        record.record(0x3E, &[0x00], &Err(ProtocolError::Timeout));
+        assert_eq!(shared.get(), Some((vec![0x3E, 0x00], vec![])));
+    }
+
+    #[test]
+    fn test_sae_name() {
+        assert_eq!(dtc("0123").get_sae_name().as_deref(), Some("P0123"));
+        assert_eq!(dtc("8123").get_sae_name().as_deref(), Some("B0123"));
+        assert_eq!(dtc("D00187").get_sae_name().as_deref(), Some("U100187"));
+        assert_eq!(dtc("4A1B2C").get_sae_name().as_deref(), Some("C0A1B2C"));
+        // Already a name, or not hex at all
+        assert_eq!(dtc("P0123").get_sae_name(), None);
+        assert_eq!(dtc("XYZW").get_sae_name(), None);
+    }
+}
@@ -26,6 +26,7 @@ use super::{
 };
 
 pub mod codes;
+pub mod report;
 pub mod service01;
 pub mod service02;
 pub mod service03;
@@ -174,6 +175,19 @@ pub struct ObdServer {
 }
 
 impl ObdServer {
+    pub fn req_service01<T, F: Fn(&Service01) -> ProtocolResult<T>>(
+        &self,
+        func: F,
+    ) -> ProtocolResult<T> {
+        if let Some(s) = &self.s01 {
+            func(s)
+        } else {
+            Err(ProtocolError::CustomError(
+                "Service not supported by ECU".into(),
+            ))
+        }
+    }
+
     pub fn req_service09<T, F: Fn(&Service09) -> ProtocolResult<T>>(
         &self,
         func: F,
@@ -210,8 +224,23 @@ impl ObdServer {
         return res;
     }
 
+    /// Reads the DTCs reported by service 03 (Stored), 07 (Pending) or 0A (Permanent)
+    pub(crate) fn read_dtc_service(&self, sid: u8, state: DTCState) -> OBDError<Vec<DTC>> {
+        let resp = self.run_command(sid, &[])?;
+        let count = resp.get(1).copied().unwrap_or_default() as usize;
+        if resp.len() < 2 + count * 2 {
+            return Err(ProtocolError::InvalidResponseSize {
+                expect: 2 + count * 2,
+                actual: resp.len(),
+            });
+        }
+        let mut res = Vec::new();
+        Self::decode_dtc_resp(&resp[1..], state, &mut res);
+        Ok(res)
+    }
+
     // Used for services 03, 07 and 0A
-    fn decode_dtc_resp(&self, bytes: &[u8], state: DTCState, res: &mut Vec<DTC>) {
+    fn decode_dtc_resp(bytes: &[u8], state: DTCState, res: &mut Vec<DTC>) {
         let num_dtcs = bytes[0];
         if num_dtcs == 0 {
             return;
@@ -226,7 +255,7 @@ impl ObdServer {
                 _ => 'U',
             };
 
-            let second = match (a & 0b00001100) >> 4 {
+            let second = match (a & 0b00110000) >> 4 {
                 0 => '0',
                 1 => '1',
                 2 => '2',
@@ -344,6 +373,7 @@ impl ProtocolServer for ObdServer {
             server.s01 = Some(r)
         }
         server.s03 = Some(service03::Service03);
+        server.s07 = Some(service07::Service07);
         if let Some(r) = Service09::init(&server) {
             server.s09 = Some(r)
         }
@@ -374,17 +404,17 @@ impl ProtocolServer for ObdServer {
         if let Ok(resp) = self.run_command(0x03, &[]) {
             //  Stored DTCs
             println!("S03: {:02X?}", resp);
-            self.decode_dtc_resp(&resp[1..], DTCState::Stored, &mut res);
+            Self::decode_dtc_resp(&resp[1..], DTCState::Stored, &mut res);
         }
         if let Ok(resp) = self.run_command(0x07, &[]) {
             // Pending DTCs
             println!("S07: {:02X?}", resp);
-            self.decode_dtc_resp(&resp[1..], DTCState::Pending, &mut res);
+            Self::decode_dtc_resp(&resp[1..], DTCState::Pending, &mut res);
         }
         if let Ok(resp) = self.run_command(0x0A, &[]) {
             // Permanent DTCs
             println!("S0A: {:02X?}", resp);
-            self.decode_dtc_resp(&resp[1..], DTCState::Permanent, &mut res);
+            Self::decode_dtc_resp(&resp[1..], DTCState::Permanent, &mut res);
         }
         return Ok(res);
     }
@@ -0,0 +1,244 @@
+//! One-shot vehicle health report, combining the VIN, ECU identification and DTCs
+//! read over OBD-II into something that can be handed to a customer.
+//!
+//! Services the vehicle does not support are left empty in the report rather than
+//! failing it, as most vehicles only support a subset of them.
+
+use serde::Serialize;
+
+use crate::commapi::protocols::{vin::Vin, DTCState, DTC};
+
+use super::ObdServer;
+
+/// A DTC, with its description from the generic OBD-II code list
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportDtc {
+    pub code: String,
+    pub description: String,
+}
+
+impl From<&DTC> for ReportDtc {
+    fn from(dtc: &DTC) -> Self {
+        Self {
+            code: dtc.error.clone(),
+            description: ObdServer::get_dtc_desc(dtc),
+        }
+    }
+}
+
+/// Everything in a vehicle report. Each field is None if the vehicle did not
+/// support reading it
+#[derive(Debug, Clone, Serialize)]
+pub struct VehicleReport {
+    /// Local time the report was generated
+    pub generated: String,
+    pub vin: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model_year: Option<u32>,
+    pub ecu_name: Option<String>,
+    pub calibration_id: Option<String>,
+    pub cvns: Option<Vec<String>>,
+    /// DTCs which have turned on the check engine light (Service 03)
+    pub stored_dtcs: Option<Vec<ReportDtc>>,
+    /// DTCs detected in the current or last drive cycle (Service 07)
+    pub pending_dtcs: Option<Vec<ReportDtc>>,
+    /// DTCs which cannot be cleared with a scan tool (Service 0A)
+    pub permanent_dtcs: Option<Vec<ReportDtc>>,
+}
+
+/// Reads DTCs with service 03, 07 or 0A. Returns None if the service is not
+/// supported, or the response is malformed
+fn read_dtcs(server: &ObdServer, sid: u8, state: DTCState) -> Option<Vec<ReportDtc>> {
+    let dtcs = server
+        .read_dtc_service(sid, state)
+        .inspect_err(|e| {
+            log::warn!(
+                "OBD - Cannot read DTCs with service {:02X}: {}",
+                sid,
+                e.get_text()
+            )
+        })
+        .ok()?;
+    Some(dtcs.iter().map(ReportDtc::from).collect())
+}
+
+/// Strips the message count and any padding around an ASCII Service 09 value
+fn clean_ascii(s: String) -> String {
+    s.trim_matches(|c: char| !c.is_ascii_graphic()).to_string()
+}
+
+/// Reads everything in a [VehicleReport] from the vehicle
+pub fn generate_report(server: &ObdServer) -> VehicleReport {
+    let vin = server
+        .req_service09(|s| s.get_vin(server))
+        .ok()
+        .map(clean_ascii);
+    let decoded = vin.clone().and_then(Vin::new);
+    VehicleReport {
+        generated: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        manufacturer: decoded.as_ref().map(|v| v.manufacture_name.clone()),
+        model_year: decoded.as_ref().map(|v| v.year),
+        vin,
+        ecu_name: server
+            .req_service09(|s| s.get_ecu_name(server))
+            .ok()
+            .map(clean_ascii),
+        calibration_id: server
+            .req_service09(|s| s.get_calibration_id(server))
+            .ok()
+            .map(clean_ascii),
+        cvns: server
+            .req_service09(|s| s.get_calibration_verification_numbers(server))
+            .ok(),
+        stored_dtcs: read_dtcs(server, 0x03, DTCState::Stored),
+        pending_dtcs: read_dtcs(server, 0x07, DTCState::Pending),
+        permanent_dtcs: read_dtcs(server, 0x0A, DTCState::Permanent),
+    }
+}
+
+const NOT_SUPPORTED: &str = "Not supported";
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl VehicleReport {
+    /// Identification fields as (Name, value) pairs, for rendering
+    fn ident_rows(&self) -> Vec<(&'static str, String)> {
+        let or_unsupported = |v: &Option<String>| v.clone().unwrap_or_else(|| NOT_SUPPORTED.into());
+        vec![
+            ("VIN", or_unsupported(&self.vin)),
+            ("Manufacturer", or_unsupported(&self.manufacturer)),
+            (
+                "Model year",
+                or_unsupported(&self.model_year.map(|y| y.to_string())),
+            ),
+            ("ECU name", or_unsupported(&self.ecu_name)),
+            ("Calibration ID", or_unsupported(&self.calibration_id)),
+            (
+                "CVNs",
+                or_unsupported(&self.cvns.as_ref().map(|c| c.join(", "))),
+            ),
+        ]
+    }
+
+    fn dtc_sections(&self) -> [(&'static str, &Option<Vec<ReportDtc>>); 3] {
+        [
+            ("Stored DTCs", &self.stored_dtcs),
+            ("Pending DTCs", &self.pending_dtcs),
+            ("Permanent DTCs", &self.permanent_dtcs),
+        ]
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut res = format!("Vehicle report - {}\n\n", self.generated);
+        for (name, value) in self.ident_rows() {
+            res.push_str(&format!("{:<16}{}\n", format!("{}:", name), value));
+        }
+        for (name, dtcs) in self.dtc_sections().iter() {
+            res.push_str(&format!("\n{}:\n", name));
+            match dtcs {
+                None => res.push_str(&format!("  {}\n", NOT_SUPPORTED)),
+                Some(d) if d.is_empty() => res.push_str("  None\n"),
+                Some(d) => {
+                    for dtc in d {
+                        res.push_str(&format!("  {} - {}\n", dtc.code, dtc.description))
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut res = String::from("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\">");
+        res.push_str("<title>Vehicle report</title></head>\n<body>\n");
+        res.push_str(&format!(
+            "<h1>Vehicle report</h1>\n<p>Generated {}</p>\n<table>\n",
+            escape_html(&self.generated)
+        ));
+        for (name, value) in self.ident_rows() {
+            res.push_str(&format!(
+                "<tr><th>{}</th><td>{}</td></tr>\n",
+                name,
+                escape_html(&value)
+            ));
+        }
+        res.push_str("</table>\n");
+        for (name, dtcs) in self.dtc_sections().iter() {
+            res.push_str(&format!("<h2>{}</h2>\n", name));
+            match dtcs {
+                None => res.push_str(&format!("<p>{}</p>\n", NOT_SUPPORTED)),
+                Some(d) if d.is_empty() => res.push_str("<p>None</p>\n"),
+                Some(d) => {
+                    res.push_str("<ul>\n");
+                    for dtc in d {
+                        res.push_str(&format!(
+                            "<li><b>{}</b> - {}</li>\n",
+                            escape_html(&dtc.code),
+                            escape_html(&dtc.description)
+                        ));
+                    }
+                    res.push_str("</ul>\n");
+                }
+            }
+        }
+        res.push_str("</body>\n</html>\n");
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> VehicleReport {
+        VehicleReport {
+            generated: "2021-06-01 12:00:00".into(),
+            vin: Some("WDD2030462A123456".into()),
+            manufacturer: None,
+            model_year: None,
+            ecu_name: Some("ECM-EngineControl".into()),
+            calibration_id: None,
+            cvns: None,
+            stored_dtcs: Some(vec![ReportDtc {
+                code: "P0101".into(),
+                description: "Mass <Air> Flow".into(),
+            }]),
+            pending_dtcs: Some(vec![]),
+            permanent_dtcs: None,
+        }
+    }
+
+    #[test]
+    fn test_render_report() {
+        let text = report().to_text();
+        assert!(text.contains("VIN:            WDD2030462A123456\n"));
+        assert!(text.contains("Calibration ID: Not supported\n"));
+        assert!(text.contains("Stored DTCs:\n  P0101 - Mass <Air> Flow\n"));
+        assert!(text.contains("Pending DTCs:\n  None\n"));
+        assert!(text.contains("Permanent DTCs:\n  Not supported\n"));
+
+        let html = report().to_html();
+        assert!(html.contains("<li><b>P0101</b> - Mass &lt;Air&gt; Flow</li>"));
+
+        let json: serde_json::Value = serde_json::from_str(&report().to_json()).unwrap();
+        assert_eq!(json["stored_dtcs"][0]["code"], "P0101");
+        assert!(json["permanent_dtcs"].is_null());
+    }
+
+    #[test]
+    fn test_clean_ascii() {
+        assert_eq!(
+            clean_ascii("\u{1}WDD2030462A123456\0\0".into()),
+            "WDD2030462A123456"
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::{borrow::Borrow, cmp::min, sync::Arc, vec};
+use std::{borrow::Borrow, cmp::min, collections::HashMap, sync::Arc, vec};
 
 use lazy_static::lazy_static;
 
@@ -156,7 +156,7 @@ impl PidList {
             "Engine speed",
             "rpm",
             (0.0, 16383.75),
-            &|a, b, _, _| ((256.0 * a) + b) / 4.0,
+            &|a, b, _, _| PidList::be_u16(a, b) / 4.0,
         );
         res.add_func_num(0x0D, "Vehicle speed", "km/h", (0.0, 255.0), &|a, _, _, _| a);
         res.add_func_num(
@@ -178,7 +178,7 @@ impl PidList {
             "Mass air flow sensor (MAF)",
             "grams/sec",
             (0.0, 655.35),
-            &|a, b, _, _| ((256.0 * a) + b) / 100.0,
+            &|a, b, _, _| PidList::be_u16(a, b) / 100.0,
         );
         res.add_func_num(
             0x11,
@@ -262,28 +262,28 @@ impl PidList {
             "Run time since engine start",
             "seconds",
             (0.0, 65535.0),
-            &|a, b, _, _| (a * 256.0) + b,
+            &|a, b, _, _| PidList::be_u16(a, b),
         );
         res.add_func_num(
             0x21,
             "Distance traveled with MIL on",
             "km",
             (0.0, 65535.0),
-            &|a, b, _, _| (a * 256.0) + b,
+            &|a, b, _, _| PidList::be_u16(a, b),
         );
         res.add_func_num(
             0x22,
             "Fuel rail pressure",
             "kPa",
             (0.0, 5177.265),
-            &|a, b, _, _| 0.079 * ((a * 256.0) + b),
+            &|a, b, _, _| 0.079 * PidList::be_u16(a, b),
         );
         res.add_func_num(
             0x23,
             "Fuel rail gauge pressure",
             "kPa",
             (0.0, 655350.0),
-            &|a, b, _, _| 10.0 * ((a * 256.0) + b),
+            &|a, b, _, _| 10.0 * PidList::be_u16(a, b),
         );
         res.add_func_mult(
             0x24,
@@ -292,8 +292,8 @@ impl PidList {
             &[(0.0, 2.0), (-100.0, 8.0)],
             &|a, b, c, d| {
                 vec![
-                    (2.0 / 65536.0) * ((256.0 * a) + b),
-                    (8.0 / 65536.0) * ((256.0 * c) + d),
+                    (2.0 / 65536.0) * PidList::be_u16(a, b),
+                    (8.0 / 65536.0) * PidList::be_u16(c, d),
                 ]
             },
         );
@@ -304,8 +304,8 @@ impl PidList {
             &[(0.0, 2.0), (-100.0, 8.0)],
             &|a, b, c, d| {
                 vec![
-                    (2.0 / 65536.0) * ((256.0 * a) + b),
-                    (8.0 / 65536.0) * ((256.0 * c) + d),
+                    (2.0 / 65536.0) * PidList::be_u16(a, b),
+                    (8.0 / 65536.0) * PidList::be_u16(c, d),
                 ]
             },
         );
@@ -316,8 +316,8 @@ impl PidList {
             &[(0.0, 2.0), (-100.0, 8.0)],
             &|a, b, c, d| {
                 vec![
-                    (2.0 / 65536.0) * ((256.0 * a) + b),
-                    (8.0 / 65536.0) * ((256.0 * c) + d),
+                    (2.0 / 65536.0) * PidList::be_u16(a, b),
+                    (8.0 / 65536.0) * PidList::be_u16(c, d),
                 ]
             },
         );
@@ -328,8 +328,8 @@ impl PidList {
             &[(0.0, 2.0), (-100.0, 8.0)],
             &|a, b, c, d| {
                 vec![
-                    (2.0 / 65536.0) * ((256.0 * a) + b),
-                    (8.0 / 65536.0) * ((256.0 * c) + d),
+                    (2.0 / 65536.0) * PidList::be_u16(a, b),
+                    (8.0 / 65536.0) * PidList::be_u16(c, d),
                 ]
             },
         );
@@ -340,8 +340,8 @@ impl PidList {
             &[(0.0, 2.0), (-100.0, 8.0)],
             &|a, b, c, d| {
                 vec![
-                    (2.0 / 65536.0) * ((256.0 * a) + b),
-                    (8.0 / 65536.0) * ((256.0 * c) + d),
+                    (2.0 / 65536.0) * PidList::be_u16(a, b),
+                    (8.0 / 65536.0) * PidList::be_u16(c, d),
                 ]
             },
         );
@@ -352,8 +352,8 @@ impl PidList {
             &[(0.0, 2.0), (-100.0, 8.0)],
             &|a, b, c, d| {
                 vec![
-                    (2.0 / 65536.0) * ((256.0 * a) + b),
-                    (8.0 / 65536.0) * ((256.0 * c) + d),
+                    (2.0 / 65536.0) * PidList::be_u16(a, b),
+                    (8.0 / 65536.0) * PidList::be_u16(c, d),
                 ]
             },
         );
@@ -364,8 +364,8 @@ impl PidList {
             &[(0.0, 2.0), (-100.0, 8.0)],
             &|a, b, c, d| {
                 vec![
-                    (2.0 / 65536.0) * ((256.0 * a) + b),
-                    (8.0 / 65536.0) * ((256.0 * c) + d),
+                    (2.0 / 65536.0) * PidList::be_u16(a, b),
+                    (8.0 / 65536.0) * PidList::be_u16(c, d),
                 ]
             },
         );
@@ -376,8 +376,8 @@ impl PidList {
             &[(0.0, 2.0), (-100.0, 8.0)],
             &|a, b, c, d| {
                 vec![
-                    (2.0 / 65536.0) * ((256.0 * a) + b),
-                    (8.0 / 65536.0) * ((256.0 * c) + d),
+                    (2.0 / 65536.0) * PidList::be_u16(a, b),
+                    (8.0 / 65536.0) * PidList::be_u16(c, d),
                 ]
             },
         );
@@ -406,14 +406,14 @@ impl PidList {
             "Distance traveled since codes cleared",
             "km",
             (0.0, 65535.0),
-            &|a, b, _, _| 0.079 * ((a * 256.0) + b),
+            &|a, b, _, _| 0.079 * PidList::be_u16(a, b),
         );
         res.add_func_num(
             0x32,
             "Evap. System vapor pressure",
             "Pa",
             (-8192.0, 8191.75),
-            &|a, b, _, _| 0.25 * ((a * 256.0) + b),
+            &|a, b, _, _| 0.25 * PidList::be_i16(a, b),
         );
         res.add_func_num(
             0x33,
@@ -427,105 +427,105 @@ impl PidList {
             &["O2 sensor 1 Air-fuel ratio", "O2 sensor 1 current"],
             &["", "mA"],
             &[(0.0, 2.0), (0.0, 128.0)],
-            &|a, b, c, d| vec![(2.0 / 65536.0) * ((256.0 * a) + b), c + (d / 256.0) - 128.0],
+            &|a, b, c, d| vec![(2.0 / 65536.0) * PidList::be_u16(a, b), c + (d / 256.0) - 128.0],
         );
         res.add_func_mult(
             0x35,
             &["O2 sensor 2 Air-fuel ratio", "O2 sensor 2 current"],
             &["", "mA"],
             &[(0.0, 2.0), (0.0, 128.0)],
-            &|a, b, c, d| vec![(2.0 / 65536.0) * ((256.0 * a) + b), c + (d / 256.0) - 128.0],
+            &|a, b, c, d| vec![(2.0 / 65536.0) * PidList::be_u16(a, b), c + (d / 256.0) - 128.0],
         );
         res.add_func_mult(
             0x36,
             &["O2 sensor 3 Air-fuel ratio", "O2 sensor 3 current"],
             &["", "mA"],
             &[(0.0, 2.0), (0.0, 128.0)],
-            &|a, b, c, d| vec![(2.0 / 65536.0) * ((256.0 * a) + b), c + (d / 256.0) - 128.0],
+            &|a, b, c, d| vec![(2.0 / 65536.0) * PidList::be_u16(a, b), c + (d / 256.0) - 128.0],
         );
         res.add_func_mult(
             0x37,
             &["O2 sensor 4 Air-fuel ratio", "O2 sensor 4 current"],
             &["", "mA"],
             &[(0.0, 2.0), (0.0, 128.0)],
-            &|a, b, c, d| vec![(2.0 / 65536.0) * ((256.0 * a) + b), c + (d / 256.0) - 128.0],
+            &|a, b, c, d| vec![(2.0 / 65536.0) * PidList::be_u16(a, b), c + (d / 256.0) - 128.0],
         );
         res.add_func_mult(
             0x38,
             &["O2 sensor 5 Air-fuel ratio", "O2 sensor 5 current"],
             &["", "mA"],
             &[(0.0, 2.0), (0.0, 128.0)],
-            &|a, b, c, d| vec![(2.0 / 65536.0) * ((256.0 * a) + b), c + (d / 256.0) - 128.0],
+            &|a, b, c, d| vec![(2.0 / 65536.0) * PidList::be_u16(a, b), c + (d / 256.0) - 128.0],
         );
         res.add_func_mult(
             0x39,
             &["O2 sensor 6 Air-fuel ratio", "O2 sensor 6 current"],
             &["", "mA"],
             &[(0.0, 2.0), (0.0, 128.0)],
-            &|a, b, c, d| vec![(2.0 / 65536.0) * ((256.0 * a) + b), c + (d / 256.0) - 128.0],
+            &|a, b, c, d| vec![(2.0 / 65536.0) * PidList::be_u16(a, b), c + (d / 256.0) - 128.0],
         );
         res.add_func_mult(
             0x3A,
             &["O2 sensor 7 Air-fuel ratio", "O2 sensor 7 current"],
             &["", "mA"],
             &[(0.0, 2.0), (0.0, 128.0)],
-            &|a, b, c, d| vec![(2.0 / 65536.0) * ((256.0 * a) + b), c + (d / 256.0) - 128.0],
+            &|a, b, c, d| vec![(2.0 / 65536.0) * PidList::be_u16(a, b), c + (d / 256.0) - 128.0],
         );
         res.add_func_mult(
             0x3B,
             &["O2 sensor 8 Air-fuel ratio", "O2 sensor 8 current"],
             &["", "mA"],
             &[(0.0, 2.0), (0.0, 128.0)],
-            &|a, b, c, d| vec![(2.0 / 65536.0) * ((256.0 * a) + b), c + (d / 256.0) - 128.0],
+            &|a, b, c, d| vec![(2.0 / 65536.0) * PidList::be_u16(a, b), c + (d / 256.0) - 128.0],
         );
         res.add_func_num(
             0x3C,
             "Catalyst temperature. Bank 1, Sensor 1",
             "\u{00B0}C",
             (-40.0, 6513.5),
-            &|a, b, _, _| 0.1 * ((a * 256.0) + b),
+            &|a, b, _, _| 0.1 * PidList::be_u16(a, b),
         );
         res.add_func_num(
             0x3D,
             "Catalyst temperature. Bank 2, Sensor 1",
             "\u{00B0}C",
             (-40.0, 6513.5),
-            &|a, b, _, _| 0.1 * ((a * 256.0) + b),
+            &|a, b, _, _| 0.1 * PidList::be_u16(a, b),
         );
         res.add_func_num(
             0x3E,
             "Catalyst temperature. Bank 1, Sensor 2",
             "\u{00B0}C",
             (-40.0, 6513.5),
-            &|a, b, _, _| 0.1 * ((a * 256.0) + b),
+            &|a, b, _, _| 0.1 * PidList::be_u16(a, b),
         );
         res.add_func_num(
             0x3F,
             "Catalyst temperature. Bank 2, Sensor 2",
             "\u{00B0}C",
             (-40.0, 6513.5),
-            &|a, b, _, _| 0.1 * ((a * 256.0) + b),
+            &|a, b, _, _| 0.1 * PidList::be_u16(a, b),
         );
         res.add_func_num(
             0x42,
             "Control module voltage",
             "V",
             (0.0, 65.535),
-            &|a, b, _, _| 0.001 * ((a * 256.0) + b),
+            &|a, b, _, _| 0.001 * PidList::be_u16(a, b),
         );
         res.add_func_num(
             0x43,
             "Absolute load value",
             "%",
             (0.0, 25700.0),
-            &|a, b, _, _| ((a * 256.0) + b) * (100.0 / 255.0),
+            &|a, b, _, _| PidList::be_u16(a, b) * (100.0 / 255.0),
         );
         res.add_func_num(
             0x44,
             "Commanded air-fuel ratio",
             "",
             (0.0, 2.0),
-            &|a, b, _, _| ((a * 256.0) + b) * (2.0 / 65536.0),
+            &|a, b, _, _| PidList::be_u16(a, b) * (2.0 / 65536.0),
         );
         res.add_func_num(
             0x45,
@@ -588,14 +588,14 @@ impl PidList {
             "Time with MIL on",
             "Minutes",
             (0.0, 65535.0),
-            &|a, b, _, _| (a * 256.0) + b,
+            &|a, b, _, _| PidList::be_u16(a, b),
         );
         res.add_func_num(
             0x4E,
             "Time since trouble code cleared",
             "Minutes",
             (0.0, 65535.0),
-            &|a, b, _, _| (a * 256.0) + b,
+            &|a, b, _, _| PidList::be_u16(a, b),
         );
         res.add_func_str(0x51, "Fuel type", &|a, _, _, _| PidList::get_fuel_type(a));
         res.add_func_num(
@@ -610,14 +610,14 @@ impl PidList {
             "Absolute Evap system vapor pressure",
             "kPa",
             (0.0, 327.675),
-            &|a, b, _, _| ((256.0 * a) + b) / 200.0,
+            &|a, b, _, _| PidList::be_u16(a, b) / 200.0,
         );
         res.add_func_num(
             0x54,
             "Evap system vapor pressure",
             "Pa",
-            (-32767.0, 32768.0),
-            &|a, b, _, _| ((256.0 * a) + b) - 32767.0,
+            (-32768.0, 32767.0),
+            &|a, b, _, _| PidList::be_i16(a, b),
         );
 
         res.add_func_mult(
@@ -665,7 +665,7 @@ impl PidList {
             "Fuel rail absolute pressure",
             "kPa",
             (0.0, 655350.0),
-            &|a, b, _, _| 10.0 * (a * 256.0 + b),
+            &|a, b, _, _| 10.0 * PidList::be_u16(a, b),
         );
         res.add_func_num(
             0x5A,
@@ -693,14 +693,14 @@ impl PidList {
             "Fuel injection timing",
             "\u{00B0}",
             (0.0, 100.0),
-            &|a, b, _, _| (((256.0 * a) + b) / 128.0) - 210.0,
+            &|a, b, _, _| (PidList::be_u16(a, b) / 128.0) - 210.0,
         );
         res.add_func_num(
             0x5E,
             "Engine fuel rate",
             "L/h",
             (0.0, 3212.75),
-            &|a, b, _, _| ((256.0 * a) + b) / 20.0,
+            &|a, b, _, _| PidList::be_u16(a, b) / 20.0,
         );
 
         res.add_func_num(
@@ -708,13 +708,7 @@ impl PidList {
             "Odometer reading",
             "km",
             (0.0, 429496729.5),
-            &|a, b, c, d| {
-                ((a * 2i32.pow(24) as f32)
-                    + (b * 2i32.pow(16) as f32)
-                    + (c * 2i32.pow(8) as f32)
-                    + d)
-                    / 10.0
-            },
+            &|a, b, c, d| (PidList::be_u32(a, b, c, d) as f64 / 10.0) as f32,
         );
         res
     }
@@ -782,6 +776,22 @@ impl PidList {
         })
     }
 
+    /// Assembles a 2 byte PID value (A, B) as big endian, as per SAE J1979
+    fn be_u16(a: f32, b: f32) -> f32 {
+        u16::from_be_bytes([a as u8, b as u8]) as f32
+    }
+
+    /// Assembles a signed (two's complement) 2 byte PID value (A, B) as big endian
+    fn be_i16(a: f32, b: f32) -> f32 {
+        i16::from_be_bytes([a as u8, b as u8]) as f32
+    }
+
+    /// Assembles a 4 byte PID value (A, B, C, D) as big endian, as per SAE J1979.
+    /// Returned as an integer, since f32 cannot represent every 32 bit value
+    fn be_u32(a: f32, b: f32, c: f32, d: f32) -> u32 {
+        u32::from_be_bytes([a as u8, b as u8, c as u8, d as u8])
+    }
+
     pub fn parse_pid(&self, pid: u8, args: &[u8]) -> Option<PidReturnType> {
         let parser = self.pids[pid as usize].as_ref()?;
         let len = min(4, args.len());
@@ -882,6 +892,175 @@ impl PidList {
     }
 }
 
+/// An emissions monitor, whose readiness is reported by PID 0x01
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Monitor {
+    Misfire,
+    FuelSystem,
+    Components,
+    // Spark ignition (Petrol) engines only
+    Catalyst,
+    HeatedCatalyst,
+    EvapSystem,
+    SecondaryAir,
+    AcRefrigerant,
+    O2Sensor,
+    O2SensorHeater,
+    Egr,
+    // Compression ignition (Diesel) engines only
+    NmhcCatalyst,
+    NoxScr,
+    BoostPressure,
+    ExhaustGasSensor,
+    PmFilter,
+    EgrVvt,
+}
+
+/// Monitors which every engine can have (Byte B bits 0-2)
+const COMMON_MONITORS: [Monitor; 3] = [Monitor::Misfire, Monitor::FuelSystem, Monitor::Components];
+
+/// Monitors of a spark ignition engine, by bit in bytes C and D
+const SPARK_MONITORS: [Option<Monitor>; 8] = [
+    Some(Monitor::Catalyst),
+    Some(Monitor::HeatedCatalyst),
+    Some(Monitor::EvapSystem),
+    Some(Monitor::SecondaryAir),
+    Some(Monitor::AcRefrigerant),
+    Some(Monitor::O2Sensor),
+    Some(Monitor::O2SensorHeater),
+    Some(Monitor::Egr),
+];
+
+/// Monitors of a compression ignition engine, by bit in bytes C and D. Bits 2 and 4 are
+/// reserved
+const COMPRESSION_MONITORS: [Option<Monitor>; 8] = [
+    Some(Monitor::NmhcCatalyst),
+    Some(Monitor::NoxScr),
+    None,
+    Some(Monitor::BoostPressure),
+    None,
+    Some(Monitor::ExhaustGasSensor),
+    Some(Monitor::PmFilter),
+    Some(Monitor::EgrVvt),
+];
+
+impl Monitor {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Monitor::Misfire => "Misfire",
+            Monitor::FuelSystem => "Fuel system",
+            Monitor::Components => "Comprehensive components",
+            Monitor::Catalyst => "Catalyst",
+            Monitor::HeatedCatalyst => "Heated catalyst",
+            Monitor::EvapSystem => "Evaporative system",
+            Monitor::SecondaryAir => "Secondary air system",
+            Monitor::AcRefrigerant => "A/C refrigerant",
+            Monitor::O2Sensor => "Oxygen sensor",
+            Monitor::O2SensorHeater => "Oxygen sensor heater",
+            Monitor::Egr => "EGR system",
+            Monitor::NmhcCatalyst => "NMHC catalyst",
+            Monitor::NoxScr => "NOx/SCR aftertreatment",
+            Monitor::BoostPressure => "Boost pressure",
+            Monitor::ExhaustGasSensor => "Exhaust gas sensor",
+            Monitor::PmFilter => "PM filter",
+            Monitor::EgrVvt => "EGR and/or VVT system",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MonitorReadiness {
+    /// The monitor has completed its test since DTCs were last cleared
+    Ready,
+    /// The monitor has not completed its test yet
+    NotReady,
+    /// The vehicle does not have the monitor
+    NotSupported,
+}
+
+/// Monitor status since DTCs were last cleared (PID 0x01). This is what an emissions
+/// inspection checks before testing the vehicle
+#[derive(Debug, Clone)]
+pub struct MonitorStatus {
+    /// Check engine light (Malfunction indicator lamp) is on
+    pub mil_on: bool,
+    /// Number of emissions related DTCs stored
+    pub dtc_count: u8,
+    /// Vehicle is a compression ignition (Diesel) engine. This changes which monitors
+    /// bytes C and D describe
+    pub compression_ignition: bool,
+    pub readiness: HashMap<Monitor, MonitorReadiness>,
+}
+
+impl MonitorStatus {
+    /// Decodes the 4 bytes (A-D) of PID 0x01
+    pub fn decode(args: &[u8]) -> OBDError<Self> {
+        if args.len() < 4 {
+            return Err(ProtocolError::InvalidResponseSize {
+                expect: 4,
+                actual: args.len(),
+            });
+        }
+        let (a, b, c, d) = (args[0], args[1], args[2], args[3]);
+        let compression_ignition = b & 0b00001000 != 0;
+        let readiness_of = |supported: bool, incomplete: bool| match (supported, incomplete) {
+            (false, _) => MonitorReadiness::NotSupported,
+            (true, false) => MonitorReadiness::Ready,
+            (true, true) => MonitorReadiness::NotReady,
+        };
+
+        let mut readiness = HashMap::new();
+        for (bit, m) in COMMON_MONITORS.iter().enumerate() {
+            // Supported bits are 0-2, incomplete bits are 4-6
+            readiness.insert(
+                *m,
+                readiness_of(b & (1 << bit) != 0, b & (1 << (bit + 4)) != 0),
+            );
+        }
+        let monitors = if compression_ignition {
+            &COMPRESSION_MONITORS
+        } else {
+            &SPARK_MONITORS
+        };
+        for (bit, m) in monitors.iter().enumerate() {
+            if let Some(m) = m {
+                readiness.insert(*m, readiness_of(c & (1 << bit) != 0, d & (1 << bit) != 0));
+            }
+        }
+        Ok(Self {
+            mil_on: a & 0b10000000 != 0,
+            dtc_count: a & 0b01111111,
+            compression_ignition,
+            readiness,
+        })
+    }
+
+    /// Returns the monitors the vehicle has, in the order J1979 lists them
+    pub fn get_monitors(&self) -> Vec<(Monitor, MonitorReadiness)> {
+        let monitors = if self.compression_ignition {
+            &COMPRESSION_MONITORS
+        } else {
+            &SPARK_MONITORS
+        };
+        COMMON_MONITORS
+            .iter()
+            .chain(monitors.iter().flatten())
+            .filter_map(|m| match self.readiness.get(m) {
+                Some(MonitorReadiness::NotSupported) | None => None,
+                Some(r) => Some((*m, *r)),
+            })
+            .collect()
+    }
+
+    /// Number of monitors which have not completed their test
+    pub fn not_ready_count(&self) -> usize {
+        self.readiness
+            .values()
+            .filter(|r| **r == MonitorReadiness::NotReady)
+            .count()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Service01 {
     supported_pids: Vec<bool>,
@@ -964,6 +1143,13 @@ impl Service01 {
         Ok(PID_LIST.parse_pid(pid, &bytes[2..]))
     }
 
+    /// Reads the MIL, DTC count and readiness of each emissions monitor (PID 0x01)
+    pub fn read_monitor_status(&self, s: &ObdServer) -> OBDError<MonitorStatus> {
+        self.check_service_supported(0x01)?;
+        let bytes = s.run_command(0x01, &[0x01])?;
+        MonitorStatus::decode(bytes.get(2..).unwrap_or_default())
+    }
+
     pub fn get_supported_chartable_pids(&self) -> Vec<(u8, Vec<&'static str>)> {
         (0x01..0xFF as u8)
             .filter(|x| self.check_service_supported(*x).is_ok())
@@ -973,3 +1159,112 @@ impl Service01 {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_num(pid: u8, args: &[u8]) -> f32 {
+        match PID_LIST.parse_pid(pid, args) {
+            Some(PidReturnType::Number(r)) => r.res,
+            x => panic!("PID 0x{:02X} did not decode to a number: {:?}", pid, x),
+        }
+    }
+
+    #[test]
+    fn test_rpm_big_endian() {
+        assert_eq!(parse_num(0x0C, &[0x00, 0x00]), 0.0);
+        assert_eq!(parse_num(0x0C, &[0x0F, 0xA0]), 1000.0);
+        assert_eq!(parse_num(0x0C, &[0x00, 0x04]), 1.0);
+        assert_eq!(parse_num(0x0C, &[0xFF, 0xFF]), 16383.75);
+    }
+
+    #[test]
+    fn test_maf_big_endian() {
+        assert_eq!(parse_num(0x10, &[0x01, 0x00]), 2.56);
+        assert_eq!(parse_num(0x10, &[0xFF, 0xFF]), 655.35);
+    }
+
+    #[test]
+    fn test_evap_vapor_pressure_signed() {
+        assert_eq!(parse_num(0x32, &[0x00, 0x00]), 0.0);
+        assert_eq!(parse_num(0x32, &[0xFF, 0xF0]), -4.0);
+        assert_eq!(parse_num(0x32, &[0x7F, 0xFF]), 8191.75);
+        assert_eq!(parse_num(0x32, &[0x80, 0x00]), -8192.0);
+
+        assert_eq!(parse_num(0x54, &[0x00, 0x00]), 0.0);
+        assert_eq!(parse_num(0x54, &[0xFF, 0xF0]), -16.0);
+        assert_eq!(parse_num(0x54, &[0x7F, 0xFF]), 32767.0);
+        assert_eq!(parse_num(0x54, &[0x80, 0x00]), -32768.0);
+    }
+
+    #[test]
+    fn test_odometer_big_endian() {
+        assert_eq!(parse_num(0xC0, &[0x00, 0x00, 0x00, 0x0A]), 1.0);
+        assert_eq!(parse_num(0xC0, &[0x01, 0x00, 0x00, 0x00]), 1677721.6);
+        assert_eq!(parse_num(0xC0, &[0xFF, 0xFF, 0xFF, 0xFF]), 429496729.5);
+    }
+
+    #[test]
+    fn test_monitor_status() {
+        // MIL on, 2 DTCs. Spark ignition, misfire ready, fuel system not ready.
+        // Catalyst ready, evap and O2 sensor not ready
+        let status = MonitorStatus::decode(&[0x82, 0x23, 0x25, 0x24]).unwrap();
+        assert!(status.mil_on);
+        assert_eq!(status.dtc_count, 2);
+        assert!(!status.compression_ignition);
+        assert_eq!(status.readiness[&Monitor::Misfire], MonitorReadiness::Ready);
+        assert_eq!(
+            status.readiness[&Monitor::FuelSystem],
+            MonitorReadiness::NotReady
+        );
+        assert_eq!(
+            status.readiness[&Monitor::Components],
+            MonitorReadiness::NotSupported
+        );
+        assert_eq!(
+            status.readiness[&Monitor::Catalyst],
+            MonitorReadiness::Ready
+        );
+        assert_eq!(
+            status.readiness[&Monitor::EvapSystem],
+            MonitorReadiness::NotReady
+        );
+        assert_eq!(
+            status.readiness[&Monitor::O2Sensor],
+            MonitorReadiness::NotReady
+        );
+        assert_eq!(status.not_ready_count(), 3);
+        assert_eq!(status.get_monitors().len(), 5);
+
+        // Same bytes C and D on a compression ignition engine
+        let status = MonitorStatus::decode(&[0x00, 0x0B, 0x25, 0x24]).unwrap();
+        assert!(!status.mil_on);
+        assert!(status.compression_ignition);
+        assert_eq!(
+            status.readiness[&Monitor::NmhcCatalyst],
+            MonitorReadiness::Ready
+        );
+        assert_eq!(
+            status.readiness[&Monitor::ExhaustGasSensor],
+            MonitorReadiness::NotReady
+        );
+        assert!(!status.readiness.contains_key(&Monitor::Catalyst));
+        // Reserved bit 2 is not a monitor
+        assert_eq!(status.not_ready_count(), 1);
+
+        assert!(MonitorStatus::decode(&[0x00, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_multi_number_big_endian() {
+        // O2 sensor 1 (PID 0x24), ratio uses AB, voltage uses CD
+        match PID_LIST.parse_pid(0x24, &[0x80, 0x00, 0x80, 0x00]) {
+            Some(PidReturnType::MultiNumber(r)) => {
+                assert_eq!(r[0].res, 1.0);
+                assert_eq!(r[1].res, 4.0);
+            }
+            x => panic!("PID 0x24 did not decode to multiple numbers: {:?}", x),
+        }
+    }
+}
@@ -1,36 +1,35 @@
-use crate::commapi::protocols::{DTCState, ProtocolServer, DTC};
+use crate::commapi::protocols::{DTCState, DTC};
 
 use super::{OBDError, ObdServer};
 
+/// Stored DTCs. These are confirmed faults, which turn on the check engine light
 #[derive(Debug, Clone)]
 pub struct Service03;
 
 impl Service03 {
+    /// Reads the stored DTCs. More than 3 DTCs do not fit in a single frame, so the
+    /// ECU sends them as a multi-frame ISO-TP response
     pub fn read_dtcs(s: &ObdServer) -> OBDError<Vec<DTC>> {
-        let mut bytes = s.run_command(0x07, &[])?;
-        let num_dtcs = bytes[1];
-        bytes.drain(0..2);
+        s.read_dtc_service(0x03, DTCState::Stored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_stored_dtcs() {
+        // 4 DTCs, one of each prefix
         let mut res = Vec::new();
-        for _ in 0..num_dtcs {
-            let prefix = match (bytes[0] >> 6) & 0b0000011 {
-                1 => 'C', // Chassis
-                2 => 'B', // Body
-                3 => 'U', // Network
-                _ => 'P', // Powertrain
-            };
-            let n1 = (bytes[0] >> 4) & 0b0000011;
-            let n2 = (bytes[0] >> 2) & 0b0000011;
-            let dtc = DTC {
-                error: format!("{}{:1X}{:1X}{:2X}", prefix, n1, n2, bytes[1]),
-                state: DTCState::Stored, // TODO Fix this
-                check_engine_on: true,
-                id: bytes[1] as u32,
-            };
-            bytes.drain(0..2);
-            res.push(dtc);
-        }
-        // TODO
-        println!("DTC BYTES: {:02X?}", res);
-        Ok(res)
+        ObdServer::decode_dtc_resp(
+            &[0x04, 0x01, 0x33, 0x42, 0x10, 0x9A, 0xBC, 0xC1, 0x00],
+            DTCState::Stored,
+            &mut res,
+        );
+        let codes: Vec<&str> = res.iter().map(|d| d.error.as_str()).collect();
+        assert_eq!(codes, vec!["P0133", "C0210", "B1ABC", "U0100"]);
+        assert!(res.iter().all(|d| d.check_engine_on));
+        assert_eq!(res[1].id, 0x4210);
     }
 }
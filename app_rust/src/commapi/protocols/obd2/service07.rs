@@ -1,2 +1,35 @@
+use crate::commapi::protocols::{DTCState, DTC};
+
+use super::{OBDError, ObdServer};
+
+/// Pending DTCs. These are faults detected in the current or last drive cycle which
+/// have not been confirmed yet, so have not turned on the check engine light
 #[derive(Debug, Clone)]
 pub struct Service07;
+
+impl Service07 {
+    pub fn read_pending_dtcs(s: &ObdServer) -> OBDError<Vec<DTC>> {
+        s.read_dtc_service(0x07, DTCState::Pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_pending_dtcs() {
+        let mut res = Vec::new();
+        ObdServer::decode_dtc_resp(&[0x00], DTCState::Pending, &mut res);
+        assert!(res.is_empty());
+
+        ObdServer::decode_dtc_resp(
+            &[0x02, 0x01, 0x71, 0x61, 0x23],
+            DTCState::Pending,
+            &mut res,
+        );
+        let codes: Vec<&str> = res.iter().map(|d| d.error.as_str()).collect();
+        assert_eq!(codes, vec!["P0171", "C2123"]);
+        assert!(!res[0].check_engine_on);
+    }
+}
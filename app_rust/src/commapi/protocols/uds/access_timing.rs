@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use crate::commapi::protocols::{ProtocolError, ProtocolResult, ProtocolServer};
+
+use super::{diag_session_control::SessionTiming, UDSECU};
+
+// The service, Access Timing Parameters ($83), is used to read and change the timing
+// parameters (P2server_max and P2*server_max) of the active diagnostic session. Not all
+// ECUs support it, and those that do may only accept certain values.
+
+/// Sub function to read the currently active timing parameters
+const READ_ACTIVE_TIMING: u8 = 0x03;
+/// Sub function to set the timing parameters to the given values
+const SET_GIVEN_TIMING: u8 = 0x04;
+
+/// Encodes a timing record, rounding P2* up to the next 10ms
+pub(crate) fn encode_timing(p2: Duration, p2_star: Duration) -> ProtocolResult<[u8; 4]> {
+    let p2_ms = p2.as_millis();
+    let p2_star_10ms = p2_star.as_millis().div_ceil(10);
+    if p2_ms > u16::MAX as u128 || p2_star_10ms > u16::MAX as u128 {
+        return Err(ProtocolError::CustomError(format!(
+            "Timing P2 {} ms, P2* {} ms is out of range",
+            p2_ms,
+            p2_star.as_millis()
+        )));
+    }
+    let [p2_hi, p2_lo] = (p2_ms as u16).to_be_bytes();
+    let [p2_star_hi, p2_star_lo] = (p2_star_10ms as u16).to_be_bytes();
+    Ok([p2_hi, p2_lo, p2_star_hi, p2_star_lo])
+}
+
+/// Reads the timing parameters which are active in the current session
+pub fn read_timing(ecu: &UDSECU) -> ProtocolResult<SessionTiming> {
+    let res = ecu.run_command(
+        super::UDSCommand::AccessTimingParameters.into(),
+        &[READ_ACTIVE_TIMING],
+    )?;
+    res.get(2..)
+        .and_then(SessionTiming::from_record)
+        .ok_or(ProtocolError::InvalidResponseSize {
+            expect: 6,
+            actual: res.len(),
+        })
+}
+
+/// Asks the ECU to use the given timing parameters for the current session
+pub fn set_timing(ecu: &UDSECU, p2: Duration, p2_star: Duration) -> ProtocolResult<()> {
+    let mut args = vec![SET_GIVEN_TIMING];
+    args.extend_from_slice(&encode_timing(p2, p2_star)?);
+    ecu.run_command(super::UDSCommand::AccessTimingParameters.into(), &args)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_timing() {
+        let record = encode_timing(Duration::from_millis(25), Duration::from_millis(1005)).unwrap();
+        assert_eq!(record, [0x00, 0x19, 0x00, 0x65]);
+        let t = SessionTiming::from_record(&record).unwrap();
+        assert_eq!(t.p2_max, Duration::from_millis(25));
+        assert_eq!(t.p2_star_max, Duration::from_millis(1010));
+
+        assert!(encode_timing(Duration::from_secs(70), Duration::from_secs(1)).is_err());
+        assert!(encode_timing(Duration::from_millis(50), Duration::from_secs(700)).is_err());
+    }
+}
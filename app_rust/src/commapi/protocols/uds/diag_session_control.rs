@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use crate::commapi::protocols::{ProtocolResult, ProtocolServer};
 
 use super::UDSECU;
@@ -18,6 +20,17 @@ pub enum DiagSession {
 }
 
 impl DiagSession {
+    pub(crate) fn from_byte(b: u8) -> Self {
+        match b & 0x7F {
+            0x01 => DiagSession::Default,
+            0x02 => DiagSession::Programming,
+            0x03 => DiagSession::Extended,
+            0x04 => DiagSession::SafetySystem,
+            x @ 0x40..=0x5F => DiagSession::VehicleSpecific(x),
+            x => DiagSession::SystemSupplier(x),
+        }
+    }
+
     fn to_byte(&self) -> u8 {
         match &self {
             DiagSession::Default => 0x01,
@@ -38,3 +51,52 @@ pub fn set_diag_session(ecu: &UDSECU, mode: DiagSession) -> ProtocolResult<()> {
     )?;
     Ok(())
 }
+
+/// Timing parameters the ECU reports in its positive response to DiagnosticSessionControl.
+/// These apply for as long as the ECU stays in the session
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SessionTiming {
+    /// Maximum time the ECU takes to start responding to a request (P2server_max)
+    pub p2_max: Duration,
+    /// Maximum time the ECU takes to respond after sending ResponsePending (P2*server_max)
+    pub p2_star_max: Duration,
+}
+
+impl SessionTiming {
+    /// Parses the timing from a positive response (0x50). P2 has a resolution of 1ms,
+    /// P2* has a resolution of 10ms. Returns None if the ECU did not include them
+    pub fn from_response(resp: &[u8]) -> Option<Self> {
+        if resp.len() < 6 || resp[0] != 0x50 {
+            return None;
+        }
+        Self::from_record(&resp[2..])
+    }
+
+    /// Parses a 4 byte timing record (P2 in 1ms, then P2* in 10ms). This format is
+    /// shared by DiagnosticSessionControl and AccessTimingParameters
+    pub fn from_record(record: &[u8]) -> Option<Self> {
+        if record.len() < 4 {
+            return None;
+        }
+        let p2 = u16::from_be_bytes([record[0], record[1]]) as u64;
+        let p2_star = u16::from_be_bytes([record[2], record[3]]) as u64 * 10;
+        Some(Self {
+            p2_max: Duration::from_millis(p2),
+            p2_star_max: Duration::from_millis(p2_star),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_timing() {
+        let t = SessionTiming::from_response(&[0x50, 0x03, 0x00, 0x32, 0x01, 0xF4]).unwrap();
+        assert_eq!(t.p2_max, Duration::from_millis(50));
+        assert_eq!(t.p2_star_max, Duration::from_millis(5000));
+        // Older ECUs only echo the session type
+        assert_eq!(SessionTiming::from_response(&[0x50, 0x03]), None);
+    }
+}
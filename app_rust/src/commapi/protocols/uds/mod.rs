@@ -1,22 +1,30 @@
-use self::diag_session_control::DiagSession;
+use self::diag_session_control::{DiagSession, SessionTiming};
 use super::{
-    CautionLevel, CommandError, DiagCfg, ECUCommand, ProtocolError, ProtocolResult, ProtocolServer,
-    Selectable, DTC,
+    CautionLevel, CommandError, DiagCfg, ECUCommand, ExchangeRecord, ProtocolError, ProtocolResult,
+    ProtocolServer, Selectable, DTC, DEFAULT_PENDING_BUDGET, DEFAULT_RESPONSE_TIMEOUT_MS,
 };
-use crate::commapi::{comm_api::{ComServer, FilterType}, iface::{InterfaceConfig, InterfaceType, IsoTPInterface, PayloadFlag}, protocols::DTCState};
+use crate::commapi::{comm_api::{ComServer, FilterType}, iface::{InterfaceConfig, InterfaceType, IsoTPInterface, PayloadFlag}, latency::{LatencyHistogram, DIAG_BUCKETS_US}, protocols::DTCState};
+use serde::Deserialize;
 use std::sync::atomic::Ordering::Relaxed;
 use std::{
+    collections::{BTreeMap, HashMap},
     sync::{
         atomic::AtomicBool,
         mpsc::{self, Receiver, Sender},
         Arc, Mutex, RwLock,
     },
-    time::Instant,
+    time::{Duration, Instant},
 };
 
+pub mod access_timing;
 pub mod diag_session_control;
 pub mod read_data;
 pub mod read_dtc_info;
+pub mod routine;
+pub mod scan;
+pub mod script;
+pub mod security_access;
+pub mod upload;
 
 #[derive(Copy, Clone, Debug, Eq, PartialOrd, PartialEq)]
 /// UDS Commands AKA SID (Service identifiers)
@@ -43,6 +51,7 @@ pub enum UDSCommand {
     WriteMemoryByAddress,
     TesterPresent,
     RequestFileTransfer,
+    AccessTimingParameters,
     ControlDTCSetting,
     LinkControl,
 }
@@ -76,6 +85,7 @@ impl Selectable for UDSCommand {
             UDSCommand::WriteMemoryByAddress => {}
             UDSCommand::TesterPresent => {}
             UDSCommand::RequestFileTransfer => {}
+            UDSCommand::AccessTimingParameters => {}
             UDSCommand::ControlDTCSetting => {}
             UDSCommand::LinkControl => {}
         }
@@ -114,12 +124,105 @@ impl Into<u8> for UDSCommand {
             UDSCommand::WriteMemoryByAddress => 0x3D,
             UDSCommand::TesterPresent => 0x3E,
             UDSCommand::RequestFileTransfer => 0x3F,
+            UDSCommand::AccessTimingParameters => 0x83,
             UDSCommand::ControlDTCSetting => 0x85,
             UDSCommand::LinkControl => 0x87,
         }
     }
 }
 
+impl UDSCommand {
+    /// Default time to wait for the ECU to respond to each service.
+    ///
+    /// * Reads, tester present, session and security control - 2 seconds
+    /// * ECU Reset, Communication control - 5 seconds
+    /// * Clearing DTCs, writing data, IO control - 10 seconds (ECU may have to write to EEPROM)
+    /// * Download / upload / transfer - 10 seconds (ECU may have to erase or write flash)
+    /// * Routine control - 30 seconds (Routines can run for a long time)
+    ///
+    /// Note that these apply to each response, so an ECU sending ResponsePending resets the wait
+    pub fn default_timeout(&self) -> Duration {
+        let ms = match &self {
+            UDSCommand::ECUReset | UDSCommand::CommunicationControl => 5000,
+            UDSCommand::ClearDTCInformation
+            | UDSCommand::WriteDataByID
+            | UDSCommand::WriteMemoryByAddress
+            | UDSCommand::IOCTLById
+            | UDSCommand::RequestDownload
+            | UDSCommand::RequestUpload
+            | UDSCommand::TransferData
+            | UDSCommand::TransferExit
+            | UDSCommand::RequestFileTransfer => 10000,
+            UDSCommand::RoutineControl => 30000,
+            _ => DEFAULT_RESPONSE_TIMEOUT_MS,
+        };
+        Duration::from_millis(ms as u64)
+    }
+
+    /// Returns true if the first byte of the request is a sub function, whose highest
+    /// bit is the suppressPosRspMsgIndicationBit
+    pub fn has_sub_function(&self) -> bool {
+        matches!(
+            self,
+            UDSCommand::DiagnosticSessionControl
+                | UDSCommand::ECUReset
+                | UDSCommand::SecurityAccess
+                | UDSCommand::CommunicationControl
+                | UDSCommand::Authentication
+                | UDSCommand::DynamicDefineDataId
+                | UDSCommand::RoutineControl
+                | UDSCommand::TesterPresent
+                | UDSCommand::AccessTimingParameters
+                | UDSCommand::ControlDTCSetting
+                | UDSCommand::LinkControl
+        )
+    }
+
+    /// Converts a SID to a UDS command, if it is known
+    pub fn from_sid(sid: u8) -> Option<Self> {
+        ALL_UDS_COMMANDS
+            .iter()
+            .copied()
+            .find(|c| Into::<u8>::into(*c) == sid)
+    }
+
+    /// Converts a command name (Such as `ReadDataByID`) to a UDS command, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        ALL_UDS_COMMANDS
+            .iter()
+            .copied()
+            .find(|c| c.get_name().eq_ignore_ascii_case(name))
+    }
+}
+
+const ALL_UDS_COMMANDS: [UDSCommand; 25] = [
+    UDSCommand::DiagnosticSessionControl,
+    UDSCommand::ECUReset,
+    UDSCommand::ClearDTCInformation,
+    UDSCommand::ReadDTCInformation,
+    UDSCommand::ReadDataByID,
+    UDSCommand::ReadMemoryByAddress,
+    UDSCommand::ReadScalingDataById,
+    UDSCommand::SecurityAccess,
+    UDSCommand::CommunicationControl,
+    UDSCommand::Authentication,
+    UDSCommand::ReadDataByPeriodicID,
+    UDSCommand::DynamicDefineDataId,
+    UDSCommand::WriteDataByID,
+    UDSCommand::IOCTLById,
+    UDSCommand::RoutineControl,
+    UDSCommand::RequestDownload,
+    UDSCommand::RequestUpload,
+    UDSCommand::TransferData,
+    UDSCommand::TransferExit,
+    UDSCommand::WriteMemoryByAddress,
+    UDSCommand::TesterPresent,
+    UDSCommand::RequestFileTransfer,
+    UDSCommand::AccessTimingParameters,
+    UDSCommand::ControlDTCSetting,
+    UDSCommand::LinkControl,
+];
+
 impl ECUCommand for UDSCommand {
     fn get_caution_level(&self) -> CautionLevel {
         match &self {
@@ -145,6 +248,7 @@ impl ECUCommand for UDSCommand {
             UDSCommand::WriteMemoryByAddress => CautionLevel::Alert,
             UDSCommand::TesterPresent => CautionLevel::None,
             UDSCommand::RequestFileTransfer => CautionLevel::Alert,
+            UDSCommand::AccessTimingParameters => CautionLevel::Warn,
             UDSCommand::ControlDTCSetting => CautionLevel::Warn,
             UDSCommand::LinkControl => CautionLevel::Warn,
         }
@@ -174,6 +278,7 @@ impl ECUCommand for UDSCommand {
             Self::WriteMemoryByAddress,
             //Self::TesterPresent,
             Self::RequestFileTransfer,
+            Self::AccessTimingParameters,
             Self::ControlDTCSetting,
             Self::LinkControl,
         ]
@@ -222,7 +327,8 @@ pub enum UDSNegativeCode {
     TorqueConverterClutchLocked,
     VoltageTooHigh,
     VoltageTooLow,
-    ReservedSpecificConditionsIncorrect,
+    /// Manufacturer specific conditions not correct (0x94-0xEF)
+    ReservedSpecificConditionsIncorrect(u8),
     NoResponseSubnetComponent,
     FailurePreventsExecutionOfRequestedAction,
     Reserved(u8),
@@ -273,11 +379,13 @@ impl CommandError for UDSNegativeCode {
             UDSNegativeCode::TorqueConverterClutchLocked => "Torque converter clutch is locked",
             UDSNegativeCode::VoltageTooHigh => "Voltage is too high",
             UDSNegativeCode::VoltageTooLow => "Voltage is too low",
-            UDSNegativeCode::ReservedSpecificConditionsIncorrect => {
-                "Failure condition for test met"
+            UDSNegativeCode::ReservedSpecificConditionsIncorrect(b) => {
+                return format!("Specific conditions are not correct (0x{:02X})", b)
             }
             UDSNegativeCode::NoResponseSubnetComponent => "Subnet component did not respond",
-            UDSNegativeCode::FailurePreventsExecutionOfRequestedAction => "",
+            UDSNegativeCode::FailurePreventsExecutionOfRequestedAction => {
+                "A failure prevents the request from being run"
+            }
             UDSNegativeCode::Reserved(b) => return format!("Reserved error 0x{:02X}", b),
         }
         .into()
@@ -323,7 +431,7 @@ impl CommandError for UDSNegativeCode {
             UDSNegativeCode::TorqueConverterClutchLocked => {}
             UDSNegativeCode::VoltageTooHigh => {}
             UDSNegativeCode::VoltageTooLow => {}
-            UDSNegativeCode::ReservedSpecificConditionsIncorrect => {}
+            UDSNegativeCode::ReservedSpecificConditionsIncorrect(_) => {}
             UDSNegativeCode::NoResponseSubnetComponent => {}
             UDSNegativeCode::FailurePreventsExecutionOfRequestedAction => {}
             UDSNegativeCode::Reserved(_) => {}
@@ -388,29 +496,593 @@ impl CommandError for UDSNegativeCode {
             0x91 => Self::TorqueConverterClutchLocked,
             0x92 => Self::VoltageTooHigh,
             0x93 => Self::VoltageTooLow,
+            0x94..=0xEF => Self::ReservedSpecificConditionsIncorrect(b),
             // Reserved
             _ => Self::Reserved(b),
         }
     }
+
+    fn get_byte(&self) -> Option<u8> {
+        Some(match self {
+            Self::GeneralReject => 0x10,
+            Self::ServiceNotSupported => 0x11,
+            Self::SubFunctionNotSupported => 0x12,
+            Self::IncorrectMessageLength => 0x13,
+            Self::ResponseTooLong => 0x14,
+            Self::BusyRepeatRequest => 0x21,
+            Self::ConditionsNotCorrect => 0x22,
+            Self::RequestSequenceError => 0x24,
+            Self::NoResponseSubnetComponent => 0x25,
+            Self::FailurePreventsExecutionOfRequestedAction => 0x26,
+            Self::RequestOutOfRange => 0x31,
+            Self::SecurityAccessDenied => 0x33,
+            Self::InvalidKey => 0x35,
+            Self::ExceedNumberOfAttempts => 0x36,
+            Self::RequiredTimeDelayNotExpired => 0x37,
+            Self::UploadDownloadNotAccepted => 0x70,
+            Self::TransferDataSuspended => 0x71,
+            Self::GeneralProgrammingFailure => 0x72,
+            Self::WrongBlockSequenceCounter => 0x73,
+            Self::ResponsePending => 0x78,
+            Self::SubFunctionNotSupportedActiveSession => 0x7E,
+            Self::ServiceNotSupportedActiveSession => 0x7F,
+            Self::RpmTooHigh => 0x81,
+            Self::RpmTooLow => 0x82,
+            Self::EngineIsRunning => 0x83,
+            Self::EngineIsNotRunning => 0x84,
+            Self::EngineRunTimeTooLow => 0x85,
+            Self::TempTooHigh => 0x86,
+            Self::TempTooLow => 0x87,
+            Self::SpeedTooHigh => 0x88,
+            Self::SpeedTooLow => 0x89,
+            Self::ThrottleTooHigh => 0x8A,
+            Self::ThrottleTooLow => 0x8B,
+            Self::TransmissionNotInNeutral => 0x8C,
+            Self::TransmissionNotInGear => 0x8D,
+            Self::BrakeNotApplied => 0x8F,
+            Self::ShifterNotInPark => 0x90,
+            Self::TorqueConverterClutchLocked => 0x91,
+            Self::VoltageTooHigh => 0x92,
+            Self::VoltageTooLow => 0x93,
+            Self::ReservedSpecificConditionsIncorrect(b) => *b,
+            Self::Reserved(b) => *b,
+        })
+    }
+}
+
+/// DTC group which selects every DTC, for [UDSECU::clear_dtcs]
+pub const DTC_GROUP_ALL: u32 = 0xFFFFFF;
+
+/// Added to the ECU's P2/P2* timing to allow for delays in the adapter and network (ΔP2)
+const P2_CLIENT_MARGIN: Duration = Duration::from_millis(100);
+
+/// Interval at which TesterPresent is sent whilst in a non-default session
+const TESTER_PRESENT_INTERVAL: Duration = Duration::from_millis(2000);
+
+/// Decides when the background TesterPresent should be sent.
+///
+/// Any request keeps the session alive, so TesterPresent is only sent once the
+/// bus has been idle for the full interval, and never whilst a request is waiting
+/// for its response (Which could be a multi-frame response still arriving)
+#[derive(Debug, Clone)]
+struct KeepAliveTimer {
+    interval: Duration,
+    last_activity: Instant,
+    request_busy: Arc<AtomicBool>,
+}
+
+impl KeepAliveTimer {
+    fn new(interval: Duration, request_busy: Arc<AtomicBool>, now: Instant) -> Self {
+        Self {
+            interval,
+            last_activity: now,
+            request_busy,
+        }
+    }
+
+    /// Marks that the ECU was just talked to
+    fn on_activity(&mut self, now: Instant) {
+        self.last_activity = now
+    }
+
+    fn should_send(&self, now: Instant) -> bool {
+        !self.request_busy.load(Relaxed)
+            && now.saturating_duration_since(self.last_activity) >= self.interval
+    }
+}
+
+/// Sequence which re-establishes the diagnostic session (And security access) with
+/// the ECU after it has reset. See [UDSECU::set_reestablish_handler]
+pub type ReestablishFn = dyn Fn(&UDSECU) -> ProtocolResult<()> + Send + Sync;
+
+#[derive(Clone)]
+struct ReestablishHandler(Arc<ReestablishFn>);
+
+impl std::fmt::Debug for ReestablishHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ReestablishHandler")
+    }
+}
+
+/// Round trip latency of requests (Request sent until the full response is received),
+/// recorded once enabled with [UDSECU::set_latency_recording]
+#[derive(Debug, Clone)]
+pub struct LatencyStats {
+    pub overall: LatencyHistogram,
+    pub per_sid: BTreeMap<u8, LatencyHistogram>,
+}
+
+impl Default for LatencyStats {
+    fn default() -> Self {
+        Self {
+            overall: LatencyHistogram::with_buckets(DIAG_BUCKETS_US),
+            per_sid: BTreeMap::new(),
+        }
+    }
+}
+
+impl LatencyStats {
+    fn add(&mut self, sid: u8, d: Duration) {
+        self.overall.add(d);
+        self.per_sid
+            .entry(sid)
+            .or_insert_with(|| LatencyHistogram::with_buckets(DIAG_BUCKETS_US))
+            .add(d);
+    }
+}
+
+/// Largest request (SID and parameters) which fits in a single ISO-TP frame. Functional
+/// requests cannot be segmented, as there is no single ECU to send the flow control
+const MAX_FUNCTIONAL_REQUEST_LEN: usize = 7;
+
+/// Who a request is sent to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestAddressing {
+    /// Sent to the ECU's request ID, so only the ECU responds
+    #[default]
+    Physical,
+    /// Sent to the functional (Broadcast) ID, [DiagCfg::global_id]. Every ECU on the
+    /// bus which supports the request may respond
+    Functional,
+}
+
+/// Checks a request can be sent functionally, and returns the ID to send it to.
+/// Also returns a warning if the request expects a response, as every ECU which receives
+/// it may respond, but only the first response from `recv_id` is read
+pub(crate) fn check_functional_request(
+    global_id: Option<u32>,
+    recv_id: u32,
+    cmd: u8,
+    args: &[u8],
+) -> ProtocolResult<(u32, Option<String>)> {
+    let id = global_id.ok_or_else(|| {
+        ProtocolError::CustomError("ECU has no functional request ID configured".into())
+    })?;
+    if args.len() + 1 > MAX_FUNCTIONAL_REQUEST_LEN {
+        return Err(ProtocolError::CustomError(format!(
+            "Functional request 0x{:02X} is {} bytes, but must fit in a single frame ({} bytes)",
+            cmd,
+            args.len() + 1,
+            MAX_FUNCTIONAL_REQUEST_LEN
+        )));
+    }
+    let warning = if UDSECU::is_positive_response_suppressed(cmd, args) {
+        None
+    } else {
+        Some(format!(
+            "Functional request 0x{:02X} to 0x{:X} expects a single response, but multiple \
+             ECUs may respond. Only the first response from 0x{:X} is read",
+            cmd, id, recv_id
+        ))
+    };
+    Ok((id, warning))
 }
 
 #[derive(Debug, Clone)]
 pub struct UDSECU {
     should_run: Arc<AtomicBool>,
     last_error: Arc<RwLock<Option<ProtocolError>>>,
-    cmd_tx: Sender<(u8, Vec<u8>, bool)>,
+    cmd_tx: Sender<(u8, Vec<u8>, bool, u32)>,
     cmd_rx: Arc<Receiver<ProtocolResult<Vec<u8>>>>,
     curr_session_type: Arc<RwLock<DiagSession>>,
     send_id: u32,
+    recv_id: u32,
+    global_id: Option<u32>,
     cmd_mutex: Arc<Mutex<()>>,
+    request_busy: Arc<AtomicBool>,
+    session_lost: Arc<AtomicBool>,
+    reestablishing: Arc<AtomicBool>,
+    reestablish_handler: Arc<RwLock<Option<ReestablishHandler>>>,
+    service_timeouts: Arc<RwLock<HashMap<u8, Duration>>>,
+    session_timing: Arc<RwLock<Option<SessionTiming>>>,
+    pending_budget: Arc<RwLock<Duration>>,
+    latency: Arc<Mutex<Option<LatencyStats>>>,
+    last_response_id: Arc<RwLock<Option<u32>>>,
+    request_retries: Arc<RwLock<u32>>,
+    did_lengths: Arc<RwLock<HashMap<u16, usize>>>,
+    last_exchange: ExchangeRecord,
 }
 
 impl UDSECU {
+    /// Sets the sequence to run when the ECU has lost its session or security state,
+    /// either after an ECU Reset, or when the ECU responds with
+    /// `ServiceNotSupportedActiveSession` or `SecurityAccessDenied`.
+    ///
+    /// The handler should re-run the session control and seed/key sequence. Once it
+    /// completes, the request that detected the lost session is retried once.
+    pub fn set_reestablish_handler<F>(&self, handler: F)
+    where
+        F: Fn(&UDSECU) -> ProtocolResult<()> + Send + Sync + 'static,
+    {
+        *self.reestablish_handler.write().unwrap() = Some(ReestablishHandler(Arc::new(handler)))
+    }
+
+    /// Removes the re-establish handler. Lost sessions will then only be reported
+    pub fn clear_reestablish_handler(&self) {
+        *self.reestablish_handler.write().unwrap() = None
+    }
+
+    /// Overrides how long to wait for the ECU to respond to a service (SID), both
+    /// initially and after ResponsePending. Takes priority over the ECU's [SessionTiming]
+    pub fn set_service_timeout(&self, sid: u8, timeout: Duration) {
+        self.service_timeouts.write().unwrap().insert(sid, timeout);
+    }
+
+    /// Removes a timeout override set with [UDSECU::set_service_timeout]
+    pub fn clear_service_timeout(&self, sid: u8) {
+        self.service_timeouts.write().unwrap().remove(&sid);
+    }
+
+    /// Sets the maximum total time to wait whilst the ECU keeps responding with
+    /// ResponsePending (0x78) to a request. Once exceeded, the request fails.
+    /// Defaults to [DEFAULT_PENDING_BUDGET]
+    pub fn set_pending_budget(&self, max_duration: Duration) {
+        *self.pending_budget.write().unwrap() = max_duration;
+    }
+
+    /// Sets how many times a request is re-sent if it fails with a transient error
+    /// (See [ProtocolError::is_transient]). A negative response is never retried.
+    /// Defaults to 0. This is on top of any retries done by the transport layer
+    pub fn set_request_retries(&self, retries: u32) {
+        *self.request_retries.write().unwrap() = retries;
+    }
+
+    /// Returns how long to wait for the ECU to respond to a service (SID)
+    pub fn get_service_timeout(&self, sid: u8) -> Duration {
+        Self::lookup_timeouts(
+            &self.service_timeouts.read().unwrap(),
+            *self.session_timing.read().unwrap(),
+            sid,
+        )
+        .0
+    }
+
+    /// Returns the CAN ID of the last positive response from the ECU. With
+    /// [DiagCfg::recv_id_mask] set, this is how to find which ID the ECU responds on
+    pub fn last_response_id(&self) -> Option<u32> {
+        *self.last_response_id.read().unwrap()
+    }
+
+    /// Returns the P2/P2* timing the ECU reported when the current session was started
+    pub fn get_session_timing(&self) -> Option<SessionTiming> {
+        *self.session_timing.read().unwrap()
+    }
+
+    /// Reads the P2/P2* timing active in the current session with AccessTimingParameters.
+    /// The ECU's values are then used for timeouts, unless overridden per service.
+    ///
+    /// If the ECU rejects the service, the error is returned and the current timing is kept
+    pub fn read_timing_params(&self) -> ProtocolResult<SessionTiming> {
+        let timing = access_timing::read_timing(self).inspect_err(|e| {
+            log::warn!(
+                "UDS - Cannot read timing parameters, keeping current timing: {}",
+                e.get_text()
+            )
+        })?;
+        *self.session_timing.write().unwrap() = Some(timing);
+        Ok(timing)
+    }
+
+    /// Asks the ECU to use a different P2/P2* timing for the current session with
+    /// AccessTimingParameters. P2* is rounded up to the next 10ms. Once accepted, the
+    /// new timing is used for timeouts, unless overridden per service.
+    ///
+    /// If the ECU rejects the service, the error is returned and the current timing is kept
+    pub fn set_timing_params(&self, p2: Duration, p2_star: Duration) -> ProtocolResult<()> {
+        access_timing::set_timing(self, p2, p2_star).inspect_err(|e| {
+            log::warn!(
+                "UDS - ECU rejected timing parameters, keeping current timing: {}",
+                e.get_text()
+            )
+        })?;
+        let record = access_timing::encode_timing(p2, p2_star)?;
+        *self.session_timing.write().unwrap() = SessionTiming::from_record(&record);
+        Ok(())
+    }
+
+    /// Dumps `size` bytes of the ECU's memory starting at `address`, using RequestUpload,
+    /// TransferData and RequestTransferExit. The ECU normally has to be in the programming
+    /// session and unlocked with SecurityAccess first.
+    ///
+    /// `progress` is called with the number of bytes received so far and the total size
+    pub fn read_upload(
+        &self,
+        address: u32,
+        size: u32,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> ProtocolResult<Vec<u8>> {
+        upload::read_upload(self, address, size, progress)
+    }
+
+    /// Unlocks a security level with SecurityAccess. `level` is the odd requestSeed sub
+    /// function, and `key_fn` computes the key from the ECU's seed. If the ECU replies with
+    /// an all-zero seed, it is already unlocked and no key is sent.
+    ///
+    /// See [security_access::security_access]
+    pub fn security_access(
+        &self,
+        level: u8,
+        key_fn: impl Fn(&[u8]) -> Vec<u8>,
+    ) -> ProtocolResult<()> {
+        security_access::security_access(self, level, &key_fn)
+    }
+
+    /// Sets the length of a DID's data, for reading it from responses which do not say
+    /// how long each DID is, such as [UDSECU::read_dtc_snapshot]
+    pub fn set_did_length(&self, did: u16, len: usize) {
+        self.did_lengths.write().unwrap().insert(did, len);
+    }
+
+    /// Reads the snapshot (Freeze frame) data stored with a DTC, with
+    /// ReadDTCInformation reportDTCSnapshotRecordByDTCNumber ($19 $04).
+    ///
+    /// `record` is the snapshot record number, or [read_dtc_info::SNAPSHOT_ALL_RECORDS].
+    /// The data of each DID is split using the lengths set with [UDSECU::set_did_length],
+    /// see [read_dtc_info::parse_snapshot]
+    pub fn read_dtc_snapshot(
+        &self,
+        dtc: u32,
+        record: u8,
+    ) -> ProtocolResult<read_dtc_info::DtcSnapshot> {
+        let lengths = self.did_lengths.read().unwrap().clone();
+        read_dtc_info::read_dtc_snapshot(self, dtc, record, &|did| {
+            lengths.get(&did).copied()
+        })
+    }
+
+    /// Counts the DTCs which have any of the status bits in `mask` set, with ReadDTCInformation
+    /// reportNumberOfDTCByStatusMask ($19 $01). This is much quicker than reading every DTC
+    pub fn count_dtcs(&self, mask: u8) -> ProtocolResult<read_dtc_info::DtcCount> {
+        read_dtc_info::count_dtcs(self, mask)
+    }
+
+    /// Reads the extended data records stored with a DTC (Such as its occurrence counter),
+    /// with ReadDTCInformation reportDTCExtDataRecordByDTCNumber ($19 $06).
+    ///
+    /// `record` is the extended data record number, or [read_dtc_info::EXT_DATA_ALL_RECORDS].
+    /// See [read_dtc_info::parse_ext_data] for which records are decoded
+    pub fn read_dtc_extended_data(
+        &self,
+        dtc: u32,
+        record: u8,
+    ) -> ProtocolResult<read_dtc_info::DtcExtData> {
+        read_dtc_info::read_dtc_extended_data(self, dtc, record)
+    }
+
+    /// Starts a routine with RoutineControl, then polls RequestRoutineResults every
+    /// `poll_interval` until the routine is done, or `timeout` passes. The routine is done
+    /// once the routineInfo byte in the result is not [routine::ROUTINE_IN_PROGRESS].
+    ///
+    /// Returns the final result record, starting with the routineInfo byte
+    pub fn run_routine_to_completion(
+        &self,
+        routine_id: u16,
+        input: &[u8],
+        poll_interval: Duration,
+        timeout: Duration,
+    ) -> ProtocolResult<Vec<u8>> {
+        routine::run_routine_to_completion(self, routine_id, input, poll_interval, timeout)
+    }
+
+    /// Returns how long to wait for the first response to a service, and how long to wait
+    /// after each ResponsePending. In order of priority, this comes from the overrides, the
+    /// timing the ECU reported for the session, or [UDSCommand::default_timeout]
+    fn lookup_timeouts(
+        overrides: &HashMap<u8, Duration>,
+        timing: Option<SessionTiming>,
+        sid: u8,
+    ) -> (Duration, Duration) {
+        if let Some(t) = overrides.get(&sid) {
+            return (*t, *t);
+        }
+        if let Some(t) = timing {
+            return (
+                t.p2_max + P2_CLIENT_MARGIN,
+                t.p2_star_max + P2_CLIENT_MARGIN,
+            );
+        }
+        let t = UDSCommand::from_sid(sid)
+            .map(|c| c.default_timeout())
+            .unwrap_or_else(|| Duration::from_millis(DEFAULT_RESPONSE_TIMEOUT_MS as u64));
+        (t, t)
+    }
+
+    /// Enables or disables recording the latency of each request. Enabling
+    /// recording discards any previously recorded latencies
+    pub fn set_latency_recording(&self, enabled: bool) {
+        *self.latency.lock().unwrap() = if enabled {
+            Some(LatencyStats::default())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the request latencies recorded so far, or None if recording is disabled
+    pub fn latency_stats(&self) -> Option<LatencyStats> {
+        self.latency.lock().unwrap().clone()
+    }
+
+    /// Returns true if a request is currently waiting for a response from the ECU
+    pub fn is_request_in_progress(&self) -> bool {
+        self.request_busy.load(Relaxed)
+    }
+
+    /// Returns true if the ECU is known to have lost its session or security state
+    pub fn is_session_lost(&self) -> bool {
+        self.session_lost.load(Relaxed)
+    }
+
+    /// Negative responses that indicate the ECU is no longer in the session
+    /// (Or security level) that we put it in
+    fn is_session_lost_nrc(nrc: u8) -> bool {
+        matches!(nrc, 0x33 | 0x7E | 0x7F)
+    }
+
+    /// Returns the CAN ID to send a request to
+    fn request_target(
+        &self,
+        cmd: u8,
+        args: &[u8],
+        addressing: RequestAddressing,
+    ) -> ProtocolResult<u32> {
+        match addressing {
+            RequestAddressing::Physical => Ok(self.send_id),
+            RequestAddressing::Functional => {
+                let (id, warning) =
+                    check_functional_request(self.global_id, self.recv_id, cmd, args)?;
+                if let Some(w) = warning {
+                    log::warn!("UDS - {}", w);
+                }
+                Ok(id)
+            }
+        }
+    }
+
+    /// Sends a request and waits for the response from the ECU
+    fn exchange(&self, cmd: u8, args: &[u8]) -> ProtocolResult<Vec<u8>> {
+        self.exchange_to(cmd, args, self.send_id)
+    }
+
+    /// Sends a request to `target` and waits for the response from the ECU
+    fn exchange_to(&self, cmd: u8, args: &[u8], target: u32) -> ProtocolResult<Vec<u8>> {
+        let _guard = self.cmd_mutex.lock().unwrap(); // We are allowed to send / receive!
+        self.request_busy.store(true, Relaxed); // Hold off TesterPresent until we are done
+        if self.cmd_tx.send((cmd, Vec::from(args), true, target)).is_err() {
+            self.request_busy.store(false, Relaxed);
+            return Err(ProtocolError::CustomError("Channel Tx failed".into()));
+        }
+        let start = Instant::now();
+        let resp = self.cmd_rx.recv().unwrap();
+        self.request_busy.store(false, Relaxed);
+        self.last_exchange.record(cmd, args, &resp);
+        if resp.is_ok() {
+            if let Some(stats) = self.latency.lock().unwrap().as_mut() {
+                stats.add(cmd, start.elapsed());
+            }
+        }
+        let resp = resp?;
+        // Empty if the request suppressed its positive response
+        if resp.first() == Some(&0x7F) {
+            match resp.get(2) {
+                Some(nrc) => Err(ProtocolError::ProtocolError(Box::new(
+                    UDSNegativeCode::from_byte(*nrc),
+                ))),
+                None => Err(ProtocolError::InvalidResponseSize {
+                    expect: 3,
+                    actual: resp.len(),
+                }),
+            }
+        } else {
+            Ok(resp)
+        }
+    }
+
+    /// Runs `f` until it succeeds, fails with an error which is not transient, or
+    /// has been retried `retries` times
+    fn with_retries<T, F: FnMut() -> ProtocolResult<T>>(
+        retries: u32,
+        cmd: u8,
+        mut f: F,
+    ) -> ProtocolResult<T> {
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Err(e) if e.is_transient() && attempt < retries => {
+                    attempt += 1;
+                    log::warn!(
+                        "UDS - Request 0x{:02X} failed ({}), retrying ({}/{})",
+                        cmd,
+                        e.get_text(),
+                        attempt,
+                        retries
+                    );
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Sends a request, re-sending it on transient errors. See [UDSECU::set_request_retries]
+    fn exchange_with_retries(
+        &self,
+        cmd: u8,
+        args: &[u8],
+        target: u32,
+    ) -> ProtocolResult<Vec<u8>> {
+        let retries = *self.request_retries.read().unwrap();
+        Self::with_retries(retries, cmd, || self.exchange_to(cmd, args, target))
+    }
+
+    /// Runs the re-establish handler if one is set. Returns true if the handler ran successfully
+    fn try_reestablish(&self) -> ProtocolResult<bool> {
+        let handler = match self.reestablish_handler.read().unwrap().clone() {
+            Some(h) => h,
+            None => return Ok(false),
+        };
+        if self.reestablishing.swap(true, Relaxed) {
+            return Ok(false); // Requests sent by the handler itself
+        }
+        log::info!("UDS - ECU session lost, re-establishing");
+        let res = (handler.0)(self);
+        self.reestablishing.store(false, Relaxed);
+        res?;
+        self.session_lost.store(false, Relaxed);
+        Ok(true)
+    }
+
+    fn mark_session_lost(&self) {
+        self.session_lost.store(true, Relaxed);
+        *self.curr_session_type.write().unwrap() = DiagSession::Default;
+        *self.session_timing.write().unwrap() = None;
+    }
+
     pub fn clear_errors(&self) -> std::result::Result<(), ProtocolError> {
-        self.run_command(UDSCommand::ClearDTCInformation.into(), &[0xFF, 0x00])?;
+        self.clear_dtcs(DTC_GROUP_ALL)
+    }
+
+    /// Clears the DTCs in a group (ClearDiagnosticInformation). `group` is the 3 byte
+    /// group of DTC, such as [DTC_GROUP_ALL]. Some ECUs reject the 'all' group, and
+    /// only accept their specific groups (Powertrain, chassis...)
+    pub fn clear_dtcs(&self, group: u32) -> ProtocolResult<()> {
+        self.run_command(
+            UDSCommand::ClearDTCInformation.into(),
+            &Self::dtc_group_bytes(group)?,
+        )?;
         Ok(())
     }
 
+    fn dtc_group_bytes(group: u32) -> ProtocolResult<[u8; 3]> {
+        if group > DTC_GROUP_ALL {
+            return Err(ProtocolError::CustomError(format!(
+                "DTC group 0x{:X} is not a 3 byte value",
+                group
+            )));
+        }
+        let [_, b0, b1, b2] = group.to_be_bytes();
+        Ok([b0, b1, b2])
+    }
+
     fn set_diag_session_mode(
         &mut self,
         mode: DiagSession,
@@ -430,6 +1102,56 @@ impl UDSECU {
     pub fn get_session_type(&self) -> DiagSession {
         *self.curr_session_type.read().unwrap()
     }
+
+    /// Same as [ProtocolServer::run_command], but the request can be sent functionally
+    /// (See [RequestAddressing]), so physical and functional requests can be mixed.
+    /// Functional requests fail if the ECU has no [DiagCfg::global_id]
+    pub fn run_command_addressed(
+        &self,
+        cmd: u8,
+        args: &[u8],
+        addressing: RequestAddressing,
+    ) -> ProtocolResult<Vec<u8>> {
+        let sid_session: u8 = UDSCommand::DiagnosticSessionControl.into();
+        let sid_security: u8 = UDSCommand::SecurityAccess.into();
+        let sid_reset: u8 = UDSCommand::ECUReset.into();
+        // These services are what the re-establish sequence itself is made of
+        let is_session_cmd = cmd == sid_session || cmd == sid_security || cmd == sid_reset;
+
+        let target = self.request_target(cmd, args, addressing)?;
+        let mut retried = false;
+        if self.is_session_lost() && !is_session_cmd {
+            retried = self.try_reestablish()?;
+        }
+        let mut res = self.exchange_with_retries(cmd, args, target);
+        let lost = match &res {
+            Err(e) => matches!(e.get_nrc(), Some(nrc) if Self::is_session_lost_nrc(nrc)),
+            Ok(_) => false,
+        };
+        if lost && !is_session_cmd {
+            self.mark_session_lost();
+            if !retried && self.try_reestablish()? {
+                res = self.exchange_with_retries(cmd, args, target);
+            }
+        }
+        let resp = res?;
+        if cmd == sid_session && !args.is_empty() {
+            *self.curr_session_type.write().unwrap() = DiagSession::from_byte(args[0]);
+            let timing = SessionTiming::from_response(&resp);
+            if let Some(t) = timing {
+                log::info!(
+                    "UDS - ECU session timing P2 {} ms, P2* {} ms",
+                    t.p2_max.as_millis(),
+                    t.p2_star_max.as_millis()
+                );
+            }
+            *self.session_timing.write().unwrap() = timing;
+        } else if cmd == sid_reset {
+            // ECU will come back up in the default session
+            self.mark_session_lost();
+        }
+        Ok(resp)
+    }
 }
 
 impl ProtocolServer for UDSECU {
@@ -453,7 +1175,7 @@ impl ProtocolServer for UDSECU {
         interface.setup(&interface_cfg)?;
         interface.add_filter(FilterType::IsoTP {
             id: diag_cfg.recv_id,
-            mask: 0xFFFF,
+            mask: diag_cfg.recv_id_mask.unwrap_or(0xFFFF),
             fc: diag_cfg.send_id,
         })?;
 
@@ -464,8 +1186,8 @@ impl ProtocolServer for UDSECU {
         let last_error_t = last_error.clone();
 
         let (channel_tx_sender, channel_tx_receiver): (
-            Sender<(u8, Vec<u8>, bool)>,
-            Receiver<(u8, Vec<u8>, bool)>,
+            Sender<(u8, Vec<u8>, bool, u32)>,
+            Receiver<(u8, Vec<u8>, bool, u32)>,
         ) = mpsc::channel();
         let (channel_rx_sender, channel_rx_receiver): (
             Sender<ProtocolResult<Vec<u8>>>,
@@ -475,28 +1197,58 @@ impl ProtocolServer for UDSECU {
         let session_type = Arc::new(RwLock::new(DiagSession::Default));
         let session_type_t = session_type.clone();
 
+        let service_timeouts = Arc::new(RwLock::new(HashMap::new()));
+        let service_timeouts_t = service_timeouts.clone();
+
+        let session_timing = Arc::new(RwLock::new(None));
+        let session_timing_t = session_timing.clone();
+
+        let pending_budget = Arc::new(RwLock::new(DEFAULT_PENDING_BUDGET));
+        let pending_budget_t = pending_budget.clone();
+
+        let last_response_id = Arc::new(RwLock::new(None));
+        let last_response_id_t = last_response_id.clone();
+
         // Enter extended diagnostic session (Full features)
+        let request_busy = Arc::new(AtomicBool::new(false));
+        let mut keep_alive =
+            KeepAliveTimer::new(TESTER_PRESENT_INTERVAL, request_busy.clone(), Instant::now());
+
         let s_id = diag_cfg.send_id;
         std::thread::spawn(move || {
             println!("UDS Diag server start!");
-            let mut timer = Instant::now();
             while should_run_t.load(Relaxed) {
                 if let Ok(data) = channel_tx_receiver.try_recv() {
-                    let res = Self::run_command_resp(
+                    let (timeout, pending_timeout) = Self::lookup_timeouts(
+                        &service_timeouts_t.read().unwrap(),
+                        *session_timing_t.read().unwrap(),
+                        data.0,
+                    );
+                    let res = Self::run_command_resp_from(
                         &mut interface,
                         &tx_flags,
-                        s_id,
+                        data.3,
                         data.0,
                         &data.1,
                         data.2,
-                    );
+                        timeout.as_millis() as u32,
+                        pending_timeout.as_millis() as u32,
+                        *pending_budget_t.read().unwrap(),
+                    )
+                    .map(|(id, data)| {
+                        if id.is_some() {
+                            *last_response_id_t.write().unwrap() = id;
+                        }
+                        data
+                    });
+                    keep_alive.on_activity(Instant::now());
                     if channel_rx_sender.send(res).is_err() {
                         *last_error_t.write().unwrap() =
                             Some(ProtocolError::CustomError("Sender channel died".into()));
                         break;
                     }
                 }
-                if timer.elapsed().as_millis() >= 2000
+                if keep_alive.should_send(Instant::now())
                     && *session_type_t.read().unwrap() != DiagSession::Default
                 {
                     if Self::run_command_resp(
@@ -511,7 +1263,7 @@ impl ProtocolServer for UDSECU {
                     {
                         println!("Lost connection with ECU!");
                     }
-                    timer = Instant::now();
+                    keep_alive.on_activity(Instant::now());
                 }
                 std::thread::sleep(std::time::Duration::from_micros(100))
             }
@@ -525,8 +1277,22 @@ impl ProtocolServer for UDSECU {
             cmd_tx: channel_tx_sender,
             cmd_rx: Arc::new(channel_rx_receiver),
             send_id: diag_cfg.send_id,
+            recv_id: diag_cfg.recv_id,
+            global_id: diag_cfg.global_id,
             curr_session_type: session_type, // Assumed,
             cmd_mutex: Arc::new(Mutex::new(())),
+            request_busy,
+            session_lost: Arc::new(AtomicBool::new(false)),
+            reestablishing: Arc::new(AtomicBool::new(false)),
+            reestablish_handler: Arc::new(RwLock::new(None)),
+            service_timeouts,
+            session_timing,
+            pending_budget,
+            latency: Arc::new(Mutex::new(None)),
+            last_response_id,
+            request_retries: Arc::new(RwLock::new(0)),
+            did_lengths: Arc::new(RwLock::new(HashMap::new())),
+            last_exchange: ExchangeRecord::default(),
         };
 
         if let Err(e) = ecu.set_diag_session_mode(DiagSession::Extended) {
@@ -541,17 +1307,7 @@ impl ProtocolServer for UDSECU {
     }
 
     fn run_command(&self, cmd: u8, args: &[u8]) -> ProtocolResult<Vec<u8>> {
-        let _guard = self.cmd_mutex.lock().unwrap(); // We are allowed to send / receive!
-        if self.cmd_tx.send((cmd, Vec::from(args), true)).is_err() {
-            return Err(ProtocolError::CustomError("Channel Tx failed".into()));
-        }
-        let resp = self.cmd_rx.recv().unwrap()?;
-        if resp[0] == 0x7F {
-            let neg_code = UDSNegativeCode::from_byte(resp[2]);
-            Err(ProtocolError::ProtocolError(Box::new(neg_code)))
-        } else {
-            Ok(resp)
-        }
+        self.run_command_addressed(cmd, args, RequestAddressing::Physical)
     }
 
     fn read_errors(&self) -> ProtocolResult<Vec<DTC>> {
@@ -597,10 +1353,256 @@ impl ProtocolServer for UDSECU {
         self.should_run.load(Relaxed) // Diag server self-terminates upon ECU Session error
     }
 
+    fn is_positive_response_suppressed(cmd: u8, args: &[u8]) -> bool {
+        let has_sub_function = matches!(UDSCommand::from_sid(cmd), Some(c) if c.has_sub_function());
+        has_sub_function && matches!(args.first(), Some(sf) if sf & 0x80 != 0)
+    }
+
     fn get_last_error(&self) -> Option<String> {
         match self.last_error.read().unwrap().as_ref() {
             Some(x) => Some(x.get_text()),
             None => None,
         }
     }
+
+    fn last_exchange(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        self.last_exchange.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commapi::iface::{BufferType, Interface, InterfacePayload, InterfaceResult};
+    use crate::commapi::replay::{RecordedSession, ReplayServer};
+
+    #[test]
+    fn test_keep_alive_waits_for_request() {
+        let busy = Arc::new(AtomicBool::new(false));
+        let start = Instant::now();
+        let ms = |x: u64| start + Duration::from_millis(x);
+        let mut timer = KeepAliveTimer::new(Duration::from_millis(2000), busy.clone(), start);
+        assert!(!timer.should_send(ms(1999)));
+
+        // Multi-frame response is still arriving when the interval expires
+        busy.store(true, Relaxed);
+        assert!(!timer.should_send(ms(2000)));
+        assert!(!timer.should_send(ms(2500)));
+        busy.store(false, Relaxed);
+        timer.on_activity(ms(2600));
+
+        // The request kept the session alive, so wait a full interval again
+        assert!(!timer.should_send(ms(4000)));
+        assert!(timer.should_send(ms(4600)));
+    }
+
+    /// Interface which responds to each request with the next queued response (If any)
+    #[derive(Debug, Clone, Default)]
+    struct MockIface {
+        responses: std::collections::VecDeque<Vec<u8>>,
+    }
+
+    impl Interface for MockIface {
+        fn setup(&mut self, _cfg: &InterfaceConfig) -> InterfaceResult<()> {
+            Ok(())
+        }
+        fn send_data(
+            &mut self,
+            data: &[InterfacePayload],
+            _timeout: u32,
+        ) -> InterfaceResult<usize> {
+            Ok(data.len())
+        }
+        fn recv_data(
+            &mut self,
+            _max: usize,
+            _timeout: u32,
+        ) -> InterfaceResult<Vec<InterfacePayload>> {
+            Ok(self
+                .responses
+                .pop_front()
+                .map(|d| vec![InterfacePayload::new(0x7E8, &d)])
+                .unwrap_or_default())
+        }
+        fn add_filter(&mut self, _f: FilterType) -> InterfaceResult<u32> {
+            Ok(0)
+        }
+        fn rem_filter(&mut self, _f_id: u32) -> InterfaceResult<()> {
+            Ok(())
+        }
+        fn close(&mut self) -> InterfaceResult<()> {
+            Ok(())
+        }
+        fn clear_buffer(&mut self, _buffer_type: BufferType) -> InterfaceResult<()> {
+            Ok(())
+        }
+        fn get_server(&self) -> Box<dyn ComServer> {
+            // Nothing was recorded, so any request on the server fails
+            Box::new(ReplayServer::new(RecordedSession::default()))
+        }
+        fn clone_box(&self) -> Box<dyn Interface> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn test_suppressed_positive_response() {
+        assert!(UDSECU::is_positive_response_suppressed(0x3E, &[0x80]));
+        assert!(!UDSECU::is_positive_response_suppressed(0x3E, &[0x00]));
+        // ReadDataByID has no sub function, 0xF1 is the DID
+        assert!(!UDSECU::is_positive_response_suppressed(
+            0x22,
+            &[0xF1, 0x90]
+        ));
+
+        let run = |responses: &[&[u8]], cmd: u8, args: &[u8]| {
+            let mut iface: Box<dyn Interface> = Box::new(MockIface {
+                responses: responses.iter().map(|r| r.to_vec()).collect(),
+            });
+            UDSECU::run_command_resp_timeout(
+                &mut iface,
+                &None,
+                0x7E0,
+                cmd,
+                args,
+                true,
+                1000,
+                1000,
+                DEFAULT_PENDING_BUDGET,
+            )
+        };
+        // No response within the window is success
+        assert_eq!(run(&[], 0x3E, &[0x80]).unwrap(), Vec::<u8>::new());
+        // A negative response is still an error
+        let err = run(&[&[0x7F, 0x11, 0x22]], 0x11, &[0x81]).unwrap_err();
+        assert_eq!(err.get_nrc(), Some(0x22));
+        // After ResponsePending, the ECU must send the final positive response
+        assert_eq!(
+            run(
+                &[&[0x7F, 0x31, 0x78], &[0x71, 0x81, 0x02, 0x03]],
+                0x31,
+                &[0x81, 0x02, 0x03]
+            )
+            .unwrap(),
+            vec![0x71, 0x81, 0x02, 0x03]
+        );
+        // Without the bit, a missing response is a timeout
+        assert!(run(&[], 0x3E, &[0x00]).is_err());
+    }
+
+    #[test]
+    fn test_response_id() {
+        let run = |responses: &[&[u8]], cmd: u8, args: &[u8]| {
+            let mut iface: Box<dyn Interface> = Box::new(MockIface {
+                responses: responses.iter().map(|r| r.to_vec()).collect(),
+            });
+            UDSECU::run_command_resp_from(
+                &mut iface,
+                &None,
+                0x7E0,
+                cmd,
+                args,
+                true,
+                1000,
+                1000,
+                DEFAULT_PENDING_BUDGET,
+            )
+            .unwrap()
+        };
+        let (id, data) = run(&[&[0x62, 0xF1, 0x90, 0x57]], 0x22, &[0xF1, 0x90]);
+        assert_eq!(id, Some(0x7E8));
+        assert_eq!(data, vec![0x62, 0xF1, 0x90, 0x57]);
+        assert_eq!(run(&[], 0x3E, &[0x80]), (None, vec![]));
+    }
+
+    #[test]
+    fn test_dtc_group_bytes() {
+        assert_eq!(UDSECU::dtc_group_bytes(DTC_GROUP_ALL).unwrap(), [0xFF; 3]);
+        assert_eq!(
+            UDSECU::dtc_group_bytes(0x100000).unwrap(),
+            [0x10, 0x00, 0x00]
+        );
+        assert!(UDSECU::dtc_group_bytes(0x1000000).is_err());
+    }
+
+    #[test]
+    fn test_timeout_priority() {
+        let mut overrides = HashMap::new();
+        let timing = SessionTiming {
+            p2_max: Duration::from_millis(50),
+            p2_star_max: Duration::from_millis(5000),
+        };
+        let routine: u8 = UDSCommand::RoutineControl.into();
+        assert_eq!(
+            UDSECU::lookup_timeouts(&overrides, None, routine),
+            (Duration::from_secs(30), Duration::from_secs(30))
+        );
+        assert_eq!(
+            UDSECU::lookup_timeouts(&overrides, Some(timing), routine),
+            (Duration::from_millis(150), Duration::from_millis(5100))
+        );
+        overrides.insert(routine, Duration::from_secs(1));
+        assert_eq!(
+            UDSECU::lookup_timeouts(&overrides, Some(timing), routine),
+            (Duration::from_secs(1), Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn test_request_retries() {
+        let run = |retries: u32, results: Vec<ProtocolResult<Vec<u8>>>| {
+            let mut results = std::collections::VecDeque::from(results);
+            let mut attempts = 0;
+            let res = UDSECU::with_retries(retries, 0x22, || {
+                attempts += 1;
+                results.pop_front().unwrap()
+            });
+            (res, attempts)
+        };
+        let ok = || Ok(vec![0x62]);
+        let (res, attempts) = run(2, vec![Err(ProtocolError::Timeout), ok()]);
+        assert_eq!(res.unwrap(), vec![0x62]);
+        assert_eq!(attempts, 2);
+        // Gives up after the last retry
+        let (res, attempts) = run(1, vec![Err(ProtocolError::Timeout), Err(ProtocolError::Timeout)]);
+        assert!(res.unwrap_err().is_timeout());
+        assert_eq!(attempts, 2);
+        // A negative response is a valid answer from the ECU
+        let nrc = Err(ProtocolError::ProtocolError(Box::new(
+            UDSNegativeCode::from_byte(0x31),
+        )));
+        let (res, attempts) = run(3, vec![nrc, ok()]);
+        assert_eq!(res.unwrap_err().get_nrc(), Some(0x31));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_nrc_bytes() {
+        // Every NRC keeps its raw byte, including the manufacturer specific ones
+        for b in 0..=0xFF {
+            assert_eq!(UDSNegativeCode::from_byte(b).get_byte(), Some(b));
+        }
+        assert_eq!(
+            UDSNegativeCode::from_byte(0x94),
+            UDSNegativeCode::ReservedSpecificConditionsIncorrect(0x94)
+        );
+    }
+
+    #[test]
+    fn test_functional_request() {
+        // TesterPresent with the positive response suppressed, nothing to warn about
+        let (id, warning) = check_functional_request(Some(0x7DF), 0x7E8, 0x3E, &[0x80]).unwrap();
+        assert_eq!((id, warning), (0x7DF, None));
+
+        // Several ECUs may respond to a read
+        let (id, warning) = check_functional_request(Some(0x7DF), 0x7E8, 0x22, &[0xF1, 0x90])
+            .unwrap();
+        assert_eq!(id, 0x7DF);
+        assert!(warning.unwrap().contains("multiple ECUs may respond"));
+
+        // No functional ID, and a request which needs more than one frame
+        assert!(check_functional_request(None, 0x7E8, 0x3E, &[0x80]).is_err());
+        assert!(check_functional_request(Some(0x7DF), 0x7E8, 0x2E, &[0xF1, 0x90, 1, 2, 3, 4, 5])
+            .is_err());
+    }
 }
@@ -8,3 +8,19 @@ pub fn read_variant_id(ecu: &UDSECU) -> ProtocolResult<u32> {
     res.drain(0..2);
     Ok((res[0] as u32) << 24 | (res[1] as u32) << 16 | (res[2] as u32) << 8 | res[3] as u32)
 }
+
+/// Spare part number DID
+pub const DID_PART_NUMBER: u16 = 0xF187;
+/// ECU software version number DID
+pub const DID_SW_VERSION: u16 = 0xF189;
+/// ECU hardware version number DID
+pub const DID_HW_VERSION: u16 = 0xF191;
+
+/// Reads a DID which holds an ASCII string, such as [DID_PART_NUMBER]
+pub fn read_ascii_did(ecu: &UDSECU, did: u16) -> ProtocolResult<String> {
+    let res = ecu.run_command(super::UDSCommand::ReadDataByID.into(), &did.to_be_bytes())?;
+    let data = res.get(3..).unwrap_or_default();
+    Ok(String::from_utf8_lossy(data)
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string())
+}
@@ -1,4 +1,4 @@
-use crate::commapi::protocols::{DTC, ProtocolResult, ProtocolServer};
+use crate::commapi::protocols::{DTC, ProtocolError, ProtocolResult, ProtocolServer};
 
 use super::UDSECU;
 
@@ -9,7 +9,372 @@ pub fn read_dtc_information(ecu: &UDSECU, dtc: &DTC) -> ProtocolResult<Vec<u8>>
             0x06, // reportDTCExtendedDataRecordByDTCNumber
             (dtc.id >> 16) as u8, // High byte
             (dtc.id >> 8) as u8, // Mid byte
-            (dtc.id) as u8 // Low byte
+            (dtc.id) as u8, // Low byte
+            EXT_DATA_ALL_RECORDS // Record number
         ],
     )
-}
\ No newline at end of file
+}
+
+/// reportNumberOfDTCByStatusMask sub function
+const REPORT_NUMBER_BY_STATUS_MASK: u8 = 0x01;
+
+/// DTCFormatIdentifier - How the ECU encodes its DTCs
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DtcFormat {
+    /// SAE J2012-DA DTC format 00 (ISO 15031-6, OBD style P/C/B/U codes)
+    Iso15031_6,
+    /// ISO 14229-1 DTC format
+    Iso14229_1,
+    SaeJ1939_73,
+    Iso11992_4,
+    /// SAE J2012-DA DTC format 04
+    SaeJ2012Da04,
+    Unknown(u8),
+}
+
+impl DtcFormat {
+    pub fn from_byte(b: u8) -> Self {
+        match b {
+            0x00 => Self::Iso15031_6,
+            0x01 => Self::Iso14229_1,
+            0x02 => Self::SaeJ1939_73,
+            0x03 => Self::Iso11992_4,
+            0x04 => Self::SaeJ2012Da04,
+            x => Self::Unknown(x),
+        }
+    }
+}
+
+/// Number of DTCs which match a status mask
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DtcCount {
+    /// Status bits the ECU supports (DTCStatusAvailabilityMask)
+    pub status_availability: u8,
+    pub format: DtcFormat,
+    pub count: u16,
+}
+
+/// Parses a reportNumberOfDTCByStatusMask response (Starting at the SID)
+pub fn parse_dtc_count(resp: &[u8]) -> ProtocolResult<DtcCount> {
+    if resp.len() < 6 {
+        return Err(too_short(6, resp.len()));
+    }
+    Ok(DtcCount {
+        status_availability: resp[2],
+        format: DtcFormat::from_byte(resp[3]),
+        count: u16::from_be_bytes([resp[4], resp[5]]),
+    })
+}
+
+/// Counts the DTCs with any of the status bits in `mask` set, without reading them
+pub fn count_dtcs(ecu: &UDSECU, mask: u8) -> ProtocolResult<DtcCount> {
+    let resp = ecu.run_command(
+        super::UDSCommand::ReadDTCInformation.into(),
+        &[REPORT_NUMBER_BY_STATUS_MASK, mask],
+    )?;
+    parse_dtc_count(&resp)
+}
+
+/// reportDTCSnapshotRecordByDTCNumber sub function
+const REPORT_SNAPSHOT_BY_DTC: u8 = 0x04;
+
+/// Snapshot record number which requests every record the ECU has stored for a DTC
+pub const SNAPSHOT_ALL_RECORDS: u8 = 0xFF;
+
+/// reportDTCExtDataRecordByDTCNumber sub function
+const REPORT_EXT_DATA_BY_DTC: u8 = 0x06;
+
+/// Extended data record number which requests every record the ECU has stored for a DTC
+pub const EXT_DATA_ALL_RECORDS: u8 = 0xFF;
+
+/// Extended data record which is commonly the occurrence counter - How many times the
+/// fault has been detected. Record numbers below 0x90 are manufacturer specific, but
+/// this and [EXT_DATA_AGING_COUNTER] are the usual layout (1 byte each)
+pub const EXT_DATA_OCCURRENCE_COUNTER: u8 = 0x01;
+/// Extended data record which is commonly the aging counter - How many operation cycles
+/// have passed without the fault, counting towards it being cleared automatically
+pub const EXT_DATA_AGING_COUNTER: u8 = 0x02;
+
+/// One extended data record of a DTC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtDataRecord {
+    OccurrenceCounter(u8),
+    AgingCounter(u8),
+    /// Record with a layout which is not known. Holds the record number and its data
+    Raw(u8, Vec<u8>),
+}
+
+impl ExtDataRecord {
+    /// Length of the records with a known layout
+    fn known_len(number: u8) -> Option<usize> {
+        match number {
+            EXT_DATA_OCCURRENCE_COUNTER | EXT_DATA_AGING_COUNTER => Some(1),
+            _ => None,
+        }
+    }
+
+    fn decode(number: u8, data: &[u8]) -> Self {
+        match (number, data) {
+            (EXT_DATA_OCCURRENCE_COUNTER, [c]) => Self::OccurrenceCounter(*c),
+            (EXT_DATA_AGING_COUNTER, [c]) => Self::AgingCounter(*c),
+            _ => Self::Raw(number, data.to_vec()),
+        }
+    }
+}
+
+/// Extended data records the ECU stored for a DTC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtcExtData {
+    pub dtc: u32,
+    pub status: u8,
+    pub records: Vec<ExtDataRecord>,
+}
+
+impl DtcExtData {
+    /// Returns the occurrence counter, if the ECU reported one
+    pub fn occurrence_counter(&self) -> Option<u8> {
+        self.records.iter().find_map(|r| match r {
+            ExtDataRecord::OccurrenceCounter(c) => Some(*c),
+            _ => None,
+        })
+    }
+}
+
+/// One snapshot (Freeze frame) record of a DTC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotRecord {
+    pub number: u8,
+    /// Each DID in the record, with its data
+    pub dids: Vec<(u16, Vec<u8>)>,
+}
+
+/// Snapshot records the ECU stored for a DTC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtcSnapshot {
+    pub dtc: u32,
+    pub status: u8,
+    pub records: Vec<SnapshotRecord>,
+}
+
+fn too_short(expect: usize, actual: usize) -> ProtocolError {
+    ProtocolError::InvalidResponseSize { expect, actual }
+}
+
+/// Parses a reportDTCSnapshotRecordByDTCNumber response (Starting at the SID).
+///
+/// The response does not say how long the data of each DID is, so `did_len` has to
+/// provide it. A DID of unknown length is only accepted as the last DID of a record, in which
+/// case its data is the rest of the response. This is only right for the last record, so
+/// requesting [SNAPSHOT_ALL_RECORDS] needs the length of every DID but the last one
+pub fn parse_snapshot(
+    resp: &[u8],
+    did_len: &dyn Fn(u16) -> Option<usize>,
+) -> ProtocolResult<DtcSnapshot> {
+    if resp.len() < 6 {
+        return Err(too_short(6, resp.len()));
+    }
+    let mut res = DtcSnapshot {
+        dtc: (resp[2] as u32) << 16 | (resp[3] as u32) << 8 | resp[4] as u32,
+        status: resp[5],
+        records: Vec::new(),
+    };
+    let mut pos = 6;
+    while pos < resp.len() {
+        if pos + 2 > resp.len() {
+            return Err(too_short(pos + 2, resp.len()));
+        }
+        let mut record = SnapshotRecord {
+            number: resp[pos],
+            dids: Vec::new(),
+        };
+        let count = resp[pos + 1] as usize;
+        pos += 2;
+        for i in 0..count {
+            if pos + 2 > resp.len() {
+                return Err(too_short(pos + 2, resp.len()));
+            }
+            let did = u16::from_be_bytes([resp[pos], resp[pos + 1]]);
+            pos += 2;
+            let len = match did_len(did) {
+                Some(l) => l,
+                None if i == count - 1 => resp.len() - pos,
+                None => {
+                    return Err(ProtocolError::CustomError(format!(
+                        "Cannot read snapshot record {}, length of DID 0x{:04X} is not known",
+                        record.number, did
+                    )))
+                }
+            };
+            if pos + len > resp.len() {
+                return Err(too_short(pos + len, resp.len()));
+            }
+            record.dids.push((did, resp[pos..pos + len].to_vec()));
+            pos += len;
+        }
+        res.records.push(record);
+    }
+    Ok(res)
+}
+
+/// Parses a reportDTCExtDataRecordByDTCNumber response (Starting at the SID).
+///
+/// Like snapshots, the response does not say how long each record is. A record without
+/// a known layout is taken to be the last one, with the rest of the response as its data,
+/// which is always the case when a single record was requested
+pub fn parse_ext_data(resp: &[u8]) -> ProtocolResult<DtcExtData> {
+    if resp.len() < 6 {
+        return Err(too_short(6, resp.len()));
+    }
+    let mut res = DtcExtData {
+        dtc: (resp[2] as u32) << 16 | (resp[3] as u32) << 8 | resp[4] as u32,
+        status: resp[5],
+        records: Vec::new(),
+    };
+    let mut pos = 6;
+    while pos < resp.len() {
+        let number = resp[pos];
+        pos += 1;
+        // A record without a known layout takes the rest of the response
+        let len = ExtDataRecord::known_len(number).unwrap_or(resp.len() - pos);
+        if pos + len > resp.len() {
+            return Err(too_short(pos + len, resp.len()));
+        }
+        res.records
+            .push(ExtDataRecord::decode(number, &resp[pos..pos + len]));
+        pos += len;
+    }
+    Ok(res)
+}
+
+/// Reads extended data record `record` (Or [EXT_DATA_ALL_RECORDS]) of a DTC.
+/// See [parse_ext_data] for how the records are split
+pub fn read_dtc_extended_data(
+    ecu: &UDSECU,
+    dtc: u32,
+    record: u8,
+) -> ProtocolResult<DtcExtData> {
+    let resp = ecu.run_command(
+        super::UDSCommand::ReadDTCInformation.into(),
+        &[
+            REPORT_EXT_DATA_BY_DTC,
+            (dtc >> 16) as u8,
+            (dtc >> 8) as u8,
+            dtc as u8,
+            record,
+        ],
+    )?;
+    parse_ext_data(&resp)
+}
+
+/// Reads snapshot record `record` (Or [SNAPSHOT_ALL_RECORDS]) of a DTC.
+/// See [parse_snapshot] for how the data of each DID is split
+pub fn read_dtc_snapshot(
+    ecu: &UDSECU,
+    dtc: u32,
+    record: u8,
+    did_len: &dyn Fn(u16) -> Option<usize>,
+) -> ProtocolResult<DtcSnapshot> {
+    let resp = ecu.run_command(
+        super::UDSCommand::ReadDTCInformation.into(),
+        &[
+            REPORT_SNAPSHOT_BY_DTC,
+            (dtc >> 16) as u8,
+            (dtc >> 8) as u8,
+            dtc as u8,
+            record,
+        ],
+    )?;
+    parse_snapshot(&resp, did_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_snapshot() {
+        let lens = |did: u16| match did {
+            0xF40C => Some(2),
+            0xF405 => Some(1),
+            _ => None,
+        };
+        #[rustfmt::skip]
+        let resp = [
+            0x59, 0x04, 0x01, 0x23, 0x45, 0x2F,
+            0x01, 0x02, 0xF4, 0x0C, 0x1A, 0xF8, 0xF4, 0x05, 0x7B,
+            0x02, 0x01, 0xF4, 0x05, 0x50,
+        ];
+        let snap = parse_snapshot(&resp, &lens).unwrap();
+        assert_eq!((snap.dtc, snap.status), (0x012345, 0x2F));
+        assert_eq!(
+            snap.records,
+            vec![
+                SnapshotRecord {
+                    number: 1,
+                    dids: vec![(0xF40C, vec![0x1A, 0xF8]), (0xF405, vec![0x7B])]
+                },
+                SnapshotRecord {
+                    number: 2,
+                    dids: vec![(0xF405, vec![0x50])]
+                },
+            ]
+        );
+
+        // Last DID of unknown length takes the rest of the response
+        let resp = [0x59, 0x04, 0x01, 0x23, 0x45, 0x2F, 0x01, 0x01, 0x12, 0x34, 0xAA, 0xBB];
+        let snap = parse_snapshot(&resp, &lens).unwrap();
+        assert_eq!(snap.records[0].dids, vec![(0x1234, vec![0xAA, 0xBB])]);
+        // But not if another DID follows it
+        let resp = [0x59, 0x04, 0x01, 0x23, 0x45, 0x2F, 0x01, 0x02, 0x12, 0x34, 0xAA, 0xF4, 0x05, 0x01];
+        assert!(parse_snapshot(&resp, &lens).is_err());
+        // No records stored for the DTC
+        let snap = parse_snapshot(&[0x59, 0x04, 0x01, 0x23, 0x45, 0x00], &lens).unwrap();
+        assert!(snap.records.is_empty());
+        // Truncated data
+        let resp = [0x59, 0x04, 0x01, 0x23, 0x45, 0x2F, 0x01, 0x01, 0xF4, 0x0C, 0x1A];
+        assert!(parse_snapshot(&resp, &lens).is_err());
+    }
+
+    #[test]
+    fn test_parse_ext_data() {
+        let resp = [0x59, 0x06, 0x01, 0x23, 0x45, 0x2F, 0x01, 0x05, 0x02, 0x28];
+        let ext = parse_ext_data(&resp).unwrap();
+        assert_eq!((ext.dtc, ext.status), (0x012345, 0x2F));
+        assert_eq!(
+            ext.records,
+            vec![ExtDataRecord::OccurrenceCounter(5), ExtDataRecord::AgingCounter(0x28)]
+        );
+        assert_eq!(ext.occurrence_counter(), Some(5));
+
+        // Manufacturer specific record takes the rest of the response
+        let resp = [0x59, 0x06, 0x01, 0x23, 0x45, 0x2F, 0x01, 0x02, 0x10, 0xAA, 0xBB];
+        let ext = parse_ext_data(&resp).unwrap();
+        assert_eq!(
+            ext.records,
+            vec![ExtDataRecord::OccurrenceCounter(2), ExtDataRecord::Raw(0x10, vec![0xAA, 0xBB])]
+        );
+        // No records stored for the DTC
+        let ext = parse_ext_data(&[0x59, 0x06, 0x01, 0x23, 0x45, 0x00]).unwrap();
+        assert_eq!(ext.occurrence_counter(), None);
+        // Truncated data
+        assert!(parse_ext_data(&[0x59, 0x06, 0x01, 0x23, 0x45, 0x2F, 0x01]).is_err());
+        assert!(parse_ext_data(&[0x59, 0x06, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_parse_dtc_count() {
+        let count = parse_dtc_count(&[0x59, 0x01, 0xFF, 0x01, 0x00, 0x0C]).unwrap();
+        assert_eq!(
+            count,
+            DtcCount {
+                status_availability: 0xFF,
+                format: DtcFormat::Iso14229_1,
+                count: 12,
+            }
+        );
+        let count = parse_dtc_count(&[0x59, 0x01, 0x09, 0x00, 0x01, 0x02]).unwrap();
+        assert_eq!((count.format, count.count), (DtcFormat::Iso15031_6, 0x102));
+        assert_eq!(DtcFormat::from_byte(0x10), DtcFormat::Unknown(0x10));
+        assert!(parse_dtc_count(&[0x59, 0x01, 0xFF, 0x01, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::time::{Duration, Instant};
+
+use crate::commapi::protocols::{ProtocolError, ProtocolResult, ProtocolServer};
+
+use super::{UDSCommand, UDSECU};
+
+// Long running routines (Self tests, erasing memory...) are run with RoutineControl ($31)
+// in 2 steps:
+// 1. StartRoutine (Sub function $01) - Starts the routine, the ECU replies straight away.
+// 2. RequestRoutineResults (Sub function $03) - Polled until the routine is done.
+//
+// The response to both is [$71, sub function, routine ID (2 bytes), routineInfo, status
+// record...]. The meaning of routineInfo is left to the manufacturer by ISO 14229. Most
+// ECUs use [ROUTINE_IN_PROGRESS] whilst the routine is still running, so any other value
+// (Or no routineInfo at all) is treated as the routine being done.
+
+/// Sub function to start a routine
+const START_ROUTINE: u8 = 0x01;
+/// Sub function to request the results of a routine
+const REQUEST_ROUTINE_RESULTS: u8 = 0x03;
+/// routineInfo value for a routine which is still running
+pub const ROUTINE_IN_PROGRESS: u8 = 0x01;
+/// BusyRepeatRequest - The ECU is too busy running the routine to answer yet
+const NRC_BUSY_REPEAT_REQUEST: u8 = 0x21;
+
+/// Checks a RoutineControl response is for the routine that was requested, and
+/// returns the result record (routineInfo and status record) after the routine ID
+pub(crate) fn parse_routine_resp(
+    resp: &[u8],
+    sub_function: u8,
+    routine_id: u16,
+) -> ProtocolResult<&[u8]> {
+    if resp.len() < 4 {
+        return Err(ProtocolError::InvalidResponseSize {
+            expect: 4,
+            actual: resp.len(),
+        });
+    }
+    let resp_id = u16::from_be_bytes([resp[2], resp[3]]);
+    if resp[1] & 0x7F != sub_function || resp_id != routine_id {
+        return Err(ProtocolError::CustomError(format!(
+            "ECU replied to routine 0x{:04X} sub function {:02X}, expected routine 0x{:04X} sub function {:02X}",
+            resp_id,
+            resp[1] & 0x7F,
+            routine_id,
+            sub_function
+        )));
+    }
+    Ok(&resp[4..])
+}
+
+/// Returns true if a result record says the routine is still running
+pub(crate) fn is_in_progress(record: &[u8]) -> bool {
+    record.first() == Some(&ROUTINE_IN_PROGRESS)
+}
+
+/// Calls `poll` every `poll_interval` until it returns a result record for a routine
+/// which is done, or `timeout` has passed since `start`
+pub(crate) fn poll_until_done(
+    start: Instant,
+    poll_interval: Duration,
+    timeout: Duration,
+    poll: &mut dyn FnMut() -> ProtocolResult<Vec<u8>>,
+) -> ProtocolResult<Vec<u8>> {
+    loop {
+        match poll() {
+            Ok(record) if !is_in_progress(&record) => return Ok(record),
+            Ok(_) => {}
+            Err(e) if e.get_nrc() == Some(NRC_BUSY_REPEAT_REQUEST) => {}
+            Err(e) => return Err(e),
+        }
+        if start.elapsed() + poll_interval > timeout {
+            return Err(ProtocolError::Timeout);
+        }
+        std::thread::sleep(poll_interval);
+    }
+}
+
+/// Starts a routine, then polls its results until it is done. Returns the final result
+/// record, starting with routineInfo (If the ECU sends one)
+pub fn run_routine_to_completion(
+    ecu: &UDSECU,
+    routine_id: u16,
+    input: &[u8],
+    poll_interval: Duration,
+    timeout: Duration,
+) -> ProtocolResult<Vec<u8>> {
+    let start = Instant::now();
+    let [id_hi, id_lo] = routine_id.to_be_bytes();
+    let mut args = vec![START_ROUTINE, id_hi, id_lo];
+    args.extend_from_slice(input);
+    let res = ecu.run_command(UDSCommand::RoutineControl.into(), &args)?;
+    parse_routine_resp(&res, START_ROUTINE, routine_id)?;
+    log::debug!(
+        "UDS - Started routine 0x{:04X}, polling for results",
+        routine_id
+    );
+
+    poll_until_done(start, poll_interval, timeout, &mut || {
+        let res = ecu.run_command(
+            UDSCommand::RoutineControl.into(),
+            &[REQUEST_ROUTINE_RESULTS, id_hi, id_lo],
+        )?;
+        parse_routine_resp(&res, REQUEST_ROUTINE_RESULTS, routine_id).map(|r| r.to_vec())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commapi::protocols::uds::UDSNegativeCode;
+
+    #[test]
+    fn test_parse_routine_resp() {
+        let resp = [0x71, 0x03, 0x02, 0x03, 0x02, 0xAA];
+        assert_eq!(
+            parse_routine_resp(&resp, REQUEST_ROUTINE_RESULTS, 0x0203).unwrap(),
+            &[0x02, 0xAA]
+        );
+        assert!(parse_routine_resp(&resp, REQUEST_ROUTINE_RESULTS, 0x0204).is_err());
+        assert!(parse_routine_resp(&resp, START_ROUTINE, 0x0203).is_err());
+        let short = [0x71, 0x03, 0x02];
+        assert!(parse_routine_resp(&short, REQUEST_ROUTINE_RESULTS, 0x0203).is_err());
+    }
+
+    #[test]
+    fn test_poll_until_done() {
+        let mut polls = vec![
+            Ok(vec![ROUTINE_IN_PROGRESS]),
+            Err(ProtocolError::ProtocolError(Box::new(
+                UDSNegativeCode::BusyRepeatRequest,
+            ))),
+            Ok(vec![0x02, 0x55]),
+        ]
+        .into_iter();
+        let res = poll_until_done(
+            Instant::now(),
+            Duration::from_millis(1),
+            Duration::from_secs(1),
+            &mut || polls.next().unwrap(),
+        );
+        assert_eq!(res.unwrap(), vec![0x02, 0x55]);
+
+        // Routine which never finishes
+        let res = poll_until_done(
+            Instant::now(),
+            Duration::from_millis(5),
+            Duration::from_millis(20),
+            &mut || Ok(vec![ROUTINE_IN_PROGRESS]),
+        );
+        assert!(res.unwrap_err().is_timeout());
+    }
+}
@@ -0,0 +1,154 @@
+//! Scanning an ECU for supported services and data identifiers
+//!
+//! A negative response does not always mean something is missing. For example
+//! `SecurityAccessDenied` on a DID read means the DID exists, but is protected.
+//! [NrcPolicy] decides what each negative response code means for a scan.
+
+use std::{collections::HashMap, ops::RangeInclusive};
+
+use crate::commapi::protocols::ProtocolResult;
+
+use super::{UDSCommand, UDSECU};
+
+/// What a scan response says about the item that was scanned
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScanClass {
+    /// Item exists (It might still be protected, see [ScanResult::nrc])
+    Found,
+    /// Item does not exist
+    NotFound,
+    /// Cannot tell from the response (Timeout, busy ECU...)
+    Unknown,
+}
+
+/// Maps negative response codes to a [ScanClass]
+#[derive(Debug, Clone)]
+pub struct NrcPolicy {
+    classes: HashMap<u8, ScanClass>,
+    default: ScanClass,
+}
+
+impl Default for NrcPolicy {
+    /// Only `ServiceNotSupported` (0x11) and `RequestOutOfRange` (0x31) mean the item
+    /// does not exist. Any other negative response means it exists, but could not be used
+    fn default() -> Self {
+        Self::new(ScanClass::Found)
+            .with(0x11, ScanClass::NotFound)
+            .with(0x31, ScanClass::NotFound)
+    }
+}
+
+impl NrcPolicy {
+    /// Creates a policy where every negative response is classified as `default`
+    pub fn new(default: ScanClass) -> Self {
+        Self {
+            classes: HashMap::new(),
+            default,
+        }
+    }
+
+    /// Classifies `nrc` as `class`
+    pub fn with(mut self, nrc: u8, class: ScanClass) -> Self {
+        self.classes.insert(nrc, class);
+        self
+    }
+
+    pub fn classify_nrc(&self, nrc: u8) -> ScanClass {
+        *self.classes.get(&nrc).unwrap_or(&self.default)
+    }
+
+    /// Classifies the result of a request. Errors which are not negative
+    /// responses are always [ScanClass::Unknown]
+    pub fn classify(&self, res: &ProtocolResult<Vec<u8>>) -> ScanClass {
+        match res {
+            Ok(_) => ScanClass::Found,
+            Err(e) => match e.get_nrc() {
+                Some(nrc) => self.classify_nrc(nrc),
+                None => ScanClass::Unknown,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanResult {
+    /// DID or SID that was scanned
+    pub id: u16,
+    pub class: ScanClass,
+    /// Negative response code from the ECU, if any
+    pub nrc: Option<u8>,
+    /// Positive response from the ECU, if any
+    pub response: Option<Vec<u8>>,
+}
+
+impl ScanResult {
+    fn new(id: u16, res: ProtocolResult<Vec<u8>>, policy: &NrcPolicy) -> Self {
+        let class = policy.classify(&res);
+        let (nrc, response) = match res {
+            Ok(r) => (None, Some(r)),
+            Err(e) => (e.get_nrc(), None),
+        };
+        Self {
+            id,
+            class,
+            nrc,
+            response,
+        }
+    }
+}
+
+/// Reads every DID in `dids`, classifying each response with `policy`.
+///
+/// Requests are sent without the lost session handling of [UDSECU], as
+/// protected DIDs are expected to return `SecurityAccessDenied`
+pub fn scan_dids(ecu: &UDSECU, dids: RangeInclusive<u16>, policy: &NrcPolicy) -> Vec<ScanResult> {
+    dids.map(|did| {
+        let res = ecu.exchange(UDSCommand::ReadDataByID.into(), &did.to_be_bytes());
+        ScanResult::new(did, res, policy)
+    })
+    .collect()
+}
+
+/// Sends every SID in `sids` with no parameters, classifying each response with `policy`.
+/// ECUs typically respond with `IncorrectMessageLength` to a supported service
+pub fn scan_services(ecu: &UDSECU, sids: &[u8], policy: &NrcPolicy) -> Vec<ScanResult> {
+    sids.iter()
+        .map(|sid| ScanResult::new(*sid as u16, ecu.exchange(*sid, &[]), policy))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commapi::protocols::uds::UDSNegativeCode;
+    use crate::commapi::protocols::{CommandError, ProtocolError};
+
+    fn nrc(b: u8) -> ProtocolResult<Vec<u8>> {
+        Err(ProtocolError::ProtocolError(Box::new(
+            UDSNegativeCode::from_byte(b),
+        )))
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let p = NrcPolicy::default();
+        assert_eq!(p.classify(&Ok(vec![0x62, 0xF1, 0x90])), ScanClass::Found);
+        assert_eq!(p.classify(&nrc(0x31)), ScanClass::NotFound);
+        assert_eq!(p.classify(&nrc(0x11)), ScanClass::NotFound);
+        assert_eq!(p.classify(&nrc(0x33)), ScanClass::Found);
+        assert_eq!(p.classify(&Err(ProtocolError::Timeout)), ScanClass::Unknown);
+    }
+
+    #[test]
+    fn test_custom_policy() {
+        let p = NrcPolicy::new(ScanClass::Unknown)
+            .with(0x31, ScanClass::NotFound)
+            .with(0x33, ScanClass::Found);
+        assert_eq!(p.classify(&nrc(0x22)), ScanClass::Unknown);
+        assert_eq!(p.classify(&nrc(0x33)), ScanClass::Found);
+
+        let res = ScanResult::new(0xF190, nrc(0x33), &p);
+        assert_eq!(res.nrc, Some(0x33));
+        assert!(res.response.is_none());
+    }
+}
@@ -0,0 +1,488 @@
+//! Diagnostic scripts - Repeatable sequences of UDS (Or KWP2000) requests
+//!
+//! A script is a list of steps, each sending one request to the ECU and checking
+//! the response. Negative responses can branch to other steps, so a procedure such as
+//! "enter extended session, unlock, run routine, read result" only has to be written once.
+//! Steps are sent to the ECU's own request ID, unless they set `addressing: functional`,
+//! in which case they are sent to the functional (Broadcast) ID (UDS only).
+//! Scripts can be written in YAML or JSON:
+//!
+//! ```yaml
+//! name: Run routine
+//! steps:
+//!   - name: Extended session
+//!     service: DiagnosticSessionControl
+//!     data: "03"
+//!     expect: "50 03"
+//!   - name: Start routine
+//!     service: RoutineControl
+//!     data: "01 02 03"
+//!     on_nrc:
+//!       "0x22": { goto: Extended session }
+//!   - name: Read result
+//!     service: "0x22"
+//!     data: "F1 90"
+//!     on_fail: continue
+//!   - name: Wake all ECUs
+//!     service: TesterPresent
+//!     data: "80"
+//!     addressing: functional
+//! ```
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::commapi::protocols::{DiagProtocol, ProtocolError, ProtocolResult};
+
+use super::{RequestAddressing, UDSECU};
+
+/// Maximum number of steps a script can run, so a `goto` loop cannot run forever
+const MAX_STEPS_RUN: usize = 1000;
+
+/// What to do after a step
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepAction {
+    /// Stop running the script
+    #[default]
+    Abort,
+    /// Carry on with the next step
+    Continue,
+    /// Jump to the step with this name
+    Goto(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptStep {
+    pub name: String,
+    /// Service to send, either by name (`ReadDataByID`) or SID (`0x22`)
+    pub service: String,
+    /// Parameters of the request, as hex bytes (`"F1 90"`)
+    #[serde(default)]
+    pub data: String,
+    /// Bytes the positive response must start with (Including the response SID)
+    #[serde(default)]
+    pub expect: Option<String>,
+    /// What to do when the ECU responds with a negative response code (`"0x31"`)
+    #[serde(default)]
+    pub on_nrc: HashMap<String, StepAction>,
+    /// What to do when the step fails, and it was not handled by `on_nrc`
+    #[serde(default)]
+    pub on_fail: StepAction,
+    /// Send the request to the ECU (`physical`, default) or broadcast it (`functional`)
+    #[serde(default)]
+    pub addressing: RequestAddressing,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Script {
+    pub name: String,
+    pub steps: Vec<ScriptStep>,
+}
+
+impl Script {
+    /// Loads a script from a file. Files ending in `.yaml` or `.yml` are read as YAML,
+    /// anything else is read as JSON
+    pub fn load(path: &str) -> ProtocolResult<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ProtocolError::CustomError(format!("Cannot read {}: {}", path, e)))?;
+        let lower = path.to_lowercase();
+        if lower.ends_with(".yaml") || lower.ends_with(".yml") {
+            serde_yaml::from_str(&contents).map_err(|e| ProtocolError::CustomError(e.to_string()))
+        } else {
+            serde_json::from_str(&contents).map_err(|e| ProtocolError::CustomError(e.to_string()))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepOutcome {
+    Passed,
+    /// ECU responded with a negative response code that was handled by `on_nrc`
+    Branched(u8),
+    /// Step failed, with the reason
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    pub name: String,
+    pub outcome: StepOutcome,
+    /// Positive response from the ECU, if any
+    pub response: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScriptReport {
+    /// Result of each step that was run, in the order they were run
+    pub steps: Vec<StepResult>,
+    /// True if the script ran to the end, rather than being aborted
+    pub completed: bool,
+}
+
+impl ScriptReport {
+    /// Returns true if the script ran to the end without any step failing
+    pub fn passed(&self) -> bool {
+        self.completed
+            && !self
+                .steps
+                .iter()
+                .any(|s| matches!(s.outcome, StepOutcome::Failed(_)))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Flow {
+    Abort,
+    Continue,
+    Goto(usize),
+}
+
+/// Script step, with everything parsed and resolved
+#[derive(Debug, Clone)]
+struct Step {
+    name: String,
+    sid: u8,
+    data: Vec<u8>,
+    expect: Option<Vec<u8>>,
+    on_nrc: HashMap<u8, Flow>,
+    on_fail: Flow,
+    addressing: RequestAddressing,
+}
+
+fn script_err(step: &str, msg: String) -> ProtocolError {
+    ProtocolError::CustomError(format!("Step '{}': {}", step, msg))
+}
+
+fn parse_u8(s: &str) -> Option<u8> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Parses hex bytes, optionally separated by whitespace (`"F1 90"` or `"F190"`)
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let hex: String = s.split_whitespace().collect();
+    hex.as_bytes()
+        .chunks(2)
+        .map(|c| match c {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(c).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parses a service name of the script's protocol, or a SID
+fn parse_service(protocol: DiagProtocol, s: &str) -> Result<u8, String> {
+    if let Some(sid) = protocol.service_by_name(s).or_else(|| parse_u8(s)) {
+        return Ok(sid);
+    }
+    let other = match protocol {
+        DiagProtocol::UDS => DiagProtocol::KWP2000,
+        DiagProtocol::KWP2000 => DiagProtocol::UDS,
+    };
+    if other.service_by_name(s).is_some() {
+        Err(format!(
+            "'{}' is a {} service, not {}",
+            s,
+            other.get_name(),
+            protocol.get_name()
+        ))
+    } else {
+        Err(format!("Unknown service '{}'", s))
+    }
+}
+
+/// Runs a [Script] against an ECU
+#[derive(Debug, Clone)]
+pub struct ScriptRunner {
+    steps: Vec<Step>,
+}
+
+impl ScriptRunner {
+    /// Checks and prepares a UDS script to be run. Fails if a step has an unknown
+    /// service, invalid hex, or a `goto` to a step that does not exist
+    pub fn new(script: &Script) -> ProtocolResult<Self> {
+        Self::for_protocol(script, DiagProtocol::UDS)
+    }
+
+    /// Checks and prepares a script to be run against an ECU using `protocol`.
+    /// Service names are those of `protocol`
+    pub fn for_protocol(script: &Script, protocol: DiagProtocol) -> ProtocolResult<Self> {
+        let index: HashMap<&str, usize> = script
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.name.as_str(), i))
+            .collect();
+        let resolve = |step: &str, a: &StepAction| match a {
+            StepAction::Abort => Ok(Flow::Abort),
+            StepAction::Continue => Ok(Flow::Continue),
+            StepAction::Goto(target) => index
+                .get(target.as_str())
+                .map(|i| Flow::Goto(*i))
+                .ok_or_else(|| script_err(step, format!("No step named '{}'", target))),
+        };
+
+        let mut steps = Vec::new();
+        for s in &script.steps {
+            let name = s.name.as_str();
+            let sid = parse_service(protocol, &s.service).map_err(|e| script_err(name, e))?;
+            if s.addressing == RequestAddressing::Functional && protocol != DiagProtocol::UDS {
+                return Err(script_err(
+                    name,
+                    format!(
+                        "Functional requests are not supported with {}",
+                        protocol.get_name()
+                    ),
+                ));
+            }
+            let data = parse_hex(&s.data)
+                .ok_or_else(|| script_err(name, format!("Invalid data '{}'", s.data)))?;
+            let expect = match &s.expect {
+                Some(e) => Some(
+                    parse_hex(e)
+                        .ok_or_else(|| script_err(name, format!("Invalid expect '{}'", e)))?,
+                ),
+                None => None,
+            };
+            let mut on_nrc = HashMap::new();
+            for (nrc, action) in &s.on_nrc {
+                let nrc = parse_u8(nrc)
+                    .ok_or_else(|| script_err(name, format!("Invalid NRC '{}'", nrc)))?;
+                on_nrc.insert(nrc, resolve(name, action)?);
+            }
+            steps.push(Step {
+                name: s.name.clone(),
+                sid,
+                data,
+                expect,
+                on_nrc,
+                on_fail: resolve(name, &s.on_fail)?,
+                addressing: s.addressing,
+            });
+        }
+        Ok(Self { steps })
+    }
+
+    /// Runs the script against the ECU
+    pub fn run(&self, ecu: &UDSECU) -> ScriptReport {
+        self.run_with(|sid, data, addressing| ecu.run_command_addressed(sid, data, addressing))
+    }
+
+    /// Runs the script, using `send` to send each request with the step's addressing,
+    /// and get the ECU's response
+    pub fn run_with<F>(&self, mut send: F) -> ScriptReport
+    where
+        F: FnMut(u8, &[u8], RequestAddressing) -> ProtocolResult<Vec<u8>>,
+    {
+        let mut report = ScriptReport::default();
+        let mut idx = 0;
+        while idx < self.steps.len() {
+            if report.steps.len() >= MAX_STEPS_RUN {
+                report.steps.push(StepResult {
+                    name: self.steps[idx].name.clone(),
+                    outcome: StepOutcome::Failed(format!(
+                        "Script ran more than {} steps, aborting",
+                        MAX_STEPS_RUN
+                    )),
+                    response: None,
+                });
+                return report;
+            }
+            let step = &self.steps[idx];
+            let (outcome, response, flow) = match send(step.sid, &step.data, step.addressing) {
+                Ok(resp) => match &step.expect {
+                    Some(e) if !resp.starts_with(e) => (
+                        StepOutcome::Failed(format!(
+                            "Expected response {:02X?}, got {:02X?}",
+                            e, resp
+                        )),
+                        Some(resp),
+                        step.on_fail,
+                    ),
+                    _ => (StepOutcome::Passed, Some(resp), Flow::Continue),
+                },
+                Err(e) => {
+                    let handled = e
+                        .get_nrc()
+                        .and_then(|n| step.on_nrc.get(&n).map(|f| (n, *f)));
+                    match handled {
+                        Some((nrc, flow)) => (StepOutcome::Branched(nrc), None, flow),
+                        None => (StepOutcome::Failed(e.get_text()), None, step.on_fail),
+                    }
+                }
+            };
+            report.steps.push(StepResult {
+                name: step.name.clone(),
+                outcome,
+                response,
+            });
+            match flow {
+                Flow::Abort => return report,
+                Flow::Continue => idx += 1,
+                Flow::Goto(i) => idx = i,
+            }
+        }
+        report.completed = true;
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commapi::protocols::uds::UDSNegativeCode;
+    use crate::commapi::protocols::CommandError;
+
+    const SCRIPT: &str = r#"
+name: Test
+steps:
+  - name: Extended session
+    service: DiagnosticSessionControl
+    data: "03"
+    expect: "50 03"
+  - name: Start routine
+    service: RoutineControl
+    data: "01 02 03"
+    on_nrc:
+      "0x22": { goto: Extended session }
+  - name: Read VIN
+    service: "0x22"
+    data: "F190"
+    on_fail: continue
+  - name: Read DTCs
+    service: ReadDTCInformation
+    data: "02 FF"
+"#;
+
+    fn nrc(b: u8) -> ProtocolResult<Vec<u8>> {
+        Err(ProtocolError::ProtocolError(Box::new(
+            UDSNegativeCode::from_byte(b),
+        )))
+    }
+
+    #[test]
+    fn test_script_branching() {
+        let script: Script = serde_yaml::from_str(SCRIPT).unwrap();
+        let runner = ScriptRunner::new(&script).unwrap();
+        let mut routine_attempts = 0;
+        let report = runner.run_with(|sid, data, _| match sid {
+            0x10 => Ok(vec![0x50, data[0]]),
+            0x31 => {
+                routine_attempts += 1;
+                // Conditions not correct the first time
+                if routine_attempts == 1 {
+                    nrc(0x22)
+                } else {
+                    Ok(vec![0x71, 0x01, 0x02, 0x03])
+                }
+            }
+            0x22 => nrc(0x31),
+            _ => Ok(vec![sid + 0x40]),
+        });
+        let outcomes: Vec<_> = report.steps.iter().map(|s| s.outcome.clone()).collect();
+        assert_eq!(outcomes[0], StepOutcome::Passed);
+        assert_eq!(outcomes[1], StepOutcome::Branched(0x22));
+        assert_eq!(outcomes[2], StepOutcome::Passed);
+        assert_eq!(outcomes[3], StepOutcome::Passed);
+        assert!(matches!(outcomes[4], StepOutcome::Failed(_)));
+        assert_eq!(outcomes[5], StepOutcome::Passed);
+        assert!(report.completed);
+        // Read VIN failed, even though the script carried on
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn test_script_validation() {
+        let mut script: Script = serde_yaml::from_str(SCRIPT).unwrap();
+        script.steps[1]
+            .on_nrc
+            .insert("0x33".into(), StepAction::Goto("Unlock".into()));
+        assert!(ScriptRunner::new(&script).is_err());
+
+        let mut script: Script = serde_yaml::from_str(SCRIPT).unwrap();
+        script.steps[0].expect = Some("5".into());
+        assert!(ScriptRunner::new(&script).is_err());
+
+        // Expect mismatch aborts by default
+        let script: Script = serde_yaml::from_str(SCRIPT).unwrap();
+        let report = ScriptRunner::new(&script)
+            .unwrap()
+            .run_with(|_, _, _| Ok(vec![0x50, 0x01]));
+        assert_eq!(report.steps.len(), 1);
+        assert!(!report.completed);
+    }
+
+    #[test]
+    fn test_script_protocol() {
+        let script: Script = serde_yaml::from_str(SCRIPT).unwrap();
+        let err = ScriptRunner::for_protocol(&script, DiagProtocol::KWP2000).unwrap_err();
+        assert!(err
+            .get_text()
+            .contains("'DiagnosticSessionControl' is a UDS service"));
+
+        let script: Script = serde_yaml::from_str(
+            r#"
+name: KWP
+steps:
+  - name: Read DTCs
+    service: ReadDTCByStatus
+    data: "02 FF 00"
+  - name: Custom
+    service: "0xA0"
+"#,
+        )
+        .unwrap();
+        assert!(ScriptRunner::new(&script).is_err());
+        let mut sids = Vec::new();
+        let report = ScriptRunner::for_protocol(&script, DiagProtocol::KWP2000)
+            .unwrap()
+            .run_with(|sid, _, _| {
+                sids.push(sid);
+                Ok(vec![sid + 0x40])
+            });
+        assert!(report.passed());
+        assert_eq!(sids, vec![0x18, 0xA0]);
+    }
+
+    #[test]
+    fn test_script_addressing() {
+        let script: Script = serde_yaml::from_str(
+            r#"
+name: Mixed
+steps:
+  - name: Wake all ECUs
+    service: TesterPresent
+    data: "80"
+    addressing: functional
+  - name: Read VIN
+    service: ReadDataByID
+    data: "F1 90"
+"#,
+        )
+        .unwrap();
+        let mut sent = Vec::new();
+        let report = ScriptRunner::new(&script)
+            .unwrap()
+            .run_with(|sid, _, addressing| {
+                sent.push((sid, addressing));
+                Ok(vec![sid + 0x40])
+            });
+        assert!(report.passed());
+        assert_eq!(
+            sent,
+            vec![
+                (0x3E, RequestAddressing::Functional),
+                (0x22, RequestAddressing::Physical)
+            ]
+        );
+
+        let err = ScriptRunner::for_protocol(&script, DiagProtocol::KWP2000).unwrap_err();
+        assert!(err
+            .get_text()
+            .contains("Functional requests are not supported"));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::commapi::protocols::{ProtocolError, ProtocolResult, ProtocolServer};
+
+use super::{UDSCommand, UDSECU};
+
+// The service, Security Access ($27), unlocks protected services with a seed and key
+// handshake:
+// 1. requestSeed (Odd sub function) - The ECU replies with a random seed.
+// 2. sendKey (The next even sub function) - The key computed from the seed is sent back.
+//
+// The odd sub function is the security level. An ECU which is already unlocked at the
+// level replies with a seed of all 0s, and no key has to be sent. After too many invalid
+// keys, the ECU locks out further attempts for a while with RequiredTimeDelayNotExpired.
+
+/// Checks a requestSeed response is for the requested level, and returns the seed
+pub(crate) fn parse_seed(resp: &[u8], level: u8) -> ProtocolResult<&[u8]> {
+    if resp.len() < 3 {
+        return Err(ProtocolError::InvalidResponseSize {
+            expect: 3,
+            actual: resp.len(),
+        });
+    }
+    if resp[1] != level {
+        return Err(ProtocolError::CustomError(format!(
+            "ECU replied with a seed for security level {:02X}, expected {:02X}",
+            resp[1], level
+        )));
+    }
+    Ok(&resp[2..])
+}
+
+/// Returns the parameters of the sendKey request for a security level
+pub(crate) fn send_key_args(level: u8, key: &[u8]) -> Vec<u8> {
+    let mut args = vec![level + 1];
+    args.extend_from_slice(key);
+    args
+}
+
+/// Unlocks security level `level` (The odd requestSeed sub function), computing the
+/// key from the ECU's seed with `key_fn`.
+///
+/// If the ECU is locked out after invalid keys, this fails with RequiredTimeDelayNotExpired
+/// (0x37). It is up to the caller to wait before trying again
+pub fn security_access(
+    ecu: &UDSECU,
+    level: u8,
+    key_fn: &dyn Fn(&[u8]) -> Vec<u8>,
+) -> ProtocolResult<()> {
+    if level & 1 == 0 || level >= 0x7F {
+        return Err(ProtocolError::CustomError(format!(
+            "Security level {:02X} is not an odd requestSeed sub function",
+            level
+        )));
+    }
+    let resp = ecu.run_command(UDSCommand::SecurityAccess.into(), &[level])?;
+    let seed = parse_seed(&resp, level)?;
+    if seed.iter().all(|b| *b == 0) {
+        log::info!(
+            "UDS - ECU is already unlocked at security level {:02X}",
+            level
+        );
+        return Ok(());
+    }
+    let key = key_fn(seed);
+    ecu.run_command(
+        UDSCommand::SecurityAccess.into(),
+        &send_key_args(level, &key),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed_to_key() {
+        let resp = [0x67, 0x03, 0x12, 0x34, 0x56, 0x78];
+        let seed = parse_seed(&resp, 0x03).unwrap();
+        assert_eq!(seed, [0x12, 0x34, 0x56, 0x78]);
+        let key: Vec<u8> = seed.iter().map(|b| b ^ 0xFF).collect();
+        assert_eq!(
+            send_key_args(0x03, &key),
+            vec![0x04, 0xED, 0xCB, 0xA9, 0x87]
+        );
+
+        // Seed for a different level, and a response with no seed
+        assert!(parse_seed(&resp, 0x01).is_err());
+        assert!(parse_seed(&[0x67, 0x03], 0x03).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::commapi::protocols::{ProtocolError, ProtocolResult, ProtocolServer};
+
+use super::{UDSCommand, UDSECU};
+
+// Reading memory out of the ECU is done in 3 steps:
+// 1. RequestUpload ($35) - Tells the ECU the address and size of the memory to upload.
+//    The ECU replies with the largest TransferData message it can send.
+// 2. TransferData ($36) - Repeated until all the memory has been received. Each request
+//    carries a block sequence counter, which starts at 1 and wraps from 0xFF to 0x00.
+// 3. RequestTransferExit ($37) - Ends the upload.
+
+/// Data format identifier for data which is neither compressed nor encrypted
+const DATA_FORMAT_RAW: u8 = 0x00;
+/// Address and length format identifier - 4 byte memory size, 4 byte memory address
+const ADDR_LEN_FORMAT: u8 = 0x44;
+
+/// Builds the arguments of a RequestUpload request
+pub(crate) fn encode_request_upload(address: u32, size: u32) -> Vec<u8> {
+    let mut args = vec![DATA_FORMAT_RAW, ADDR_LEN_FORMAT];
+    args.extend_from_slice(&address.to_be_bytes());
+    args.extend_from_slice(&size.to_be_bytes());
+    args
+}
+
+/// Returns how many bytes of memory the ECU sends in each TransferData response,
+/// from its RequestUpload response. The ECU's maxNumberOfBlockLength includes the SID
+/// and block sequence counter, so 2 bytes less than it are usable
+pub(crate) fn parse_block_len(resp: &[u8]) -> ProtocolResult<usize> {
+    let len_bytes = (*resp.get(1).unwrap_or(&0) >> 4) as usize;
+    let max_len = match resp.get(2..2 + len_bytes) {
+        Some(b) if (1..=4).contains(&len_bytes) => {
+            b.iter().fold(0usize, |acc, x| (acc << 8) | *x as usize)
+        }
+        _ => {
+            return Err(ProtocolError::InvalidResponseSize {
+                expect: 2 + len_bytes.max(1),
+                actual: resp.len(),
+            })
+        }
+    };
+    if max_len <= 2 {
+        return Err(ProtocolError::CustomError(format!(
+            "ECU block length of {} bytes is too small",
+            max_len
+        )));
+    }
+    Ok(max_len - 2)
+}
+
+/// Uploads `size` bytes of memory from the ECU, starting at `address`.
+///
+/// `progress` is called after each block with the number of bytes received so far,
+/// and the total number of bytes
+pub fn read_upload(
+    ecu: &UDSECU,
+    address: u32,
+    size: u32,
+    progress: &mut dyn FnMut(usize, usize),
+) -> ProtocolResult<Vec<u8>> {
+    let res = ecu.run_command(
+        UDSCommand::RequestUpload.into(),
+        &encode_request_upload(address, size),
+    )?;
+    let block_len = parse_block_len(&res)?;
+    log::debug!(
+        "UDS - Uploading {} bytes from 0x{:08X} in blocks of {} bytes",
+        size,
+        address,
+        block_len
+    );
+
+    let size = size as usize;
+    let mut data = Vec::with_capacity(size);
+    let mut seq: u8 = 1;
+    while data.len() < size {
+        let res = ecu.run_command(UDSCommand::TransferData.into(), &[seq])?;
+        match res.get(1) {
+            Some(s) if *s == seq => {}
+            Some(s) => {
+                return Err(ProtocolError::CustomError(format!(
+                    "ECU sent block {}, expected block {}",
+                    s, seq
+                )))
+            }
+            None => {
+                return Err(ProtocolError::InvalidResponseSize {
+                    expect: 3,
+                    actual: res.len(),
+                })
+            }
+        }
+        let block = &res[2..];
+        if block.is_empty() || block.len() > block_len {
+            return Err(ProtocolError::CustomError(format!(
+                "ECU sent {} bytes in block {}, expected 1-{} bytes",
+                block.len(),
+                seq,
+                block_len
+            )));
+        }
+        data.extend_from_slice(block);
+        data.truncate(size);
+        progress(data.len(), size);
+        seq = seq.wrapping_add(1);
+    }
+    ecu.run_command(UDSCommand::TransferExit.into(), &[])?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_upload() {
+        assert_eq!(
+            encode_request_upload(0x0008_0000, 0x1000),
+            [0x00, 0x44, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00]
+        );
+        assert_eq!(parse_block_len(&[0x75, 0x20, 0x01, 0x02]).unwrap(), 256);
+        assert_eq!(parse_block_len(&[0x75, 0x10, 0x82]).unwrap(), 128);
+        assert!(parse_block_len(&[0x75, 0x20, 0x01]).is_err());
+        assert!(parse_block_len(&[0x75, 0x00]).is_err());
+        assert!(parse_block_len(&[0x75, 0x10, 0x02]).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Checks that a connected ECU is one of the variants described by a parsed ECU file.
+//!
+//! Running another variant's services against an ECU (Especially coding writes) can
+//! leave it misconfigured, so the ECU's identification should be checked against the
+//! variant patterns before any services are used.
+
+use common::schema::{variant::ECUVariantPattern, OvdECU};
+
+use super::{
+    kwp2000::read_ecu_identification,
+    uds::read_data::{self, DID_HW_VERSION, DID_PART_NUMBER, DID_SW_VERSION},
+    DiagServer, ProtocolResult,
+};
+
+/// Identification read from the ECU
+#[derive(Debug, Clone, Default)]
+pub struct EcuIdent {
+    /// ID matched against [ECUVariantPattern::vendor_id]
+    pub variant_id: u32,
+    pub part_number: Option<String>,
+    pub hw_version: Option<String>,
+    pub sw_version: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VariantCheck {
+    pub ident: EcuIdent,
+    /// Index of the matching variant in [OvdECU::variants], and the pattern that matched
+    pub matched: Option<(usize, ECUVariantPattern)>,
+}
+
+impl VariantCheck {
+    pub fn is_match(&self) -> bool {
+        self.matched.is_some()
+    }
+}
+
+/// Reads the identification of the ECU. Only the variant ID is required, the
+/// other fields are left empty if the ECU does not support reading them
+pub fn read_identification(server: &DiagServer) -> ProtocolResult<EcuIdent> {
+    match server {
+        DiagServer::KWP2000(s) => {
+            let id = read_ecu_identification::read_dcx_mmc_id(s)?;
+            Ok(EcuIdent {
+                variant_id: id.diag_information as u32,
+                part_number: Some(id.part_number),
+                hw_version: Some(id.hardware_version),
+                sw_version: Some(id.software_version),
+            })
+        }
+        DiagServer::UDS(s) => Ok(EcuIdent {
+            variant_id: read_data::read_variant_id(s)?,
+            part_number: read_data::read_ascii_did(s, DID_PART_NUMBER).ok(),
+            hw_version: read_data::read_ascii_did(s, DID_HW_VERSION).ok(),
+            sw_version: read_data::read_ascii_did(s, DID_SW_VERSION).ok(),
+        }),
+    }
+}
+
+/// Returns the index of the variant with a pattern matching `variant_id`, and the pattern
+pub fn find_variant(ecu: &OvdECU, variant_id: u32) -> Option<(usize, &ECUVariantPattern)> {
+    ecu.variants.iter().enumerate().find_map(|(idx, v)| {
+        v.patterns
+            .iter()
+            .find(|p| p.vendor_id == variant_id)
+            .map(|p| (idx, p))
+    })
+}
+
+/// Reads the ECU's identification and checks it against the variants of `ecu`.
+/// A warning is logged if the ECU does not match any variant
+pub fn verify_variant(server: &DiagServer, ecu: &OvdECU) -> ProtocolResult<VariantCheck> {
+    let ident = read_identification(server)?;
+    let matched = find_variant(ecu, ident.variant_id).map(|(idx, p)| (idx, p.clone()));
+    match &matched {
+        Some((idx, p)) => log::info!(
+            "ECU matches variant {} (Vendor: {}, ID 0x{:04X})",
+            ecu.variants[*idx].name,
+            p.vendor,
+            ident.variant_id
+        ),
+        None => log::warn!(
+            "ECU variant ID 0x{:04X} (Part number {}) does not match any variant of {}!",
+            ident.variant_id,
+            ident.part_number.as_deref().unwrap_or("unknown"),
+            ecu.name
+        ),
+    }
+    Ok(VariantCheck { ident, matched })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::schema::variant::ECUVariantDefinition;
+
+    fn variant(name: &str, ids: &[u32]) -> ECUVariantDefinition {
+        ECUVariantDefinition {
+            name: name.into(),
+            description: String::new(),
+            patterns: ids
+                .iter()
+                .map(|id| ECUVariantPattern {
+                    vendor: "Bosch".into(),
+                    vendor_id: *id,
+                })
+                .collect(),
+            errors: Vec::new(),
+            adjustments: Vec::new(),
+            actuations: Vec::new(),
+            functions: Vec::new(),
+            downloads: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_find_variant() {
+        let ecu = OvdECU {
+            name: "EGS52".into(),
+            description: String::new(),
+            variants: vec![variant("A", &[0x0100, 0x0101]), variant("B", &[0x0200])],
+            connections: Vec::new(),
+        };
+        assert_eq!(find_variant(&ecu, 0x0101).map(|(i, _)| i), Some(0));
+        assert_eq!(find_variant(&ecu, 0x0200).map(|(i, _)| i), Some(1));
+        assert!(find_variant(&ecu, 0x0300).is_none());
+    }
+}
@@ -0,0 +1,779 @@
+//! Recording ISO-TP sessions with a real ECU, and replaying them without the hardware.
+//!
+//! [SessionRecorder] wraps any [ComServer], and records every ISO-TP request sent to the
+//! ECU along with the responses read back after it. The [RecordedSession] can be saved
+//! as JSON, then loaded into a [ReplayServer], which behaves like the ECU did. Any
+//! protocol server started on a [ReplayServer] gets the recorded responses back, so an
+//! ECU interaction can be turned into a deterministic regression test.
+//!
+//! A [ReplayServer] checks every request matches the recorded one exactly (Same CAN ID
+//! and payload). TesterPresent is the exception, as it is sent on a timer and so is
+//! rarely at the same point in a replay. Recorded TesterPresents are skipped if the
+//! next request is something else, and unrecorded ones get a positive response.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::comm_api::{
+    CanFrame, Capability, ComServer, ComServerError, DeviceCapabilities, FilterType, FrameCounters,
+    ISO15765Data, LoopbackResult, TimestampSource,
+};
+
+/// TesterPresent SID, which a replay does not need to match exactly
+const TESTER_PRESENT_SID: u8 = 0x3E;
+
+/// Serializes payloads as a hex string, so recordings can be read and edited by hand
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&hex::encode_upper(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(d)?;
+        hex::decode(s.replace(' ', "")).map_err(serde::de::Error::custom)
+    }
+}
+
+/// One ISO-TP payload in a recorded session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedPayload {
+    pub id: u32,
+    #[serde(with = "hex_bytes")]
+    pub data: Vec<u8>,
+}
+
+impl From<&ISO15765Data> for RecordedPayload {
+    fn from(d: &ISO15765Data) -> Self {
+        Self {
+            id: d.id,
+            data: d.data.clone(),
+        }
+    }
+}
+
+/// A request sent to the ECU, and every payload read back before the next request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedExchange {
+    pub request: RecordedPayload,
+    pub responses: Vec<RecordedPayload>,
+}
+
+impl RecordedExchange {
+    fn is_tester_present(&self) -> bool {
+        self.request.data.first() == Some(&TESTER_PRESENT_SID)
+    }
+}
+
+/// A recorded ISO-TP session, in the order requests were sent
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedSession {
+    pub exchanges: Vec<RecordedExchange>,
+}
+
+impl RecordedSession {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ComServerError> {
+        serde_json::from_str(json).map_err(|e| ComServerError {
+            err_code: 99,
+            err_desc: format!("Invalid session recording: {}", e),
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ComServerError> {
+        std::fs::write(path, self.to_json()).map_err(|e| ComServerError {
+            err_code: 99,
+            err_desc: format!("Cannot write {}: {}", path, e),
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Self, ComServerError> {
+        let json = std::fs::read_to_string(path).map_err(|e| ComServerError {
+            err_code: 99,
+            err_desc: format!("Cannot read {}: {}", path, e),
+        })?;
+        Self::from_json(&json)
+    }
+}
+
+/// Wraps a [ComServer], recording every ISO-TP request and response which passes through it.
+/// Every other call is passed straight to the wrapped server, so recording does not change
+/// what the adapter can do
+#[derive(Debug, Clone)]
+pub struct SessionRecorder {
+    inner: Box<dyn ComServer>,
+    session: Arc<Mutex<RecordedSession>>,
+}
+
+impl SessionRecorder {
+    pub fn new(inner: Box<dyn ComServer>) -> Self {
+        Self {
+            inner,
+            session: Arc::new(Mutex::new(RecordedSession::default())),
+        }
+    }
+
+    /// Returns everything recorded so far
+    pub fn get_session(&self) -> RecordedSession {
+        self.session.lock().unwrap().clone()
+    }
+}
+
+impl ComServer for SessionRecorder {
+    fn open_device(&mut self) -> Result<(), ComServerError> {
+        self.inner.open_device()
+    }
+
+    fn close_device(&mut self) -> Result<(), ComServerError> {
+        self.inner.close_device()
+    }
+
+    fn send_can_packets(
+        &mut self,
+        data: &[CanFrame],
+        timeout_ms: u32,
+    ) -> Result<usize, ComServerError> {
+        self.inner.send_can_packets(data, timeout_ms)
+    }
+
+    fn send_can_packets_detailed(
+        &mut self,
+        data: &[CanFrame],
+        timeout_ms: u32,
+    ) -> Vec<Result<(), ComServerError>> {
+        self.inner.send_can_packets_detailed(data, timeout_ms)
+    }
+
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    fn read_can_packets(
+        &self,
+        timeout_ms: u32,
+        max_msgs: usize,
+    ) -> Result<Vec<CanFrame>, ComServerError> {
+        self.inner.read_can_packets(timeout_ms, max_msgs)
+    }
+
+    fn read_can_packets_batched(
+        &self,
+        window_ms: u32,
+        max_msgs: usize,
+    ) -> Result<Vec<CanFrame>, ComServerError> {
+        self.inner.read_can_packets_batched(window_ms, max_msgs)
+    }
+
+    fn loopback_test(
+        &mut self,
+        tx_id: u32,
+        rx_id: u32,
+        timeout_ms: u32,
+    ) -> Result<LoopbackResult, ComServerError> {
+        self.inner.loopback_test(tx_id, rx_id, timeout_ms)
+    }
+
+    fn send_iso15765_data(
+        &self,
+        data: &[ISO15765Data],
+        timeout_ms: u32,
+    ) -> Result<usize, ComServerError> {
+        let sent = self.inner.send_iso15765_data(data, timeout_ms)?;
+        let mut session = self.session.lock().unwrap();
+        for d in data.iter().take(sent) {
+            session.exchanges.push(RecordedExchange {
+                request: d.into(),
+                responses: Vec::new(),
+            })
+        }
+        Ok(sent)
+    }
+
+    fn read_iso15765_packets(
+        &self,
+        timeout_ms: u32,
+        max_msgs: usize,
+    ) -> Result<Vec<ISO15765Data>, ComServerError> {
+        let res = self.inner.read_iso15765_packets(timeout_ms, max_msgs)?;
+        // Anything read before the first request is not a response to it, so is not recorded
+        if let Some(exchange) = self.session.lock().unwrap().exchanges.last_mut() {
+            exchange
+                .responses
+                .extend(res.iter().map(RecordedPayload::from));
+        }
+        Ok(res)
+    }
+
+    fn open_can_interface(
+        &mut self,
+        bus_speed: u32,
+        is_ext_can: bool,
+    ) -> Result<(), ComServerError> {
+        self.inner.open_can_interface(bus_speed, is_ext_can)
+    }
+
+    fn open_can_interface_raw(
+        &mut self,
+        btr0btr1: u16,
+        is_ext_can: bool,
+    ) -> Result<(), ComServerError> {
+        self.inner.open_can_interface_raw(btr0btr1, is_ext_can)
+    }
+
+    fn close_can_interface(&mut self) -> Result<(), ComServerError> {
+        self.inner.close_can_interface()
+    }
+
+    fn open_iso15765_interface(
+        &mut self,
+        bus_speed: u32,
+        is_ext_can: bool,
+        ext_addressing: bool,
+    ) -> Result<(), ComServerError> {
+        self.inner
+            .open_iso15765_interface(bus_speed, is_ext_can, ext_addressing)
+    }
+
+    fn close_iso15765_interface(&mut self) -> Result<(), ComServerError> {
+        self.inner.close_iso15765_interface()
+    }
+
+    fn add_can_filter(&mut self, f: FilterType) -> Result<u32, ComServerError> {
+        self.inner.add_can_filter(f)
+    }
+
+    fn rem_can_filter(&mut self, filter_idx: u32) -> Result<(), ComServerError> {
+        self.inner.rem_can_filter(filter_idx)
+    }
+
+    fn add_iso15765_filter(&mut self, f: FilterType) -> Result<u32, ComServerError> {
+        self.inner.add_iso15765_filter(f)
+    }
+
+    fn rem_iso15765_filter(&mut self, filter_idx: u32) -> Result<(), ComServerError> {
+        self.inner.rem_iso15765_filter(filter_idx)
+    }
+
+    fn set_iso15765_params(
+        &mut self,
+        separation_time_min: u32,
+        block_size: u32,
+    ) -> Result<(), ComServerError> {
+        self.inner
+            .set_iso15765_params(separation_time_min, block_size)
+    }
+
+    fn set_rx_flow_control(&mut self, block_size: u8, st_min: u8) -> Result<(), ComServerError> {
+        self.inner.set_rx_flow_control(block_size, st_min)
+    }
+
+    fn clear_can_rx_buffer(&self) -> Result<(), ComServerError> {
+        self.inner.clear_can_rx_buffer()
+    }
+
+    fn clear_can_tx_buffer(&self) -> Result<(), ComServerError> {
+        self.inner.clear_can_tx_buffer()
+    }
+
+    fn clear_iso15765_rx_buffer(&self) -> Result<(), ComServerError> {
+        self.inner.clear_iso15765_rx_buffer()
+    }
+
+    fn clear_iso15765_tx_buffer(&self) -> Result<(), ComServerError> {
+        self.inner.clear_iso15765_tx_buffer()
+    }
+
+    fn read_battery_voltage(&self) -> Result<f32, ComServerError> {
+        self.inner.read_battery_voltage()
+    }
+
+    fn clone_box(&self) -> Box<dyn ComServer> {
+        Box::new(self.clone())
+    }
+
+    fn get_capabilities(&self) -> DeviceCapabilities {
+        self.inner.get_capabilities()
+    }
+
+    fn get_api(&self) -> &str {
+        self.inner.get_api()
+    }
+
+    fn set_timestamp_source(&mut self, source: TimestampSource) -> Result<(), ComServerError> {
+        self.inner.set_timestamp_source(source)
+    }
+
+    fn add_periodic_message(
+        &mut self,
+        frame: &CanFrame,
+        interval: Duration,
+    ) -> Result<u32, ComServerError> {
+        self.inner.add_periodic_message(frame, interval)
+    }
+
+    fn remove_periodic_message(&mut self, msg_id: u32) -> Result<(), ComServerError> {
+        self.inner.remove_periodic_message(msg_id)
+    }
+
+    fn get_bitrate(&self) -> Option<u32> {
+        self.inner.get_bitrate()
+    }
+
+    fn get_error_counters(&self) -> Option<(u8, u8)> {
+        self.inner.get_error_counters()
+    }
+
+    fn reset_error_counters(&mut self) -> Result<(), ComServerError> {
+        self.inner.reset_error_counters()
+    }
+
+    fn frame_counters(&self) -> FrameCounters {
+        self.inner.frame_counters()
+    }
+
+    fn get_active_filters(&self) -> Vec<FilterType> {
+        self.inner.get_active_filters()
+    }
+
+    fn description(&self) -> String {
+        self.inner.description()
+    }
+}
+
+#[derive(Debug, Default)]
+struct ReplayState {
+    remaining: VecDeque<RecordedExchange>,
+    /// Responses to the last request, which have not been read yet
+    rx_queue: VecDeque<RecordedPayload>,
+    /// First request which did not match the recording
+    mismatch: Option<String>,
+    /// CAN ID of the last replayed response, used for unrecorded TesterPresent responses
+    last_response_id: Option<u32>,
+    iso15765_open: bool,
+}
+
+impl ReplayState {
+    fn on_request(&mut self, req: RecordedPayload) -> Result<(), String> {
+        let is_tester_present = req.data.first() == Some(&TESTER_PRESENT_SID);
+        if !is_tester_present {
+            // TesterPresent was recorded, but has not been sent this time around
+            while self
+                .remaining
+                .front()
+                .map(|e| e.is_tester_present() && e.request != req)
+                .unwrap_or(false)
+            {
+                self.remaining.pop_front();
+            }
+        }
+        match self.remaining.front() {
+            Some(e) if e.request == req => {
+                let e = self.remaining.pop_front().unwrap();
+                if let Some(r) = e.responses.last() {
+                    self.last_response_id = Some(r.id);
+                }
+                self.rx_queue.extend(e.responses);
+                Ok(())
+            }
+            _ if is_tester_present => {
+                // TesterPresent was not recorded here. Respond unless the ECU was told not to
+                if req.data.get(1).map(|x| x & 0x80 == 0).unwrap_or(true) {
+                    self.rx_queue.push_back(RecordedPayload {
+                        id: self.last_response_id.unwrap_or(req.id),
+                        data: vec![TESTER_PRESENT_SID + 0x40, 0x00],
+                    });
+                }
+                Ok(())
+            }
+            Some(e) => Err(format!(
+                "Request {:02X?} to 0x{:04X} does not match the recorded request {:02X?} to 0x{:04X}",
+                req.data, req.id, e.request.data, e.request.id
+            )),
+            None => Err(format!(
+                "Request {:02X?} to 0x{:04X} was sent after the end of the recording",
+                req.data, req.id
+            )),
+        }
+    }
+}
+
+/// A [ComServer] which plays back a [RecordedSession] over ISO-TP, in place of a
+/// real adapter and ECU. Plain CAN is not supported, and ISO-TP filters are not applied.
+#[derive(Debug, Clone)]
+pub struct ReplayServer {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl ReplayServer {
+    pub fn new(session: RecordedSession) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ReplayState {
+                remaining: session.exchanges.into(),
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Checks the replay matched the recording. Returns an error describing the first
+    /// request which did not match, or if any recorded requests were never sent
+    pub fn verify(&self) -> Result<(), String> {
+        let state = self.state.lock().unwrap();
+        if let Some(e) = &state.mismatch {
+            return Err(e.clone());
+        }
+        let remaining = state
+            .remaining
+            .iter()
+            .filter(|e| !e.is_tester_present())
+            .count();
+        if remaining != 0 {
+            return Err(format!(
+                "{} recorded requests were never sent, starting with {:02X?}",
+                remaining,
+                state
+                    .remaining
+                    .iter()
+                    .find(|e| !e.is_tester_present())
+                    .unwrap()
+                    .request
+                    .data
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl ComServer for ReplayServer {
+    fn open_device(&mut self) -> Result<(), ComServerError> {
+        Ok(())
+    }
+
+    fn close_device(&mut self) -> Result<(), ComServerError> {
+        Ok(())
+    }
+
+    fn send_can_packets(
+        &mut self,
+        _data: &[CanFrame],
+        _timeout_ms: u32,
+    ) -> Result<usize, ComServerError> {
+        Err(ComServerError::not_supported("CAN replay"))
+    }
+
+    fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().iso15765_open
+    }
+
+    fn read_can_packets(
+        &self,
+        _timeout_ms: u32,
+        _max_msgs: usize,
+    ) -> Result<Vec<CanFrame>, ComServerError> {
+        Err(ComServerError::not_supported("CAN replay"))
+    }
+
+    fn send_iso15765_data(
+        &self,
+        data: &[ISO15765Data],
+        _timeout_ms: u32,
+    ) -> Result<usize, ComServerError> {
+        let mut state = self.state.lock().unwrap();
+        for d in data {
+            if let Err(e) = state.on_request(d.into()) {
+                log::error!("Replay - {}", e);
+                state.mismatch.get_or_insert(e.clone());
+                return Err(ComServerError {
+                    err_code: 99,
+                    err_desc: e,
+                });
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn read_iso15765_packets(
+        &self,
+        _timeout_ms: u32,
+        max_msgs: usize,
+    ) -> Result<Vec<ISO15765Data>, ComServerError> {
+        let mut state = self.state.lock().unwrap();
+        let count = max_msgs.min(state.rx_queue.len());
+        Ok(state
+            .rx_queue
+            .drain(..count)
+            .map(|p| ISO15765Data {
+                id: p.id,
+                data: p.data,
+                pad_frame: false,
+                ext_addressing: false,
+            })
+            .collect())
+    }
+
+    fn open_can_interface(
+        &mut self,
+        _bus_speed: u32,
+        _is_ext_can: bool,
+    ) -> Result<(), ComServerError> {
+        Err(ComServerError::not_supported("CAN replay"))
+    }
+
+    fn close_can_interface(&mut self) -> Result<(), ComServerError> {
+        Ok(())
+    }
+
+    fn open_iso15765_interface(
+        &mut self,
+        _bus_speed: u32,
+        _is_ext_can: bool,
+        _ext_addressing: bool,
+    ) -> Result<(), ComServerError> {
+        self.state.lock().unwrap().iso15765_open = true;
+        Ok(())
+    }
+
+    fn close_iso15765_interface(&mut self) -> Result<(), ComServerError> {
+        self.state.lock().unwrap().iso15765_open = false;
+        Ok(())
+    }
+
+    fn add_can_filter(&mut self, _f: FilterType) -> Result<u32, ComServerError> {
+        Err(ComServerError::not_supported("CAN replay"))
+    }
+
+    fn rem_can_filter(&mut self, _filter_idx: u32) -> Result<(), ComServerError> {
+        Ok(())
+    }
+
+    fn add_iso15765_filter(&mut self, _f: FilterType) -> Result<u32, ComServerError> {
+        Ok(0)
+    }
+
+    fn rem_iso15765_filter(&mut self, _filter_idx: u32) -> Result<(), ComServerError> {
+        Ok(())
+    }
+
+    fn set_iso15765_params(
+        &mut self,
+        _separation_time_min: u32,
+        _block_size: u32,
+    ) -> Result<(), ComServerError> {
+        Ok(())
+    }
+
+    fn clear_can_rx_buffer(&self) -> Result<(), ComServerError> {
+        Ok(())
+    }
+
+    fn clear_can_tx_buffer(&self) -> Result<(), ComServerError> {
+        Ok(())
+    }
+
+    fn clear_iso15765_rx_buffer(&self) -> Result<(), ComServerError> {
+        // Responses are only queued once their request is sent, so there is never
+        // anything stale to clear
+        Ok(())
+    }
+
+    fn clear_iso15765_tx_buffer(&self) -> Result<(), ComServerError> {
+        Ok(())
+    }
+
+    fn read_battery_voltage(&self) -> Result<f32, ComServerError> {
+        Err(ComServerError::not_supported("Battery voltage"))
+    }
+
+    fn clone_box(&self) -> Box<dyn ComServer> {
+        Box::new(self.clone())
+    }
+
+    fn get_capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            name: "Session replay".into(),
+            vendor: "OpenVehicleDiag".into(),
+            library_path: "N/A".into(),
+            device_fw_version: "N/A".into(),
+            library_version: "N/A".into(),
+            j1850vpw: Capability::NA,
+            j1850pwm: Capability::NA,
+            can: Capability::No,
+            iso15765: Capability::Yes,
+            iso9141: Capability::NA,
+            iso14230: Capability::NA,
+            ip: Capability::NA,
+            battery_voltage: Capability::NA,
+        }
+    }
+
+    fn get_api(&self) -> &str {
+        "Replay"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commapi::can_transport::{tests::MockEcu, TransportServer};
+    use crate::commapi::iface::{InterfaceConfig, InterfacePayload, IsoTPInterface, IFACE_CFG};
+
+    fn exchange(req: &[u8], responses: &[&[u8]]) -> RecordedExchange {
+        RecordedExchange {
+            request: RecordedPayload {
+                id: 0x7E0,
+                data: req.to_vec(),
+            },
+            responses: responses
+                .iter()
+                .map(|r| RecordedPayload {
+                    id: 0x7E8,
+                    data: r.to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    fn session() -> RecordedSession {
+        RecordedSession {
+            exchanges: vec![
+                exchange(&[0x10, 0x03], &[&[0x50, 0x03, 0x00, 0x32, 0x01, 0xF4]]),
+                exchange(&[0x3E, 0x00], &[&[0x7E, 0x00]]),
+                exchange(
+                    &[0x22, 0xF1, 0x90],
+                    &[&[0x7F, 0x22, 0x78], &[0x62, 0xF1, 0x90, 0x57, 0x44, 0x44]],
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_session_json() {
+        let json = session().to_json();
+        assert!(json.contains("\"data\": \"22F190\""));
+        assert_eq!(RecordedSession::from_json(&json).unwrap(), session());
+        assert!(RecordedSession::from_json(
+            "{\"exchanges\": [{\"request\": {\"id\": 1, \"data\": \"ZZ\"}, \"responses\": []}]}"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_replay_matches_recording() {
+        let replay = ReplayServer::new(session());
+        let mut cfg = InterfaceConfig::new();
+        cfg.add_param(IFACE_CFG::BAUDRATE, 500_000);
+        let mut iface = IsoTPInterface::new(replay.clone_box()).unwrap();
+        iface.setup(&cfg).unwrap();
+
+        let res = iface
+            .send_recv_data(InterfacePayload::new(0x7E0, &[0x10, 0x03]), 0, 100)
+            .unwrap();
+        assert_eq!(res.data, [0x50, 0x03, 0x00, 0x32, 0x01, 0xF4]);
+        // Recorded TesterPresent is skipped, and both responses are replayed in order
+        iface
+            .send_data(&[InterfacePayload::new(0x7E0, &[0x22, 0xF1, 0x90])], 0)
+            .unwrap();
+        let res = iface.recv_data(10, 100).unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[1].data, [0x62, 0xF1, 0x90, 0x57, 0x44, 0x44]);
+        // Unrecorded TesterPresent
+        let res = iface
+            .send_recv_data(InterfacePayload::new(0x7E0, &[0x3E, 0x00]), 0, 100)
+            .unwrap();
+        assert_eq!((res.id, res.data), (0x7E8, vec![0x7E, 0x00]));
+        assert!(replay.verify().is_ok());
+    }
+
+    #[test]
+    fn test_replay_mismatch() {
+        let replay = ReplayServer::new(session());
+        let mut iface = IsoTPInterface::new(replay.clone_box()).unwrap();
+        // Wrong session type
+        assert!(iface
+            .send_data(&[InterfacePayload::new(0x7E0, &[0x10, 0x02])], 0)
+            .is_err());
+        assert!(replay.verify().unwrap_err().contains("[10, 02]"));
+
+        // Requests which were never sent
+        let replay = ReplayServer::new(session());
+        let mut iface = IsoTPInterface::new(replay.clone_box()).unwrap();
+        iface
+            .send_data(&[InterfacePayload::new(0x7E0, &[0x10, 0x03])], 0)
+            .unwrap();
+        assert!(replay
+            .verify()
+            .unwrap_err()
+            .starts_with("1 recorded requests were never sent"));
+    }
+
+    #[test]
+    fn test_record_replay() {
+        // Recording a replay of a session gives back the same session
+        let mut recorder = SessionRecorder::new(Box::new(ReplayServer::new(session())));
+        recorder
+            .open_iso15765_interface(500_000, false, false)
+            .unwrap();
+        for e in &session().exchanges {
+            let req = ISO15765Data {
+                id: e.request.id,
+                data: e.request.data.clone(),
+                pad_frame: false,
+                ext_addressing: false,
+            };
+            recorder.send_iso15765_data(&[req], 0).unwrap();
+            recorder.read_iso15765_packets(0, 10).unwrap();
+        }
+        assert_eq!(recorder.get_session(), session());
+    }
+
+    #[test]
+    fn test_recorder_forwards_adapter_features() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut recorder =
+            SessionRecorder::new(Box::new(TransportServer::new(Box::new(MockEcu {
+                sent: sent.clone(),
+                ..Default::default()
+            }))));
+        recorder
+            .open_iso15765_interface(500_000, false, false)
+            .unwrap();
+        recorder
+            .add_iso15765_filter(FilterType::IsoTP {
+                id: 0x7E8,
+                mask: 0xFFFF,
+                fc: 0x7E0,
+            })
+            .unwrap();
+        assert_eq!(
+            recorder.get_active_filters(),
+            vec![FilterType::IsoTP {
+                id: 0x7E8,
+                mask: 0xFFFF,
+                fc: 0x7E0
+            }]
+        );
+        recorder
+            .set_timestamp_source(TimestampSource::Host)
+            .unwrap();
+
+        let keep_alive = CanFrame::new(0x100, &[0x01]);
+        let id = recorder
+            .add_periodic_message(&keep_alive, Duration::from_secs(10))
+            .unwrap();
+        // Goes through the scheduler, after the periodic message which is due now
+        let res = recorder.send_can_packets_detailed(&[CanFrame::new(0x7E0, &[0x3E, 0x00])], 0);
+        assert!(res[0].is_ok());
+        assert_eq!(sent.lock().unwrap()[0].id, 0x100);
+        recorder.remove_periodic_message(id).unwrap();
+        // Raw CAN traffic is not part of the recording
+        assert!(recorder.get_session().exchanges.is_empty());
+    }
+}
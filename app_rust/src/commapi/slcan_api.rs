@@ -0,0 +1,467 @@
+//! SLCAN (Lawicel) backend, for cheap USB-CAN dongles which speak
+//! the SLCAN ASCII protocol over a serial port.
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serialport::SerialPort;
+
+use super::{
+    can_transport::CanTransport,
+    comm_api::{btr_bitrate, CanFrame, Capability, ComServerError, DeviceCapabilities, FilterType},
+};
+
+/// Serial port baud rate used for the adapter. USB CDC adapters ignore this
+const SERIAL_BAUD: u32 = 115200;
+/// Time to wait for the adapter to acknowledge a command
+const CMD_TIMEOUT_MS: u128 = 500;
+/// Maximum number of software filters
+const MAX_FILTERS: usize = 10;
+
+const ACK_OK: u8 = b'\r';
+const ACK_ERR: u8 = 0x07;
+/// Returned by [SlcanApi::parse_serial] for a `z` / `Z` transmit acknowledgement
+const ACK_TX: u8 = b'z';
+
+/// Converts a CAN Bus speed into the SLCAN `Sx` bitrate code
+pub fn slcan_bitrate_code(bus_speed: u32) -> Option<char> {
+    match bus_speed {
+        10_000 => Some('0'),
+        20_000 => Some('1'),
+        50_000 => Some('2'),
+        100_000 => Some('3'),
+        125_000 => Some('4'),
+        250_000 => Some('5'),
+        500_000 => Some('6'),
+        800_000 => Some('7'),
+        1_000_000 => Some('8'),
+        _ => None,
+    }
+}
+
+/// Encodes a CAN Frame into a SLCAN transmit command (`tIIILDD..` or `TIIIIIIIILDD..`)
+pub fn encode_frame(frame: &CanFrame, is_ext_can: bool) -> String {
+    let mut res = if is_ext_can || frame.id > 0x7FF {
+        format!("T{:08X}{:01X}", frame.id & 0x1FFFFFFF, frame.dlc)
+    } else {
+        format!("t{:03X}{:01X}", frame.id, frame.dlc)
+    };
+    for b in frame.get_data() {
+        res.push_str(&format!("{:02X}", b));
+    }
+    res.push('\r');
+    res
+}
+
+/// Parses a received SLCAN frame line (Without the trailing `\r`).
+/// Returns None if the line is not a data frame
+pub fn decode_frame(line: &str) -> Option<CanFrame> {
+    let id_len = match line.chars().next()? {
+        't' => 3,
+        'T' => 8,
+        _ => return None, // RTR frames and command responses
+    };
+    let id = u32::from_str_radix(line.get(1..1 + id_len)?, 16).ok()?;
+    let dlc = usize::from_str_radix(line.get(1 + id_len..2 + id_len)?, 16).ok()?;
+    if dlc > 8 {
+        return None;
+    }
+    let mut data = Vec::with_capacity(dlc);
+    for i in 0..dlc {
+        let start = 2 + id_len + (i * 2);
+        data.push(u8::from_str_radix(line.get(start..start + 2)?, 16).ok()?);
+    }
+    Some(CanFrame::new(id, &data))
+}
+
+pub struct SlcanApi {
+    port_name: String,
+    port: Option<Mutex<Box<dyn SerialPort>>>,
+    can_open: bool,
+    is_ext_can: bool,
+    bus_speed: u32,
+    rx_buf: Vec<u8>,
+    rx_queue: VecDeque<CanFrame>,
+    filters: [Option<FilterType>; MAX_FILTERS],
+}
+
+impl std::fmt::Debug for SlcanApi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SlcanApi")
+            .field("port_name", &self.port_name)
+            .field("can_open", &self.can_open)
+            .finish()
+    }
+}
+
+impl SlcanApi {
+    pub fn new(port_name: String) -> Self {
+        Self {
+            port_name,
+            port: None,
+            can_open: false,
+            is_ext_can: false,
+            bus_speed: 0,
+            rx_buf: Vec::new(),
+            rx_queue: VecDeque::new(),
+            filters: [None; MAX_FILTERS],
+        }
+    }
+
+    /// Lists serial ports which could be SLCAN adapters
+    pub fn find_devices() -> Vec<String> {
+        serialport::available_ports()
+            .map(|ports| ports.into_iter().map(|p| p.port_name).collect())
+            .unwrap_or_default()
+    }
+
+    fn port_not_open() -> ComServerError {
+        ComServerError {
+            err_code: 1,
+            err_desc: "Serial port is not open".into(),
+        }
+    }
+
+    fn io_error<E: std::fmt::Display>(e: E) -> ComServerError {
+        ComServerError {
+            err_code: 6,
+            err_desc: e.to_string(),
+        }
+    }
+
+    fn write_raw(&mut self, data: &[u8]) -> Result<(), ComServerError> {
+        let port = self.port.as_ref().ok_or_else(Self::port_not_open)?;
+        port.lock().unwrap().write_all(data).map_err(Self::io_error)
+    }
+
+    /// Reads whatever the adapter has sent, see [SlcanApi::parse_serial]
+    fn poll_serial(&mut self, timeout: Duration) -> Result<Vec<u8>, ComServerError> {
+        let port = self.port.as_ref().ok_or_else(Self::port_not_open)?;
+        let mut buf = [0u8; 256];
+        let read = {
+            let mut p = port.lock().unwrap();
+            p.set_timeout(timeout).map_err(Self::io_error)?;
+            match p.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => 0,
+                Err(e) => return Err(Self::io_error(e)),
+            }
+        };
+        Ok(self.parse_serial(&buf[0..read]))
+    }
+
+    /// Splits bytes read from the adapter into lines. Frame lines are placed into the
+    /// Rx queue, and any acknowledgements ([ACK_OK], [ACK_TX] or [ACK_ERR]) are returned
+    fn parse_serial(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut acks = Vec::new();
+        for b in data {
+            match *b {
+                ACK_ERR => acks.push(ACK_ERR),
+                ACK_OK => {
+                    let line = String::from_utf8_lossy(&self.rx_buf).to_string();
+                    self.rx_buf.clear();
+                    match decode_frame(&line) {
+                        Some(f) => {
+                            if self.can_open && self.passes_filters(&f) {
+                                self.rx_queue.push_back(f)
+                            }
+                        }
+                        // `z` / `Z` are transmit acknowledgements
+                        None if line.starts_with('z') || line.starts_with('Z') => acks.push(ACK_TX),
+                        // Anything else is a response to a command
+                        None => acks.push(ACK_OK),
+                    }
+                }
+                x => self.rx_buf.push(x),
+            }
+        }
+        acks
+    }
+
+    /// Waits for the adapter to reply with `ack`. `what` names the command or
+    /// frame being acknowledged in the error
+    fn wait_for_ack(&mut self, ack: u8, what: &str) -> Result<(), ComServerError> {
+        let start = Instant::now();
+        while start.elapsed().as_millis() < CMD_TIMEOUT_MS {
+            let acks = self.poll_serial(Duration::from_millis(10))?;
+            if acks.contains(&ACK_ERR) {
+                return Err(ComServerError {
+                    err_code: 2,
+                    err_desc: format!("Adapter rejected {}", what),
+                });
+            } else if acks.contains(&ack) {
+                return Ok(());
+            }
+        }
+        Err(ComServerError {
+            err_code: 3,
+            err_desc: format!("Timeout waiting for adapter to acknowledge {}", what),
+        })
+    }
+
+    /// Sends a command to the adapter and waits for it to be acknowledged
+    fn send_command(&mut self, cmd: &str) -> Result<(), ComServerError> {
+        self.write_raw(format!("{}\r", cmd).as_bytes())?;
+        self.wait_for_ack(ACK_OK, &format!("command '{}'", cmd))
+    }
+
+    /// Sets the adapter's acceptance filter to pass every ID. Lawicel adapters keep the
+    /// acceptance code and mask from a previous session, which would silently drop frames.
+    /// Filters are applied in software, so the hardware filter is always left fully open.
+    /// Clones which lack the `M`/`m` commands reject them, which is harmless
+    fn open_acceptance_filter(&mut self) {
+        for cmd in &["M00000000", "mFFFFFFFF"] {
+            if let Err(e) = self.send_command(cmd) {
+                log::debug!("SLCAN - Could not open acceptance filter: {}", e);
+            }
+        }
+    }
+
+    /// Opens the channel once its bitrate has been set
+    fn open_channel(&mut self, bus_speed: u32, is_ext_can: bool) -> Result<(), ComServerError> {
+        self.open_acceptance_filter();
+        self.send_command("O")?;
+        self.is_ext_can = is_ext_can;
+        self.bus_speed = bus_speed;
+        self.can_open = true;
+        Ok(())
+    }
+
+    fn passes_filters(&self, f: &CanFrame) -> bool {
+        let mut has_pass = false;
+        let mut passed = false;
+        for filter in self.filters.iter().flatten() {
+            match filter {
+                FilterType::Block { id, mask } => {
+                    if f.id & mask == id & mask {
+                        return false;
+                    }
+                }
+                FilterType::Pass { id, mask } => {
+                    has_pass = true;
+                    if f.id & mask == id & mask {
+                        passed = true
+                    }
+                }
+                FilterType::IsoTP { .. } => {}
+            }
+        }
+        // Like the other adapters, nothing is received until a pass filter is set
+        has_pass && passed
+    }
+}
+
+impl CanTransport for SlcanApi {
+    fn open(&mut self) -> Result<(), ComServerError> {
+        let port = serialport::new(&self.port_name, SERIAL_BAUD)
+            .timeout(Duration::from_millis(10))
+            .open()
+            .map_err(Self::io_error)?;
+        self.port = Some(Mutex::new(port));
+        // Flush any half sent command, then make sure the channel is closed
+        self.write_raw(b"\r\r\r")?;
+        self.poll_serial(Duration::from_millis(50))?;
+        let _ = self.send_command("C");
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), ComServerError> {
+        if self.can_open {
+            self.close_can()?;
+        }
+        self.port.take();
+        Ok(())
+    }
+
+    fn open_can(&mut self, bus_speed: u32, is_ext_can: bool) -> Result<(), ComServerError> {
+        if self.can_open {
+            self.close_can()?;
+        }
+        let code = slcan_bitrate_code(bus_speed).ok_or(ComServerError {
+            err_code: 4,
+            err_desc: format!("SLCAN does not support a bus speed of {} bps", bus_speed),
+        })?;
+        self.send_command(&format!("S{}", code))?;
+        self.open_channel(bus_speed, is_ext_can)
+    }
+
+    fn open_can_raw(&mut self, btr0btr1: u16, is_ext_can: bool) -> Result<(), ComServerError> {
+        if self.can_open {
+            self.close_can()?;
+        }
+        self.send_command(&format!("s{:04X}", btr0btr1))?;
+        self.open_channel(btr_bitrate(btr0btr1), is_ext_can)
+    }
+
+    fn close_can(&mut self) -> Result<(), ComServerError> {
+        self.can_open = false;
+        self.filters = [None; MAX_FILTERS];
+        self.rx_queue.clear();
+        self.send_command("C")
+    }
+
+    fn send_frames(
+        &mut self,
+        data: &[CanFrame],
+        _timeout_ms: u32,
+    ) -> Result<usize, ComServerError> {
+        if !self.can_open {
+            return Err(ComServerError {
+                err_code: 5,
+                err_desc: "CAN Channel is not open".into(),
+            });
+        }
+        for (idx, frame) in data.iter().enumerate() {
+            // The adapter replies with `z` once the frame is queued, or BELL if it was rejected
+            let res = self
+                .write_raw(encode_frame(frame, self.is_ext_can).as_bytes())
+                .and_then(|_| self.wait_for_ack(ACK_TX, &format!("frame {}", frame)));
+            if let Err(e) = res {
+                if idx == 0 {
+                    return Err(e);
+                }
+                return Ok(idx);
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn read_frames(
+        &mut self,
+        timeout_ms: u32,
+        max_msgs: usize,
+    ) -> Result<Vec<CanFrame>, ComServerError> {
+        let start = Instant::now();
+        loop {
+            self.poll_serial(Duration::from_millis(1))?;
+            if self.rx_queue.len() >= max_msgs || start.elapsed().as_millis() >= timeout_ms as u128
+            {
+                break;
+            }
+        }
+        let count = std::cmp::min(max_msgs, self.rx_queue.len());
+        Ok(self.rx_queue.drain(0..count).collect())
+    }
+
+    fn add_filter(&mut self, f: FilterType) -> Result<u32, ComServerError> {
+        match self.filters.iter().position(|x| x.is_none()) {
+            Some(pos) => {
+                self.filters[pos] = Some(f);
+                Ok(pos as u32)
+            }
+            None => Err(ComServerError {
+                err_code: 98,
+                err_desc: "No free CAN Filters were found".into(),
+            }),
+        }
+    }
+
+    fn rem_filter(&mut self, filter_idx: u32) -> Result<(), ComServerError> {
+        if let Some(f) = self.filters.get_mut(filter_idx as usize) {
+            *f = None
+        }
+        Ok(())
+    }
+
+    fn clear_rx_buffer(&mut self) -> Result<(), ComServerError> {
+        self.rx_queue.clear();
+        Ok(())
+    }
+
+    fn clear_tx_buffer(&mut self) -> Result<(), ComServerError> {
+        Ok(()) // Frames are written straight to the serial port
+    }
+
+    fn is_connected(&self) -> bool {
+        self.can_open
+    }
+
+    fn get_bitrate(&self) -> Option<u32> {
+        if self.can_open {
+            Some(self.bus_speed)
+        } else {
+            None
+        }
+    }
+
+    fn get_active_filters(&self) -> Vec<FilterType> {
+        self.filters.iter().flatten().copied().collect()
+    }
+
+    fn get_capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            name: self.port_name.clone(),
+            vendor: "Unknown".into(),
+            library_path: "N/A".into(),
+            device_fw_version: "N/A".into(),
+            library_version: "N/A".into(),
+            j1850vpw: Capability::NA,
+            j1850pwm: Capability::NA,
+            can: Capability::Yes,
+            iso15765: Capability::Yes,
+            iso9141: Capability::NA,
+            iso14230: Capability::NA,
+            ip: Capability::NA,
+            battery_voltage: Capability::NA,
+        }
+    }
+
+    fn get_api(&self) -> &'static str {
+        "SLCAN"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_frame() {
+        let f = CanFrame::new(0x7E0, &[0x02, 0x10, 0x03]);
+        assert_eq!(encode_frame(&f, false), "t7E03021003\r");
+        assert_eq!(encode_frame(&f, true), "T000007E03021003\r");
+    }
+
+    #[test]
+    fn test_decode_frame() {
+        let f = decode_frame("t7E8806500300C801F4AA").unwrap();
+        assert_eq!(f.id, 0x7E8);
+        assert_eq!(
+            f.get_data(),
+            &[0x06, 0x50, 0x03, 0x00, 0xC8, 0x01, 0xF4, 0xAA]
+        );
+
+        let f = decode_frame("T18DAF1100").unwrap();
+        assert_eq!(f.id, 0x18DAF110);
+        assert_eq!(f.dlc, 0);
+
+        assert!(decode_frame("z").is_none());
+        assert!(decode_frame("t7E8").is_none());
+        assert!(decode_frame("t7E83AA").is_none()); // Truncated
+    }
+
+    #[test]
+    fn test_parse_serial() {
+        let mut api = SlcanApi::new("test".into());
+        // Frames are only queued once the channel is open with a pass filter
+        api.can_open = true;
+        api.add_filter(FilterType::Pass {
+            id: 0x7E8,
+            mask: 0x7FF,
+        })
+        .unwrap();
+
+        assert_eq!(api.parse_serial(b"\r"), vec![ACK_OK]);
+        assert_eq!(api.parse_serial(b"z\rZ\r"), vec![ACK_TX, ACK_TX]);
+        assert_eq!(api.parse_serial(&[ACK_ERR]), vec![ACK_ERR]);
+        // A frame split across reads, with the acknowledgement of a transmit in between
+        assert!(api.parse_serial(b"t7E83065").is_empty());
+        assert_eq!(api.parse_serial(b"003\rz\r"), vec![ACK_TX]);
+        assert_eq!(api.rx_queue.len(), 1);
+        assert_eq!(api.rx_queue[0].get_data(), &[0x06, 0x50, 0x03]);
+    }
+}
@@ -1,11 +1,14 @@
 use std::{
     borrow::Borrow,
+    convert::TryFrom,
+    process::Command,
     sync::{Arc, PoisonError, RwLock},
     time::Instant,
 };
 
 use crate::commapi::comm_api::{
-    CanFrame, ComServerError, DeviceCapabilities, FilterType, ISO15765Data,
+    CanFrame, ComServerError, DeviceCapabilities, FilterType, FrameCounter, FrameCounters,
+    FrameStamper, ISO15765Data, TimestampSource,
 };
 use crate::{commapi, main};
 use commapi::comm_api::ComServer;
@@ -26,6 +29,26 @@ pub struct SocketCanAPI {
     isotp_in_use: bool,
     req_iso_tp_settings: (u32, bool, bool), // Baud, ext CAN, ext Addressing
                                             // TODO SocketCAN
+    stamper: Arc<RwLock<FrameStamper>>,
+    counter: Arc<FrameCounter>,
+}
+
+/// Parses the `(berr-counter tx 0 rx 0)` part of `ip -details link show` output.
+/// Drivers which cannot read the counters from the controller omit it
+fn parse_berr_counter(ip_output: &str) -> Option<(u8, u8)> {
+    let mut words = ip_output
+        .split_whitespace()
+        .map(|w| w.trim_matches(|c| c == '(' || c == ')'));
+    words.find(|w| *w == "berr-counter")?;
+    let mut read = |name: &str| -> Option<u8> {
+        if words.next()? != name {
+            return None;
+        }
+        words.next()?.parse::<u32>().ok().map(|c| c.min(255) as u8)
+    };
+    let tec = read("tx")?;
+    let rec = read("rx")?;
+    Some((tec, rec))
 }
 
 impl std::fmt::Debug for SocketCanAPI {
@@ -79,6 +102,8 @@ impl SocketCanAPI {
             can_filters: [None; 10],
             isotp_in_use: false,
             req_iso_tp_settings: (0, false, false),
+            stamper: Arc::new(RwLock::new(FrameStamper::default())),
+            counter: Arc::new(FrameCounter::default()),
         }
     }
 }
@@ -133,9 +158,14 @@ impl ComServer for SocketCanAPI {
         timeout_ms: u32,
     ) -> Result<usize, ComServerError> {
         if let Some(socket) = self.sockcan_iface.read().unwrap().as_ref() {
+            // CAN FD frames cannot be sent on a classic CAN socket
+            let frames = data
+                .iter()
+                .map(|x| socketcan::CANFrame::try_from(*x))
+                .collect::<Result<Vec<_>, _>>()?;
             if timeout_ms == 0 {
-                for x in data {
-                    //socket.write_frame(x.into()).map_err(|x| ComServerError {
+                for x in &frames {
+                    //socket.write_frame(x).map_err(|x| ComServerError {
                     //    err_code: 1,
                     //    err_desc: x.to_string(),
                     //})?;
@@ -146,6 +176,7 @@ impl ComServer for SocketCanAPI {
                 //    err_desc: x.to_string(),
                 //})?;
             }
+            self.counter.add_tx(data.len());
             Ok(data.len())
         } else {
             Err(ComServerError {
@@ -162,30 +193,28 @@ impl ComServer for SocketCanAPI {
     ) -> Result<Vec<CanFrame>, ComServerError> {
         // Timeout is handled manually here!
         let mut res: Vec<CanFrame> = Vec::with_capacity(max_msgs);
+        // Kernel timestamps are not read, so frames are stamped with the host clock
+        let stamper = *self.stamper.read().unwrap();
 
         if timeout_ms == 0 {
             let v_timeout = 10;
             match &self.run_can_iface(|x| x.read_frame().map_err(|x| x.into())) {
-                Ok(cf) => res.push(CanFrame::from(*cf)),
-                Err(e) => {
-                    return Ok(res); // Return what we have
-                }
-            }
-            if res.len() == max_msgs {
-                return Ok(res);
+                Ok(cf) => res.push(stamper.stamp(CanFrame::from(*cf), None)),
+                Err(e) => {} // Return what we have
             }
         } else {
             let start = Instant::now();
             while start.elapsed().as_millis() <= timeout_ms as u128 {
                 match &self.run_can_iface(|x| x.read_frame().map_err(|x| x.into())) {
-                    Ok(cf) => res.push(CanFrame::from(*cf)),
+                    Ok(cf) => res.push(stamper.stamp(CanFrame::from(*cf), None)),
                     Err(_) => {} // Ignore error when using timeout
                 }
                 if res.len() == max_msgs {
-                    return Ok(res);
+                    break;
                 }
             }
         }
+        self.counter.add_rx(res.len());
         Ok(res)
     }
 
@@ -272,6 +301,13 @@ impl ComServer for SocketCanAPI {
                 err_desc: x.to_string(),
             })?; // Disable blocking
         *self.sockcan_iface.write().unwrap() = Some(tp_socket);
+        self.stamper.write().unwrap().restart();
+        self.counter.reset();
+        Ok(())
+    }
+
+    fn set_timestamp_source(&mut self, source: TimestampSource) -> Result<(), ComServerError> {
+        self.stamper.write().unwrap().source = source;
         Ok(())
     }
 
@@ -408,6 +444,18 @@ impl ComServer for SocketCanAPI {
         Ok(()) // Socket CAN does not do this
     }
 
+    fn get_error_counters(&self) -> Option<(u8, u8)> {
+        // The counters are only exposed via netlink, so ask iproute2 for them
+        let out = Command::new("ip")
+            .arg("-details")
+            .arg("link")
+            .arg("show")
+            .arg(&self.iface)
+            .output()
+            .ok()?;
+        parse_berr_counter(&String::from_utf8_lossy(&out.stdout))
+    }
+
     fn read_battery_voltage(&self) -> Result<f32, ComServerError> {
         // Socket CAN cannot measure battery voltage, so return -1.0 so user knows its not supported
         // rather than spitting out an error.
@@ -418,6 +466,10 @@ impl ComServer for SocketCanAPI {
         Box::new(self.clone())
     }
 
+    fn frame_counters(&self) -> FrameCounters {
+        self.counter.get()
+    }
+
     fn get_capabilities(&self) -> DeviceCapabilities {
         DeviceCapabilities {
             name: self.iface.clone(),
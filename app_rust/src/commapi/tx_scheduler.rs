@@ -0,0 +1,583 @@
+//! Priority aware CAN transmit scheduler
+//!
+//! Interleaves periodic messages (Keep-alives, simulated ECU traffic) with one-shot
+//! sends (Diagnostic requests) on a single adapter. Frames that are due in the
+//! same tick are sent lowest CAN ID first, just like bus arbitration would order them.
+//! One-shot sends are limited per tick, so a burst of requests cannot push the periodic
+//! messages off their cadence, and periodic messages are limited to the ones due, so
+//! requests are never starved.
+//!
+//! [TransportServer](super::can_transport::TransportServer) starts a scheduler for
+//! [ComServer::add_periodic_message](super::comm_api::ComServer::add_periodic_message),
+//! and sends all other frames through it while it is running.
+//!
+//! For timing critical bench tests, [send_timed] places a sequence of frames on the bus
+//! at exact offsets from each other, busy-waiting rather than relying on the scheduler tick.
+//! INJECT mode uses it to play back recorded traces, see [crate::cli::inject]
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Condvar, Mutex, RwLock,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use super::{
+    comm_api::{CanFrame, ComServer, ComServerError},
+    iso_tp::{Clock, SystemClock},
+};
+
+/// Maximum number of one-shot frames sent per scheduler tick
+const DEFAULT_MAX_ONE_SHOT_PER_TICK: usize = 8;
+
+/// Longest the scheduler thread waits before checking the schedule again
+const TICK: Duration = Duration::from_micros(500);
+
+/// [send_timed] sleeps until this long before a frame is due, then busy-waits.
+/// OS sleeps commonly overshoot by a millisecond or more
+const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TxKind {
+    /// Periodic message with the ID returned by [TxScheduler::add_periodic]
+    Periodic(u32),
+    /// One-shot message sent with [TxScheduler::send]
+    OneShot,
+}
+
+/// Where the result of a one-shot send is reported
+type Reply = mpsc::Sender<Result<usize, ComServerError>>;
+
+#[derive(Debug, Clone)]
+struct PeriodicMsg {
+    id: u32,
+    frame: CanFrame,
+    interval: Duration,
+    next_due: Duration,
+}
+
+/// A frame to send in the current tick
+#[derive(Debug)]
+struct TxItem {
+    kind: TxKind,
+    frame: CanFrame,
+    /// When the frame was due to be sent
+    due: Duration,
+    /// Set for one-shot frames, whose sender is waiting for the result
+    reply: Option<Reply>,
+}
+
+/// Scheduling statistics
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SchedulerStats {
+    pub periodic_sent: u64,
+    pub one_shot_sent: u64,
+    pub send_errors: u64,
+    /// Number of periodic sends skipped because the scheduler fell more than an interval behind
+    pub periodic_missed: u64,
+    /// Largest delay between when a periodic message was due and when it was sent
+    pub max_jitter: Duration,
+    total_jitter: Duration,
+}
+
+impl SchedulerStats {
+    /// Average delay between when a periodic message was due and when it was sent
+    pub fn avg_jitter(&self) -> Duration {
+        self.total_jitter
+            .as_nanos()
+            .checked_div(self.periodic_sent as u128)
+            .map_or(Duration::from_secs(0), |n| Duration::from_nanos(n as u64))
+    }
+
+    fn add_jitter(&mut self, jitter: Duration) {
+        self.max_jitter = self.max_jitter.max(jitter);
+        self.total_jitter += jitter;
+    }
+}
+
+/// The scheduling logic, without any threading. Time is provided by the caller
+#[derive(Debug, Clone)]
+struct Schedule {
+    periodic: Vec<PeriodicMsg>,
+    one_shot: VecDeque<(CanFrame, Reply)>,
+    next_periodic_id: u32,
+    max_one_shot_per_tick: usize,
+}
+
+impl Schedule {
+    fn new() -> Self {
+        Self {
+            periodic: Vec::new(),
+            one_shot: VecDeque::new(),
+            next_periodic_id: 0,
+            max_one_shot_per_tick: DEFAULT_MAX_ONE_SHOT_PER_TICK,
+        }
+    }
+
+    fn add_periodic(
+        &mut self,
+        frame: CanFrame,
+        interval: Duration,
+        now: Duration,
+    ) -> Result<u32, ComServerError> {
+        if interval == Duration::from_secs(0) {
+            return Err(ComServerError {
+                err_code: 99,
+                err_desc: "Periodic message interval must be greater than 0".into(),
+            });
+        }
+        let id = self.next_periodic_id;
+        self.next_periodic_id += 1;
+        self.periodic.push(PeriodicMsg {
+            id,
+            frame,
+            interval,
+            next_due: now,
+        });
+        Ok(id)
+    }
+
+    fn remove_periodic(&mut self, id: u32) -> bool {
+        let len = self.periodic.len();
+        self.periodic.retain(|p| p.id != id);
+        len != self.periodic.len()
+    }
+
+    /// Queues a one-shot frame. The result of sending it is sent to the returned receiver
+    fn queue(&mut self, frame: CanFrame) -> mpsc::Receiver<Result<usize, ComServerError>> {
+        let (reply, rx) = mpsc::channel();
+        self.one_shot.push_back((frame, reply));
+        rx
+    }
+
+    /// Returns the frames to send now, in the order they should be sent.
+    /// Periodic messages are rescheduled
+    fn next_batch(&mut self, now: Duration, stats: &mut SchedulerStats) -> Vec<TxItem> {
+        let mut batch = Vec::new();
+        for p in self.periodic.iter_mut().filter(|p| p.next_due <= now) {
+            batch.push(TxItem {
+                kind: TxKind::Periodic(p.id),
+                frame: p.frame,
+                due: p.next_due,
+                reply: None,
+            });
+            // Schedule from the due time so the cadence does not drift
+            p.next_due += p.interval;
+            if p.next_due <= now {
+                // Too far behind to catch up, skip the missed sends
+                let missed = (now - p.next_due).as_nanos() / p.interval.as_nanos() + 1;
+                stats.periodic_missed += missed as u64;
+                p.next_due = now + p.interval;
+            }
+        }
+        let one_shots = self.one_shot.len().min(self.max_one_shot_per_tick);
+        batch.extend(
+            self.one_shot
+                .drain(0..one_shots)
+                .map(|(frame, reply)| TxItem {
+                    kind: TxKind::OneShot,
+                    frame,
+                    due: now,
+                    reply: Some(reply),
+                }),
+        );
+        // Lowest ID wins arbitration on the bus. Stable sort keeps FIFO order within an ID
+        batch.sort_by_key(|item| item.frame.id);
+        batch
+    }
+
+    /// Time until there is something to send. Zero if something is due now, or
+    /// None if there is nothing scheduled
+    fn time_until_due(&self, now: Duration) -> Option<Duration> {
+        if !self.one_shot.is_empty() {
+            return Some(Duration::from_secs(0));
+        }
+        let next = self.periodic.iter().map(|p| p.next_due).min()?;
+        Some(next.saturating_sub(now))
+    }
+}
+
+/// Transmits periodic and one-shot CAN frames from a background thread
+#[derive(Debug)]
+pub struct TxScheduler {
+    /// The schedule, and a condition which wakes the thread when a frame is queued
+    schedule: Arc<(Mutex<Schedule>, Condvar)>,
+    stats: Arc<RwLock<SchedulerStats>>,
+    running: Arc<AtomicBool>,
+    clock: Arc<dyn Clock>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TxScheduler {
+    /// Starts the scheduler, which places frames on the bus with `send`.
+    /// `clock` decides when periodic messages are due
+    pub fn start<F>(clock: Arc<dyn Clock>, mut send: F) -> Self
+    where
+        F: FnMut(&CanFrame) -> Result<usize, ComServerError> + Send + 'static,
+    {
+        let schedule = Arc::new((Mutex::new(Schedule::new()), Condvar::new()));
+        let stats = Arc::new(RwLock::new(SchedulerStats::default()));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let schedule_t = schedule.clone();
+        let stats_t = stats.clone();
+        let running_t = running.clone();
+        let clock_t = clock.clone();
+        let handle = std::thread::spawn(move || {
+            let (lock, wake) = &*schedule_t;
+            while running_t.load(Ordering::Relaxed) {
+                let batch = {
+                    let mut stats = stats_t.write().unwrap();
+                    lock.lock().unwrap().next_batch(clock_t.now(), &mut stats)
+                };
+                for item in batch {
+                    let res = send(&item.frame);
+                    {
+                        let mut stats = stats_t.write().unwrap();
+                        match (&res, item.kind) {
+                            (Err(_), _) => stats.send_errors += 1,
+                            (Ok(_), TxKind::OneShot) => stats.one_shot_sent += 1,
+                            (Ok(_), TxKind::Periodic(_)) => {
+                                stats.periodic_sent += 1;
+                                stats.add_jitter(clock_t.now().saturating_sub(item.due));
+                            }
+                        }
+                    }
+                    if let Some(reply) = item.reply {
+                        // The sender may have given up waiting
+                        let _ = reply.send(res);
+                    }
+                }
+                let schedule = lock.lock().unwrap();
+                let wait = schedule
+                    .time_until_due(clock_t.now())
+                    .map_or(TICK, |d| d.min(TICK));
+                if wait > Duration::from_secs(0) && running_t.load(Ordering::Relaxed) {
+                    let _ = wake.wait_timeout(schedule, wait);
+                }
+            }
+        });
+        Self {
+            schedule,
+            stats,
+            running,
+            clock,
+            handle: Some(handle),
+        }
+    }
+
+    /// Adds a message that is sent every `interval`, starting now.
+    /// Returns an ID that can be used to remove the message
+    pub fn add_periodic(&self, frame: CanFrame, interval: Duration) -> Result<u32, ComServerError> {
+        let (lock, wake) = &*self.schedule;
+        let id = lock
+            .lock()
+            .unwrap()
+            .add_periodic(frame, interval, self.clock.now())?;
+        wake.notify_one();
+        Ok(id)
+    }
+
+    /// Stops sending a periodic message. Returns false if the ID was not found
+    pub fn remove_periodic(&self, id: u32) -> bool {
+        self.schedule.0.lock().unwrap().remove_periodic(id)
+    }
+
+    /// Returns true if any periodic messages are being sent
+    pub fn has_periodic(&self) -> bool {
+        !self.schedule.0.lock().unwrap().periodic.is_empty()
+    }
+
+    /// Sends a frame once, as soon as it wins arbitration against the periodic messages
+    /// due at the same time. Blocks until the frame has been sent
+    pub fn send(&self, frame: CanFrame) -> Result<usize, ComServerError> {
+        let stopped = || {
+            Err(ComServerError {
+                err_code: 99,
+                err_desc: "Transmit scheduler was stopped".into(),
+            })
+        };
+        if !self.running.load(Ordering::Relaxed) {
+            return stopped();
+        }
+        let (lock, wake) = &*self.schedule;
+        let rx = lock.lock().unwrap().queue(frame);
+        wake.notify_one();
+        rx.recv().unwrap_or_else(|_| stopped())
+    }
+
+    pub fn get_stats(&self) -> SchedulerStats {
+        *self.stats.read().unwrap()
+    }
+
+    /// Stops the scheduler thread. Any queued one-shot frames fail
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        self.schedule.1.notify_one();
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+            // Jitter is reported as average/max
+            let stats = self.get_stats();
+            log::info!(
+                "Tx scheduler - Sent {} periodic ({} missed), {} one-shot. Jitter {:?}/{:?}",
+                stats.periodic_sent,
+                stats.periodic_missed,
+                stats.one_shot_sent,
+                stats.avg_jitter(),
+                stats.max_jitter
+            );
+        }
+        // Queued frames are dropped, which fails their senders
+        self.schedule.0.lock().unwrap().one_shot.clear();
+    }
+}
+
+impl Drop for TxScheduler {
+    fn drop(&mut self) {
+        self.stop()
+    }
+}
+
+/// Achieved timing of a [send_timed] sequence
+#[derive(Debug, Clone, Default)]
+pub struct TimedSendReport {
+    /// Requested offset of each frame from the start of the sequence, in send order
+    pub target: Vec<Duration>,
+    /// Offset from the start of the sequence at which the adapter accepted each frame
+    pub actual: Vec<Duration>,
+}
+
+impl TimedSendReport {
+    /// How far off its target each frame was sent. Frames are never sent early
+    pub fn errors(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.actual
+            .iter()
+            .zip(self.target.iter())
+            .map(|(a, t)| a.saturating_sub(*t))
+    }
+
+    pub fn max_error(&self) -> Duration {
+        self.errors().max().unwrap_or_default()
+    }
+
+    pub fn mean_error(&self) -> Duration {
+        match self.actual.len() {
+            0 => Duration::from_secs(0),
+            n => self.errors().sum::<Duration>() / n as u32,
+        }
+    }
+}
+
+/// Waits until `deadline`, sleeping while it is far away and busy-waiting for the rest
+fn wait_until(clock: &dyn Clock, deadline: Duration) {
+    let remaining = deadline.saturating_sub(clock.now());
+    if remaining > SPIN_THRESHOLD {
+        clock.sleep(remaining - SPIN_THRESHOLD);
+    }
+    clock.spin_until(deadline);
+}
+
+/// Sends each frame at its offset from the start of the sequence, and reports how
+/// accurately the offsets were met. Frames are sent in order of their offset.
+///
+/// None of the adapters in use can schedule a frame in hardware, so the calling thread
+/// busy-waits for each frame. Accuracy is then limited by the adapter's send latency,
+/// which can be measured with STRESS mode. The CAN interface of `server` must already be open
+pub fn send_timed(
+    server: &mut dyn ComServer,
+    frames: &[(Duration, CanFrame)],
+) -> Result<TimedSendReport, ComServerError> {
+    send_timed_with(frames, &SystemClock::default(), |f| {
+        server.send_can_packets(&[*f], 0).map(|_| ())
+    })
+}
+
+fn send_timed_with<F>(
+    frames: &[(Duration, CanFrame)],
+    clock: &dyn Clock,
+    mut send: F,
+) -> Result<TimedSendReport, ComServerError>
+where
+    F: FnMut(&CanFrame) -> Result<(), ComServerError>,
+{
+    let mut frames = frames.to_vec();
+    frames.sort_by_key(|(offset, _)| *offset);
+    let mut report = TimedSendReport::default();
+    let start = clock.now();
+    for (offset, frame) in frames {
+        wait_until(clock, start + offset);
+        send(&frame)?;
+        report.target.push(offset);
+        report.actual.push(clock.now() - start);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commapi::iso_tp::MockClock;
+
+    fn ids(batch: &[TxItem]) -> Vec<u32> {
+        batch.iter().map(|item| item.frame.id).collect()
+    }
+
+    fn ms(x: u64) -> Duration {
+        Duration::from_millis(x)
+    }
+
+    #[test]
+    fn test_arbitration_order() {
+        let mut stats = SchedulerStats::default();
+        let mut s = Schedule::new();
+        s.add_periodic(CanFrame::new(0x500, &[0x00]), ms(100), ms(0))
+            .unwrap();
+        s.queue(CanFrame::new(0x7E0, &[0x3E, 0x00]));
+        s.queue(CanFrame::new(0x100, &[0x01]));
+        let batch = s.next_batch(ms(0), &mut stats);
+        assert_eq!(ids(&batch), vec![0x100, 0x500, 0x7E0]);
+        assert_eq!(batch[1].kind, TxKind::Periodic(0));
+        assert!(batch[1].reply.is_none() && batch[2].reply.is_some());
+    }
+
+    #[test]
+    fn test_periodic_cadence() {
+        let mut stats = SchedulerStats::default();
+        let mut s = Schedule::new();
+        s.add_periodic(CanFrame::new(0x500, &[0x00]), ms(100), ms(0))
+            .unwrap();
+        assert_eq!(s.next_batch(ms(0), &mut stats).len(), 1);
+        // Not due yet
+        assert!(s.next_batch(ms(50), &mut stats).is_empty());
+        assert_eq!(s.time_until_due(ms(50)), Some(ms(50)));
+        // Sent late, next one is still scheduled from the original cadence
+        let batch = s.next_batch(ms(120), &mut stats);
+        assert_eq!(batch[0].due, ms(100));
+        assert!(s.next_batch(ms(199), &mut stats).is_empty());
+        assert_eq!(s.next_batch(ms(200), &mut stats).len(), 1);
+        // Far behind, missed sends are skipped
+        s.next_batch(ms(650), &mut stats);
+        assert_eq!(stats.periodic_missed, 3);
+        assert!(s.next_batch(ms(700), &mut stats).is_empty());
+    }
+
+    #[test]
+    fn test_zero_interval() {
+        let mut s = Schedule::new();
+        assert!(s
+            .add_periodic(CanFrame::new(0x500, &[0x00]), ms(0), ms(0))
+            .is_err());
+        assert_eq!(s.time_until_due(ms(0)), None);
+    }
+
+    #[test]
+    fn test_avg_jitter() {
+        let mut stats = SchedulerStats::default();
+        assert_eq!(stats.avg_jitter(), ms(0));
+        stats.add_jitter(ms(1));
+        stats.add_jitter(ms(4));
+        stats.periodic_sent = 2;
+        assert_eq!(stats.avg_jitter(), Duration::from_micros(2500));
+        assert_eq!(stats.max_jitter, ms(4));
+        // More sends than fit in a u32
+        stats.periodic_sent = u32::MAX as u64 * 2;
+        assert_eq!(stats.avg_jitter(), Duration::from_nanos(0));
+    }
+
+    #[test]
+    fn test_one_shot_burst_limit() {
+        let mut stats = SchedulerStats::default();
+        let mut s = Schedule::new();
+        s.max_one_shot_per_tick = 2;
+        s.add_periodic(CanFrame::new(0x500, &[0x00]), ms(10), ms(0))
+            .unwrap();
+        for i in 0..5 {
+            s.queue(CanFrame::new(0x7E0, &[i]));
+        }
+        assert_eq!(s.next_batch(ms(0), &mut stats).len(), 3);
+        let batch = s.next_batch(ms(10), &mut stats);
+        assert_eq!(ids(&batch), vec![0x500, 0x7E0, 0x7E0]);
+        // FIFO order is kept for the same ID
+        assert_eq!(batch[1].frame.get_data(), &[2]);
+        assert_eq!(s.next_batch(ms(11), &mut stats).len(), 1);
+        assert!(s.remove_periodic(0));
+        assert!(!s.remove_periodic(0));
+    }
+
+    #[test]
+    fn test_scheduler_thread() {
+        let clock = Arc::new(MockClock::default());
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sent_t = sent.clone();
+        let mut scheduler = TxScheduler::start(clock.clone(), move |f| {
+            sent_t.lock().unwrap().push(f.id);
+            Ok(1)
+        });
+        assert!(scheduler
+            .add_periodic(CanFrame::new(0x500, &[0x00]), ms(0))
+            .is_err());
+        let id = scheduler
+            .add_periodic(CanFrame::new(0x500, &[0x00]), ms(100))
+            .unwrap();
+        assert!(scheduler.has_periodic());
+        // Blocks until sent, so the periodic message due now has gone first
+        assert_eq!(
+            scheduler.send(CanFrame::new(0x7E0, &[0x3E, 0x00])).unwrap(),
+            1
+        );
+        assert_eq!(*sent.lock().unwrap(), vec![0x500, 0x7E0]);
+        // The mock clock has not moved, so the periodic message is not due again
+        assert_eq!(
+            scheduler.send(CanFrame::new(0x7E0, &[0x3E, 0x00])).unwrap(),
+            1
+        );
+        assert_eq!(*sent.lock().unwrap(), vec![0x500, 0x7E0, 0x7E0]);
+
+        assert!(scheduler.remove_periodic(id));
+        assert!(!scheduler.has_periodic());
+        let stats = scheduler.get_stats();
+        assert_eq!((stats.periodic_sent, stats.one_shot_sent), (1, 2));
+        assert_eq!(stats.max_jitter, ms(0));
+        scheduler.stop();
+        assert!(scheduler.send(CanFrame::new(0x7E0, &[0x3E, 0x00])).is_err());
+    }
+
+    #[test]
+    fn test_send_timed() {
+        let us = Duration::from_micros;
+        let frames = [
+            (us(5000), CanFrame::new(0x7E8, &[0x02])),
+            (us(0), CanFrame::new(0x7E0, &[0x01])),
+            (us(5250), CanFrame::new(0x7E0, &[0x03])),
+        ];
+        let clock = MockClock::default();
+        clock.advance(us(1000));
+        let mut sent = Vec::new();
+        let report = send_timed_with(&frames, &clock, |f| {
+            sent.push((f.get_data()[0], clock.now()));
+            // The first frame takes the adapter 300us to accept
+            if f.get_data()[0] == 0x01 {
+                clock.advance(us(300));
+            }
+            Ok(())
+        })
+        .unwrap();
+        // Each frame goes out exactly when it is due
+        assert_eq!(
+            sent,
+            vec![(0x01, us(1000)), (0x02, us(6000)), (0x03, us(6250))]
+        );
+        assert_eq!(report.target, vec![us(0), us(5000), us(5250)]);
+        assert_eq!(report.actual, vec![us(300), us(5000), us(5250)]);
+        assert_eq!(report.max_error(), us(300));
+        assert_eq!(report.mean_error(), us(100));
+
+        let err = send_timed_with(&frames, &clock, |_| {
+            Err(ComServerError::not_supported("Test"))
+        });
+        assert!(err.is_err());
+    }
+}
@@ -0,0 +1,91 @@
+//! Error type for tools which work across the CBF parser and the communication
+//! layers, such as reading a CBF file and then talking to the ECU it describes.
+//!
+//! Each layer keeps its own error type. [OvdError] just wraps them so that a
+//! pipeline spanning several layers can return `OvdResult` and use `?` throughout
+
+use cbf_parser::caesar::CaesarError;
+
+use crate::commapi::{comm_api::ComServerError, protocols::ProtocolError};
+
+#[derive(Debug)]
+pub enum OvdError {
+    /// The CBF file could not be parsed
+    Parser(CaesarError),
+    /// The adapter failed
+    Comm(ComServerError),
+    /// The diagnostic protocol (UDS / KWP2000) failed, or the ECU rejected a request
+    Protocol(ProtocolError),
+}
+
+impl std::fmt::Display for OvdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OvdError::Parser(e) => write!(f, "CBF parser error: {}", e),
+            OvdError::Comm(e) => write!(f, "Communication error: {}", e),
+            OvdError::Protocol(e) => write!(f, "Protocol error: {}", e.get_text()),
+        }
+    }
+}
+
+impl std::error::Error for OvdError {}
+
+impl From<CaesarError> for OvdError {
+    fn from(x: CaesarError) -> Self {
+        OvdError::Parser(x)
+    }
+}
+
+impl From<ComServerError> for OvdError {
+    fn from(x: ComServerError) -> Self {
+        OvdError::Comm(x)
+    }
+}
+
+impl From<ProtocolError> for OvdError {
+    fn from(x: ProtocolError) -> Self {
+        match x {
+            // Adapter errors surface the same way whichever layer they passed through
+            ProtocolError::CommError(e) => OvdError::Comm(e),
+            e => OvdError::Protocol(e),
+        }
+    }
+}
+
+pub type OvdResult<T> = std::result::Result<T, OvdError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse() -> Result<(), CaesarError> {
+        Err(CaesarError::ProcessException("Bad pool".into()))
+    }
+
+    fn send() -> Result<(), ComServerError> {
+        Err(ComServerError {
+            err_code: 99,
+            err_desc: "Adapter unplugged".into(),
+        })
+    }
+
+    fn pipeline(step: u8) -> OvdResult<()> {
+        match step {
+            0 => parse()?,
+            1 => send()?,
+            2 => Err(ProtocolError::from(send().unwrap_err()))?,
+            _ => Err(ProtocolError::Timeout)?,
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_each_layer() {
+        assert!(matches!(pipeline(0), Err(OvdError::Parser(_))));
+        assert!(matches!(pipeline(1), Err(OvdError::Comm(_))));
+        assert!(matches!(pipeline(2), Err(OvdError::Comm(_))));
+        let err = pipeline(3).unwrap_err();
+        assert!(matches!(err, OvdError::Protocol(ProtocolError::Timeout)));
+        assert_eq!(err.to_string(), "Protocol error: Communication timeout");
+    }
+}
@@ -0,0 +1,88 @@
+//! Logger for the `log` facade which keeps recent messages in memory,
+//! so they can be shown in the GUI's log console.
+//!
+//! Messages are also printed to stdout, so running OVD from a terminal works as before.
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use lazy_static::lazy_static;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Maximum number of messages kept in memory. Older messages are dropped
+pub const MAX_LOG_ENTRIES: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    /// Local time the message was logged at (HH:MM:SS.mmm)
+    pub time: String,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {:<5} [{}] {}",
+            self.time, self.level, self.target, self.message
+        )
+    }
+}
+
+lazy_static! {
+    static ref LOG_BUFFER: Mutex<VecDeque<LogEntry>> = Mutex::new(VecDeque::new());
+}
+
+struct BufferLogger;
+
+impl Log for BufferLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Keep dependencies quiet, but log everything from OVD itself
+        metadata.level() <= Level::Info || metadata.target().starts_with("openvehiclediag")
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let entry = LogEntry {
+            time: chrono::Local::now().format("%H:%M:%S%.3f").to_string(),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        };
+        println!("{}", entry);
+        let mut buffer = LOG_BUFFER.lock().unwrap();
+        if buffer.len() >= MAX_LOG_ENTRIES {
+            buffer.pop_front();
+        }
+        buffer.push_back(entry);
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: BufferLogger = BufferLogger;
+
+/// Installs the logger. Should be called once at startup
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Debug)
+    }
+}
+
+/// Returns a copy of the messages currently in memory, oldest first
+pub fn get_entries() -> Vec<LogEntry> {
+    LOG_BUFFER.lock().unwrap().iter().cloned().collect()
+}
+
+/// Returns the number of messages currently in memory
+pub fn len() -> usize {
+    LOG_BUFFER.lock().unwrap().len()
+}
+
+/// Removes all messages from memory
+pub fn clear() {
+    LOG_BUFFER.lock().unwrap().clear()
+}
@@ -3,8 +3,12 @@ use backtrace;
 
 use dialog::DialogBox;
 use iced::{Application, Settings};
+mod cli;
 mod cli_tests;
 mod commapi;
+mod error;
+mod logger;
+#[cfg(feature = "passthru")]
 mod passthru;
 mod themes;
 mod widgets;
@@ -29,13 +33,25 @@ fn main() -> iced::Result {
             Icon::from_rgba(img.clone().into_bytes(), img.width(), img.height()).ok()
     }
 
-    let args = std::env::args();
-    for a in args {
+    logger::init();
+
+    let args: Vec<String> = std::env::args().collect();
+    for a in &args {
         if a == "-debug_ui" {
             themes::set_debug(true)
         }
     }
 
+    // Run headless if a CLI mode was requested
+    match cli::CliArgs::parse(&args) {
+        Some(Ok(cli_args)) => std::process::exit(cli::run(cli_args)),
+        Some(Err(e)) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1)
+        }
+        None => {}
+    }
+
     panic::set_hook(Box::new(|info|{
         let backtrace = backtrace::Backtrace::new();
 
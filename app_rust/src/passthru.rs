@@ -139,6 +139,14 @@ fn ret_res<T>(res: i32, ret: T) -> Result<T> {
     }
 }
 
+/// Converts a string buffer filled in by the driver to a String. Drivers should null
+/// terminate the string, but if one does not, the whole buffer is used rather than
+/// reading past the end of it. Invalid UTF-8 is replaced rather than panicking
+fn buf_to_string(buf: &[u8]) -> String {
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
 impl PassthruDrv {
     pub fn load_lib(path: String) -> std::result::Result<PassthruDrv, libloading::Error> {
         let lib = unsafe { Library::new(path)? };
@@ -303,32 +311,21 @@ impl PassthruDrv {
                 api_version.as_mut_ptr() as *mut libc::c_char,
             )
         };
-        unsafe {
-            ret_res(
-                res,
-                DrvVersion {
-                    api_version: CStr::from_ptr(api_version.as_ptr() as *const libc::c_char)
-                        .to_str()
-                        .unwrap()
-                        .to_string(),
-                    dll_version: CStr::from_ptr(dll_version.as_ptr() as *const libc::c_char)
-                        .to_str()
-                        .unwrap()
-                        .to_string(),
-                    fw_version: CStr::from_ptr(firmware_version.as_ptr() as *const libc::c_char)
-                        .to_str()
-                        .unwrap()
-                        .to_string(),
-                },
-            )
-        }
+        ret_res(
+            res,
+            DrvVersion {
+                api_version: buf_to_string(&api_version),
+                dll_version: buf_to_string(&dll_version),
+                fw_version: buf_to_string(&firmware_version),
+            },
+        )
     }
 
     //type PassThruGetLastErrorFn = unsafe extern "stdcall" fn(error_description: *mut libc::c_char) -> i32;
     pub fn get_last_error(&self) -> Result<String> {
         let mut err: [u8; 80] = [0; 80];
         let res = unsafe { (&self.get_last_err_fn)(err.as_mut_ptr() as *mut libc::c_char) };
-        ret_res(res, String::from_utf8(err.to_vec()).unwrap())
+        ret_res(res, buf_to_string(&err))
     }
 
     //type PassThruIoctlFn = unsafe extern "stdcall" fn(handle_id: u32, ioctl_id: u32, input: *mut libc::c_void, output: *mut libc::c_void) -> i32;
@@ -366,7 +363,6 @@ impl PassthruDrv {
 
     //type PassThruStartPeriodicMsgFn = unsafe extern "stdcall" fn(channel_id: u32, msg: *const PASSTHRU_MSG, msg_id: *mut u32, time_interval: u32) -> i32;
     /// Returns message ID
-    #[allow(dead_code)]
     pub fn start_periodic_msg(
         &self,
         channel_id: u32,
@@ -386,7 +382,6 @@ impl PassthruDrv {
     }
 
     //type PassThruStopPeriodicMsgFn = unsafe extern "stdcall" fn(channel_id: u32, msg_id: u32) -> i32;
-    #[allow(dead_code)]
     pub fn stop_periodic_msg(&self, channel_id: u32, msg_id: u32) -> Result<()> {
         ret_res(unsafe { (&self.stop_periodic_fn)(channel_id, msg_id) }, ())
     }
@@ -666,3 +661,19 @@ impl PassthruDevice {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buf_to_string() {
+        let mut buf = [0u8; 80];
+        buf[..5].copy_from_slice(b"04.04");
+        assert_eq!(buf_to_string(&buf), "04.04");
+        // Not null terminated
+        assert_eq!(buf_to_string(b"ERR"), "ERR");
+        assert_eq!(buf_to_string(&[0; 80]), "");
+        assert_eq!(buf_to_string(&[b'A', 0xFF, 0, b'B']), "A\u{FFFD}");
+    }
+}
@@ -2,7 +2,7 @@ use iced::{scrollable, Column, Element, Length, Row, Scrollable};
 
 use crate::themes::{button_table, text, ButtonType, TextType};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct TableMsg(pub usize, pub usize);
 
 #[derive(Debug, Clone, Copy)]
@@ -0,0 +1,207 @@
+use std::{fs::File, path::Path};
+
+use iced::{Column, Element, Length, Row, Space};
+
+use crate::{
+    commapi::protocols::{obd2::ObdServer, DTC},
+    themes::{button_outlined, text, title_text, ButtonType, TextType, TitleSize},
+    widgets::table::{Table, TableMsg},
+};
+
+// The browser shows every DTC read from the ECU along with a description. Where the
+// description comes from, in order of preference:
+// 1. The DTC pool of a CBF file which the user loaded. This is the only source for
+//    manufacturer specific codes.
+// 2. The generic SAE J2012 description, for codes which are not manufacturer specific.
+//
+// CBF qualifiers are matched by suffix, as MB prefixes them (For example DTC_P012345)
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DtcBrowserMsg {
+    LoadCbf,
+    Select(TableMsg),
+}
+
+/// DTC from the pool of a CBF file
+#[derive(Debug, Clone)]
+struct CbfDtc {
+    qualifier: String,
+    description: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DtcBrowser {
+    /// DTCs from every variant of the loaded CBF
+    pool: Vec<CbfDtc>,
+    /// Name of the loaded CBF file
+    cbf_name: Option<String>,
+    dtcs: Vec<DTC>,
+    table: Table,
+    load_btn: iced::button::State,
+    status: String,
+}
+
+/// Returns true if a SAE J2012 code (Without the failure type byte) is
+/// reserved for the manufacturer to define (P1xxx, P3xxx, B/C/U1xxx and B/C/U2xxx)
+fn is_manufacturer_specific(code: &str) -> bool {
+    let mut chars = code.chars();
+    match (chars.next(), chars.next()) {
+        (Some('P'), Some(c)) => c == '1' || c == '3',
+        (Some(_), Some(c)) => c == '1' || c == '2',
+        _ => false,
+    }
+}
+
+impl DtcBrowser {
+    pub fn new() -> Self {
+        let mut res = Self::default();
+        res.rebuild_table();
+        res
+    }
+
+    /// Shows a new set of DTCs read from the ECU
+    pub fn set_dtcs(&mut self, dtcs: Vec<DTC>) {
+        self.dtcs = dtcs;
+        self.rebuild_table();
+    }
+
+    /// Loads the DTC pool of every ECU variant in a CBF file
+    pub fn load_cbf(&mut self, path: &Path) {
+        let res = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|mut f| cbf_parser::read_cbf_complete(&mut f).map_err(|e| e.to_string()));
+        match res {
+            Ok(container) => {
+                let mut pool: Vec<CbfDtc> = Vec::new();
+                for dtc in container
+                    .ecus
+                    .iter()
+                    .flat_map(|e| e.variants.iter())
+                    .flat_map(|v| v.dtcs.iter())
+                {
+                    if pool.iter().any(|x| x.qualifier == dtc.qualifier) {
+                        continue;
+                    }
+                    pool.push(CbfDtc {
+                        qualifier: dtc.qualifier.clone(),
+                        description: dtc
+                            .description
+                            .clone()
+                            .or_else(|| dtc.reference.clone())
+                            .unwrap_or_default(),
+                    })
+                }
+                self.status = format!("{} DTCs loaded", pool.len());
+                self.pool = pool;
+                self.cbf_name = path.file_name().map(|x| x.to_string_lossy().to_string());
+            }
+            Err(e) => self.status = format!("Could not read CBF: {}", e),
+        }
+        self.rebuild_table();
+    }
+
+    /// Returns the description of a DTC, and where it came from
+    fn describe(&self, dtc: &DTC) -> (String, &'static str) {
+        let sae = dtc.get_sae_name();
+        // Most specific name first
+        let mut names: Vec<&str> = Vec::new();
+        if let Some(s) = sae.as_deref() {
+            names.push(s);
+        }
+        names.push(&dtc.error);
+        for name in &names {
+            if let Some(d) = self.pool.iter().find(|x| x.qualifier.ends_with(name)) {
+                return (d.description.clone(), "CBF");
+            }
+        }
+
+        let code = match sae.as_deref() {
+            Some(s) => &s[0..5],
+            None => dtc.error.as_str(),
+        };
+        if is_manufacturer_specific(code) {
+            let hint = match self.cbf_name {
+                Some(_) => "Not in the loaded CBF",
+                None => "Load the ECU's CBF for a description",
+            };
+            return (
+                format!("Manufacturer specific code ({})", hint),
+                "Manufacturer",
+            );
+        }
+        let generic = DTC {
+            error: code.into(),
+            ..dtc.clone()
+        };
+        (ObdServer::get_dtc_desc(&generic), "Generic")
+    }
+
+    fn rebuild_table(&mut self) {
+        let entries: Vec<Vec<String>> = self
+            .dtcs
+            .iter()
+            .map(|dtc| {
+                let (desc, source) = self.describe(dtc);
+                vec![
+                    dtc.get_sae_name().unwrap_or_else(|| dtc.error.clone()),
+                    desc,
+                    source.into(),
+                    format!("{:?}", dtc.state),
+                    if dtc.check_engine_on {
+                        "YES".into()
+                    } else {
+                        "NO ".into()
+                    },
+                ]
+            })
+            .collect();
+        self.table = Table::new(
+            vec![
+                "Error".into(),
+                "Description".into(),
+                "Source".into(),
+                "State".into(),
+                "MIL on".into(),
+            ],
+            entries,
+            vec![120, 500, 120, 100, 80],
+            false,
+            400,
+        );
+    }
+
+    pub fn update(&mut self, msg: &DtcBrowserMsg) {
+        match msg {
+            DtcBrowserMsg::LoadCbf => {
+                if let nfd::Response::Okay(f_path) =
+                    nfd::open_file_dialog(Some("cbf"), None).unwrap_or(nfd::Response::Cancel)
+                {
+                    self.load_cbf(Path::new(&f_path))
+                }
+            }
+            DtcBrowserMsg::Select(m) => self.table.update(m),
+        }
+    }
+
+    pub fn view(&mut self) -> Element<DtcBrowserMsg> {
+        let source = match &self.cbf_name {
+            Some(n) => format!("Descriptions from {}", n),
+            None => "No CBF loaded, showing generic descriptions".into(),
+        };
+        let header = Row::new()
+            .spacing(8)
+            .push(text(&source, TextType::Normal))
+            .push(Space::with_width(Length::Fill))
+            .push(
+                button_outlined(&mut self.load_btn, "Load CBF", ButtonType::Secondary)
+                    .on_press(DtcBrowserMsg::LoadCbf),
+            );
+        Column::new()
+            .spacing(8)
+            .push(title_text("Error codes", TitleSize::P4))
+            .push(header)
+            .push(text(&self.status, TextType::Normal))
+            .push(self.table.view().map(DtcBrowserMsg::Select))
+            .into()
+    }
+}
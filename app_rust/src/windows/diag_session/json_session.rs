@@ -1,6 +1,6 @@
 use crate::commapi::{
     iface::{InterfaceConfig, InterfaceType, PayloadFlag, IFACE_CFG},
-    protocols::{kwp2000::read_ecu_identification, DiagCfg},
+    protocols::{kwp2000::read_ecu_identification, variant_check::verify_variant, DiagCfg},
 };
 use common::schema::{
     diag::{dtc::ECUDTC, service::Service},
@@ -122,6 +122,7 @@ impl JsonDiagSession {
                 st_min,
                 ext_isotp_addr,
                 ext_can_addr,
+                isotp_addr_ext,
             } => {
                 let mut cfg = InterfaceConfig::new();
                 cfg.add_param(IFACE_CFG::BAUDRATE, connection_settings.baud);
@@ -129,11 +130,15 @@ impl JsonDiagSession {
                 cfg.add_param(IFACE_CFG::EXT_ISOTP_ADDR, ext_isotp_addr as u32);
                 cfg.add_param(IFACE_CFG::ISOTP_BS, blocksize);
                 cfg.add_param(IFACE_CFG::ISOTP_ST_MIN, st_min);
+                if let Some(ae) = isotp_addr_ext {
+                    cfg.add_param(IFACE_CFG::ISOTP_ADDR_EXT, ae as u32);
+                }
 
                 let diag_cfg = DiagCfg {
                     send_id: connection_settings.send_id,
                     recv_id: connection_settings.recv_id,
                     global_id: connection_settings.global_send_id,
+                    recv_id_mask: None,
                 };
 
                 let tx_flags = vec![PayloadFlag::ISOTP_PAD_FRAME];
@@ -156,29 +161,21 @@ impl JsonDiagSession {
         match create_server {
             Ok(server) => {
                 println!("Server started");
-                let variant = server.get_variant_id()? as u32;
-                let mut unknown_variant = false;
-                let ecu_varient = ecu_data
-                    .variants
-                    .clone()
-                    .into_iter()
-                    .find(|x| {
-                        x.clone()
-                            .patterns
-                            .into_iter()
-                            .any(|p| p.vendor_id == variant)
-                    })
-                    .unwrap_or_else(|| {
+                let check = verify_variant(&server, &ecu_data)?;
+                let unknown_variant = !check.is_match();
+                let (ecu_varient, pattern) = match check.matched {
+                    Some((idx, pattern)) => (ecu_data.variants[idx].clone(), pattern),
+                    None => {
                         eprintln!("WARNING. Unknown ECU Variant!");
-                        unknown_variant = true;
-                        ecu_data.variants[0].clone()
-                    });
-                let pattern = &ecu_varient
-                    .patterns
-                    .iter()
-                    .find(|x| x.vendor_id == variant)
-                    .unwrap()
-                    .clone();
+                        (
+                            ecu_data.variants[0].clone(),
+                            ECUVariantPattern {
+                                vendor: "Unknown".into(),
+                                vendor_id: check.ident.variant_id,
+                            },
+                        )
+                    }
+                };
                 println!(
                     "ECU Variant: {} (Vendor: {})",
                     ecu_varient.name, pattern.vendor
@@ -175,6 +175,7 @@ impl SessionTrait for KWP2000DiagSession {
                     send_id: self.ecu.send_id,
                     recv_id: self.ecu.recv_id,
                     global_id: None,
+                    recv_id_mask: None,
                 };
 
                 match KWP2000ECU::start_diag_session(
@@ -13,6 +13,7 @@ use crate::commapi::{
 use self::{json_session::JsonDiagSessionMsg, kwp2000_session::KWP2000DiagSessionMsg};
 
 pub mod custom_session;
+pub mod dtc_browser;
 pub mod json_session;
 pub mod kwp2000_session;
 pub mod log_view;
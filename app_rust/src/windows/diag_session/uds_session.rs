@@ -20,7 +20,10 @@ use crate::{
     windows::window,
 };
 
-use super::{log_view, DiagMessageTrait, SessionResult, SessionTrait};
+use super::{
+    dtc_browser::{DtcBrowser, DtcBrowserMsg},
+    log_view, DiagMessageTrait, SessionResult, SessionTrait,
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum UDSDiagSessionMsg {
@@ -33,6 +36,7 @@ pub enum UDSDiagSessionMsg {
     ReadCodes,
     SendPayload,
     EnterPayload(String),
+    Dtc(DtcBrowserMsg),
 }
 
 impl DiagMessageTrait for UDSDiagSessionMsg {
@@ -57,6 +61,7 @@ pub struct UDSDiagSession {
     payload_input: iced::text_input::State,
     can_send: bool,
     logview: LogView,
+    dtc_browser: DtcBrowser,
 }
 
 impl UDSDiagSession {
@@ -76,6 +81,7 @@ impl UDSDiagSession {
             payload_send_btn: Default::default(),
             payload_input: Default::default(),
             can_send: false,
+            dtc_browser: DtcBrowser::new(),
         })
     }
 }
@@ -149,6 +155,7 @@ impl SessionTrait for UDSDiagSession {
             }
             ui = ui.push(btn);
         }
+        ui = ui.push(self.dtc_browser.view().map(UDSDiagSessionMsg::Dtc));
         ui = ui.push(Space::with_height(Length::Fill));
         if let Some(se) = &self.diag_server {
             ui = ui.push(Row::new().push(text(
@@ -182,6 +189,7 @@ impl SessionTrait for UDSDiagSession {
                     send_id: self.ecu.send_id,
                     recv_id: self.ecu.recv_id,
                     global_id: None,
+                    recv_id_mask: None,
                 };
 
                 match UDSECU::start_diag_session(
@@ -236,9 +244,11 @@ impl SessionTrait for UDSDiagSession {
                             format!("Error clearing ECU errors: {}", e.get_text()).as_str(),
                             LogType::Error,
                         ),
-                        Ok(_) => self
-                            .logview
-                            .add_msg("ECU Errors cleared successfully", LogType::Error),
+                        Ok(_) => {
+                            self.dtc_browser.set_dtcs(Vec::new());
+                            self.logview
+                                .add_msg("ECU Errors cleared successfully", LogType::Error)
+                        }
                     }
                 }
             }
@@ -263,6 +273,7 @@ impl SessionTrait for UDSDiagSession {
                                     self.logview.add_msg(x.error.as_str(), LogType::Warn);
                                 }
                             }
+                            self.dtc_browser.set_dtcs(errors);
                         }
                     }
                 }
@@ -295,6 +306,7 @@ impl SessionTrait for UDSDiagSession {
                     }
                 }
             }
+            UDSDiagSessionMsg::Dtc(m) => self.dtc_browser.update(m),
             UDSDiagSessionMsg::Back => {}
         }
         None
@@ -1,22 +1,28 @@
 use std::process::Command;
 
-use crate::commapi::passthru_api::PassthruApi;
-use crate::passthru::{PassthruDevice, PassthruDrv};
+use crate::commapi::can_transport::TransportServer;
+use crate::commapi::slcan_api::SlcanApi;
 use crate::themes::{button_coloured, container, picklist, radio_btn, text, ButtonType, TextType};
 use crate::windows::launcher::LauncherMessage::LaunchRequested;
-use crate::windows::window::ApplicationError::DriverError;
 use crate::windows::window::{ApplicationError, WindowMessage};
-use crate::{
-    commapi::comm_api::{ComServer, ComServerError},
-    themes::images::get_launcher_image,
-};
+use crate::{commapi::comm_api::ComServer, themes::images::get_launcher_image};
 use iced::{button, pick_list, Align, Column, Element, Length, Row, Text};
 
-#[cfg(target_os = "linux")]
+#[cfg(feature = "passthru")]
+use crate::commapi::comm_api::ComServerError;
+#[cfg(feature = "passthru")]
+use crate::commapi::passthru_api::PassthruApi;
+#[cfg(feature = "passthru")]
+use crate::passthru::{PassthruDevice, PassthruDrv};
+#[cfg(feature = "passthru")]
+use crate::windows::window::ApplicationError::DriverError;
+
+#[cfg(all(target_os = "linux", feature = "socket-can"))]
 use crate::commapi::socket_can_api::SocketCanAPI;
 
 #[derive(Debug, Clone)]
 pub struct Launcher {
+    #[cfg(feature = "passthru")]
     device_list_passthru: Vec<PassthruDevice>,
     device_names_passthru: Vec<String>,
     selected_device_passthru: String,
@@ -26,11 +32,14 @@ pub struct Launcher {
     device_names_dpdu: Vec<String>,
     selected_device_dpdu: String,
 
-    #[cfg(target_os = "linux")]
+    #[cfg(all(target_os = "linux", feature = "socket-can"))]
     device_names_socketcan: Vec<String>,
-    #[cfg(target_os = "linux")]
+    #[cfg(all(target_os = "linux", feature = "socket-can"))]
     selected_device_socketcan: String,
 
+    device_names_slcan: Vec<String>,
+    selected_device_slcan: String,
+
     api_selection: API,
 
     launch_state: button::State,
@@ -43,6 +52,7 @@ pub enum API {
     DPdu,
     Passthru,
     SocketCAN,
+    Slcan,
 }
 
 #[derive(Debug, Clone)]
@@ -60,16 +70,24 @@ impl ToString for ApplicationError {
     }
 }
 
+#[cfg(feature = "passthru")]
 type Result<T> = std::result::Result<T, ApplicationError>;
 impl Launcher {
     pub fn new() -> Self {
+        #[cfg(feature = "passthru")]
         let passthru_devices = PassthruDevice::find_all().unwrap_or_default();
+        #[cfg(feature = "passthru")]
         let passthru_device_names: Vec<String> =
             passthru_devices.iter().map(|d| d.name.clone()).collect();
+        #[cfg(not(feature = "passthru"))]
+        let passthru_device_names: Vec<String> = Vec::new();
         let selected_passthru_device: String =
             passthru_device_names.get(0).cloned().unwrap_or_default();
+        let slcan_device_names = SlcanApi::find_devices();
+        let selected_slcan_device: String = slcan_device_names.get(0).cloned().unwrap_or_default();
 
         Self {
+            #[cfg(feature = "passthru")]
             device_list_passthru: passthru_devices,
 
             device_names_passthru: passthru_device_names,
@@ -78,13 +96,20 @@ impl Launcher {
             device_names_dpdu: vec![],
             selected_device_dpdu: "".to_string(),
 
-            #[cfg(target_os = "linux")]
+            #[cfg(all(target_os = "linux", feature = "socket-can"))]
             device_names_socketcan: Self::find_devices_socketcan(),
-            #[cfg(target_os = "linux")]
+            #[cfg(all(target_os = "linux", feature = "socket-can"))]
             selected_device_socketcan: "".to_string(),
 
+            device_names_slcan: slcan_device_names,
+            selected_device_slcan: selected_slcan_device,
+
             selection: pick_list::State::default(),
-            api_selection: API::Passthru,
+            api_selection: if cfg!(feature = "passthru") {
+                API::Passthru
+            } else {
+                API::Slcan
+            },
             launch_state: button::State::default(),
             status_text: "".into(),
         }
@@ -98,8 +123,10 @@ impl Launcher {
                     self.selected_device_passthru = d.clone()
                 } else if self.api_selection == API::DPdu {
                     self.selected_device_dpdu = d.clone()
+                } else if self.api_selection == API::Slcan {
+                    self.selected_device_slcan = d.clone()
                 } else {
-                    #[cfg(target_os = "linux")]
+                    #[cfg(all(target_os = "linux", feature = "socket-can"))]
                     {
                         self.selected_device_socketcan = d.clone()
                     }
@@ -107,6 +134,7 @@ impl Launcher {
             }
             LauncherMessage::LaunchRequested => {
                 if self.api_selection == API::Passthru {
+                    #[cfg(feature = "passthru")]
                     match self.get_device_passthru() {
                         Ok((details, driver)) => {
                             let mut server = PassthruApi::new(details, driver);
@@ -121,8 +149,18 @@ impl Launcher {
                     }
                 } else if self.api_selection == API::DPdu {
                     // TODO D-PDU Launching
+                } else if self.api_selection == API::Slcan {
+                    let mut server = TransportServer::new(Box::new(SlcanApi::new(
+                        self.selected_device_slcan.clone(),
+                    )));
+                    if let Err(e) = server.open_device() {
+                        self.status_text = e.to_string()
+                    } else {
+                        // Ready to launch OVD!
+                        return Some(WindowMessage::StartApp(server.clone_box()));
+                    }
                 } else if self.api_selection == API::SocketCAN {
-                    #[cfg(target_os = "linux")]
+                    #[cfg(all(target_os = "linux", feature = "socket-can"))]
                     {
                         let mut server = SocketCanAPI::new(self.selected_device_socketcan.clone());
                         if let Err(e) = server.open_device() {
@@ -139,18 +177,29 @@ impl Launcher {
     }
 
     pub fn view(&mut self) -> Element<LauncherMessage> {
-        let mut selection = Row::new()
-            .push(Text::new("API:"))
-            .push(radio_btn(
-                API::DPdu,
-                "D-PDU",
+        let mut selection = Row::new().push(Text::new("API:")).push(radio_btn(
+            API::DPdu,
+            "D-PDU",
+            Some(self.api_selection),
+            LauncherMessage::SwitchAPI,
+            ButtonType::Primary,
+        ));
+
+        #[cfg(feature = "passthru")]
+        {
+            selection = selection.push(radio_btn(
+                API::Passthru,
+                "Passthru",
                 Some(self.api_selection),
                 LauncherMessage::SwitchAPI,
                 ButtonType::Primary,
-            ))
+            ));
+        }
+
+        selection = selection
             .push(radio_btn(
-                API::Passthru,
-                "Passthru",
+                API::Slcan,
+                "SLCAN",
                 Some(self.api_selection),
                 LauncherMessage::SwitchAPI,
                 ButtonType::Primary,
@@ -159,7 +208,7 @@ impl Launcher {
             .spacing(10)
             .align_items(Align::Center);
 
-        #[cfg(target_os = "linux")] // Only available on Linux
+        #[cfg(all(target_os = "linux", feature = "socket-can"))] // Only available on Linux
         {
             selection = selection.push(radio_btn(
                 API::SocketCAN,
@@ -191,7 +240,7 @@ impl Launcher {
                 )
                 .push(selection)
                 .spacing(10);
-            #[cfg(target_os = "linux")]
+            #[cfg(all(target_os = "linux", feature = "socket-can"))]
             {
                 if self.device_names_socketcan.is_empty() {
                     c = c.push(text(
@@ -219,6 +268,36 @@ impl Launcher {
                 }
             }
             c
+        } else if self.api_selection == API::Slcan {
+            let mut c = Column::new()
+                .push(
+                    get_launcher_image()
+                        .width(Length::Units(300))
+                        .height(Length::Units(300)),
+                )
+                .push(selection)
+                .spacing(10);
+            if self.device_names_slcan.is_empty() {
+                c = c.push(text(
+                    "No serial ports found on this system",
+                    TextType::Normal,
+                ))
+            } else {
+                c = c
+                    .push(Text::new("Select SLCAN serial port"))
+                    .push(picklist(
+                        &mut self.selection,
+                        &self.device_names_slcan,
+                        Some(self.selected_device_slcan.clone()),
+                        LauncherMessage::DeviceSelected,
+                    ))
+                    .push(
+                        button_coloured(&mut self.launch_state, "Launch OVD", ButtonType::Primary)
+                            .on_press(LaunchRequested),
+                    )
+                    .push(Text::new(&self.status_text));
+            }
+            c
         } else {
             let mut c = Column::new()
                 .push(
@@ -262,6 +341,7 @@ impl Launcher {
             .into()
     }
 
+    #[cfg(feature = "passthru")]
     fn get_device_passthru(&self) -> Result<(PassthruDevice, PassthruDrv)> {
         match self
             .device_list_passthru
@@ -283,6 +363,7 @@ impl Launcher {
         }
     }
 
+    #[cfg(all(target_os = "linux", feature = "socket-can"))]
     fn find_devices_socketcan() -> Vec<String> {
         let cmd = Command::new("ip")
             .arg("-o")
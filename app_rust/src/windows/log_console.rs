@@ -0,0 +1,93 @@
+use iced::{
+    button, scrollable, time, Clipboard, Column, Element, Length, Row, Scrollable, Space,
+    Subscription,
+};
+use log::Level;
+
+use crate::{
+    logger::{self, LogEntry},
+    themes::{button_outlined, text, title_text, ButtonType, TextType, TitleSize},
+};
+
+#[derive(Debug, Clone)]
+pub enum LogConsoleMessage {
+    Refresh,
+    Clear,
+    CopyToClipboard,
+}
+
+/// Console panel showing the most recent messages sent to the [logger]
+#[derive(Debug, Clone, Default)]
+pub struct LogConsole {
+    entries: Vec<LogEntry>,
+    scroll_state: scrollable::State,
+    clear_btn: button::State,
+    copy_btn: button::State,
+}
+
+impl LogConsole {
+    pub fn new() -> Self {
+        Self {
+            entries: logger::get_entries(),
+            ..Default::default()
+        }
+    }
+
+    pub fn update(&mut self, msg: &LogConsoleMessage, clipboard: &mut Clipboard) {
+        match msg {
+            LogConsoleMessage::Refresh => {
+                if logger::len() != self.entries.len() {
+                    self.entries = logger::get_entries()
+                }
+            }
+            LogConsoleMessage::Clear => {
+                logger::clear();
+                self.entries.clear();
+            }
+            LogConsoleMessage::CopyToClipboard => {
+                let contents: Vec<String> = self.entries.iter().map(|e| e.to_string()).collect();
+                clipboard.write(contents.join("\n"))
+            }
+        }
+    }
+
+    pub fn subscription(&self) -> Subscription<LogConsoleMessage> {
+        time::every(std::time::Duration::from_millis(500)).map(|_| LogConsoleMessage::Refresh)
+    }
+
+    pub fn view(&mut self) -> Element<LogConsoleMessage> {
+        let header = Row::new()
+            .spacing(5)
+            .width(Length::Fill)
+            .push(title_text("Log console", TitleSize::P4))
+            .push(Space::with_width(Length::Fill))
+            .push(
+                button_outlined(&mut self.copy_btn, "Copy to clipboard", ButtonType::Info)
+                    .on_press(LogConsoleMessage::CopyToClipboard),
+            )
+            .push(
+                button_outlined(&mut self.clear_btn, "Clear", ButtonType::Warning)
+                    .on_press(LogConsoleMessage::Clear),
+            );
+
+        // Newest messages first, so they are visible without scrolling
+        let mut s = Scrollable::new(&mut self.scroll_state)
+            .width(Length::Fill)
+            .height(Length::Fill);
+        for e in self.entries.iter().rev() {
+            let text_type = match e.level {
+                Level::Error => TextType::Danger,
+                Level::Warn => TextType::Warning,
+                Level::Info => TextType::Normal,
+                Level::Debug | Level::Trace => TextType::Disabled,
+            };
+            s = s.push(text(&e.to_string(), text_type).size(14))
+        }
+        Column::new()
+            .padding(5)
+            .spacing(5)
+            .push(header)
+            .push(s)
+            .into()
+    }
+}
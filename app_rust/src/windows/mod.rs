@@ -5,5 +5,6 @@ pub(crate) mod diag_scanner;
 pub(crate) mod diag_session;
 pub(crate) mod home;
 pub(crate) mod launcher;
+pub(crate) mod log_console;
 pub(crate) mod obd;
 pub mod window;
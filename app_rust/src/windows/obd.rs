@@ -4,19 +4,35 @@ use crate::{
         comm_api::{Capability, ComServer},
         iface::{InterfaceConfig, InterfaceType, PayloadFlag, IFACE_CFG},
         protocols::{
-            obd2::{service09::Service09Data, ObdServer},
-            DiagCfg, ProtocolServer,
+            obd2::{
+                service01::{MonitorReadiness, MonitorStatus},
+                service03::Service03,
+                service07::Service07,
+                service09::Service09Data,
+                ObdServer,
+            },
+            DiagCfg, ProtocolServer, DTC,
         },
     },
     themes::button_coloured,
 };
-use iced::{button, Align, Button, Column, Element, Length, Row, Space, Text};
+use iced::{button, time, Align, Button, Column, Element, Length, Row, Space, Subscription, Text};
+use std::time::{Duration, Instant};
+
+/// How often the adapter is checked whilst in a session
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// Battery voltage reads can time out if the driver is under heavy IO load, so only
+/// give up on the adapter after this many failed checks in a row
+const MAX_FAILED_HEALTH_CHECKS: u32 = 3;
 
 #[derive(Debug, Clone)]
 pub enum OBDMessage {
     InitIsoTP,
     Disconnect,
     ChooseService(u8),
+    HealthCheck(Instant),
+    Reconnect,
+    ReadDtcs,
 }
 
 #[derive(Debug, Clone)]
@@ -27,8 +43,20 @@ pub struct OBDHome {
     obd_server: Option<ObdServer>,
     in_session: bool,
     s09_data: Service09Data,
+    /// MIL and emissions monitor readiness, if the ECU supports PID 0x01
+    monitor_status: Option<MonitorStatus>,
+    /// Stored DTCs, or the error reading them
+    stored_dtcs: Result<Vec<DTC>, String>,
+    read_dtcs_state: button::State,
+    /// Pending DTCs, or the error reading them
+    pending_dtcs: Result<Vec<DTC>, String>,
     curr_service: u8,
     service_btn_states: [button::State; 10],
+    /// Number of health checks in a row which have failed
+    failed_checks: u32,
+    /// Set if the session was ended because the adapter stopped responding
+    connection_lost: Option<String>,
+    reconnect_state: button::State,
 }
 
 impl OBDHome {
@@ -40,11 +68,39 @@ impl OBDHome {
             obd_server: None,
             in_session: false,
             s09_data: Default::default(),
+            monitor_status: None,
+            stored_dtcs: Ok(Vec::new()),
+            read_dtcs_state: Default::default(),
+            pending_dtcs: Ok(Vec::new()),
             curr_service: 0,
             service_btn_states: [button::State::default(); 10],
+            failed_checks: 0,
+            connection_lost: None,
+            reconnect_state: Default::default(),
         }
     }
 
+    pub fn subscription(&self) -> Subscription<OBDMessage> {
+        if self.in_session {
+            return time::every(HEALTH_CHECK_INTERVAL).map(OBDMessage::HealthCheck);
+        }
+        Subscription::none()
+    }
+
+    /// Checks the adapter is still there. [ComServer::is_connected] only reports if a
+    /// channel is open, so the battery voltage is also read, which needs the adapter to
+    /// respond. Adapters which cannot read the battery voltage are only checked
+    /// with [ComServer::is_connected]
+    fn check_adapter(&self) -> Result<(), String> {
+        if !self.server.is_connected() {
+            return Err("Adapter has no open channels".into());
+        }
+        if self.server.get_capabilities().battery_voltage == Capability::Yes {
+            self.server.read_battery_voltage().map_err(|e| e.err_desc)?;
+        }
+        Ok(())
+    }
+
     pub fn update(&mut self, msg: &OBDMessage) -> Option<OBDMessage> {
         match msg {
             OBDMessage::InitIsoTP => {
@@ -59,6 +115,7 @@ impl OBDHome {
                         send_id: 0x07DF,
                         recv_id: *test_id,
                         global_id: None,
+                        recv_id_mask: None,
                     };
                     if let Ok(server) = ObdServer::start_diag_session(
                         &self.server,
@@ -70,9 +127,14 @@ impl OBDHome {
                         if let Ok(r) = server.req_service09(|x| Ok(x.get_everything(&server))) {
                             self.s09_data = r;
                         }
+                        self.monitor_status = server
+                            .req_service01(|x| x.read_monitor_status(&server))
+                            .ok();
                         self.obd_server = Some(server);
                         self.in_session = true;
                         self.curr_service = 0; // Reset to landing page of OBD
+                        self.failed_checks = 0;
+                        self.connection_lost = None;
                         println!("Found OBD receiver on address 0x{:04X}", test_id);
                         break;
                     }
@@ -84,17 +146,41 @@ impl OBDHome {
                     self.in_session = false;
                 }
             }
+            OBDMessage::HealthCheck(_) => match self.check_adapter() {
+                Ok(()) => self.failed_checks = 0,
+                Err(e) => {
+                    self.failed_checks += 1;
+                    log::warn!(
+                        "OBD - Adapter health check failed ({}/{}): {}",
+                        self.failed_checks,
+                        MAX_FAILED_HEALTH_CHECKS,
+                        e
+                    );
+                    if self.failed_checks >= MAX_FAILED_HEALTH_CHECKS {
+                        log::error!("OBD - Lost connection to the adapter, ending session");
+                        self.obd_server.take();
+                        self.in_session = false;
+                        self.curr_service = 0;
+                        self.connection_lost = Some(e);
+                    }
+                }
+            },
+            OBDMessage::Reconnect => return Some(OBDMessage::InitIsoTP),
+            OBDMessage::ReadDtcs => {
+                self.stored_dtcs = match self.obd_server.as_ref() {
+                    Some(s) => Service03::read_dtcs(s).map_err(|e| e.get_text()),
+                    None => Err("Not connected to the vehicle".into()),
+                };
+                self.curr_service = 0x03;
+            }
             &OBDMessage::ChooseService(sid) => {
                 if sid == 0x03 {
-                    for dtc in &self.obd_server.as_ref().unwrap().read_errors().unwrap() {
-                        println!(
-                            "Code: {} State: {:?} Desc: {}",
-                            dtc.error,
-                            dtc.state,
-                            ObdServer::get_dtc_desc(dtc)
-                        )
-                    }
-                    return None;
+                    return Some(OBDMessage::ReadDtcs);
+                }
+                if sid == 0x07 {
+                    self.pending_dtcs =
+                        Service07::read_pending_dtcs(self.obd_server.as_ref().unwrap())
+                            .map_err(|e| e.get_text());
                 }
                 self.curr_service = sid; // What service UI should we be in?
             }
@@ -105,6 +191,8 @@ impl OBDHome {
     pub fn view(&mut self) -> Element<OBDMessage> {
         if self.in_session {
             match self.curr_service {
+                0x03 => self.create_s03_ui(),
+                0x07 => self.create_s07_ui(),
                 0x09 => self.create_s09_ui(),
                 _ => self.create_main_ui(),
             }
@@ -131,6 +219,30 @@ impl OBDHome {
             row = row.push(btn)
         }
 
+        let mut status_col = Column::new().spacing(5);
+        if let Some(status) = &self.monitor_status {
+            status_col = match status.mil_on {
+                true => status_col.push(text(
+                    format!("Check engine light ON ({} DTCs)", status.dtc_count).as_str(),
+                    TextType::Danger,
+                )),
+                false => status_col.push(text("Check engine light off", TextType::Success)),
+            };
+            let not_ready = status.not_ready_count();
+            status_col = match not_ready {
+                0 => status_col.push(text("All monitors ready", TextType::Success)),
+                _ => status_col.push(text(
+                    format!("{} monitors not ready", not_ready).as_str(),
+                    TextType::Warning,
+                )),
+            };
+            for (monitor, readiness) in status.get_monitors() {
+                if readiness == MonitorReadiness::NotReady {
+                    status_col = status_col.push(text(monitor.get_name(), TextType::Normal));
+                }
+            }
+        }
+
         Column::new()
             .padding(10)
             .spacing(10)
@@ -139,6 +251,7 @@ impl OBDHome {
                 button_outlined(&mut self.can_state, "Disconnect", ButtonType::Primary)
                     .on_press(OBDMessage::Disconnect),
             )
+            .push(status_col)
             .push(row)
             .into()
     }
@@ -187,16 +300,98 @@ impl OBDHome {
                 .into();
         }
 
-        Column::new()
+        let mut col = Column::new()
             .padding(10)
             .spacing(10)
-            .push(title_text("OBD Diagnostics", TitleSize::P2))
-            .push(Space::with_height(Length::Units(10)))
+            .push(title_text("OBD Diagnostics", TitleSize::P2));
+        if let Some(e) = &self.connection_lost {
+            col = col
+                .push(text(
+                    format!("Connection to the adapter was lost: {}", e).as_str(),
+                    TextType::Danger,
+                ))
+                .push(text(
+                    "Check the adapter is plugged in, then try to reconnect.",
+                    TextType::Normal,
+                ))
+                .push(
+                    button_coloured(&mut self.reconnect_state, "Reconnect", ButtonType::Warning)
+                        .on_press(OBDMessage::Reconnect),
+                );
+        }
+        col.push(Space::with_height(Length::Units(10)))
             .push(btn_row)
             .align_items(Align::Center)
             .into()
     }
 
+    pub fn create_s03_ui(&mut self) -> Element<OBDMessage> {
+        let mut col = Column::new().push(title_text("Stored DTCs", TitleSize::P3));
+        match &self.stored_dtcs {
+            Err(e) => {
+                col = col.push(text(
+                    format!("Cannot read stored DTCs: {}", e).as_str(),
+                    TextType::Danger,
+                ))
+            }
+            Ok(dtcs) if dtcs.is_empty() => {
+                col = col.push(text(
+                    "No stored DTCs, the vehicle has not logged any faults",
+                    TextType::Success,
+                ))
+            }
+            Ok(dtcs) => {
+                col = col.push(text(
+                    format!("{} stored DTCs", dtcs.len()).as_str(),
+                    TextType::Normal,
+                ));
+                for dtc in dtcs {
+                    col = col.push(text(
+                        format!("{} - {}", dtc.error, ObdServer::get_dtc_desc(dtc)).as_str(),
+                        TextType::Danger,
+                    ))
+                }
+            }
+        }
+        let mut read_btn =
+            button_outlined(&mut self.read_dtcs_state, "Read DTCs", ButtonType::Info);
+        if self.server.get_capabilities().supports_iso15765() == Capability::Yes {
+            read_btn = read_btn.on_press(OBDMessage::ReadDtcs);
+        }
+        // Not add_back_button, as the read button already borrows self
+        let back_btn = button_coloured(
+            &mut self.service_btn_states[0],
+            "Go back",
+            ButtonType::Primary,
+        )
+        .on_press(OBDMessage::ChooseService(0));
+        col.push(read_btn).push(back_btn).into()
+    }
+
+    pub fn create_s07_ui(&mut self) -> Element<OBDMessage> {
+        let mut col = Column::new().push(title_text("Pending DTCs", TitleSize::P3));
+        match &self.pending_dtcs {
+            Err(e) => {
+                col = col.push(text(
+                    format!("Cannot read pending DTCs: {}", e).as_str(),
+                    TextType::Danger,
+                ))
+            }
+            Ok(dtcs) if dtcs.is_empty() => {
+                col = col.push(text("No pending DTCs", TextType::Success))
+            }
+            Ok(dtcs) => {
+                for dtc in dtcs {
+                    col = col.push(text(
+                        format!("{} - {}", dtc.error, ObdServer::get_dtc_desc(dtc)).as_str(),
+                        TextType::Warning,
+                    ))
+                }
+            }
+        }
+        col.push(self.add_back_button()).into()
+    }
+
     pub fn create_s09_ui(&mut self) -> Element<OBDMessage> {
         Column::new()
             .push(title_text("Vehicle information", TitleSize::P3))
@@ -3,6 +3,7 @@ use crate::windows::cantracer::{CanTracer, TracerMessage};
 use crate::windows::diag_home::DiagHomeMessage;
 use crate::windows::home::{Home, HomeMessage};
 use crate::windows::launcher::{Launcher, LauncherMessage};
+use crate::windows::log_console::{LogConsole, LogConsoleMessage};
 use crate::windows::obd::{OBDHome, OBDMessage};
 use crate::{
     commapi::comm_api::{Capability, ComServer, ComServerError},
@@ -17,6 +18,9 @@ use std::time::Instant;
 
 use super::diag_home::DiagHome;
 
+/// Height of the log console panel, when shown
+const LOG_CONSOLE_HEIGHT: u16 = 250;
+
 // This can be modified by diagnostic sessions in order to disable going
 // home option in case a sensitive operation is in progress!
 // True by default unless a diagnostic session requests it to be disabled
@@ -115,11 +119,13 @@ pub enum WindowMessage {
     OBDTools(OBDMessage),
     StartApp(Box<dyn ComServer>),
     StatusUpdate(Instant),
-    GoHome,      // Goto home page
-    GoCanTracer, // Goto Can Tracer page
-    GoUDS,       // Goto UDS Scanner page
-    GoOBD,       // Goto OBD Toolbox page
-    ToggleTheme, // Toggle the theme
+    LogConsole(LogConsoleMessage),
+    ToggleLogConsole, // Show or hide the log console
+    GoHome,           // Goto home page
+    GoCanTracer,      // Goto Can Tracer page
+    GoUDS,            // Goto UDS Scanner page
+    GoOBD,            // Goto OBD Toolbox page
+    ToggleTheme,      // Toggle the theme
 }
 
 pub struct MainWindow {
@@ -129,6 +135,9 @@ pub struct MainWindow {
     poll_voltage: bool,
     back_btn_state: button::State,
     theme_toggle: button::State,
+    log_toggle: button::State,
+    log_console: LogConsole,
+    show_log_console: bool,
 }
 
 impl Application for MainWindow {
@@ -145,6 +154,9 @@ impl Application for MainWindow {
                 poll_voltage: false,
                 back_btn_state: button::State::default(),
                 theme_toggle: button::State::default(),
+                log_toggle: button::State::default(),
+                log_console: LogConsole::new(),
+                show_log_console: false,
             },
             Command::none(),
         )
@@ -169,7 +181,7 @@ impl Application for MainWindow {
     fn update(
         &mut self,
         message: Self::Message,
-        _clipboard: &mut Clipboard,
+        clipboard: &mut Clipboard,
     ) -> Command<WindowMessage> {
         match message {
             WindowMessage::StatusUpdate(_) => {
@@ -195,6 +207,14 @@ impl Application for MainWindow {
                 self.state = WindowState::OBDTools(OBDHome::new(self.server.clone().unwrap()))
             }
             WindowMessage::ToggleTheme => toggle_theme(),
+            WindowMessage::ToggleLogConsole => {
+                self.show_log_console = !self.show_log_console;
+                if self.show_log_console {
+                    self.log_console
+                        .update(&LogConsoleMessage::Refresh, clipboard)
+                }
+            }
+            WindowMessage::LogConsole(msg) => self.log_console.update(&msg, clipboard),
             _ => return self.update_children(&message),
         }
         Command::none()
@@ -216,6 +236,15 @@ impl Application for MainWindow {
                 batch.push(tracer.subscription().map(WindowMessage::CanTracer))
             } else if let WindowState::DiagHome(d) = &self.state {
                 batch.push(d.subscription().map(WindowMessage::DiagHome))
+            } else if let WindowState::OBDTools(o) = &self.state {
+                batch.push(o.subscription().map(WindowMessage::OBDTools))
+            }
+            if self.show_log_console {
+                batch.push(
+                    self.log_console
+                        .subscription()
+                        .map(WindowMessage::LogConsole),
+                )
             }
             Subscription::batch(batch)
         }
@@ -244,8 +273,13 @@ impl Application for MainWindow {
                 text("Not supported", TextType::Disabled)
             };
             let page_name = &self.state.get_name();
+            let console_height = if self.show_log_console {
+                LOG_CONSOLE_HEIGHT
+            } else {
+                0
+            };
             let view_contents = Container::new(self.state.view())
-                .height(Length::Units(WIN_HEIGHT as u16 - 50))
+                .height(Length::Units(WIN_HEIGHT as u16 - 50 - console_height))
                 .width(Length::Fill);
             let mut s_bar = Row::new()
                 .padding(5)
@@ -267,14 +301,28 @@ impl Application for MainWindow {
                 )
                 .push(Space::with_width(Length::Fill));
 
-            let mut btn_row = Row::new().spacing(5).push(
-                button_coloured(
-                    &mut self.theme_toggle,
-                    "Toggle theme",
-                    ButtonType::Secondary,
+            let mut btn_row = Row::new()
+                .spacing(5)
+                .push(
+                    button_coloured(
+                        &mut self.log_toggle,
+                        if self.show_log_console {
+                            "Hide log"
+                        } else {
+                            "Show log"
+                        },
+                        ButtonType::Secondary,
+                    )
+                    .on_press(WindowMessage::ToggleLogConsole),
                 )
-                .on_press(WindowMessage::ToggleTheme),
-            );
+                .push(
+                    button_coloured(
+                        &mut self.theme_toggle,
+                        "Toggle theme",
+                        ButtonType::Secondary,
+                    )
+                    .on_press(WindowMessage::ToggleTheme),
+                );
 
             if page_name != &WindowStateName::Home {
                 let mut home_btn =
@@ -286,11 +334,15 @@ impl Application for MainWindow {
             }
             s_bar = s_bar.push(btn_row).height(Length::Units(50));
 
-            let mut c: Element<_> = Column::new()
-                .push(view_contents)
-                .push(Rule::horizontal(1))
-                .push(s_bar)
-                .into();
+            let mut c = Column::new().push(view_contents);
+            if self.show_log_console {
+                c = c.push(Rule::horizontal(1)).push(
+                    Container::new(self.log_console.view().map(WindowMessage::LogConsole))
+                        .height(Length::Units(LOG_CONSOLE_HEIGHT))
+                        .width(Length::Fill),
+                );
+            }
+            let mut c: Element<_> = c.push(Rule::horizontal(1)).push(s_bar).into();
             if themes::is_debug() {
                 c = c.explain(iced::Color::BLACK);
             }
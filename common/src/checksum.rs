@@ -0,0 +1,196 @@
+//! Parameterized CRC implementations
+//!
+//! Any CRC up to 32 bits wide can be described with [CrcParams], using the
+//! same parameter model as the "Catalogue of parametrised CRC algorithms".
+//! Common presets are provided as constants.
+
+/// Parameters describing a CRC algorithm
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CrcParams {
+    /// Width of the CRC in bits (1-32)
+    pub width: u8,
+    /// Generator polynomial, without the leading bit
+    pub poly: u32,
+    /// Initial value of the CRC register
+    pub init: u32,
+    /// Reflect each input byte before processing
+    pub reflect_in: bool,
+    /// Reflect the final CRC value before the XOR out
+    pub reflect_out: bool,
+    /// Value XORed with the final CRC value
+    pub xor_out: u32,
+}
+
+/// CRC-8/SAE-J1850
+pub const CRC_8_SAE_J1850: CrcParams = CrcParams {
+    width: 8,
+    poly: 0x1D,
+    init: 0xFF,
+    reflect_in: false,
+    reflect_out: false,
+    xor_out: 0xFF,
+};
+
+/// CRC-16/CCITT-FALSE (Also known as CRC-16/IBM-3740)
+pub const CRC_16_CCITT_FALSE: CrcParams = CrcParams {
+    width: 16,
+    poly: 0x1021,
+    init: 0xFFFF,
+    reflect_in: false,
+    reflect_out: false,
+    xor_out: 0x0000,
+};
+
+/// CRC-16/XMODEM
+pub const CRC_16_XMODEM: CrcParams = CrcParams {
+    width: 16,
+    poly: 0x1021,
+    init: 0x0000,
+    reflect_in: false,
+    reflect_out: false,
+    xor_out: 0x0000,
+};
+
+/// CRC-32 (As used by Zip, Ethernet, PNG, etc...)
+pub const CRC_32: CrcParams = CrcParams {
+    width: 32,
+    poly: 0x04C11DB7,
+    init: 0xFFFFFFFF,
+    reflect_in: true,
+    reflect_out: true,
+    xor_out: 0xFFFFFFFF,
+};
+
+impl CrcParams {
+    fn mask(&self) -> u32 {
+        if self.width >= 32 {
+            0xFFFFFFFF
+        } else {
+            (1u32 << self.width) - 1
+        }
+    }
+
+    /// Starts a new incremental CRC calculation
+    pub fn digest(&self) -> CrcDigest {
+        CrcDigest {
+            params: *self,
+            state: self.init & self.mask(),
+        }
+    }
+
+    /// Calculates the CRC of a block of data
+    pub fn checksum(&self, data: &[u8]) -> u32 {
+        let mut d = self.digest();
+        d.update(data);
+        d.finish()
+    }
+}
+
+/// An in progress CRC calculation, for data which arrives in chunks
+#[derive(Debug, Copy, Clone)]
+pub struct CrcDigest {
+    params: CrcParams,
+    state: u32,
+}
+
+impl CrcDigest {
+    /// Adds data to the CRC calculation
+    pub fn update(&mut self, data: &[u8]) {
+        let width = self.params.width as u32;
+        let top_bit = 1u32 << (width - 1);
+        let mask = self.params.mask();
+        for b in data {
+            let byte = if self.params.reflect_in {
+                b.reverse_bits()
+            } else {
+                *b
+            };
+            for i in (0..8).rev() {
+                let in_bit = (byte >> i) & 1 == 1;
+                let crc_bit = self.state & top_bit != 0;
+                self.state = (self.state << 1) & mask;
+                if in_bit ^ crc_bit {
+                    self.state ^= self.params.poly;
+                }
+            }
+        }
+        self.state &= mask;
+    }
+
+    /// Returns the final CRC value
+    pub fn finish(&self) -> u32 {
+        let mut res = self.state;
+        if self.params.reflect_out {
+            res = res.reverse_bits() >> (32 - self.params.width as u32);
+        }
+        (res ^ self.params.xor_out) & self.params.mask()
+    }
+}
+
+/// Calculates CRC-8/SAE-J1850 of data
+pub fn crc8_sae_j1850(data: &[u8]) -> u8 {
+    CRC_8_SAE_J1850.checksum(data) as u8
+}
+
+/// Calculates CRC-16/CCITT-FALSE of data
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    CRC_16_CCITT_FALSE.checksum(data) as u16
+}
+
+/// Calculates CRC-32 of data
+pub fn crc32(data: &[u8]) -> u32 {
+    CRC_32.checksum(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECK: &[u8] = b"123456789";
+
+    #[test]
+    fn test_check_values() {
+        assert_eq!(crc8_sae_j1850(CHECK), 0x4B);
+        assert_eq!(crc16_ccitt(CHECK), 0x29B1);
+        assert_eq!(CRC_16_XMODEM.checksum(CHECK), 0x31C3);
+        assert_eq!(crc32(CHECK), 0xCBF43926);
+    }
+
+    #[test]
+    fn test_other_params() {
+        // CRC-16/ARC - Reflected, 16 bit
+        let arc = CrcParams {
+            width: 16,
+            poly: 0x8005,
+            init: 0x0000,
+            reflect_in: true,
+            reflect_out: true,
+            xor_out: 0x0000,
+        };
+        assert_eq!(arc.checksum(CHECK), 0xBB3D);
+        // CRC-8/AUTOSAR
+        let autosar = CrcParams {
+            width: 8,
+            poly: 0x2F,
+            init: 0xFF,
+            reflect_in: false,
+            reflect_out: false,
+            xor_out: 0xFF,
+        };
+        assert_eq!(autosar.checksum(CHECK), 0xDF);
+    }
+
+    #[test]
+    fn test_incremental() {
+        let mut d = CRC_32.digest();
+        d.update(&CHECK[0..4]);
+        d.update(&CHECK[4..]);
+        assert_eq!(d.finish(), crc32(CHECK));
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(crc32(&[]), 0x00000000);
+        assert_eq!(crc16_ccitt(&[]), 0xFFFF);
+    }
+}
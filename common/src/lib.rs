@@ -1,2 +1,3 @@
+pub mod checksum;
 pub mod raf;
 pub mod schema;
\ No newline at end of file
@@ -81,7 +81,7 @@ impl Raf {
 
 
     pub fn read_bytes(&mut self, num_bytes: usize) -> Result<Vec<u8>> {
-        if self.pos+num_bytes-1 > self.size {
+        if num_bytes > self.size.saturating_sub(self.pos) {
             return Err(RafError::BufferOverflow);
         }
         let res = Vec::from(&self.data[self.pos..self.pos + num_bytes]);
@@ -90,7 +90,7 @@ impl Raf {
     }
 
     pub fn read_bytes_as_generic<const SIZE: usize>(&mut self) -> Result<[u8; SIZE]> {
-        if self.pos+SIZE-1 > self.size {
+        if SIZE > self.size.saturating_sub(self.pos) {
             return Err(RafError::BufferOverflow);
         }
         self.pos += SIZE;
@@ -120,6 +120,7 @@ impl Raf {
     /// 
     /// # Example
     /// ```
+    /// # use common::raf::{Raf, RafByteOrder};
     /// let data: Vec<u8> = (0x00..0xFF).collect();
     /// let mut reader: Raf = Raf::from_bytes(&data, RafByteOrder::BE);
     /// reader.seek_read(2, Raf::read_i32); // Seeks to position 2 and reads i32
@@ -204,7 +205,7 @@ impl Raf {
     }
 
     pub fn read_byte(&mut self) -> Result<u8> {
-        if self.pos > self.size {
+        if self.pos >= self.size {
             return Err(RafError::StartOutOfRange);
         }
         let res = self.data[self.pos];
@@ -212,3 +213,22 @@ impl Raf {
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_past_end() {
+        let mut reader = Raf::from_bytes(&[0x41, 0x42, 0x43], RafByteOrder::LE);
+        assert!(reader.read_bytes(4).is_err());
+        assert_eq!(reader.read_bytes(3).unwrap(), [0x41, 0x42, 0x43]);
+        assert!(reader.read_u8().is_err());
+
+        // C String without a terminator at the end of the data
+        reader.seek(1);
+        assert!(reader.read_cstr_bytes().is_err());
+        reader.seek(usize::MAX);
+        assert!(reader.read_u16().is_err());
+    }
+}
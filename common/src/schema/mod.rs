@@ -59,6 +59,11 @@ pub enum ConType {
         ext_can_addr: bool,
         /// Extended ISO-TP Addressing?
         ext_isotp_addr: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(default = "Option::default")]
+        /// Address extension byte sent before the PCI of each CAN Frame,
+        /// for ECUs using extended or mixed ISO-TP addressing
+        isotp_addr_ext: Option<u8>,
     },
 }
 